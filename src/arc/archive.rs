@@ -0,0 +1,203 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{common::headers::Header, ArcOutput, DkimResult};
+
+use super::{ArcInstanceResult, Results, Seal, Set, Signature};
+
+/// Owned copy of an ARC chain and the results of validating it, which can be
+/// stored and inspected later without the original message. Archived chains
+/// are not meant to be verified again.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ArcArchive {
+    pub(crate) result: DkimResult,
+    pub(crate) sets: Vec<ArchivedSet>,
+    pub(crate) instances: Vec<ArcInstanceResult>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ArchivedSet {
+    pub(crate) signature: ArchivedHeader<Signature>,
+    pub(crate) seal: ArchivedHeader<Seal>,
+    pub(crate) results: ArchivedHeader<Results>,
+}
+
+/// A parsed ARC header along with its raw name and value.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ArchivedHeader<T> {
+    pub(crate) name: String,
+    pub(crate) value: String,
+    pub(crate) header: T,
+}
+
+impl ArcArchive {
+    /// Rebuilds the `ArcOutput` the archive was created from, borrowing
+    /// the archived headers.
+    pub fn to_output(&self) -> ArcOutput<'_> {
+        ArcOutput {
+            result: self.result.clone(),
+            set: self
+                .sets
+                .iter()
+                .map(|set| Set {
+                    signature: set.signature.to_header(),
+                    seal: set.seal.to_header(),
+                    results: set.results.to_header(),
+                })
+                .collect(),
+            instances: self.instances.clone(),
+        }
+    }
+}
+
+impl<T: Clone> ArchivedHeader<T> {
+    fn new(header: &Header<'_, &T>) -> Self {
+        ArchivedHeader {
+            name: String::from_utf8_lossy(header.name).into_owned(),
+            value: String::from_utf8_lossy(header.value).into_owned(),
+            header: header.header.clone(),
+        }
+    }
+
+    fn to_header(&self) -> Header<'_, &T> {
+        Header::new(self.name.as_bytes(), self.value.as_bytes(), &self.header)
+    }
+}
+
+impl<'x> ArcOutput<'x> {
+    /// Returns an owned copy of the chain and its validation results.
+    pub fn to_archive(&self) -> ArcArchive {
+        ArcArchive {
+            result: self.result.clone(),
+            sets: self
+                .set
+                .iter()
+                .map(|set| ArchivedSet {
+                    signature: ArchivedHeader::new(&set.signature),
+                    seal: ArchivedHeader::new(&set.seal),
+                    results: ArchivedHeader::new(&set.results),
+                })
+                .collect(),
+            instances: self.instances.clone(),
+        }
+    }
+}
+
+impl Serialize for ArcOutput<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_archive().serialize(serializer)
+    }
+}
+
+pub(crate) mod base64 {
+    use mail_builder::encoders::base64::base64_encode;
+    use mail_parser::decoders::base64::base64_decode;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(
+            std::str::from_utf8(&base64_encode(bytes).unwrap_or_default()).unwrap_or_default(),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        base64_decode(String::deserialize(deserializer)?.as_bytes())
+            .ok_or_else(|| D::Error::custom("invalid base64"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    use trust_dns_resolver::proto::op::ResponseCode;
+
+    use crate::{
+        common::{crypto::RSA_KEY_FORMATS, parse::TxtRecordParser, verify::DomainKey},
+        AuthenticatedMessage, DkimResult, Error, Resolver,
+    };
+
+    use super::ArcArchive;
+
+    #[tokio::test]
+    async fn arc_archive() {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources");
+        test_file.push("arc");
+        test_file.push("002.txt");
+
+        let test = String::from_utf8(fs::read(&test_file).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (key, value) in dns_records
+            .split('\n')
+            .filter_map(|r| r.split_once(' ').map(|(a, b)| (a, b.as_bytes())))
+        {
+            #[cfg(any(test, feature = "test"))]
+            resolver.txt_add(
+                format!("{key}."),
+                DomainKey::parse(value).unwrap(),
+                Instant::now() + Duration::new(3200, 0),
+            );
+        }
+        let raw_message = raw_message.replace('\n', "\r\n");
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc = resolver.verify_arc(&message).await;
+        assert_eq!(arc.result(), &DkimResult::Pass);
+
+        // Compare with the golden file
+        test_file.set_extension("json");
+        let archive = serde_json::from_slice::<ArcArchive>(&fs::read(&test_file).unwrap()).unwrap();
+        assert_eq!(archive, arc.to_archive());
+        assert_eq!(
+            serde_json::from_str::<ArcArchive>(&serde_json::to_string(&arc).unwrap()).unwrap(),
+            archive
+        );
+
+        // Accessors of the rebuilt chain behave the same
+        let output = archive.to_output();
+        assert_eq!(output, arc);
+        assert_eq!(output.instance_results(), arc.instance_results());
+        assert_eq!(output.origin_results(), arc.origin_results());
+        for (set, expected) in output.sets().iter().zip(arc.sets()) {
+            assert_eq!(set.instance(), expected.instance());
+            assert_eq!(set.signature(), expected.signature());
+            assert_eq!(set.seal(), expected.seal());
+            assert_eq!(set.results(), expected.results());
+            assert_eq!(set.raw_signature(), expected.raw_signature());
+            assert_eq!(set.raw_seal(), expected.raw_seal());
+            assert_eq!(set.raw_results(), expected.raw_results());
+        }
+
+        // Verdicts with errors
+        for result in [
+            DkimResult::Fail(Error::ArcMissingInstance(2)),
+            DkimResult::Neutral(Error::FailedBodyHashMatch),
+            DkimResult::TempError(Error::DnsError("timed out".to_string())),
+            DkimResult::PermError(Error::DnsRecordNotFound(ResponseCode::NXDomain)),
+            DkimResult::PermError(Error::InvalidTag("bh")),
+            DkimResult::PermError(Error::UnsupportedKeyFormat(RSA_KEY_FORMATS)),
+        ] {
+            let mut archive = archive.clone();
+            archive.result = result.clone();
+            let archive = serde_json::from_str::<ArcArchive>(
+                &serde_json::to_string(&archive.to_output()).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(archive.to_output().result(), &result);
+        }
+    }
+}
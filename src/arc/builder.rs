@@ -8,8 +8,13 @@
  * except according to those terms.
  */
 
+use std::time::{Duration, SystemTime};
+
 use crate::{
-    common::crypto::{Sha256, SigningKey},
+    common::{
+        crypto::{Sha256, SigningKey},
+        verify::to_ascii_domain,
+    },
     dkim::{Canonicalization, Done, NeedDomain, NeedHeaders, NeedSelector},
 };
 
@@ -28,20 +33,24 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T> {
                 ..Default::default()
             },
             key,
+            expires_at: None,
         }
     }
 }
 
 impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, NeedDomain> {
-    /// Sets the domain to use for signing.
-    pub fn domain(mut self, domain: impl Into<String> + Clone) -> ArcSealer<T, NeedSelector> {
-        self.signature.d = domain.clone().into();
-        self.seal.d = domain.into();
+    /// Sets the domain to use for signing. Internationalized domains are
+    /// converted to A-labels.
+    pub fn domain(mut self, domain: impl Into<String>) -> ArcSealer<T, NeedSelector> {
+        let domain = to_ascii_domain(&domain.into()).into_owned();
+        self.signature.d = domain.clone();
+        self.seal.d = domain;
         ArcSealer {
             _state: Default::default(),
             key: self.key,
             signature: self.signature,
             seal: self.seal,
+            expires_at: self.expires_at,
         }
     }
 }
@@ -56,6 +65,7 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, NeedSelector> {
             key: self.key,
             signature: self.signature,
             seal: self.seal,
+            expires_at: self.expires_at,
         }
     }
 }
@@ -72,6 +82,40 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, NeedHeaders> {
             key: self.key,
             signature: self.signature,
             seal: self.seal,
+            expires_at: self.expires_at,
+        }
+    }
+
+    /// Sets the headers to sign, listing each one more time than it appears in
+    /// the message so that any added instance invalidates the signature.
+    pub fn headers_oversigned(
+        self,
+        headers: impl IntoIterator<Item = impl Into<String>>,
+    ) -> ArcSealer<T, Done> {
+        self.headers_repeated(headers.into_iter().map(|h| (h, 1)))
+    }
+
+    /// Sets the headers to sign, each one followed by the number of extra
+    /// occurrences to add to the h= tag.
+    pub fn headers_repeated(
+        mut self,
+        headers: impl IntoIterator<Item = (impl Into<String>, usize)>,
+    ) -> ArcSealer<T, Done> {
+        let mut oversigned = Vec::new();
+        for (header, count) in headers {
+            let header = header.into();
+            for _ in 0..count {
+                oversigned.push(header.clone());
+            }
+            self.signature.h.push(header);
+        }
+        self.signature.h.extend(oversigned);
+        ArcSealer {
+            _state: Default::default(),
+            key: self.key,
+            signature: self.signature,
+            seal: self.seal,
+            expires_at: self.expires_at,
         }
     }
 }
@@ -80,12 +124,31 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
     /// Sets the number of seconds from now to use for the signature expiration.
     pub fn expiration(mut self, expiration: u64) -> Self {
         self.signature.x = expiration;
+        self.expires_at = None;
         self
     }
 
+    /// Expires signatures at `expiration`, regardless of their signing timestamp.
+    /// Sealing fails with `Error::SignatureExpired` once this time is reached.
+    pub fn expiration_at(mut self, expiration: SystemTime) -> Self {
+        self.signature.x = 0;
+        self.expires_at = expiration
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+            .into();
+        self
+    }
+
+    /// Expires signatures `expiration` after their signing timestamp. Durations are
+    /// rounded up to whole seconds, and only a zero duration disables the x= tag.
+    pub fn expiration_after(self, expiration: Duration) -> Self {
+        self.expiration(expiration.as_secs() + u64::from(expiration.subsec_nanos() > 0))
+    }
+
     /// Include the body length in the signature.
     pub fn body_length(mut self, body_length: bool) -> Self {
-        self.signature.l = u64::from(body_length);
+        self.signature.l = if body_length { Some(0) } else { None };
         self
     }
 
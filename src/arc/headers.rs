@@ -61,11 +61,11 @@ impl Signature {
         }
 
         for (tag, value) in [
-            (&b"t="[..], self.t),
-            (&b"x="[..], self.x),
+            (&b"t="[..], Some(self.t).filter(|&t| t > 0)),
+            (&b"x="[..], Some(self.x).filter(|&x| x > 0)),
             (&b"l="[..], self.l),
         ] {
-            if value > 0 {
+            if let Some(value) = value {
                 let value = value.to_string();
                 writer.write_len(b";", &mut bw);
                 if bw + tag.len() + value.len() >= 76 {
@@ -163,9 +163,14 @@ impl<'x> AuthenticationResults<'x> {
         writer.write(i.to_string().as_bytes());
         writer.write(b"; ");
         writer.write(self.hostname.as_bytes());
+        let auth_results = if !self.auth_results.is_empty() {
+            self.auth_results.as_str()
+        } else {
+            "; none"
+        };
         if !as_header {
             let mut last_is_space = false;
-            for &ch in self.auth_results.as_bytes() {
+            for &ch in auth_results.as_bytes() {
                 if !ch.is_ascii_whitespace() {
                     if last_is_space {
                         writer.write(&[b' ']);
@@ -177,7 +182,7 @@ impl<'x> AuthenticationResults<'x> {
                 }
             }
         } else {
-            writer.write(self.auth_results.as_bytes());
+            writer.write(auth_results.as_bytes());
         }
         writer.write(b"\r\n");
     }
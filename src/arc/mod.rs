@@ -8,12 +8,17 @@
  * except according to those terms.
  */
 
+#[cfg(feature = "serde")]
+pub mod archive;
 pub mod builder;
 pub mod headers;
 pub mod parse;
 pub mod seal;
 pub mod verify;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use crate::{
     common::{
         crypto::{Algorithm, Sha256, SigningKey},
@@ -21,28 +26,35 @@ use crate::{
         verify::VerifySignature,
     },
     dkim::{Canonicalization, NeedDomain},
-    ArcOutput, AuthenticationResults, DkimResult,
+    ArcOutput, AuthenticatedMessage, AuthenticationResults, DkimResult,
 };
 
+/// Maximum number of ARC sets allowed in a chain (RFC 8617, Section 4.2.1).
+pub(crate) const MAX_ARC_INSTANCES: u32 = 50;
+
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct ArcSealer<T: SigningKey<Hasher = Sha256>, State = NeedDomain> {
     _state: std::marker::PhantomData<State>,
     pub(crate) key: T,
     pub(crate) signature: Signature,
     pub(crate) seal: Seal,
+    pub(crate) expires_at: Option<u64>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Signature {
     pub(crate) i: u32,
     pub(crate) a: Algorithm,
     pub(crate) d: String,
     pub(crate) s: String,
+    #[cfg_attr(feature = "serde", serde(with = "archive::base64"))]
     pub(crate) b: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "archive::base64"))]
     pub(crate) bh: Vec<u8>,
     pub(crate) h: Vec<String>,
     pub(crate) z: Vec<String>,
-    pub(crate) l: u64,
+    pub(crate) l: Option<u64>,
     pub(crate) x: u64,
     pub(crate) t: u64,
     pub(crate) ch: Canonicalization,
@@ -50,9 +62,11 @@ pub struct Signature {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Seal {
     pub(crate) i: u32,
     pub(crate) a: Algorithm,
+    #[cfg_attr(feature = "serde", serde(with = "archive::base64"))]
     pub(crate) b: Vec<u8>,
     pub(crate) d: String,
     pub(crate) s: String,
@@ -61,8 +75,47 @@ pub struct Seal {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Results {
     pub(crate) i: u32,
+    pub(crate) authserv_id: String,
+    pub(crate) results: Vec<ResultInfo>,
+    pub(crate) is_malformed: bool,
+}
+
+/// A single result (`method=result` followed by its properties) found in an
+/// ARC-Authentication-Results header.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ResultInfo {
+    pub(crate) method: String,
+    pub(crate) result: String,
+    pub(crate) reason: Option<String>,
+    pub(crate) properties: Vec<(String, String)>,
+    pub(crate) comments: Vec<String>,
+}
+
+/// DMARC results recorded by the first sealer of a validated chain in which
+/// every seal was added by a trusted sealer.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ArcOverride {
+    pub(crate) domain: String,
+    pub(crate) sealers: Vec<String>,
+    pub(crate) results: Results,
+}
+
+/// Outcome of validating each header of an ARC set. Results are
+/// `DkimResult::None` for headers that were not evaluated, either because
+/// validation stopped at an earlier failure or because only the most recent
+/// ARC-Message-Signature is verified.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArcInstanceResult {
+    pub(crate) instance: u32,
+    pub(crate) seal: DkimResult,
+    pub(crate) message_signature: DkimResult,
+    pub(crate) cv: ChainValidation,
+    pub(crate) is_testing: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -79,8 +132,20 @@ pub struct Set<'x> {
     pub(crate) results: Header<'x, &'x Results>,
 }
 
+/// How the order of ARC headers in the message is checked. Header names are
+/// always matched case-insensitively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArcStrictness {
+    /// Headers are sorted by instance before validating the chain.
+    #[default]
+    Lenient,
+    /// Headers of newer instances have to appear above those of older ones.
+    Strict,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub(crate) enum ChainValidation {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ChainValidation {
     None,
     Fail,
     Pass,
@@ -128,7 +193,265 @@ impl VerifySignature for Seal {
     }
 }
 
+impl Signature {
+    /// Returns the instance number (i= tag).
+    pub fn instance(&self) -> u32 {
+        self.i
+    }
+
+    /// Returns the signed header names (h= tag).
+    pub fn signed_headers(&self) -> &[String] {
+        &self.h
+    }
+
+    /// Returns the body hash (bh= tag).
+    pub fn body_hash(&self) -> &[u8] {
+        &self.bh
+    }
+
+    /// Returns the signature timestamp (t= tag), or 0 if absent.
+    pub fn timestamp(&self) -> u64 {
+        self.t
+    }
+
+    /// Returns the signature expiration (x= tag), or 0 if absent.
+    pub fn expiration(&self) -> u64 {
+        self.x
+    }
+}
+
+impl Seal {
+    /// Returns the instance number (i= tag).
+    pub fn instance(&self) -> u32 {
+        self.i
+    }
+
+    /// Returns the chain validation status (cv= tag) stated by the sealer.
+    pub fn chain_validation(&self) -> &ChainValidation {
+        &self.cv
+    }
+
+    /// Returns the seal timestamp (t= tag), or 0 if absent.
+    pub fn timestamp(&self) -> u64 {
+        self.t
+    }
+}
+
+impl Results {
+    /// Returns the instance number (i= tag).
+    pub fn instance(&self) -> u32 {
+        self.i
+    }
+
+    /// Returns the authserv-id of the host that computed these results.
+    pub fn authserv_id(&self) -> &str {
+        &self.authserv_id
+    }
+
+    /// Returns the results in the order they appear in the header.
+    pub fn results(&self) -> &[ResultInfo] {
+        &self.results
+    }
+
+    /// Returns the first result reported for `method` (e.g. `dmarc`).
+    pub fn result(&self, method: &str) -> Option<&ResultInfo> {
+        self.results
+            .iter()
+            .find(|r| r.method.eq_ignore_ascii_case(method))
+    }
+
+    /// Returns `true` if the header value could not be parsed, in which case
+    /// only its raw value is available through [`Set::raw_results`].
+    pub fn is_malformed(&self) -> bool {
+        self.is_malformed
+    }
+}
+
+impl ResultInfo {
+    /// Returns the authentication method, in lowercase and without its version.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the result value, in lowercase.
+    pub fn result(&self) -> &str {
+        &self.result
+    }
+
+    /// Returns the reason= value, if present.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /// Returns the `ptype.property` and value pairs.
+    pub fn properties(&self) -> &[(String, String)] {
+        &self.properties
+    }
+
+    /// Returns the comments found in this result, without their parentheses.
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// Returns the value of the `ptype.property` named `name` (e.g. `header.from`).
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl<'x> Set<'x> {
+    /// Returns the instance number of this set.
+    pub fn instance(&self) -> u32 {
+        self.seal.header.i
+    }
+
+    /// Returns the parsed ARC-Message-Signature.
+    pub fn signature(&self) -> &Signature {
+        self.signature.header
+    }
+
+    /// Returns the parsed ARC-Seal.
+    pub fn seal(&self) -> &Seal {
+        self.seal.header
+    }
+
+    /// Returns the parsed ARC-Authentication-Results.
+    pub fn results(&self) -> &Results {
+        self.results.header
+    }
+
+    /// Returns the raw value of the ARC-Message-Signature header.
+    pub fn raw_signature(&self) -> &'x [u8] {
+        self.signature.value
+    }
+
+    /// Returns the raw value of the ARC-Seal header.
+    pub fn raw_seal(&self) -> &'x [u8] {
+        self.seal.value
+    }
+
+    /// Returns the raw value of the ARC-Authentication-Results header.
+    pub fn raw_results(&self) -> &'x [u8] {
+        self.results.value
+    }
+}
+
+impl ArcOverride {
+    /// Returns the RFC5322.From domain that passed DMARC at the first sealer.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns the d= domain of each seal, starting with the first instance.
+    pub fn sealers(&self) -> &[String] {
+        &self.sealers
+    }
+
+    /// Returns the ARC-Authentication-Results of the first instance.
+    pub fn results(&self) -> &Results {
+        &self.results
+    }
+}
+
+impl ArcInstanceResult {
+    /// Returns the instance (i= tag) of the set.
+    pub fn instance(&self) -> u32 {
+        self.instance
+    }
+
+    /// Returns the result of validating the ARC-Seal, which includes the
+    /// checks on instance numbers and chain validation status.
+    pub fn seal(&self) -> &DkimResult {
+        &self.seal
+    }
+
+    /// Returns the result of validating the ARC-Message-Signature.
+    pub fn message_signature(&self) -> &DkimResult {
+        &self.message_signature
+    }
+
+    /// Returns the chain validation status (cv= tag) claimed by the seal.
+    pub fn cv(&self) -> &ChainValidation {
+        &self.cv
+    }
+
+    /// Returns `true` if the sealer's key is in testing mode (t=y flag).
+    pub fn is_testing(&self) -> bool {
+        self.is_testing
+    }
+
+    pub(crate) fn has_failed(&self) -> bool {
+        [&self.seal, &self.message_signature]
+            .iter()
+            .any(|result| !matches!(result, DkimResult::Pass | DkimResult::None))
+    }
+}
+
 impl<'x> ArcOutput<'x> {
+    /// Returns the ARC-Authentication-Results recorded by the first hop of the
+    /// chain. Only trust these if the chain validated.
+    pub fn origin_results(&self) -> Option<&Results> {
+        self.results(1)
+    }
+
+    /// Returns the ARC-Authentication-Results of instance `i`.
+    pub fn results(&self, i: u32) -> Option<&Results> {
+        self.set
+            .iter()
+            .find(|set| set.results.header.i == i)
+            .map(|set| set.results.header)
+    }
+
+    /// Returns the original DMARC results if the chain validated, every seal was
+    /// added by one of `trusted_sealers` and the first instance reports a DMARC pass.
+    pub fn dmarc_override(&self, trusted_sealers: &[&str]) -> Option<ArcOverride> {
+        if self.result != DkimResult::Pass
+            || !self.set.iter().all(|set| {
+                trusted_sealers
+                    .iter()
+                    .any(|sealer| set.seal.header.d.eq_ignore_ascii_case(sealer))
+            })
+        {
+            return None;
+        }
+
+        let results = self.set.first()?.results.header;
+        let domain = results
+            .result("dmarc")
+            .filter(|dmarc| dmarc.result == "pass")?
+            .property("header.from")?
+            .to_ascii_lowercase();
+
+        Some(ArcOverride {
+            domain,
+            sealers: self
+                .set
+                .iter()
+                .map(|set| set.seal.header.d.to_ascii_lowercase())
+                .collect(),
+            results: results.clone(),
+        })
+    }
+
+    /// Returns an empty output, used to seal messages without ARC headers.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the sets were obtained from the ARC-Seal headers
+    /// of `message`.
+    pub(crate) fn is_chain_of(&self, message: &AuthenticatedMessage<'_>) -> bool {
+        self.set.len() == message.as_headers.len()
+            && self
+                .set
+                .iter()
+                .zip(message.as_headers.iter())
+                .all(|(set, header)| set.seal.value == header.value)
+    }
+
     pub(crate) fn with_result(mut self, result: DkimResult) -> Self {
         self.result = result;
         self
@@ -144,6 +467,7 @@ impl<'x> Default for ArcOutput<'x> {
         Self {
             result: DkimResult::None,
             set: Vec::new(),
+            instances: Vec::new(),
         }
     }
 }
@@ -16,7 +16,7 @@ use crate::{
     Error,
 };
 
-use super::{ChainValidation, Results, Seal, Signature};
+use super::{ChainValidation, ResultInfo, Results, Seal, Signature, MAX_ARC_INSTANCES};
 
 use crate::common::parse::*;
 
@@ -33,7 +33,7 @@ impl Signature {
             bh: Vec::with_capacity(0),
             h: Vec::with_capacity(0),
             z: Vec::with_capacity(0),
-            l: 0,
+            l: None,
             x: 0,
             t: 0,
             i: 0,
@@ -47,7 +47,7 @@ impl Signature {
             match key {
                 I => {
                     signature.i = header.number().unwrap_or(0) as u32;
-                    if !(1..=50).contains(&signature.i) {
+                    if !(1..=MAX_ARC_INSTANCES).contains(&signature.i) {
                         return Err(Error::ArcInvalidInstance(signature.i));
                     }
                 }
@@ -69,7 +69,7 @@ impl Signature {
                 }
                 D => signature.d = header.text(true),
                 H => signature.h = header.items(),
-                L => signature.l = header.number().unwrap_or(0),
+                L => signature.l = header.number(),
                 S => signature.s = header.text(true),
                 T => signature.t = header.number().unwrap_or(0),
                 X => signature.x = header.number().unwrap_or(0),
@@ -147,7 +147,7 @@ impl Seal {
         }
         seal.cv = cv.ok_or(Error::ArcInvalidCV)?;
 
-        if !(1..=50).contains(&seal.i) {
+        if !(1..=MAX_ARC_INSTANCES).contains(&seal.i) {
             Err(Error::ArcInvalidInstance(seal.i))
         } else if !seal.d.is_empty() && !seal.s.is_empty() && !seal.b.is_empty() {
             Ok(seal)
@@ -160,7 +160,12 @@ impl Seal {
 impl Results {
     #[allow(clippy::while_let_on_iterator)]
     pub fn parse(header: &'_ [u8]) -> crate::Result<Self> {
-        let mut results = Results { i: 0 };
+        let mut results = Results {
+            i: 0,
+            authserv_id: String::new(),
+            results: Vec::new(),
+            is_malformed: false,
+        };
         let mut header = header.iter();
 
         while let Some(key) = header.key() {
@@ -173,10 +178,142 @@ impl Results {
             }
         }
 
-        if (1..=50).contains(&results.i) {
-            Ok(results)
-        } else {
-            Err(Error::ArcInvalidInstance(results.i))
+        if !(1..=MAX_ARC_INSTANCES).contains(&results.i) {
+            return Err(Error::ArcInvalidInstance(results.i));
+        }
+
+        // Parse the authserv-id and each method=result entry, keeping only
+        // the raw header if the value is malformed
+        let mut segments = match tokenize_results(&String::from_utf8_lossy(header.as_slice())) {
+            Some(segments) if segments.first().map_or(false, |s| !s.tokens.is_empty()) => {
+                segments.into_iter()
+            }
+            _ => {
+                results.is_malformed = true;
+                return Ok(results);
+            }
+        };
+        if let Some(segment) = segments.next() {
+            results.authserv_id = segment.tokens.into_iter().next().unwrap_or_default();
+        }
+        for segment in segments {
+            let mut tokens = segment.tokens.into_iter();
+            let (method, result) = match tokens.next().as_ref().and_then(|t| t.split_once('=')) {
+                Some((method, result)) => (
+                    method
+                        .split_once('/')
+                        .map_or(method, |(method, _)| method)
+                        .trim()
+                        .to_ascii_lowercase(),
+                    result.trim().to_ascii_lowercase(),
+                ),
+                None => continue,
+            };
+            let mut info = ResultInfo {
+                method,
+                result,
+                comments: segment.comments,
+                ..Default::default()
+            };
+            for token in tokens {
+                if let Some((name, value)) = token.split_once('=') {
+                    if name.eq_ignore_ascii_case("reason") {
+                        info.reason = value.to_string().into();
+                    } else {
+                        info.properties
+                            .push((name.to_ascii_lowercase(), value.to_string()));
+                    }
+                }
+            }
+            results.results.push(info);
         }
+
+        Ok(results)
     }
 }
+
+#[derive(Default)]
+struct Segment {
+    tokens: Vec<String>,
+    comments: Vec<String>,
+}
+
+/// Splits an Authentication-Results value into `;` separated segments of
+/// whitespace separated tokens, unquoting strings and setting comments aside.
+/// Returns `None` if a quoted string or comment is not terminated.
+fn tokenize_results(value: &str) -> Option<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut segment = Segment::default();
+    let mut token = String::new();
+    let mut comment = String::new();
+    let mut in_quotes = false;
+    let mut is_escaped = false;
+    let mut comment_depth = 0u32;
+    let mut pending_space = false;
+
+    for ch in value.chars() {
+        if in_quotes {
+            if is_escaped {
+                token.push(ch);
+                is_escaped = false;
+            } else if ch == '\\' {
+                is_escaped = true;
+            } else if ch == '"' {
+                in_quotes = false;
+            } else {
+                token.push(ch);
+            }
+            continue;
+        } else if comment_depth > 0 {
+            match ch {
+                '(' => comment_depth += 1,
+                ')' => comment_depth -= 1,
+                _ => (),
+            }
+            if comment_depth > 0 {
+                comment.push(ch);
+            } else {
+                segment.comments.push(std::mem::take(&mut comment));
+            }
+            pending_space = true;
+            continue;
+        }
+
+        match ch {
+            '(' => {
+                comment_depth = 1;
+                pending_space = true;
+            }
+            ';' => {
+                if !token.is_empty() {
+                    segment.tokens.push(std::mem::take(&mut token));
+                }
+                segments.push(std::mem::take(&mut segment));
+                pending_space = false;
+            }
+            _ if ch.is_whitespace() => pending_space = true,
+            _ => {
+                // Spaces around '=' do not separate tokens
+                if pending_space && !token.is_empty() && !token.ends_with('=') && ch != '=' {
+                    segment.tokens.push(std::mem::take(&mut token));
+                }
+                pending_space = false;
+                in_quotes = ch == '"';
+                if !in_quotes {
+                    token.push(ch);
+                }
+            }
+        }
+    }
+    if in_quotes || comment_depth > 0 {
+        return None;
+    }
+    if !token.is_empty() {
+        segment.tokens.push(token);
+    }
+    if !segment.tokens.is_empty() || !segment.comments.is_empty() {
+        segments.push(segment);
+    }
+
+    Some(segments)
+}
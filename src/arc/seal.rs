@@ -14,24 +14,45 @@ use mail_builder::encoders::base64::base64_encode;
 
 use crate::{
     common::{
-        crypto::{HashAlgorithm, Sha256, SigningKey},
-        headers::{Writable, Writer},
+        crypto::{Sha256, SigningKey},
+        headers::{HeaderWriter, Writable, Writer},
     },
     dkim::{canonicalize::CanonicalHeaders, Canonicalization, Done},
     ArcOutput, AuthenticatedMessage, AuthenticationResults, DkimResult, Error,
 };
 
-use super::{ArcSealer, ArcSet, ChainValidation, Signature};
+use super::{verify::arc_sets, ArcSealer, ArcSet, ChainValidation, Signature, MAX_ARC_INSTANCES};
 
 impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
+    /// Seals a message with the cv= tag derived from `arc_output`, which has to
+    /// be the result of validating the ARC chain of `message`, or
+    /// `ArcOutput::none()` if the message has no ARC headers.
+    #[inline(always)]
     pub fn seal<'x>(
         &self,
         message: &'x AuthenticatedMessage<'x>,
         results: &'x AuthenticationResults,
         arc_output: &ArcOutput,
+    ) -> crate::Result<ArcSet<'x>> {
+        self.seal_at(message, results, arc_output, SystemTime::now())
+    }
+
+    /// Seals a message as if at `timestamp`, which is used for the t= tags and
+    /// as the base of the x= tag instead of the system clock. Chains that did not
+    /// validate are sealed with cv=fail.
+    pub fn seal_at<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+        results: &'x AuthenticationResults,
+        arc_output: &ArcOutput,
+        timestamp: SystemTime,
     ) -> crate::Result<ArcSet<'x>> {
         if !arc_output.can_be_sealed() {
             return Err(Error::ArcInvalidCV);
+        } else if (arc_output.result == DkimResult::None && message.has_arc_headers())
+            || (arc_output.result == DkimResult::Pass && !arc_output.is_chain_of(message))
+        {
+            return Err(Error::ArcNotValidated);
         }
 
         // Create set
@@ -42,12 +63,39 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
         };
 
         // Set i= and cv=
-        if arc_output.set.is_empty() {
+        if arc_output.set.is_empty() && arc_output.result == DkimResult::None {
             set.signature.i = 1;
             set.seal.i = 1;
             set.seal.cv = ChainValidation::None;
         } else {
-            let i = arc_output.set.last().unwrap().seal.header.i + 1;
+            // Number after the highest instance present, even if the chain is broken
+            let i = message
+                .as_headers
+                .iter()
+                .filter_map(|h| h.header.as_ref().ok().map(|seal| seal.i))
+                .chain(
+                    message
+                        .ams_headers
+                        .iter()
+                        .filter_map(|h| h.header.as_ref().ok().map(|signature| signature.i)),
+                )
+                .chain(
+                    message
+                        .aar_headers
+                        .iter()
+                        .filter_map(|h| h.header.as_ref().ok().map(|results| results.i)),
+                )
+                .chain([
+                    message.as_headers.len() as u32,
+                    message.ams_headers.len() as u32,
+                    message.aar_headers.len() as u32,
+                ])
+                .max()
+                .unwrap_or(0)
+                + 1;
+            if i > MAX_ARC_INSTANCES {
+                return Err(Error::ArcChainTooLong);
+            }
             set.signature.i = i;
             set.seal.i = i;
             set.seal.cv = match &arc_output.result {
@@ -63,41 +111,39 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
         }
 
         // Canonicalize body
-        if set.signature.l > 0 {
-            set.signature.l = (message.raw_message.len() - message.body_offset) as u64;
+        let body = message
+            .raw_message
+            .get(message.body_offset..)
+            .unwrap_or_default();
+        if set.signature.l.is_some() {
+            set.signature.l = Some(
+                message
+                    .body_length_for(set.signature.cb, set.signature.a.into(), None)
+                    .unwrap_or_else(|| set.signature.cb.canonical_body(body, 0).canonical_len()),
+            );
         }
-        let ha = HashAlgorithm::from(set.signature.a);
-        if let Some((_, _, _, bh)) = message
-            .body_hashes
-            .iter()
-            .find(|(c, h, l, _)| c == &set.signature.cb && h == &ha && l == &set.signature.l)
+        if let Some(bh) =
+            message.body_hash_for(set.signature.cb, set.signature.a.into(), set.signature.l)
         {
             // Use cached hash
             set.signature.bh = base64_encode(bh)?;
         } else {
-            let hash = self.key.hash(
-                set.signature.cb.canonical_body(
-                    message
-                        .raw_message
-                        .get(message.body_offset..)
-                        .unwrap_or_default(),
-                    u64::MAX,
-                ),
-            );
+            let hash = self.key.hash(set.signature.cb.canonical_body(body, 0));
             set.signature.bh = base64_encode(hash.as_ref())?;
         }
 
         // Create Signature
-        let now = SystemTime::now()
+        let now = timestamp
             .duration_since(SystemTime::UNIX_EPOCH)
             .map(|d| d.as_secs())
             .unwrap_or(0);
 
         set.signature.t = now;
-        set.signature.x = if set.signature.x > 0 {
-            now + set.signature.x
-        } else {
-            0
+        set.signature.x = match self.expires_at {
+            Some(x) if x <= now => return Err(Error::SignatureExpired),
+            Some(x) => x,
+            None if set.signature.x > 0 => now.saturating_add(set.signature.x),
+            None => 0,
         };
         set.signature.h = signed_headers;
 
@@ -117,6 +163,102 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
 
         Ok(set)
     }
+
+    /// Seals a message with the chain validation status `cv` determined by the
+    /// caller, for relays that validate the ARC chain elsewhere. Fails with
+    /// `Error::ArcInvalidCV` if `cv` contradicts the ARC headers present in
+    /// the message. Prefer [`ArcSealer::seal`], which derives cv= from the
+    /// validation result.
+    pub fn seal_unchecked<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+        results: &'x AuthenticationResults,
+        cv: ChainValidation,
+    ) -> crate::Result<ArcSet<'x>> {
+        let (set, parse_error) = arc_sets(message);
+        let result = match cv {
+            ChainValidation::None if !message.has_arc_headers() => DkimResult::None,
+            ChainValidation::Pass if !set.is_empty() && parse_error.is_none() => DkimResult::Pass,
+            ChainValidation::Fail if message.has_arc_headers() => {
+                DkimResult::Fail(Error::ArcBrokenChain)
+            }
+            _ => return Err(Error::ArcInvalidCV),
+        };
+
+        self.seal(
+            message,
+            results,
+            &ArcOutput {
+                result,
+                set,
+                instances: Vec::new(),
+            },
+        )
+    }
+
+    /// Seals a message that was modified after `arc_output` was obtained, such as
+    /// by a mailing list adding a footer or tagging the Subject. If the chain
+    /// validated, any ARC headers in `message` are replaced with the sets from
+    /// `arc_output`, copied unchanged. The new set is computed over the modified
+    /// content. Sealers configured to sign `ARC-Seal` fail with
+    /// `Error::InvalidSignedHeaders`, as the AMS may not cover it.
+    ///
+    /// Returns the complete message with the new ARC set at the top.
+    pub fn reseal(
+        &self,
+        message: &[u8],
+        results: &AuthenticationResults,
+        arc_output: &ArcOutput,
+    ) -> crate::Result<Vec<u8>> {
+        let mut raw_message = Vec::with_capacity(message.len() + 1024);
+        if arc_output.result == DkimResult::Pass {
+            for set in arc_output.set.iter().rev() {
+                for (name, value) in [
+                    (set.seal.name, set.seal.value),
+                    (set.signature.name, set.signature.value),
+                    (set.results.name, set.results.value),
+                ] {
+                    raw_message.extend_from_slice(name);
+                    raw_message.push(b':');
+                    raw_message.extend_from_slice(value);
+                }
+            }
+
+            let parsed_message = AuthenticatedMessage::parse(message).ok_or(Error::ParseError)?;
+            let base = message.as_ptr() as usize;
+            let mut offset = 0;
+            for (name, value) in &parsed_message.headers {
+                let start = name.as_ptr() as usize - base;
+                let end = if !value.is_empty() {
+                    value.as_ptr() as usize + value.len() - base
+                } else {
+                    start + name.len()
+                };
+                if ![
+                    &b"ARC-Seal"[..],
+                    b"ARC-Message-Signature",
+                    b"ARC-Authentication-Results",
+                ]
+                .iter()
+                .any(|arc_name| name.eq_ignore_ascii_case(arc_name))
+                {
+                    raw_message.extend_from_slice(&message[offset..end]);
+                }
+                offset = end;
+            }
+            raw_message.extend_from_slice(&message[offset..]);
+        } else {
+            raw_message.extend_from_slice(message);
+        }
+
+        let parsed_message = AuthenticatedMessage::parse(&raw_message).ok_or(Error::ParseError)?;
+        let set = self.seal(&parsed_message, results, arc_output)?;
+        let mut sealed_message = Vec::with_capacity(raw_message.len() + 1024);
+        set.write_header(&mut sealed_message);
+        sealed_message.extend_from_slice(&raw_message);
+
+        Ok(sealed_message)
+    }
 }
 
 struct SignableSet<'a> {
@@ -138,7 +280,8 @@ struct SignableChain<'a> {
 
 impl<'a> Writable for SignableChain<'a> {
     fn write(self, writer: &mut impl Writer) {
-        if !self.arc_output.set.is_empty() {
+        // A failed chain is sealed as if the new set was the first one (RFC 8617, Section 5.1.2)
+        if !self.arc_output.set.is_empty() && self.set.seal.cv != ChainValidation::Fail {
             Canonicalization::Relaxed.canonicalize_headers(
                 self.arc_output.set.iter().flat_map(|set| {
                     [
@@ -163,6 +306,13 @@ impl Signature {
         &self,
         message: &'x AuthenticatedMessage<'x>,
     ) -> crate::Result<(CanonicalHeaders<'x>, Vec<String>)> {
+        // ARC-Seal headers are never covered by the AMS (RFC 8617, Section 4.1.2)
+        if self.h.iter().any(|h| h.eq_ignore_ascii_case("ARC-Seal")) {
+            return Err(Error::InvalidSignedHeaders(
+                "ARC-Seal cannot be signed by the ARC-Message-Signature".to_string(),
+            ));
+        }
+
         let mut headers = Vec::with_capacity(self.h.len());
         let mut found_headers = vec![false; self.h.len()];
         let mut signed_headers = Vec::with_capacity(self.h.len());
@@ -196,23 +346,26 @@ impl Signature {
 #[cfg(test)]
 #[allow(unused)]
 mod test {
-    use std::time::{Duration, Instant};
+    use std::time::{Duration, Instant, SystemTime};
 
     use mail_parser::decoders::base64::base64_decode;
 
     use crate::{
-        arc::ArcSealer,
+        arc::{ArcSealer, ChainValidation},
         common::{
-            crypto::{Ed25519Key, RsaKey, Sha256, SigningKey},
+            crypto::{Algorithm, Ed25519Key, RsaKey, Sha256, SigningKey},
             headers::HeaderWriter,
             parse::TxtRecordParser,
-            verify::DomainKey,
+            verify::{DomainKey, VerifySignature},
         },
-        dkim::DkimSigner,
-        AuthenticatedMessage, AuthenticationResults, DkimResult, Resolver,
+        dkim::{DkimSigner, DomainKeyBuilder, MinimumKeyStrength},
+        report::{ActionDisposition, PolicyOverride, PolicyOverrideReason, Record},
+        ArcOutput, AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcOutput,
+        DmarcResult, Error, Resolver,
     };
 
     const RSA_PRIVATE_KEY: &str = include_str!("../../resources/rsa-private.pem");
+    const RSA_4096_PRIVATE_KEY: &str = include_str!("../../resources/rsa-4096-private.pem");
 
     const RSA_PUBLIC_KEY: &str = concat!(
         "v=DKIM1; t=s; p=MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ",
@@ -230,41 +383,55 @@ mod test {
     const ED25519_PUBLIC_KEY: &str =
         "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
 
-    #[cfg(any(
-        feature = "rust-crypto",
-        all(feature = "ring", feature = "rustls-pemfile")
-    ))]
-    #[tokio::test]
-    async fn arc_seal() {
-        let message = concat!(
-            "From: queso@manchego.org\r\n",
-            "To: affumicata@scamorza.org\r\n",
-            "Subject: Say cheese\r\n",
-            "\r\n",
-            "We need to settle which one of us ",
-            "is tastier.\r\n"
-        );
+    const MESSAGE: &str = concat!(
+        "From: queso@manchego.org\r\n",
+        "To: affumicata@scamorza.org\r\n",
+        "Subject: Say cheese\r\n",
+        "\r\n",
+        "We need to settle which one of us ",
+        "is tastier.\r\n"
+    );
 
-        // Crate resolver
+    fn ed25519_key() -> Ed25519Key {
+        let pk_ed_public =
+            base64_decode(ED25519_PUBLIC_KEY.rsplit_once("p=").unwrap().1.as_bytes()).unwrap();
+        let pk_ed_private = base64_decode(ED25519_PRIVATE_KEY.as_bytes()).unwrap();
+        #[cfg(feature = "rust-crypto")]
+        let pk_ed = Ed25519Key::from_bytes(&pk_ed_public, &pk_ed_private).unwrap();
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        let pk_ed = Ed25519Key::from_seed_and_public_key(&pk_ed_private, &pk_ed_public).unwrap();
+        pk_ed
+    }
+
+    /// Returns a resolver publishing the Ed25519 test key at the `ed` selector of
+    /// each domain, the matching signing key and the test message.
+    fn ed25519_fixture(domains: &[&str]) -> (Resolver, Ed25519Key, &'static str) {
         let resolver = Resolver::new_system_conf().unwrap();
-        #[cfg(any(test, feature = "test"))]
-        {
+        for domain in domains {
+            #[cfg(any(test, feature = "test"))]
             resolver.txt_add(
-                "rsa._domainkey.manchego.org.".to_string(),
-                DomainKey::parse(RSA_PUBLIC_KEY.as_bytes()).unwrap(),
-                Instant::now() + Duration::new(3600, 0),
-            );
-            resolver.txt_add(
-                "ed._domainkey.scamorza.org.".to_string(),
+                format!("ed._domainkey.{domain}."),
                 DomainKey::parse(ED25519_PUBLIC_KEY.as_bytes()).unwrap(),
                 Instant::now() + Duration::new(3600, 0),
             );
         }
+        (resolver, ed25519_key(), MESSAGE)
+    }
 
-        // Create private keys
-        let pk_ed_public =
-            base64_decode(ED25519_PUBLIC_KEY.rsplit_once("p=").unwrap().1.as_bytes()).unwrap();
-        let pk_ed_private = base64_decode(ED25519_PRIVATE_KEY.as_bytes()).unwrap();
+    #[cfg(any(
+        feature = "rust-crypto",
+        all(feature = "ring", feature = "rustls-pemfile")
+    ))]
+    #[tokio::test]
+    async fn arc_seal() {
+        // Crate resolver
+        let (resolver, _, message) = ed25519_fixture(&["scamorza.org"]);
+        #[cfg(any(test, feature = "test"))]
+        resolver.txt_add(
+            "rsa._domainkey.manchego.org.".to_string(),
+            DomainKey::parse(RSA_PUBLIC_KEY.as_bytes()).unwrap(),
+            Instant::now() + Duration::new(3600, 0),
+        );
 
         // Create DKIM-signed message
         #[cfg(feature = "rust-crypto")]
@@ -287,17 +454,9 @@ mod test {
             #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
             let pk_rsa = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
 
-            raw_message = arc_verify_and_seal(
-                &resolver,
-                &raw_message,
-                "scamorza.org",
-                "ed",
-                #[cfg(feature = "rust-crypto")]
-                Ed25519Key::from_bytes(&pk_ed_public, &pk_ed_private).unwrap(),
-                #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
-                Ed25519Key::from_seed_and_public_key(&pk_ed_private, &pk_ed_public).unwrap(),
-            )
-            .await;
+            raw_message =
+                arc_verify_and_seal(&resolver, &raw_message, "scamorza.org", "ed", ed25519_key())
+                    .await;
             raw_message =
                 arc_verify_and_seal(&resolver, &raw_message, "manchego.org", "rsa", pk_rsa).await;
         }
@@ -305,6 +464,783 @@ mod test {
         //println!("{}", raw_message);
     }
 
+    #[cfg(any(
+        feature = "rust-crypto",
+        all(feature = "ring", feature = "rustls-pemfile")
+    ))]
+    #[tokio::test]
+    async fn arc_seal_mixed_algorithms() {
+        let (resolver, pk_ed, message) = ed25519_fixture(&["scamorza.org"]);
+        #[cfg(any(test, feature = "test"))]
+        resolver.txt_add(
+            "rsa._domainkey.manchego.org.".to_string(),
+            DomainKey::parse(RSA_PUBLIC_KEY.as_bytes()).unwrap(),
+            Instant::now() + Duration::new(3600, 0),
+        );
+
+        // Seal i=1 with RSA and i=2 with Ed25519
+        #[cfg(feature = "rust-crypto")]
+        let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        let pk_rsa = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+        let raw_message =
+            arc_verify_and_seal(&resolver, message, "manchego.org", "rsa", pk_rsa).await;
+        let raw_message =
+            arc_verify_and_seal(&resolver, &raw_message, "scamorza.org", "ed", pk_ed).await;
+        for header in [
+            "ARC-Seal: i=1; a=rsa-sha256;",
+            "ARC-Message-Signature: i=1; a=rsa-sha256;",
+            "ARC-Seal: i=2; a=ed25519-sha256;",
+            "ARC-Message-Signature: i=2; a=ed25519-sha256;",
+        ] {
+            assert!(raw_message.contains(header), "{header}");
+        }
+
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc_result = resolver.verify_arc(&message).await;
+        assert_eq!(arc_result.result(), &DkimResult::Pass);
+        assert_eq!(
+            arc_result
+                .sets()
+                .iter()
+                .map(|set| (set.seal().algorithm(), set.signature().algorithm()))
+                .collect::<Vec<_>>(),
+            [
+                (Algorithm::RsaSha256, Algorithm::RsaSha256),
+                (Algorithm::Ed25519Sha256, Algorithm::Ed25519Sha256)
+            ]
+        );
+
+        // Publishing the wrong key type for a selector fails the seal
+        #[cfg(any(test, feature = "test"))]
+        resolver.txt_add(
+            "ed._domainkey.scamorza.org.".to_string(),
+            DomainKey::parse(RSA_PUBLIC_KEY.as_bytes()).unwrap(),
+            Instant::now() + Duration::new(3600, 0),
+        );
+        assert_eq!(
+            resolver.verify_arc(&message).await.result(),
+            &DkimResult::Fail(Error::IncompatibleAlgorithms)
+        );
+    }
+
+    #[tokio::test]
+    async fn arc_seal_at() {
+        let (_, pk_ed, raw_message) = ed25519_fixture(&[]);
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let auth_results = AuthenticationResults::new("scamorza.org");
+        let arc_result = ArcOutput::default();
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000);
+        let sealer = ArcSealer::from_key(pk_ed)
+            .domain("scamorza.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .expiration_after(Duration::from_secs(3600));
+
+        let set = sealer
+            .seal_at(&message, &auth_results, &arc_result, timestamp)
+            .unwrap();
+        assert_eq!(set.signature.t, 1700000000);
+        assert_eq!(set.signature.x, 1700003600);
+        assert_eq!(
+            sealer
+                .seal_at(&message, &auth_results, &arc_result, timestamp)
+                .unwrap()
+                .to_header(),
+            set.to_header()
+        );
+
+        // Absolute expirations have to be after the sealing timestamp
+        let mut sealer = sealer.expiration_at(timestamp + Duration::from_secs(60));
+        let set = sealer
+            .seal_at(&message, &auth_results, &arc_result, timestamp)
+            .unwrap();
+        assert_eq!(set.signature.t, 1700000000);
+        assert_eq!(set.signature.x, 1700000060);
+        for expiration in [
+            timestamp,
+            timestamp - Duration::from_secs(1),
+            SystemTime::UNIX_EPOCH,
+        ] {
+            sealer = sealer.expiration_at(expiration);
+            assert_eq!(
+                sealer
+                    .seal_at(&message, &auth_results, &arc_result, timestamp)
+                    .err(),
+                Some(Error::SignatureExpired),
+                "{expiration:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_seal_oversigned() {
+        let (resolver, pk_ed, raw_message) = ed25519_fixture(&["xn--scamorz-fwa.org"]);
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let auth_results = AuthenticationResults::new("scamorza.org");
+        let set = ArcSealer::from_key(pk_ed)
+            .domain("scamorzà.org")
+            .selector("ed")
+            .headers_oversigned(["From", "To", "Subject"])
+            .seal(&message, &auth_results, &ArcOutput::default())
+            .unwrap();
+        assert_eq!(set.signature.d, "xn--scamorz-fwa.org");
+        assert_eq!(set.seal.d, "xn--scamorz-fwa.org");
+        assert_eq!(
+            set.signature.h,
+            ["Subject", "To", "From", "From", "To", "Subject"]
+        );
+        let headers = format!("{}{}", set.to_header(), auth_results.to_header());
+
+        // Adding another instance of an oversigned header breaks the chain
+        for (extra_header, expected) in [
+            ("", DkimResult::Pass),
+            (
+                "Subject: Hostile takeover\r\n",
+                DkimResult::Fail(Error::FailedVerification),
+            ),
+        ] {
+            let sealed_message = format!("{headers}{extra_header}{raw_message}");
+            let message = AuthenticatedMessage::parse(sealed_message.as_bytes()).unwrap();
+            assert_eq!(resolver.verify_arc(&message).await.result(), &expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_seal_authserv_id() {
+        let (resolver, pk_ed, raw_message) = ed25519_fixture(&["example.org"]);
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let sealer = ArcSealer::from_key(pk_ed)
+            .domain("example.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"]);
+
+        for auth_results in [
+            AuthenticationResults::from_value(
+                "mx.example.net",
+                "mx.example.net; spf=pass smtp.mailfrom=example.org; dkim=none",
+            )
+            .unwrap(),
+            AuthenticationResults::new("mx.example.net").with_dkim_results(&[], "example.org"),
+        ] {
+            let set = sealer
+                .seal(&message, &auth_results, &ArcOutput::default())
+                .unwrap();
+            assert_eq!(set.signature.d, "example.org");
+            let sealed_message = format!("{}{}", set.to_header(), raw_message);
+            assert!(
+                sealed_message.contains("\r\nARC-Authentication-Results: i=1; mx.example.net;"),
+                "{sealed_message}"
+            );
+            let message = AuthenticatedMessage::parse(sealed_message.as_bytes()).unwrap();
+            assert_eq!(
+                resolver.verify_arc(&message).await.result(),
+                &DkimResult::Pass
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_set_accessors() {
+        let (resolver, pk_ed, raw_message) = ed25519_fixture(&["google.com"]);
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let auth_results = AuthenticationResults::from_value(
+            "mx.google.com",
+            concat!(
+                "mx.google.com; dkim=none (no signature);\r\n ",
+                "spf=pass (google.com: domain of queso@example.org) ",
+                "smtp.mailfrom=queso@example.org; DMARC/1=PASS ",
+                "reason=\"policy \\\"ok\\\"\" header.from = example.org"
+            ),
+        )
+        .unwrap();
+        let set = ArcSealer::from_key(pk_ed)
+            .domain("google.com")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .seal(&message, &auth_results, &ArcOutput::default())
+            .unwrap();
+        let sealed_message = format!("{}{}", set.to_header(), raw_message);
+
+        // Sets are available whether or not the chain validates
+        for (sealed_message, expected) in [
+            (sealed_message.clone(), DkimResult::Pass),
+            (
+                sealed_message.replace("tastier", "saltier"),
+                DkimResult::Neutral(Error::FailedBodyHashMatch),
+            ),
+        ] {
+            let message = AuthenticatedMessage::parse(sealed_message.as_bytes()).unwrap();
+            let arc = resolver.verify_arc(&message).await;
+            assert_eq!(arc.result(), &expected);
+            assert_eq!(arc.is_validated(), expected == DkimResult::Pass);
+            assert_eq!(arc.sets().len(), 1);
+
+            let set = &arc.sets()[0];
+            assert_eq!(set.instance(), 1);
+            assert_eq!(set.seal().instance(), 1);
+            assert_eq!(set.seal().chain_validation(), &ChainValidation::None);
+            assert_eq!(set.seal().domain(), "google.com");
+            assert_eq!(set.seal().selector(), "ed");
+            assert_eq!(set.signature().instance(), 1);
+            assert_eq!(set.signature().domain(), "google.com");
+            assert_eq!(set.signature().selector(), "ed");
+            assert_eq!(set.signature().signed_headers(), ["From", "To", "Subject"]);
+            assert!(std::str::from_utf8(set.raw_results())
+                .unwrap()
+                .contains("spf=pass (google.com: domain of queso@example.org)"));
+
+            let results = set.results();
+            assert_eq!(results.instance(), 1);
+            assert_eq!(results.authserv_id(), "mx.google.com");
+            assert_eq!(
+                results
+                    .results()
+                    .iter()
+                    .map(|r| (r.method(), r.result()))
+                    .collect::<Vec<_>>(),
+                [("dkim", "none"), ("spf", "pass"), ("dmarc", "pass")]
+            );
+            let spf = results.result("spf").unwrap();
+            assert_eq!(spf.property("smtp.mailfrom"), Some("queso@example.org"));
+            assert_eq!(spf.reason(), None);
+            let dmarc = results.result("DMARC").unwrap();
+            assert_eq!(dmarc.reason(), Some("policy \"ok\""));
+            assert_eq!(
+                dmarc.properties(),
+                [("header.from".to_string(), "example.org".to_string())]
+            );
+            assert_eq!(results.result("arc"), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_seal_failed_chain() {
+        let (resolver, pk_ed, raw_message) = ed25519_fixture(&["scamorza.org"]);
+        let sealer = ArcSealer::from_key(pk_ed)
+            .domain("scamorza.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"]);
+
+        // First hop, then break the AMS at i=1 by changing a signed header
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let auth_results = AuthenticationResults::new("mx1.scamorza.org");
+        let set = sealer
+            .seal(&message, &auth_results, &ArcOutput::default())
+            .unwrap();
+        let raw_message =
+            format!("{}{}", set.to_header(), raw_message).replace("Say cheese", "Say cheddar");
+
+        // Second hop seals the broken chain with cv=fail
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc_result = resolver.verify_arc(&message).await;
+        assert_eq!(
+            arc_result.result(),
+            &DkimResult::Fail(Error::FailedVerification)
+        );
+        assert!(arc_result.can_be_sealed());
+        let auth_results = AuthenticationResults::new("mx2.scamorza.org");
+        let set = sealer.seal(&message, &auth_results, &arc_result).unwrap();
+        assert_eq!(set.seal.i, 2);
+        assert_eq!(set.signature.i, 2);
+        assert_eq!(set.seal.cv, ChainValidation::Fail);
+        let raw_message = format!("{}{}", set.to_header(), raw_message);
+
+        // Third hop finds a failed chain that can no longer be sealed
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc_result = resolver.verify_arc(&message).await;
+        assert_eq!(
+            arc_result.result(),
+            &DkimResult::Fail(Error::ArcBrokenChain)
+        );
+        assert_eq!(arc_result.sets().len(), 2);
+        let last_set = arc_result.sets().last().unwrap();
+        assert_eq!(last_set.instance(), 2);
+        assert_eq!(last_set.seal().chain_validation(), &ChainValidation::Fail);
+        assert_eq!(last_set.results().authserv_id(), "mx2.scamorza.org");
+        assert!(!arc_result.can_be_sealed());
+        assert_eq!(
+            sealer
+                .seal(&message, &auth_results, &arc_result)
+                .unwrap_err(),
+            Error::ArcInvalidCV
+        );
+
+        // Chains with no usable sets are still numbered after the highest instance
+        let arc_result = ArcOutput::default().with_result(DkimResult::Fail(Error::ArcBrokenChain));
+        let set = sealer.seal(&message, &auth_results, &arc_result).unwrap();
+        assert_eq!(set.seal.i, 3);
+        assert_eq!(set.seal.cv, ChainValidation::Fail);
+    }
+
+    #[tokio::test]
+    async fn arc_reseal_modified() {
+        let (resolver, pk_ed, message) =
+            ed25519_fixture(&["manchego.org", "scamorza.org", "list.example.org"]);
+
+        // Hop 1 receives a DKIM signed message and seals it
+        let dkim_message = DkimSigner::from_key(pk_ed)
+            .domain("manchego.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .sign(message.as_bytes())
+            .unwrap()
+            .to_header()
+            + message;
+        let raw_message = arc_verify_and_seal(
+            &resolver,
+            &dkim_message,
+            "scamorza.org",
+            "ed",
+            ed25519_key(),
+        )
+        .await;
+
+        // Hop 2 is a mailing list that tags the Subject, adds a footer and a List-Id
+        // header and drops the ARC headers, which are restored when resealing
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let dkim_result = resolver.verify_dkim(&message).await;
+        let arc_result = resolver.verify_arc(&message).await;
+        assert!(dkim_result.iter().any(|d| d.result() == &DkimResult::Pass));
+        assert_eq!(arc_result.result(), &DkimResult::Pass);
+        let modified_message = format!(
+            "List-Id: <cheese.list.example.org>\r\n{}-- \r\nCheese lovers\r\n",
+            dkim_message.replace("Subject: Say cheese", "Subject: [cheese] Say cheese")
+        );
+        let auth_results = AuthenticationResults::new("list.example.org")
+            .with_dkim_results(&dkim_result, "manchego.org");
+        let sealer = |headers: &[&str]| {
+            ArcSealer::from_key(ed25519_key())
+                .domain("list.example.org")
+                .selector("ed")
+                .headers(headers.iter().copied())
+                .reseal(modified_message.as_bytes(), &auth_results, &arc_result)
+        };
+
+        // The AMS may not cover ARC-Seal headers
+        assert!(matches!(
+            sealer(&["From", "To", "Subject", "ARC-Seal"]),
+            Err(Error::InvalidSignedHeaders(_))
+        ));
+
+        let raw_message = sealer(&["From", "To", "Subject", "DKIM-Signature", "List-Id"]).unwrap();
+        let raw_message = String::from_utf8(raw_message).unwrap();
+
+        // Hop 3 sees a broken DKIM signature but a valid chain
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let dkim_result = resolver.verify_dkim(&message).await;
+        let arc_result = resolver.verify_arc(&message).await;
+        assert!(dkim_result.iter().all(|d| d.result() != &DkimResult::Pass));
+        assert_eq!(arc_result.result(), &DkimResult::Pass);
+        assert_eq!(arc_result.sets().len(), 2);
+        let set = &arc_result.sets()[1];
+        assert_eq!(set.seal().domain(), "list.example.org");
+        assert_eq!(
+            set.signature().signed_headers(),
+            ["Subject", "To", "From", "DKIM-Signature", "List-Id"]
+        );
+        assert_eq!(
+            set.results()
+                .result("dkim")
+                .map(|r| (r.result(), r.property("header.d"))),
+            Some(("pass", Some("manchego.org")))
+        );
+
+        // Resealing an unmodified message keeps the chain intact
+        let raw_message = ArcSealer::from_key(ed25519_key())
+            .domain("scamorza.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .reseal(
+                raw_message.as_bytes(),
+                &AuthenticationResults::new("scamorza.org")
+                    .with_arc_result(&arc_result, "127.0.0.1".parse().unwrap()),
+                &arc_result,
+            )
+            .unwrap();
+        let message = AuthenticatedMessage::parse(&raw_message).unwrap();
+        let arc_result = resolver.verify_arc(&message).await;
+        assert_eq!(arc_result.result(), &DkimResult::Pass);
+        assert_eq!(arc_result.sets().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn arc_dmarc_override() {
+        let (resolver, _, message) = ed25519_fixture(&["google.com", "list.example.org"]);
+
+        // Sealed first by google.com, then by a mailing list
+        let mut raw_message = message.to_string();
+        for (sealer, authserv_id, auth_results) in [
+            (
+                "google.com",
+                "mx.google.com",
+                concat!(
+                    "mx.google.com; dkim=pass header.d=manchego.org; ",
+                    "spf=pass smtp.mailfrom=queso@manchego.org; ",
+                    "dmarc=pass (p=reject) header.from=Manchego.org"
+                ),
+            ),
+            (
+                "list.example.org",
+                "list.example.org",
+                "list.example.org; arc=pass",
+            ),
+        ] {
+            let parsed_message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+            let arc_result = resolver.verify_arc(&parsed_message).await;
+            let auth_results =
+                AuthenticationResults::from_value(authserv_id, auth_results).unwrap();
+            let set = ArcSealer::from_key(ed25519_key())
+                .domain(sealer)
+                .selector("ed")
+                .headers(["From", "To", "Subject"])
+                .seal(&parsed_message, &auth_results, &arc_result)
+                .unwrap();
+            raw_message = format!("{}{}", set.to_header(), raw_message);
+        }
+        let parsed_message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc_result = resolver.verify_arc(&parsed_message).await;
+        assert_eq!(arc_result.result(), &DkimResult::Pass);
+
+        // Every sealer has to be trusted
+        for trusted_sealers in [
+            &[][..],
+            &["mail.example.com"][..],
+            &["list.example.org"][..],
+        ] {
+            assert_eq!(arc_result.dmarc_override(trusted_sealers), None);
+        }
+        let arc_override = arc_result
+            .dmarc_override(&["LIST.example.org", "google.com"])
+            .unwrap();
+        assert_eq!(arc_override.domain(), "manchego.org");
+        assert_eq!(arc_override.sealers(), ["google.com", "list.example.org"]);
+        assert_eq!(arc_override.results().authserv_id(), "mx.google.com");
+        assert_eq!(
+            arc_override.results().result("spf").map(|r| r.result()),
+            Some("pass")
+        );
+
+        // Only DMARC failures for the same domain are overridden
+        let dmarc_output = DmarcOutput::default()
+            .with_domain("manchego.org")
+            .with_dkim_result(DmarcResult::Fail(Error::NotAligned))
+            .with_spf_result(DmarcResult::Fail(Error::NotAligned));
+        for (dmarc_output, expected) in [
+            (dmarc_output.clone(), true),
+            (dmarc_output.clone().with_domain("scamorza.org"), false),
+            (dmarc_output.with_dkim_result(DmarcResult::Pass), false),
+        ] {
+            let dmarc_output = dmarc_output.with_arc_override(arc_override.clone());
+            assert_eq!(dmarc_output.arc_override().is_some(), expected);
+        }
+
+        // Overrides are included in aggregate reports
+        let dmarc_output = DmarcOutput::default()
+            .with_domain("manchego.org")
+            .with_dkim_result(DmarcResult::Fail(Error::NotAligned))
+            .with_arc_override(arc_override);
+        let record = Record::new().with_dmarc_output(&dmarc_output);
+        assert_eq!(record.action_disposition(), ActionDisposition::None);
+        assert_eq!(
+            record.policy_override_reason(),
+            [PolicyOverrideReason::new(PolicyOverride::Forwarded)
+                .with_comment("arc=pass as[2].d=list.example.org as[1].d=google.com")]
+        );
+    }
+
+    #[tokio::test]
+    async fn arc_seal_derived_cv() {
+        let (resolver, pk_ed, message) = ed25519_fixture(&["scamorza.org"]);
+        let sealer = ArcSealer::from_key(pk_ed)
+            .domain("scamorza.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"]);
+        let auth_results = AuthenticationResults::new("scamorza.org");
+
+        // No chain
+        let unsealed = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let set = sealer
+            .seal(&unsealed, &auth_results, &ArcOutput::none())
+            .unwrap();
+        assert_eq!((set.seal.i, set.seal.cv), (1, ChainValidation::None));
+
+        // Two different chains for the same message
+        let now = SystemTime::now();
+        let raw_messages = [now, now - Duration::from_secs(60)].map(|timestamp| {
+            format!(
+                "{}{}",
+                sealer
+                    .seal_at(&unsealed, &auth_results, &ArcOutput::none(), timestamp)
+                    .unwrap()
+                    .to_header(),
+                message
+            )
+        });
+        let sealed = AuthenticatedMessage::parse(raw_messages[0].as_bytes()).unwrap();
+        let other_sealed = AuthenticatedMessage::parse(raw_messages[1].as_bytes()).unwrap();
+        let arc_result = resolver.verify_arc(&sealed).await;
+        assert_eq!(arc_result.result(), &DkimResult::Pass);
+
+        // Validated chain
+        let set = sealer.seal(&sealed, &auth_results, &arc_result).unwrap();
+        assert_eq!((set.seal.i, set.seal.cv), (2, ChainValidation::Pass));
+
+        // Chain that did not validate
+        let raw_message = raw_messages[0].replace("is tastier.", "is smellier.");
+        let modified = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let modified_result = resolver.verify_arc(&modified).await;
+        assert_eq!(
+            modified_result.result(),
+            &DkimResult::Neutral(Error::FailedBodyHashMatch)
+        );
+        let set = sealer
+            .seal(&modified, &auth_results, &modified_result)
+            .unwrap();
+        assert_eq!((set.seal.i, set.seal.cv), (2, ChainValidation::Fail));
+
+        // Skipped validation or the validation result of another chain
+        for (message, arc_result) in [
+            (&sealed, &ArcOutput::none()),
+            (&other_sealed, &arc_result),
+            (&unsealed, &arc_result),
+        ] {
+            assert_eq!(
+                sealer.seal(message, &auth_results, arc_result).unwrap_err(),
+                Error::ArcNotValidated
+            );
+        }
+
+        // Validation done elsewhere
+        for (message, cv, expected) in [
+            (&unsealed, ChainValidation::None, Ok(1)),
+            (&unsealed, ChainValidation::Pass, Err(Error::ArcInvalidCV)),
+            (&unsealed, ChainValidation::Fail, Err(Error::ArcInvalidCV)),
+            (
+                &other_sealed,
+                ChainValidation::None,
+                Err(Error::ArcInvalidCV),
+            ),
+            (&other_sealed, ChainValidation::Pass, Ok(2)),
+            (&other_sealed, ChainValidation::Fail, Ok(2)),
+        ] {
+            let result = sealer.seal_unchecked(message, &auth_results, cv.clone());
+            assert_eq!(
+                result.as_ref().map(|set| set.seal.i).map_err(Clone::clone),
+                expected,
+                "{cv:?}"
+            );
+            if let Ok(set) = result {
+                assert_eq!(set.seal.cv, cv);
+                if cv == ChainValidation::Pass {
+                    let raw_message = format!("{}{}", set.to_header(), raw_messages[1]);
+                    let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+                    assert_eq!(
+                        resolver.verify_arc(&message).await.result(),
+                        &DkimResult::Pass
+                    );
+                }
+            }
+        }
+    }
+
+    #[cfg(any(
+        feature = "rust-crypto",
+        all(feature = "ring", feature = "rustls-pemfile")
+    ))]
+    #[tokio::test]
+    async fn arc_seal_weak_keys() {
+        let message = MESSAGE;
+        #[cfg(feature = "rust-crypto")]
+        let (pk_strong, pk_weak) = (
+            || RsaKey::<Sha256>::from_pkcs1_pem(RSA_4096_PRIVATE_KEY).unwrap(),
+            || RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap(),
+        );
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        let (pk_strong, pk_weak) = (
+            || RsaKey::<Sha256>::from_rsa_pem(RSA_4096_PRIVATE_KEY).unwrap(),
+            || RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap(),
+        );
+
+        // The strong key is in testing mode, the weak one is 2048 bits
+        let resolver = Resolver::new_system_conf().unwrap();
+        let strict_resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_minimum_key_strength(MinimumKeyStrength {
+                min_rsa_bits: 4096,
+                allow_sha1: false,
+            });
+        for resolver in [&resolver, &strict_resolver] {
+            #[cfg(any(test, feature = "test"))]
+            {
+                resolver.txt_add(
+                    "rsa4096._domainkey.manchego.org.".to_string(),
+                    DomainKey::parse(
+                        DomainKeyBuilder::new(
+                            Algorithm::RsaSha256,
+                            pk_strong().public_key().unwrap(),
+                        )
+                        .testing(true)
+                        .to_txt_record()
+                        .unwrap()
+                        .as_bytes(),
+                    )
+                    .unwrap(),
+                    Instant::now() + Duration::new(3600, 0),
+                );
+                resolver.txt_add(
+                    "rsa._domainkey.scamorza.org.".to_string(),
+                    DomainKey::parse(RSA_PUBLIC_KEY.as_bytes()).unwrap(),
+                    Instant::now() + Duration::new(3600, 0),
+                );
+            }
+        }
+
+        // i=1 strong and i=2 weak, then i=1 weak and i=2 strong
+        let raw_message = arc_verify_and_seal(
+            &resolver,
+            &arc_verify_and_seal(&resolver, message, "manchego.org", "rsa4096", pk_strong()).await,
+            "scamorza.org",
+            "rsa",
+            pk_weak(),
+        )
+        .await;
+        let raw_message_rev = arc_verify_and_seal(
+            &resolver,
+            &arc_verify_and_seal(&resolver, message, "scamorza.org", "rsa", pk_weak()).await,
+            "manchego.org",
+            "rsa4096",
+            pk_strong(),
+        )
+        .await;
+        let raw_message_sha1 =
+            raw_message_rev.replace("ARC-Seal: i=1; a=rsa-sha256;", "ARC-Seal: i=1; a=rsa-sha1;");
+
+        for (raw_message, expected_result, expected_strict) in [
+            (
+                &raw_message,
+                [(true, DkimResult::Pass), (false, DkimResult::Pass)],
+                [
+                    (DkimResult::None, DkimResult::None),
+                    (DkimResult::None, DkimResult::Fail(Error::WeakCryptography)),
+                ],
+            ),
+            (
+                &raw_message_rev,
+                [(false, DkimResult::Pass), (true, DkimResult::Pass)],
+                [
+                    (DkimResult::Fail(Error::WeakCryptography), DkimResult::None),
+                    (DkimResult::Pass, DkimResult::Pass),
+                ],
+            ),
+            (
+                &raw_message_sha1,
+                [
+                    (false, DkimResult::Fail(Error::WeakCryptography)),
+                    (false, DkimResult::None),
+                ],
+                [
+                    (DkimResult::Fail(Error::WeakCryptography), DkimResult::None),
+                    (DkimResult::None, DkimResult::None),
+                ],
+            ),
+        ] {
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+
+            // Testing flags are reported for each sealer
+            let arc_result = resolver.verify_arc(&message).await;
+            assert_eq!(
+                arc_result
+                    .instance_results()
+                    .iter()
+                    .map(|r| (r.is_testing(), r.seal().clone()))
+                    .collect::<Vec<_>>(),
+                expected_result
+            );
+
+            // The weak instance fails the chain
+            let arc_result = strict_resolver.verify_arc(&message).await;
+            assert_eq!(
+                arc_result.result(),
+                &DkimResult::Fail(Error::WeakCryptography)
+            );
+            assert_eq!(
+                arc_result
+                    .instance_results()
+                    .iter()
+                    .map(|r| (r.seal().clone(), r.message_signature().clone()))
+                    .collect::<Vec<_>>(),
+                expected_strict
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_seal_max_instances() {
+        let (resolver, _, message) = ed25519_fixture(&["scamorza.org"]);
+
+        // Seal the message 50 times, keeping the headers added by each instance
+        let mut raw_message = message.to_string();
+        let mut sets = Vec::new();
+        for _ in 0..50 {
+            let sealed_message =
+                arc_verify_and_seal(&resolver, &raw_message, "scamorza.org", "ed", ed25519_key())
+                    .await;
+            sets.push(sealed_message[..sealed_message.len() - raw_message.len()].to_string());
+            raw_message = sealed_message;
+        }
+        let parsed_message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc_result = resolver.verify_arc(&parsed_message).await;
+        assert_eq!(arc_result.result(), &DkimResult::Pass);
+        assert_eq!(arc_result.sets().len(), 50);
+
+        // A 51st instance is never emitted
+        let auth_results = AuthenticationResults::new("scamorza.org");
+        assert_eq!(
+            ArcSealer::from_key(ed25519_key())
+                .domain("scamorza.org")
+                .selector("ed")
+                .headers(["From", "To", "Subject"])
+                .seal(&parsed_message, &auth_results, &arc_result)
+                .unwrap_err(),
+            Error::ArcChainTooLong
+        );
+
+        // Chains longer than the configured limit fail
+        let resolver = resolver.with_max_arc_instances(49);
+        assert_eq!(
+            resolver.verify_arc(&parsed_message).await.result(),
+            &DkimResult::Fail(Error::ArcChainTooLong)
+        );
+        let resolver = resolver.with_max_arc_instances(100);
+        let raw_message = format!("{}{}", sets[49], raw_message);
+        let parsed_message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        assert_eq!(
+            resolver.verify_arc(&parsed_message).await.result(),
+            &DkimResult::Fail(Error::ArcChainTooLong)
+        );
+
+        // Repeated and missing instances
+        for (chain, expected) in [
+            (
+                format!("{}{}{}{}", sets[2], sets[1], sets[0], sets[0]),
+                Error::ArcDuplicateInstance(1),
+            ),
+            (
+                format!("{}{}", sets[2], sets[0]),
+                Error::ArcMissingInstance(2),
+            ),
+            (sets[1].clone(), Error::ArcMissingInstance(1)),
+        ] {
+            let raw_message = format!("{chain}{message}");
+            let parsed_message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+            assert_eq!(
+                resolver.verify_arc(&parsed_message).await.result(),
+                &DkimResult::Fail(expected)
+            );
+        }
+    }
+
     async fn arc_verify_and_seal(
         resolver: &Resolver,
         raw_message: &str,
@@ -8,11 +8,11 @@
  * except according to those terms.
  */
 
-use std::time::SystemTime;
+use std::{cmp::Ordering, time::SystemTime};
 
 use crate::{
     common::{
-        crypto::HashAlgorithm,
+        crypto::Algorithm,
         headers::Header,
         verify::{DomainKey, VerifySignature},
     },
@@ -20,20 +20,24 @@ use crate::{
     ArcOutput, AuthenticatedMessage, DkimResult, Error, Resolver,
 };
 
-use super::{ChainValidation, Set};
+use super::{ArcInstanceResult, ArcStrictness, ChainValidation, Set};
 
 impl Resolver {
     /// Verifies ARC headers of an RFC5322 message.
     pub async fn verify_arc<'x>(&self, message: &'x AuthenticatedMessage<'x>) -> ArcOutput<'x> {
         let arc_headers = message.ams_headers.len();
-        if arc_headers == 0 {
+        if !message.has_arc_headers() {
             return ArcOutput::default();
-        } else if arc_headers > 50 {
+        } else if arc_headers > self.max_arc_instances {
             return ArcOutput::default().with_result(DkimResult::Fail(Error::ArcChainTooLong));
         } else if (arc_headers != message.as_headers.len())
             || (arc_headers != message.aar_headers.len())
         {
-            return ArcOutput::default().with_result(DkimResult::Fail(Error::ArcBrokenChain));
+            return ArcOutput::default().with_result(DkimResult::Fail(
+                instance_error(message, self.max_arc_instances).unwrap_or(Error::ArcBrokenChain),
+            ));
+        } else if self.arc_strictness == ArcStrictness::Strict && !is_ordered(message) {
+            return ArcOutput::default().with_result(DkimResult::Fail(Error::ArcInvalidOrder));
         }
 
         let now = SystemTime::now()
@@ -41,79 +45,80 @@ impl Resolver {
             .map(|d| d.as_secs())
             .unwrap_or(0);
 
+        // Group ARC headers in sets, keeping those that parse even if others do not
+        let (set, parse_error) = arc_sets(message);
         let mut output = ArcOutput {
             result: DkimResult::None,
-            set: Vec::with_capacity(message.aar_headers.len() / 3),
+            instances: set
+                .iter()
+                .map(|set| ArcInstanceResult {
+                    instance: set.seal.header.i,
+                    seal: DkimResult::None,
+                    message_signature: DkimResult::None,
+                    cv: set.seal.header.cv.clone(),
+                    is_testing: false,
+                })
+                .collect(),
+            set,
         };
 
-        // Group ARC headers in sets
-        for (pos, ((seal_, signature_), results_)) in message
-            .as_headers
-            .iter()
-            .zip(message.ams_headers.iter())
-            .zip(message.aar_headers.iter())
-            .enumerate()
-        {
-            let seal = match &seal_.header {
-                Ok(seal) => seal,
-                Err(err) => return output.with_result(DkimResult::Neutral(err.clone())),
-            };
-            let signature = match &signature_.header {
-                Ok(signature) => signature,
-                Err(err) => return output.with_result(DkimResult::Neutral(err.clone())),
-            };
-            let results = match &results_.header {
-                Ok(results) => results,
-                Err(err) => return output.with_result(DkimResult::Neutral(err.clone())),
-            };
+        if let Some(err) = parse_error {
+            return output.with_result(DkimResult::Neutral(err));
+        }
 
-            if output.result == DkimResult::None {
-                if (seal.i as usize != (pos + 1))
-                    || (signature.i as usize != (pos + 1))
-                    || (results.i as usize != (pos + 1))
-                {
-                    output.result = DkimResult::Fail(Error::ArcInvalidInstance((pos + 1) as u32));
-                } else if (pos == 0 && seal.cv != ChainValidation::None)
-                    || (pos > 0 && seal.cv != ChainValidation::Pass)
-                {
-                    output.result = DkimResult::Fail(Error::ArcInvalidCV);
-                } else if pos == arc_headers - 1 {
-                    // Validate last signature in the chain
-                    if signature.x == 0 || (signature.x > signature.t && signature.x > now) {
-                        // Validate body hash
-                        let ha = HashAlgorithm::from(signature.a);
-                        let bh = &message
-                            .body_hashes
-                            .iter()
-                            .find(|(c, h, l, _)| {
-                                c == &signature.cb && h == &ha && l == &signature.l
-                            })
-                            .unwrap()
-                            .3;
-                        if bh != &signature.bh {
-                            output.result = DkimResult::Neutral(Error::FailedBodyHashMatch);
-                        }
-                    } else {
-                        output.result = DkimResult::Neutral(Error::SignatureExpired);
+        // Validate the structure of the chain
+        for (pos, set) in output.set.iter().enumerate() {
+            let seal = set.seal.header;
+            let signature = set.signature.header;
+
+            // Headers are sorted by instance, so a lower i= is a repeated
+            // instance and a higher one means the expected instance is missing.
+            let instance = (pos + 1) as u32;
+            let result = &mut output.instances[pos];
+            if let Some(err) = [seal.i, signature.i, set.results.header.i]
+                .into_iter()
+                .find_map(|i| match i.cmp(&instance) {
+                    Ordering::Less => Some(Error::ArcDuplicateInstance(i)),
+                    Ordering::Greater => Some(Error::ArcMissingInstance(instance)),
+                    Ordering::Equal => None,
+                })
+            {
+                result.seal = DkimResult::Fail(err);
+            } else if seal.cv == ChainValidation::Fail {
+                result.seal = DkimResult::Fail(Error::ArcBrokenChain);
+            } else if (pos == 0 && seal.cv != ChainValidation::None)
+                || (pos > 0 && seal.cv != ChainValidation::Pass)
+            {
+                result.seal = DkimResult::Fail(Error::ArcInvalidCV);
+            } else if seal.a == Algorithm::RsaSha1 && !self.min_key_strength.allow_sha1 {
+                result.seal = DkimResult::Fail(Error::WeakCryptography);
+            } else if pos == arc_headers - 1 {
+                // Validate last signature in the chain
+                if signature.a == Algorithm::RsaSha1 && !self.min_key_strength.allow_sha1 {
+                    result.message_signature = DkimResult::Fail(Error::WeakCryptography);
+                } else if let Err(err) = self.validate_timestamps(signature.t, signature.x, now) {
+                    result.message_signature = DkimResult::Neutral(err);
+                } else {
+                    // Validate body hash
+                    let bh = message
+                        .body_hash_for(signature.cb, signature.a.into(), signature.l)
+                        .unwrap_or_default();
+                    if bh != signature.bh {
+                        result.message_signature = DkimResult::Neutral(Error::FailedBodyHashMatch);
                     }
                 }
             }
 
-            output.set.push(Set {
-                signature: Header::new(signature_.name, signature_.value, signature),
-                seal: Header::new(seal_.name, seal_.value, seal),
-                results: Header::new(results_.name, results_.value, results),
-            });
-        }
-
-        if output.result != DkimResult::None {
-            return output;
+            if result.has_failed() {
+                return output.with_instance_results();
+            }
         }
 
         // Validate ARC Set
         let arc_set = output.set.last().unwrap();
         let header = &arc_set.signature;
         let signature = &header.header;
+        let result = output.instances.last_mut().unwrap();
 
         // Hash headers
         let dkim_hdr_value = header.value.strip_signature();
@@ -123,26 +128,42 @@ impl Resolver {
         let record = match self.txt_lookup::<DomainKey>(signature.domain_key()).await {
             Ok(record) => record,
             Err(err) => {
-                return output.with_result(err.into());
+                result.message_signature = err.into();
+                return output.with_instance_results();
             }
         };
 
+        // Reject undersized RSA keys
+        if !self.is_strong_key(signature.a, &record) {
+            result.message_signature = DkimResult::Fail(Error::WeakCryptography);
+            return output.with_instance_results();
+        }
+
         // Verify signature
         if let Err(err) = record.verify(&mut headers, *signature, signature.ch) {
-            return output.with_result(DkimResult::Fail(err));
+            result.message_signature = DkimResult::Fail(err);
+            return output.with_instance_results();
         }
+        result.message_signature = DkimResult::Pass;
 
         // Validate ARC Seals
         for (pos, set) in output.set.iter().enumerate().rev() {
             // Obtain record
             let header = &set.seal;
             let seal = &header.header;
+            let result = &mut output.instances[pos];
             let record = match self.txt_lookup::<DomainKey>(seal.domain_key()).await {
                 Ok(record) => record,
                 Err(err) => {
-                    return output.with_result(err.into());
+                    result.seal = err.into();
+                    return output.with_instance_results();
                 }
             };
+            result.is_testing = record.is_testing();
+            if !self.is_strong_key(seal.a, &record) {
+                result.seal = DkimResult::Fail(Error::WeakCryptography);
+                return output.with_instance_results();
+            }
 
             // Build Seal headers
             let seal_signature = header.value.strip_signature();
@@ -165,13 +186,138 @@ impl Resolver {
 
             // Verify ARC Seal
             if let Err(err) = record.verify(&mut headers, *seal, Canonicalization::Relaxed) {
-                return output.with_result(DkimResult::Fail(err));
+                result.seal = DkimResult::Fail(err);
+                return output.with_instance_results();
             }
+            result.seal = DkimResult::Pass;
         }
 
         // ARC Validation successful
-        output.with_result(DkimResult::Pass)
+        output.with_instance_results()
+    }
+
+    fn is_strong_key(&self, algorithm: Algorithm, record: &DomainKey) -> bool {
+        algorithm == Algorithm::Ed25519Sha256
+            || record.key_bits() >= self.min_key_strength.min_rsa_bits
+    }
+}
+
+impl<'x> ArcOutput<'x> {
+    /// Sets the chain result from the results of each instance. Validation
+    /// stops at the first failure, so at most one instance result has failed.
+    fn with_instance_results(mut self) -> Self {
+        self.result = self
+            .instances
+            .iter()
+            .flat_map(|i| [&i.seal, &i.message_signature])
+            .find(|result| !matches!(result, DkimResult::Pass | DkimResult::None))
+            .cloned()
+            .unwrap_or_else(|| {
+                if self.instances.iter().all(|i| i.seal == DkimResult::Pass)
+                    && self
+                        .instances
+                        .last()
+                        .map_or(false, |i| i.message_signature == DkimResult::Pass)
+                {
+                    DkimResult::Pass
+                } else {
+                    DkimResult::None
+                }
+            });
+        self
+    }
+}
+
+/// Groups the ARC headers of a message in sets, skipping those with a header
+/// that failed to parse. Returns the first parse error found.
+pub(crate) fn arc_sets<'x>(message: &'x AuthenticatedMessage<'x>) -> (Vec<Set<'x>>, Option<Error>) {
+    let mut sets = Vec::with_capacity(message.ams_headers.len());
+    let mut parse_error = None;
+    for ((seal_, signature_), results_) in message
+        .as_headers
+        .iter()
+        .zip(message.ams_headers.iter())
+        .zip(message.aar_headers.iter())
+    {
+        match (&seal_.header, &signature_.header, &results_.header) {
+            (Ok(seal), Ok(signature), Ok(results)) => sets.push(Set {
+                signature: Header::new(signature_.name, signature_.value, signature),
+                seal: Header::new(seal_.name, seal_.value, seal),
+                results: Header::new(results_.name, results_.value, results),
+            }),
+            (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+                parse_error.get_or_insert_with(|| err.clone());
+            }
+        }
+    }
+
+    (sets, parse_error)
+}
+
+/// Finds the first instance up to `max_instances` that is missing or repeats
+/// one of its ARC headers. Headers that failed to parse are ignored.
+fn instance_error(message: &AuthenticatedMessage<'_>, max_instances: usize) -> Option<Error> {
+    let mut members = vec![[0u32; 3]; max_instances];
+    for (member, i) in message
+        .as_headers
+        .iter()
+        .map(|h| (0, h.header.as_ref().map(|seal| seal.i)))
+        .chain(
+            message
+                .ams_headers
+                .iter()
+                .map(|h| (1, h.header.as_ref().map(|signature| signature.i))),
+        )
+        .chain(
+            message
+                .aar_headers
+                .iter()
+                .map(|h| (2, h.header.as_ref().map(|results| results.i))),
+        )
+    {
+        if let Some(m) = i
+            .ok()
+            .and_then(|i| (i as usize).checked_sub(1))
+            .and_then(|i| members.get_mut(i))
+        {
+            m[member] += 1;
+        }
     }
+
+    let last = members.iter().rposition(|m| m.iter().any(|&c| c > 0))?;
+    members[..=last].iter().zip(1..).find_map(|(m, i)| {
+        if m.iter().any(|&c| c > 1) {
+            Some(Error::ArcDuplicateInstance(i))
+        } else if m.contains(&0) {
+            Some(Error::ArcMissingInstance(i))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns `true` if no ARC header appears below one of an older instance.
+/// Headers that failed to parse are ignored.
+fn is_ordered(message: &AuthenticatedMessage<'_>) -> bool {
+    let mut headers = message
+        .as_headers
+        .iter()
+        .filter_map(|h| Some((h.name.as_ptr() as usize, h.header.as_ref().ok()?.i)))
+        .chain(
+            message
+                .ams_headers
+                .iter()
+                .filter_map(|h| Some((h.name.as_ptr() as usize, h.header.as_ref().ok()?.i))),
+        )
+        .chain(
+            message
+                .aar_headers
+                .iter()
+                .filter_map(|h| Some((h.name.as_ptr() as usize, h.header.as_ref().ok()?.i))),
+        )
+        .collect::<Vec<_>>();
+    headers.sort_unstable();
+    headers.windows(2).all(|w| w[0].1 >= w[1].1)
 }
 
 #[cfg(test)]
@@ -184,8 +330,9 @@ mod test {
     };
 
     use crate::{
+        arc::{ArcStrictness, ChainValidation, Results},
         common::{parse::TxtRecordParser, verify::DomainKey},
-        AuthenticatedMessage, DkimResult, Resolver,
+        AuthenticatedMessage, DkimResult, Error, Resolver,
     };
 
     #[tokio::test]
@@ -196,6 +343,9 @@ mod test {
 
         for file_name in fs::read_dir(&test_dir).unwrap() {
             let file_name = file_name.unwrap().path();
+            if !file_name.extension().map_or(false, |e| e == "txt") {
+                continue;
+            }
             /*if !file_name.to_str().unwrap().contains("002") {
                 continue;
             }*/
@@ -212,6 +362,220 @@ mod test {
 
             let dkim = resolver.verify_dkim(&message).await;
             assert!(dkim.iter().any(|o| o.result() == &DkimResult::Pass));
+
+            // The DKIM signature limit does not apply to ARC sets
+            let resolver = resolver.with_max_signatures(0);
+            assert_eq!(
+                resolver.verify_arc(&message).await.result(),
+                &DkimResult::Pass
+            );
+
+            let resolver = resolver.with_max_arc_instances(0);
+            assert_eq!(
+                resolver.verify_arc(&message).await.result(),
+                &DkimResult::Fail(Error::ArcChainTooLong)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_verify_strictness() {
+        let mut test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_dir.push("resources");
+        test_dir.push("arc");
+
+        // 003.txt has the headers of 002.txt out of order and with mixed casing
+        for (file_name, strict_result) in [
+            ("002.txt", DkimResult::Pass),
+            ("003.txt", DkimResult::Fail(Error::ArcInvalidOrder)),
+        ] {
+            let test = String::from_utf8(fs::read(test_dir.join(file_name)).unwrap()).unwrap();
+            let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+            let raw_message = raw_message.replace('\n', "\r\n");
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+
+            for (strictness, expected) in [
+                (ArcStrictness::Lenient, DkimResult::Pass),
+                (ArcStrictness::Strict, strict_result.clone()),
+            ] {
+                let resolver = new_resolver(dns_records).with_arc_strictness(strictness);
+                assert_eq!(
+                    resolver.verify_arc(&message).await.result(),
+                    &expected,
+                    "{file_name} {strictness:?}"
+                );
+            }
+        }
+
+        // Missing and repeated headers fail in both modes
+        let test = String::from_utf8(fs::read(test_dir.join("002.txt")).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let raw_message = raw_message.replace('\n', "\r\n");
+        let aar_start = raw_message
+            .find("ARC-Authentication-Results: i=1;")
+            .unwrap();
+        let aar_end = raw_message
+            .find("Authentication-Results: scamorza.org;")
+            .unwrap();
+        let aar = &raw_message[aar_start..aar_end];
+        for (raw_message, expected) in [
+            (raw_message.replace(aar, ""), Error::ArcMissingInstance(1)),
+            (
+                format!(
+                    "ARC-Seal: i=nope; a=rsa-sha256\r\n{}",
+                    raw_message.replace(aar, "")
+                ),
+                Error::ArcMissingInstance(1),
+            ),
+            (
+                format!("{aar}{raw_message}"),
+                Error::ArcDuplicateInstance(1),
+            ),
+        ] {
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+            for strictness in [ArcStrictness::Lenient, ArcStrictness::Strict] {
+                let resolver = new_resolver(dns_records).with_arc_strictness(strictness);
+                assert_eq!(
+                    resolver.verify_arc(&message).await.result(),
+                    &DkimResult::Fail(expected.clone())
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_verify_instance_results() {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources");
+        test_file.push("arc");
+        test_file.push("002.txt");
+
+        let test = String::from_utf8(fs::read(&test_file).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let resolver = new_resolver(dns_records);
+        let raw_message = raw_message.replace('\n', "\r\n");
+
+        for (raw_message, expected_result, expected_instances) in [
+            (
+                raw_message.clone(),
+                DkimResult::Pass,
+                [
+                    (1, DkimResult::Pass, DkimResult::None, ChainValidation::None),
+                    (2, DkimResult::Pass, DkimResult::Pass, ChainValidation::Pass),
+                ],
+            ),
+            (
+                raw_message.replace("is tastier.", "is smellier."),
+                DkimResult::Neutral(Error::FailedBodyHashMatch),
+                [
+                    (1, DkimResult::None, DkimResult::None, ChainValidation::None),
+                    (
+                        2,
+                        DkimResult::None,
+                        DkimResult::Neutral(Error::FailedBodyHashMatch),
+                        ChainValidation::Pass,
+                    ),
+                ],
+            ),
+            (
+                raw_message.replace("d=scamorza.org; cv=none;", "d=scamorza.org; cv=pass;"),
+                DkimResult::Fail(Error::ArcInvalidCV),
+                [
+                    (
+                        1,
+                        DkimResult::Fail(Error::ArcInvalidCV),
+                        DkimResult::None,
+                        ChainValidation::Pass,
+                    ),
+                    (2, DkimResult::None, DkimResult::None, ChainValidation::Pass),
+                ],
+            ),
+            (
+                raw_message.replacen("header.b=IN4oMvqq", "header.b=IN4oMvqx", 1),
+                DkimResult::Fail(Error::FailedVerification),
+                [
+                    (1, DkimResult::None, DkimResult::None, ChainValidation::None),
+                    (
+                        2,
+                        DkimResult::Fail(Error::FailedVerification),
+                        DkimResult::Pass,
+                        ChainValidation::Pass,
+                    ),
+                ],
+            ),
+        ] {
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+            let arc = resolver.verify_arc(&message).await;
+            assert_eq!(arc.result(), &expected_result);
+            assert_eq!(
+                arc.instance_results()
+                    .iter()
+                    .map(|r| (
+                        r.instance(),
+                        r.seal().clone(),
+                        r.message_signature().clone(),
+                        r.cv().clone()
+                    ))
+                    .collect::<Vec<_>>(),
+                expected_instances
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn arc_origin_results() {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources");
+        test_file.push("arc");
+        test_file.push("001.txt");
+
+        let test = String::from_utf8(fs::read(&test_file).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let resolver = new_resolver(dns_records);
+        let raw_message = raw_message.replace('\n', "\r\n");
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc = resolver.verify_arc(&message).await;
+        assert_eq!(arc.result(), &DkimResult::Pass);
+
+        let results = arc.origin_results().unwrap();
+        assert_eq!(arc.results(1), Some(results));
+        assert_eq!(arc.results(2), None);
+        assert!(!results.is_malformed());
+        assert_eq!(results.authserv_id(), "mx.google.com");
+        assert_eq!(
+            results
+                .results()
+                .iter()
+                .map(|r| r.method())
+                .collect::<Vec<_>>(),
+            ["dkim", "dkim", "dkim", "spf", "dmarc"]
+        );
+
+        let spf = results.result("spf").unwrap();
+        assert_eq!(spf.result(), "pass");
+        assert_eq!(spf.property("smtp.mailfrom"), Some("jmap-bounces@ietf.org"));
+        assert_eq!(
+            spf.comments(),
+            ["google.com: domain of jmap-bounces@ietf.org designates 50.223.129.194 as permitted sender"]
+        );
+        let dkim = results.result("dkim").unwrap();
+        assert_eq!(dkim.result(), "pass");
+        assert_eq!(dkim.property("header.i"), Some("@ietf.org"));
+        assert_eq!(dkim.property("header.s"), Some("ietf1"));
+        let dmarc = results.result("dmarc").unwrap();
+        assert_eq!(dmarc.result(), "fail");
+        assert_eq!(dmarc.comments(), ["p=NONE sp=NONE dis=NONE"]);
+        assert_eq!(dmarc.property("header.from"), Some("stalw.art"));
+
+        // Malformed values are kept as raw headers only
+        for value in [
+            &b" i=1; mx.example.org; spf=pass (unterminated comment"[..],
+            b" i=1; mx.example.org; dkim=pass reason=\"unterminated",
+            b" i=1;",
+        ] {
+            let results = Results::parse(value).unwrap();
+            assert!(results.is_malformed());
+            assert!(results.results().is_empty());
         }
     }
 
@@ -11,14 +11,14 @@
 use std::{
     borrow::Cow,
     fmt::{Display, Write},
-    net::IpAddr,
+    net::{IpAddr, Ipv4Addr},
 };
 
 use mail_builder::encoders::base64::base64_encode;
 
 use crate::{
     ArcOutput, AuthenticationResults, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error,
-    IprevOutput, IprevResult, ReceivedSpf, SpfOutput, SpfResult,
+    IprevOutput, IprevResult, ReceivedSpf, SpfIdentity, SpfOutput, SpfResult,
 };
 
 use super::headers::{HeaderWriter, Writer};
@@ -31,6 +31,73 @@ impl<'x> AuthenticationResults<'x> {
         }
     }
 
+    /// Creates an Authentication-Results payload from a preformatted header value,
+    /// such as `mx.example.net; spf=pass smtp.mailfrom=example.org`. The value must
+    /// start with the `authserv_id` token, compared case-insensitively, and may only
+    /// contain folded line breaks.
+    pub fn from_value(authserv_id: &'x str, value: &str) -> crate::Result<Self> {
+        if authserv_id.is_empty()
+            || authserv_id
+                .bytes()
+                .any(|ch| ch.is_ascii_whitespace() || ch.is_ascii_control() || ch == b';')
+        {
+            return Err(Error::ParseError);
+        }
+
+        // Unfold, rejecting any CR or LF that is not part of a folding sequence
+        let mut unfolded = String::with_capacity(value.len());
+        let mut chars = value.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if ch == '\r' || ch == '\n' {
+                if ch != '\r'
+                    || chars.next() != Some('\n')
+                    || !matches!(chars.peek(), Some(' ' | '\t'))
+                {
+                    return Err(Error::ParseError);
+                }
+            } else {
+                unfolded.push(ch);
+            }
+        }
+        let value = unfolded.trim();
+        let value = value
+            .get(..authserv_id.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(authserv_id))
+            .map(|_| &value[authserv_id.len()..])
+            .filter(|value| value.is_empty() || value.starts_with([';', ' ', '\t']))
+            .ok_or(Error::ParseError)?;
+
+        // Place each result on its own line
+        let mut auth_results = String::with_capacity(value.len() + 16);
+        let mut in_quotes = false;
+        let mut comment_depth = 0u32;
+        let mut last_ch = '\0';
+        for ch in value.chars() {
+            match ch {
+                '"' if comment_depth == 0 && last_ch != '\\' => in_quotes = !in_quotes,
+                '(' if !in_quotes => comment_depth += 1,
+                ')' if !in_quotes => comment_depth = comment_depth.saturating_sub(1),
+                ';' if !in_quotes && comment_depth == 0 => {
+                    auth_results.truncate(auth_results.trim_end().len());
+                    auth_results.push_str(";\r\n\t");
+                    last_ch = ch;
+                    continue;
+                }
+                _ => (),
+            }
+            if !(ch.is_whitespace() && auth_results.ends_with(['\t', ' '])) {
+                auth_results.push(if ch.is_whitespace() { ' ' } else { ch });
+            }
+            last_ch = ch;
+        }
+        auth_results.truncate(auth_results.trim_end().len());
+
+        Ok(AuthenticationResults {
+            hostname: authserv_id,
+            auth_results,
+        })
+    }
+
     pub fn with_dkim_results(mut self, dkim: &[DkimOutput], header_from: &str) -> Self {
         for dkim in dkim {
             self.set_dkim_result(dkim, header_from);
@@ -44,7 +111,9 @@ impl<'x> AuthenticationResults<'x> {
     }
 
     pub fn set_dkim_result(&mut self, dkim: &DkimOutput, header_from: &str) {
-        if !dkim.is_atps {
+        if dkim.is_domainkeys {
+            self.auth_results.push_str(";\r\n\tdomainkeys=");
+        } else if !dkim.is_atps {
             self.auth_results.push_str(";\r\n\tdkim=");
         } else {
             self.auth_results.push_str(";\r\n\tdkim-atps=");
@@ -69,7 +138,7 @@ impl<'x> AuthenticationResults<'x> {
             }
         }
 
-        if dkim.is_atps {
+        if dkim.is_atps || dkim.is_domainkeys {
             write!(self.auth_results, " header.from={header_from}").ok();
         }
     }
@@ -81,16 +150,13 @@ impl<'x> AuthenticationResults<'x> {
         ehlo_domain: &str,
     ) -> Self {
         self.auth_results.push_str(";\r\n\tspf=");
-        spf.result.as_spf_result(
-            &mut self.auth_results,
-            self.hostname,
-            &format!("postmaster@{ehlo_domain}"),
-            ip_addr,
-        );
+        spf.as_spf_auth_result(&mut self.auth_results, ip_addr);
         write!(self.auth_results, " smtp.helo={ehlo_domain}").ok();
         self
     }
 
+    /// Adds the result of an SPF MAIL FROM check, along with `smtp.helo` when
+    /// the HELO identity was checked because the sender was empty.
     pub fn with_spf_mailfrom_result(
         mut self,
         spf: &SpfOutput,
@@ -98,19 +164,17 @@ impl<'x> AuthenticationResults<'x> {
         from: &str,
         ehlo_domain: &str,
     ) -> Self {
-        let (mail_from, addr) = if !from.is_empty() {
-            (Cow::from(from), from)
-        } else {
-            (format!("postmaster@{ehlo_domain}").into(), "<>")
-        };
         self.auth_results.push_str(";\r\n\tspf=");
-        spf.result.as_spf_result(
-            &mut self.auth_results,
-            self.hostname,
-            mail_from.as_ref(),
-            ip_addr,
-        );
-        write!(self.auth_results, " smtp.mailfrom={addr}").ok();
+        spf.as_spf_auth_result(&mut self.auth_results, ip_addr);
+        self.auth_results.push_str(" smtp.mailfrom=");
+        if !from.is_empty() {
+            write_address(&mut self.auth_results, from);
+        } else {
+            self.auth_results.push_str("<>");
+        }
+        if spf.identity == SpfIdentity::Helo {
+            write!(self.auth_results, " smtp.helo={ehlo_domain}").ok();
+        }
         self
     }
 
@@ -192,19 +256,171 @@ impl ReceivedSpf {
             format!("postmaster@{helo}").into()
         };
 
-        spf.result
-            .as_spf_result(&mut received_spf, hostname, mail_from.as_ref(), ip_addr);
+        spf.reported_result().as_spf_result(
+            &mut received_spf,
+            hostname,
+            mail_from.as_ref(),
+            ip_addr,
+        );
+        spf.as_policy_comment(&mut received_spf);
+
+        received_spf.push_str("\r\n\treceiver=");
+        write_value(&mut received_spf, hostname);
+        write!(received_spf, "; client-ip={ip_addr}; envelope-from=").ok();
+        write_quoted(&mut received_spf, mail_from.as_ref());
+        received_spf.push_str("; helo=");
+        write_value(&mut received_spf, helo);
+        received_spf.push_str(match spf.identity {
+            SpfIdentity::Helo => "; identity=helo;",
+            SpfIdentity::MailFrom => "; identity=mailfrom;",
+        });
+        if let Some(mechanism) = spf.matched_mechanism() {
+            received_spf.push_str(" mechanism=");
+            write_value(&mut received_spf, mechanism);
+            received_spf.push(';');
+        }
 
-        write!(
-            received_spf,
-            "\r\n\treceiver={hostname}; client-ip={ip_addr}; envelope-from=\"{mail_from}\"; helo={helo};",
-        )
-        .ok();
+        if let (SpfResult::PermError | SpfResult::TempError, Some(error)) =
+            (&spf.result, &spf.error)
+        {
+            received_spf.push_str("\r\n\tproblem=");
+            write_quoted(&mut received_spf, &error.to_string());
+            received_spf.push(';');
+        }
 
         ReceivedSpf { received_spf }
     }
 }
 
+/// Writes a key-value pair value as a dot-atom, or as a quoted-string
+/// when it contains characters not allowed in a dot-atom.
+fn write_value(header: &mut String, value: &str) {
+    if !value.is_empty()
+        && value.split('.').all(|atom| {
+            !atom.is_empty()
+                && atom
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(ch))
+        })
+    {
+        header.push_str(value);
+    } else {
+        write_quoted(header, value);
+    }
+}
+
+/// Writes an address, quoting its local-part when it is not a dot-atom.
+fn write_address(header: &mut String, address: &str) {
+    match address.rsplit_once('@') {
+        Some((local_part, domain)) => {
+            if local_part.len() > 1 && local_part.starts_with('"') && local_part.ends_with('"') {
+                header.push_str(local_part);
+            } else {
+                write_value(header, local_part);
+            }
+            header.push('@');
+            header.push_str(domain);
+        }
+        None => write_value(header, address),
+    }
+}
+
+/// Writes text inside a comment, escaping parentheses and backslashes.
+fn write_comment(header: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '(' | ')' | '\\' => {
+                header.push('\\');
+                header.push(ch);
+            }
+            '\r' | '\n' => (),
+            _ => header.push(ch),
+        }
+    }
+}
+
+fn write_quoted(header: &mut String, value: &str) {
+    header.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' | '\\' => {
+                header.push('\\');
+                header.push(ch);
+            }
+            '\r' | '\n' => (),
+            _ => header.push(ch),
+        }
+    }
+    header.push('"');
+}
+
+impl SpfOutput {
+    /// Returns the value of a `Received-SPF` header for this result, as
+    /// added by `hostname`, using the client IP, HELO domain and sender
+    /// the check was performed with.
+    pub fn to_received_spf(&self, hostname: &str) -> String {
+        ReceivedSpf::new(
+            self,
+            self.client_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            &self.helo_domain,
+            &self.sender,
+            hostname,
+        )
+        .received_spf
+    }
+
+    /// Best-guess results are reported as none, since the domain published
+    /// no SPF record.
+    fn reported_result(&self) -> &SpfResult {
+        if self.best_guess {
+            &SpfResult::None
+        } else {
+            &self.result
+        }
+    }
+
+    /// Writes the result followed by a comment with the client IP, and either
+    /// the matched mechanism or the error that occurred.
+    fn as_spf_auth_result(&self, header: &mut String, ip_addr: IpAddr) {
+        let result = self.reported_result();
+        header.push_str(match result {
+            SpfResult::Pass => "pass",
+            SpfResult::Fail => "fail",
+            SpfResult::SoftFail => "softfail",
+            SpfResult::Neutral => "neutral",
+            SpfResult::TempError => "temperror",
+            SpfResult::PermError => "permerror",
+            SpfResult::None => "none",
+        });
+        write!(header, " (sender IP is {ip_addr}").ok();
+        if let Some(mechanism) = self.matched_mechanism() {
+            header.push_str(", mechanism ");
+            write_comment(header, mechanism);
+        }
+        if let (SpfResult::TempError | SpfResult::PermError, Some(error)) = (result, &self.error) {
+            header.push_str(", ");
+            write_comment(header, &error.to_string());
+        }
+        header.push(')');
+        self.as_policy_comment(header);
+    }
+
+    /// Notes a result escalated by local policy, as the header reports
+    /// the unmodified result, or the outcome of a best-guess evaluation.
+    fn as_policy_comment(&self, header: &mut String) {
+        if self.escalated {
+            header.push_str(" (treated as fail by local policy)");
+        } else if self.best_guess {
+            write!(
+                header,
+                " (best guess {})",
+                self.result.to_string().to_ascii_lowercase()
+            )
+            .ok();
+        }
+    }
+}
+
 impl SpfResult {
     fn as_spf_result(&self, header: &mut String, hostname: &str, mail_from: &str, ip_addr: IpAddr) {
         match &self {
@@ -218,23 +434,23 @@ impl SpfResult {
             ),
             SpfResult::SoftFail => write!(
                 header,
-                "softfail ({hostname}: domain of {mail_from} reports soft fail for {ip_addr})",
+                "softfail ({hostname}: domain of transitioning {mail_from} does not designate {ip_addr} as permitted sender)",
             ),
             SpfResult::Neutral => write!(
                 header,
-                "neutral ({hostname}: domain of {mail_from} reports neutral for {ip_addr})",
+                "neutral ({hostname}: {ip_addr} is neither permitted nor denied by domain of {mail_from})",
             ),
             SpfResult::TempError => write!(
                 header,
-                "temperror ({hostname}: temporary dns error validating {mail_from})",
+                "temperror ({hostname}: error in processing during lookup of {mail_from})",
             ),
             SpfResult::PermError => write!(
                 header,
-                "permerror ({hostname}: unable to verify SPF record for {mail_from})",
+                "permerror ({hostname}: error in processing during lookup of {mail_from})",
             ),
             SpfResult::None => write!(
                 header,
-                "none ({hostname}: no SPF records found for {mail_from})",
+                "none ({hostname}: domain of {mail_from} does not designate permitted sender hosts)",
             ),
         }
         .ok();
@@ -338,12 +554,45 @@ impl AsAuthResult for Error {
                 write!(header, "invalid ARC instance {i})").ok();
                 return;
             }
+            Error::ArcDuplicateInstance(i) => {
+                write!(header, "duplicate ARC instance {i}").ok();
+                return;
+            }
+            Error::ArcMissingInstance(i) => {
+                write!(header, "missing ARC instance {i}").ok();
+                return;
+            }
             Error::ArcInvalidCV => "invalid ARC cv",
             Error::ArcChainTooLong => "too many ARC headers",
             Error::ArcHasHeaderTag => "ARC has header tag",
             Error::ArcBrokenChain => "broken ARC chain",
             Error::NotAligned => "policy not aligned",
             Error::InvalidRecordType => "invalid dns record type",
+            Error::UnusableRecord => "unusable record",
+            Error::MissingFrom => "missing from",
+            Error::MultipleFrom => "multiple from",
+            Error::MxLimitExceeded => "too many mx records",
+            Error::MultipleRecords => "multiple dns records",
+            Error::RecordTooLong(_) => "record too long",
+            Error::DeadlineExceeded => "spf evaluation deadline exceeded",
+            Error::EvaluationLoop(_) => "evaluation loop",
+            Error::LookupLimitExceeded => "dns lookup limit exceeded",
+            Error::VoidLookupLimitExceeded => "void dns lookup limit exceeded",
+            Error::ArcNotValidated => "ARC chain not validated",
+            Error::ArcInvalidOrder => "ARC headers out of order",
+            Error::NotEvaluated => "not evaluated",
+            Error::UnsupportedQueryMethod => "unsupported query method",
+            Error::NoValidSigner => "no valid signing key",
+            Error::InvalidTag(_) => "invalid tag",
+            Error::AuidNotExactDomain => "auid domain not exact",
+            Error::AuidNotInDomain => "auid not within domain",
+            Error::InvalidSignedHeaders(_) => "invalid signed headers",
+            Error::UnsupportedKeyFormat(_) => "unsupported key format",
+            Error::KeyGranularityMismatch => "key granularity mismatch",
+            Error::WeakCryptography => "weak cryptography",
+            Error::SignatureLimitExceeded => "too many signatures",
+            Error::PartialBody => "partial body signature",
+            Error::FutureSignature => "signature timestamp in the future",
         });
         header.push(')');
     }
@@ -352,9 +601,10 @@ impl AsAuthResult for Error {
 #[cfg(test)]
 mod test {
     use crate::{
-        dkim::Signature, dmarc::Policy, ArcOutput, AuthenticationResults, DkimOutput, DkimResult,
-        DmarcOutput, DmarcResult, Error, IprevOutput, IprevResult, ReceivedSpf, SpfOutput,
-        SpfResult,
+        dkim::Signature,
+        dmarc::{AuthMechanism, Policy, PolicySource},
+        ArcOutput, AuthenticationResults, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error,
+        IprevOutput, IprevResult, ReceivedSpf, SpfIdentity, SpfOutput, SpfResult,
     };
 
     #[test]
@@ -372,8 +622,7 @@ mod test {
                         ..Default::default()
                     })
                         .into(),
-                    report: None,
-                    is_atps: false,
+                    ..Default::default()
                 },
             ),
             (
@@ -390,8 +639,7 @@ mod test {
                         ..Default::default()
                     })
                         .into(),
-                    report: None,
-                    is_atps: false,
+                    ..Default::default()
                 },
             ),
             (
@@ -408,8 +656,8 @@ mod test {
                         ..Default::default()
                     })
                         .into(),
-                    report: None,
                     is_atps: true,
+                    ..Default::default()
                 },
             ),
         ] {
@@ -430,14 +678,11 @@ mod test {
             mail_from,
         ) in [
             (
-                concat!(
-                    "spf=pass (localhost: domain of jdoe@example.org designates 192.168.1.1 ",
-                    "as permitted sender) smtp.mailfrom=jdoe@example.org"
-                ),
+                "spf=pass (sender IP is 192.168.1.1) smtp.mailfrom=jdoe@example.org",
                 concat!(
                     "pass (localhost: domain of jdoe@example.org designates 192.168.1.1 as ",
                     "permitted sender)\r\n\treceiver=localhost; client-ip=192.168.1.1; ",
-                    "envelope-from=\"jdoe@example.org\"; helo=example.org;"
+                    "envelope-from=\"jdoe@example.org\"; helo=example.org; identity=mailfrom;"
                 ),
                 SpfResult::Pass,
                 "192.168.1.1".parse().unwrap(),
@@ -446,15 +691,12 @@ mod test {
                 "jdoe@example.org",
             ),
             (
-                concat!(
-                    "spf=fail (mx.domain.org: domain of sender@otherdomain.org does not ",
-                    "designate a:b:c::f as permitted sender) smtp.mailfrom=sender@otherdomain.org"
-                ),
+                "spf=fail (sender IP is a:b:c::f) smtp.mailfrom=sender@otherdomain.org",
                 concat!(
                     "fail (mx.domain.org: domain of sender@otherdomain.org does not designate ",
                     "a:b:c::f as permitted sender)\r\n\treceiver=mx.domain.org; ",
                     "client-ip=a:b:c::f; envelope-from=\"sender@otherdomain.org\"; ",
-                    "helo=otherdomain.org;"
+                    "helo=otherdomain.org; identity=mailfrom;"
                 ),
                 SpfResult::Fail,
                 "a:b:c::f".parse().unwrap(),
@@ -463,14 +705,12 @@ mod test {
                 "sender@otherdomain.org",
             ),
             (
+                "spf=neutral (sender IP is a:b:c::f) smtp.mailfrom=<>",
                 concat!(
-                    "spf=neutral (mx.domain.org: domain of postmaster@example.org reports neutral ",
-                    "for a:b:c::f) smtp.mailfrom=<>"
-                ),
-                concat!(
-                    "neutral (mx.domain.org: domain of postmaster@example.org reports neutral for ",
-                    "a:b:c::f)\r\n\treceiver=mx.domain.org; client-ip=a:b:c::f; ",
-                    "envelope-from=\"postmaster@example.org\"; helo=example.org;"
+                    "neutral (mx.domain.org: a:b:c::f is neither permitted nor denied by domain ",
+                    "of postmaster@example.org)\r\n\treceiver=mx.domain.org; client-ip=a:b:c::f; ",
+                    "envelope-from=\"postmaster@example.org\"; helo=example.org; ",
+                    "identity=mailfrom;"
                 ),
                 SpfResult::Neutral,
                 "a:b:c::f".parse().unwrap(),
@@ -483,9 +723,7 @@ mod test {
             auth_results = auth_results.with_spf_mailfrom_result(
                 &SpfOutput {
                     result,
-                    domain: "".to_string(),
-                    report: None,
-                    explanation: None,
+                    ..Default::default()
                 },
                 ip_addr,
                 mail_from,
@@ -494,9 +732,7 @@ mod test {
             let received_spf = ReceivedSpf::new(
                 &SpfOutput {
                     result,
-                    domain: "".to_string(),
-                    report: None,
-                    explanation: None,
+                    ..Default::default()
                 },
                 ip_addr,
                 helo,
@@ -510,6 +746,257 @@ mod test {
             assert_eq!(received_spf.received_spf, expected_received_spf);
         }
 
+        for (expected_received_spf, result, identity, matched, error, helo, mail_from) in [
+            (
+                concat!(
+                    "pass (mx.example.org: domain of jdoe@example.com designates 192.0.2.1 as ",
+                    "permitted sender)\r\n\treceiver=mx.example.org; client-ip=192.0.2.1; ",
+                    "envelope-from=\"jdoe@example.com\"; helo=mail.example.com; ",
+                    "identity=mailfrom; mechanism=\"ip4:192.0.2.0/24\";"
+                ),
+                SpfResult::Pass,
+                SpfIdentity::MailFrom,
+                Some("ip4:192.0.2.0/24"),
+                None,
+                "mail.example.com",
+                "jdoe@example.com",
+            ),
+            (
+                concat!(
+                    "softfail (mx.example.org: domain of transitioning \"john\\\"doe\"@example.com ",
+                    "does not designate 192.0.2.1 as permitted sender)\r\n\treceiver=mx.example.org; ",
+                    "client-ip=192.0.2.1; envelope-from=\"\\\"john\\\\\\\"doe\\\"@example.com\"; ",
+                    "helo=\"[192.0.2.1]\"; identity=mailfrom; mechanism=~all;"
+                ),
+                SpfResult::SoftFail,
+                SpfIdentity::MailFrom,
+                Some("~all"),
+                None,
+                "[192.0.2.1]",
+                "\"john\\\"doe\"@example.com",
+            ),
+            (
+                concat!(
+                    "none (mx.example.org: domain of postmaster@mail.example.com does not ",
+                    "designate permitted sender hosts)\r\n\treceiver=mx.example.org; ",
+                    "client-ip=192.0.2.1; envelope-from=\"postmaster@mail.example.com\"; ",
+                    "helo=mail.example.com; identity=helo;"
+                ),
+                SpfResult::None,
+                SpfIdentity::Helo,
+                None,
+                None,
+                "mail.example.com",
+                "",
+            ),
+            (
+                concat!(
+                    "temperror (mx.example.org: error in processing during lookup of ",
+                    "jdoe@example.com)\r\n\treceiver=mx.example.org; client-ip=192.0.2.1; ",
+                    "envelope-from=\"jdoe@example.com\"; helo=mail.example.com; ",
+                    "identity=mailfrom;"
+                ),
+                SpfResult::TempError,
+                SpfIdentity::MailFrom,
+                None,
+                None,
+                "mail.example.com",
+                "jdoe@example.com",
+            ),
+            (
+                concat!(
+                    "permerror (mx.example.org: error in processing during lookup of ",
+                    "jdoe@example.com)\r\n\treceiver=mx.example.org; client-ip=192.0.2.1; ",
+                    "envelope-from=\"jdoe@example.com\"; helo=mail.example.com; ",
+                    "identity=mailfrom;\r\n\tproblem=\"DNS lookup limit exceeded\";"
+                ),
+                SpfResult::PermError,
+                SpfIdentity::MailFrom,
+                None,
+                Some(Error::LookupLimitExceeded),
+                "mail.example.com",
+                "jdoe@example.com",
+            ),
+        ] {
+            let received_spf = ReceivedSpf::new(
+                &SpfOutput {
+                    result,
+                    identity,
+                    domain: "example.com".to_string(),
+                    matched: matched
+                        .map(|mechanism| (mechanism.to_string(), "example.com".to_string())),
+                    error,
+                    ..Default::default()
+                },
+                "192.0.2.1".parse().unwrap(),
+                helo,
+                mail_from,
+                "mx.example.org",
+            );
+            assert_eq!(received_spf.received_spf, expected_received_spf);
+        }
+
+        // Results escalated by local policy are reported unmodified
+        let spf = SpfOutput {
+            result: SpfResult::SoftFail,
+            escalated: true,
+            ..Default::default()
+        };
+        let ip_addr = "192.0.2.1".parse().unwrap();
+        auth_results.hostname = "mx.example.org";
+        auth_results =
+            auth_results.with_spf_mailfrom_result(&spf, ip_addr, "jdoe@example.com", "example.com");
+        assert_eq!(
+            auth_results.auth_results.rsplit_once(';').unwrap().1.trim(),
+            concat!(
+                "spf=softfail (sender IP is 192.0.2.1) (treated as fail by local policy) ",
+                "smtp.mailfrom=jdoe@example.com"
+            )
+        );
+        assert_eq!(
+            ReceivedSpf::new(
+                &spf,
+                ip_addr,
+                "example.com",
+                "jdoe@example.com",
+                "mx.example.org"
+            )
+            .received_spf,
+            concat!(
+                "softfail (mx.example.org: domain of transitioning jdoe@example.com does not ",
+                "designate 192.0.2.1 as permitted sender) (treated as fail by local policy)",
+                "\r\n\treceiver=mx.example.org; client-ip=192.0.2.1; ",
+                "envelope-from=\"jdoe@example.com\"; helo=example.com; identity=mailfrom;"
+            )
+        );
+
+        // Best-guess results are reported as none
+        let spf = SpfOutput {
+            result: SpfResult::Pass,
+            best_guess: true,
+            ..Default::default()
+        };
+        auth_results =
+            auth_results.with_spf_mailfrom_result(&spf, ip_addr, "jdoe@example.com", "example.com");
+        assert_eq!(
+            auth_results.auth_results.rsplit_once(';').unwrap().1.trim(),
+            "spf=none (sender IP is 192.0.2.1) (best guess pass) smtp.mailfrom=jdoe@example.com"
+        );
+
+        // Mechanisms, errors and identities
+        let ip_addr = "203.0.113.5".parse().unwrap();
+        for (expected_auth_results, result, identity, matched, error, from) in [
+            (
+                concat!(
+                    "spf=pass (sender IP is 203.0.113.5, mechanism ip4:203.0.113.0/24) ",
+                    "smtp.mailfrom=jdoe@example.com"
+                ),
+                SpfResult::Pass,
+                SpfIdentity::MailFrom,
+                Some("ip4:203.0.113.0/24"),
+                None,
+                "jdoe@example.com",
+            ),
+            (
+                concat!(
+                    "spf=pass (sender IP is 203.0.113.5, mechanism a) smtp.mailfrom=<> ",
+                    "smtp.helo=mail.example.com"
+                ),
+                SpfResult::Pass,
+                SpfIdentity::Helo,
+                Some("a"),
+                None,
+                "",
+            ),
+            (
+                concat!(
+                    "spf=softfail (sender IP is 203.0.113.5, mechanism ~all) ",
+                    "smtp.mailfrom=\"john doe\"@example.com"
+                ),
+                SpfResult::SoftFail,
+                SpfIdentity::MailFrom,
+                Some("~all"),
+                None,
+                "john doe@example.com",
+            ),
+            (
+                concat!(
+                    "spf=fail (sender IP is 203.0.113.5, mechanism -all) ",
+                    "smtp.mailfrom=\"john\\\"doe\"@example.com"
+                ),
+                SpfResult::Fail,
+                SpfIdentity::MailFrom,
+                Some("-all"),
+                None,
+                "\"john\\\"doe\"@example.com",
+            ),
+            (
+                concat!(
+                    "spf=permerror (sender IP is 203.0.113.5, DNS lookup limit exceeded) ",
+                    "smtp.mailfrom=jdoe@example.com"
+                ),
+                SpfResult::PermError,
+                SpfIdentity::MailFrom,
+                None,
+                Some(Error::LookupLimitExceeded),
+                "jdoe@example.com",
+            ),
+            (
+                concat!(
+                    "spf=temperror (sender IP is 203.0.113.5, DNS resolution error: timed out) ",
+                    "smtp.mailfrom=jdoe@example.com"
+                ),
+                SpfResult::TempError,
+                SpfIdentity::MailFrom,
+                None,
+                Some(Error::DnsError("timed out".to_string())),
+                "jdoe@example.com",
+            ),
+            (
+                concat!(
+                    "spf=permerror (sender IP is 203.0.113.5, Record too long \\(512 bytes\\)) ",
+                    "smtp.mailfrom=jdoe@example.com"
+                ),
+                SpfResult::PermError,
+                SpfIdentity::MailFrom,
+                None,
+                Some(Error::RecordTooLong(512)),
+                "jdoe@example.com",
+            ),
+        ] {
+            auth_results = auth_results.with_spf_mailfrom_result(
+                &SpfOutput {
+                    result,
+                    identity,
+                    matched: matched
+                        .map(|mechanism| (mechanism.to_string(), "example.com".to_string())),
+                    error,
+                    ..Default::default()
+                },
+                ip_addr,
+                from,
+                "mail.example.com",
+            );
+            assert_eq!(
+                auth_results.auth_results.rsplit_once(';').unwrap().1.trim(),
+                expected_auth_results
+            );
+        }
+        auth_results = auth_results.with_spf_ehlo_result(
+            &SpfOutput {
+                result: SpfResult::Fail,
+                identity: SpfIdentity::Helo,
+                matched: Some(("-all".to_string(), "mail.example.com".to_string())),
+                ..Default::default()
+            },
+            ip_addr,
+            "mail.example.com",
+        );
+        assert_eq!(
+            auth_results.auth_results.rsplit_once(';').unwrap().1.trim(),
+            "spf=fail (sender IP is 203.0.113.5, mechanism -all) smtp.helo=mail.example.com"
+        );
+
         for (expected_auth_results, dmarc) in [
             (
                 "dmarc=pass header.from=example.org policy.dmarc=none",
@@ -519,6 +1006,19 @@ mod test {
                     domain: "example.org".to_string(),
                     policy: Policy::None,
                     record: None,
+                    record_domain: None,
+                    sampled_policy: None,
+                    dkim_alignment: None,
+                    spf_alignment: None,
+                    dkim_misalignment: None,
+                    spf_misalignment: None,
+                    policy_source: PolicySource::P,
+                    from_domains: Vec::new(),
+                    arc_override: None,
+                    evaluated_with: AuthMechanism::Dkim as u8 | AuthMechanism::Spf as u8,
+                    failed_with: 0,
+                    is_psd_fallback: false,
+                    overrides: vec![],
                 },
             ),
             (
@@ -529,6 +1029,19 @@ mod test {
                     domain: "example.com".to_string(),
                     policy: Policy::Quarantine,
                     record: None,
+                    record_domain: None,
+                    sampled_policy: None,
+                    dkim_alignment: None,
+                    spf_alignment: None,
+                    dkim_misalignment: None,
+                    spf_misalignment: None,
+                    policy_source: PolicySource::P,
+                    from_domains: Vec::new(),
+                    arc_override: None,
+                    evaluated_with: AuthMechanism::Dkim as u8 | AuthMechanism::Spf as u8,
+                    failed_with: 0,
+                    is_psd_fallback: false,
+                    overrides: vec![],
                 },
             ),
         ] {
@@ -555,6 +1068,7 @@ mod test {
                 &ArcOutput {
                     result: arc,
                     set: vec![],
+                    instances: vec![],
                 },
                 remote_ip,
             );
@@ -589,4 +1103,46 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn authentication_results_from_value() {
+        for (value, expected) in [
+            (
+                "mx.example.net; spf=pass smtp.mailfrom=example.org; dkim=none",
+                Ok(";\r\n\tspf=pass smtp.mailfrom=example.org;\r\n\tdkim=none"),
+            ),
+            (
+                "  mx.example.net 1;\r\n  dkim=pass (\"quoted; text\") header.d=a.org  ",
+                Ok(" 1;\r\n\tdkim=pass (\"quoted; text\") header.d=a.org"),
+            ),
+            (
+                "mx.example.net; dkim=fail (bad; signature) header.d=a.org",
+                Ok(";\r\n\tdkim=fail (bad; signature) header.d=a.org"),
+            ),
+            ("mx.example.net", Ok("")),
+            ("MX.Example.NET; spf=pass", Ok(";\r\n\tspf=pass")),
+            ("mx.example.org; spf=pass", Err(Error::ParseError)),
+            ("mx.example.network; spf=pass", Err(Error::ParseError)),
+            (
+                "mx.example.net; spf=pass\r\nX-Injected: yes",
+                Err(Error::ParseError),
+            ),
+            (
+                "mx.example.net; spf=pass\nX-Injected: yes",
+                Err(Error::ParseError),
+            ),
+            ("mx.example.net; spf=pass\r", Err(Error::ParseError)),
+        ] {
+            assert_eq!(
+                AuthenticationResults::from_value("mx.example.net", value)
+                    .map(|auth_results| auth_results.auth_results),
+                expected.map(|auth_results| auth_results.to_string()),
+                "{value:?}"
+            );
+        }
+        assert_eq!(
+            AuthenticationResults::from_value("mx.example.net\r\n", "mx.example.net\r\n"),
+            Err(Error::ParseError)
+        );
+    }
 }
@@ -1,6 +1,10 @@
 #[cfg(feature = "sha1")]
 use sha1::{digest::Output, Digest};
 
+use mail_parser::decoders::base64::base64_decode;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use crate::{dkim::Canonicalization, Result};
 
 use super::headers::{Writable, Writer};
@@ -19,6 +23,67 @@ pub use ring_impls::{Ed25519Key, RsaKey};
 #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
 pub(crate) use ring_impls::{Ed25519PublicKey, RsaPublicKey};
 
+pub(crate) const RSA_KEY_FORMATS: &[&str] =
+    &["PKCS#1 PEM", "PKCS#8 PEM", "PKCS#1 DER", "PKCS#8 DER"];
+pub(crate) const ED25519_KEY_FORMATS: &[&str] = &[
+    "PKCS#8 PEM",
+    "PKCS#8 DER",
+    "raw 32-byte seed",
+    "raw 64-byte seed and public key",
+];
+
+/// A private key as read from disk, with any PEM armor removed.
+pub(crate) enum PrivateKeyBytes {
+    Pkcs1(Vec<u8>),
+    Pkcs8(Vec<u8>),
+    Der(Vec<u8>),
+    Raw(Vec<u8>),
+}
+
+impl PrivateKeyBytes {
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        if let Some(pem) = std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|text| text.split_once("-----BEGIN "))
+            .map(|(_, pem)| pem)
+        {
+            let (label, pem) = pem.split_once("-----")?;
+            let (pem, _) = pem.split_once("-----END ")?;
+            let der = base64_decode(
+                &pem.bytes()
+                    .filter(|ch| !ch.is_ascii_whitespace())
+                    .collect::<Vec<_>>(),
+            )?;
+            match label {
+                "RSA PRIVATE KEY" => Some(PrivateKeyBytes::Pkcs1(der)),
+                "PRIVATE KEY" => Some(PrivateKeyBytes::Pkcs8(der)),
+                _ => None,
+            }
+        } else if matches!(bytes.len(), 32 | 64) {
+            Some(PrivateKeyBytes::Raw(bytes.to_vec()))
+        } else if bytes.first() == Some(&0x30) {
+            Some(PrivateKeyBytes::Der(bytes.to_vec()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the seed of a PKCS#8 encoded Ed25519 private key (RFC 8410).
+#[cfg(feature = "rust-crypto")]
+pub(crate) fn ed25519_pkcs8_seed(der: &[u8]) -> Option<&[u8]> {
+    // SEQUENCE { INTEGER version, SEQUENCE { OID 1.3.101.112 }, OCTET STRING { OCTET STRING seed } }
+    const PREFIX: &[u8] = &[
+        0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+    ];
+    match der {
+        [0x30, _, 0x02, 0x01, 0x00 | 0x01, rest @ ..] if rest.starts_with(PREFIX) => {
+            rest.get(PREFIX.len()..PREFIX.len() + 32)
+        }
+        _ => None,
+    }
+}
+
 pub trait SigningKey {
     type Hasher: HashImpl;
 
@@ -31,6 +96,16 @@ pub trait SigningKey {
     }
 
     fn algorithm(&self) -> Algorithm;
+
+    /// Returns the length in bytes of the signatures produced by this key. The
+    /// default is an upper bound for the algorithm, which is 64 bytes for
+    /// Ed25519 and the size of a signature by an 8192-bit RSA key otherwise.
+    fn signature_len(&self) -> usize {
+        match self.algorithm() {
+            Algorithm::Ed25519Sha256 => 64,
+            Algorithm::RsaSha1 | Algorithm::RsaSha256 => 1024,
+        }
+    }
 }
 
 pub trait VerifyingKey {
@@ -41,9 +116,14 @@ pub trait VerifyingKey {
         canonicalication: Canonicalization,
         algorithm: Algorithm,
     ) -> Result<()>;
+
+    /// Returns the size of the key in bits.
+    fn key_bits(&self) -> usize;
 }
 
-pub(crate) enum VerifyingKeyType {
+/// The type of a public key published in a DKIM record (k= tag).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyingKeyType {
     Rsa,
     Ed25519,
 }
@@ -145,6 +225,7 @@ impl AsRef<[u8]> for HashOutput {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Algorithm {
     RsaSha1,
     RsaSha256,
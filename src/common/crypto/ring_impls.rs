@@ -3,7 +3,7 @@ use std::marker::PhantomData;
 use ring::digest::{Context, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
 use ring::rand::SystemRandom;
 use ring::signature::{
-    Ed25519KeyPair, RsaKeyPair, UnparsedPublicKey, ED25519,
+    Ed25519KeyPair, KeyPair, RsaKeyPair, UnparsedPublicKey, ED25519,
     RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY, RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
     RSA_PKCS1_SHA256,
 };
@@ -14,8 +14,13 @@ use crate::{
     Error, Result,
 };
 
-use super::{Algorithm, HashContext, HashImpl, HashOutput, Sha1, Sha256, SigningKey, VerifyingKey};
+use super::{
+    Algorithm, HashContext, HashImpl, HashOutput, PrivateKeyBytes, Sha1, Sha256, SigningKey,
+    VerifyingKey, ED25519_KEY_FORMATS, RSA_KEY_FORMATS,
+};
 
+/// An RSA private key. `ring` cannot generate RSA keys, so `RsaKey::generate`
+/// is only available with the `rust-crypto` feature.
 #[derive(Debug)]
 pub struct RsaKey<T> {
     inner: RsaKeyPair,
@@ -68,6 +73,29 @@ impl<T: HashImpl> RsaKey<T> {
             padding: PhantomData,
         })
     }
+
+    /// Creates a new RSA private key from PKCS1 or PKCS8 bytes, either PEM or DER encoded.
+    pub fn from_key_bytes(private_key: &[u8]) -> Result<Self> {
+        match PrivateKeyBytes::decode(private_key) {
+            Some(PrivateKeyBytes::Pkcs1(der)) => RsaKeyPair::from_der(&der).ok(),
+            Some(PrivateKeyBytes::Pkcs8(der)) => RsaKeyPair::from_pkcs8(&der).ok(),
+            Some(PrivateKeyBytes::Der(der)) => RsaKeyPair::from_der(&der)
+                .ok()
+                .or_else(|| RsaKeyPair::from_pkcs8(&der).ok()),
+            _ => None,
+        }
+        .map(|inner| Self {
+            inner,
+            rng: SystemRandom::new(),
+            padding: PhantomData,
+        })
+        .ok_or(Error::UnsupportedKeyFormat(RSA_KEY_FORMATS))
+    }
+
+    /// Returns the DER-encoded SubjectPublicKeyInfo of this key.
+    pub fn public_key(&self) -> Result<Vec<u8>> {
+        Ok(rsa_spki(self.inner.public_key().as_ref()))
+    }
 }
 
 impl SigningKey for RsaKey<Sha256> {
@@ -87,6 +115,10 @@ impl SigningKey for RsaKey<Sha256> {
     fn algorithm(&self) -> Algorithm {
         Algorithm::RsaSha256
     }
+
+    fn signature_len(&self) -> usize {
+        self.inner.public_modulus_len()
+    }
 }
 
 pub struct Ed25519Key {
@@ -114,6 +146,43 @@ impl Ed25519Key {
                 .map_err(|err| Error::CryptoError(err.to_string()))?,
         })
     }
+
+    /// Creates an Ed25519 private key from PKCS8 bytes, either PEM or DER encoded,
+    /// a raw 32-byte seed or a raw 64-byte seed followed by the public key.
+    pub fn from_key_bytes(private_key: &[u8]) -> Result<Self> {
+        match PrivateKeyBytes::decode(private_key) {
+            Some(PrivateKeyBytes::Pkcs8(der) | PrivateKeyBytes::Der(der)) => {
+                Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).ok()
+            }
+            Some(PrivateKeyBytes::Raw(raw)) if raw.len() == 32 => {
+                Ed25519KeyPair::from_seed_unchecked(&raw).ok()
+            }
+            Some(PrivateKeyBytes::Raw(raw)) => {
+                Ed25519KeyPair::from_seed_and_public_key(&raw[..32], &raw[32..]).ok()
+            }
+            _ => None,
+        }
+        .map(|inner| Self { inner })
+        .ok_or(Error::UnsupportedKeyFormat(ED25519_KEY_FORMATS))
+    }
+
+    /// Generates a new Ed25519 key pair, returned as PKCS8 DER-encoded bytes.
+    pub fn generate_pkcs8() -> Result<Vec<u8>> {
+        Ok(Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|err| Error::CryptoError(err.to_string()))?
+            .as_ref()
+            .to_vec())
+    }
+
+    /// Generates a new Ed25519 private key.
+    pub fn generate() -> Result<Self> {
+        Self::from_pkcs8_der(&Self::generate_pkcs8()?)
+    }
+
+    /// Returns the raw 32-byte public key.
+    pub fn public_key(&self) -> Vec<u8> {
+        self.inner.public_key().as_ref().to_vec()
+    }
 }
 
 impl SigningKey for Ed25519Key {
@@ -128,11 +197,16 @@ impl SigningKey for Ed25519Key {
     fn algorithm(&self) -> Algorithm {
         Algorithm::Ed25519Sha256
     }
+
+    fn signature_len(&self) -> usize {
+        64
+    }
 }
 
 pub(crate) struct RsaPublicKey {
     sha1: UnparsedPublicKey<Vec<u8>>,
     sha2: UnparsedPublicKey<Vec<u8>>,
+    bits: usize,
 }
 
 impl RsaPublicKey {
@@ -149,10 +223,34 @@ impl RsaPublicKey {
                 &RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
                 key.to_vec(),
             ),
+            bits: rsa_modulus_bits(key),
         }))
     }
 }
 
+/// Returns the size in bits of the modulus of a PKCS1 RSA public key
+///
+/// Returns zero if the key cannot be decoded.
+fn rsa_modulus_bits(pkcs1: &[u8]) -> usize {
+    let modulus = pkcs1
+        .strip_prefix(&[DER_SEQUENCE_TAG])
+        .filter(|bytes| !bytes.is_empty())
+        .map(|bytes| decode_multi_byte_len(bytes).1)
+        .and_then(|bytes| bytes.strip_prefix(&[DER_INTEGER_TAG]))
+        .filter(|bytes| !bytes.is_empty())
+        .and_then(|bytes| {
+            let (len, bytes) = decode_multi_byte_len(bytes);
+            bytes.get(..len)
+        })
+        .unwrap_or_default();
+
+    // Skip the leading zero bytes used to keep the integer positive
+    match modulus.iter().position(|&b| b != 0) {
+        Some(pos) => (modulus.len() - pos) * 8 - modulus[pos].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
 /// Try to strip an ASN.1 DER-encoded RSA public key prefix
 ///
 /// Returns the original slice if the prefix is not found.
@@ -181,6 +279,37 @@ fn try_strip_rsa_prefix(bytes: &[u8]) -> &[u8] {
     &bytes[1..] // skip the unused bits byte
 }
 
+/// Wraps a PKCS1 RSA public key in a SubjectPublicKeyInfo structure
+fn rsa_spki(pkcs1: &[u8]) -> Vec<u8> {
+    const RSA_ALGORITHM_ID: &[u8] = &[
+        0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+    ];
+
+    let mut bit_string = Vec::with_capacity(pkcs1.len() + 6);
+    bit_string.push(DER_BIT_STRING_TAG);
+    encode_multi_byte_len(pkcs1.len() + 1, &mut bit_string);
+    bit_string.push(0); // no unused bits
+    bit_string.extend_from_slice(pkcs1);
+
+    let mut spki = Vec::with_capacity(RSA_ALGORITHM_ID.len() + bit_string.len() + 6);
+    spki.push(DER_SEQUENCE_TAG);
+    encode_multi_byte_len(RSA_ALGORITHM_ID.len() + bit_string.len(), &mut spki);
+    spki.extend_from_slice(RSA_ALGORITHM_ID);
+    spki.extend_from_slice(&bit_string);
+    spki
+}
+
+fn encode_multi_byte_len(len: usize, bytes: &mut Vec<u8>) {
+    if len < 0x80 {
+        bytes.push(len as u8);
+    } else {
+        let len_bytes = len.to_be_bytes();
+        let len_bytes = &len_bytes[len_bytes.iter().position(|&b| b != 0).unwrap_or(0)..];
+        bytes.push(0x80 | len_bytes.len() as u8);
+        bytes.extend_from_slice(len_bytes);
+    }
+}
+
 fn decode_multi_byte_len(bytes: &[u8]) -> (usize, &[u8]) {
     if bytes[0] & 0x80 == 0 {
         return (bytes[0] as usize, &bytes[1..]);
@@ -195,6 +324,7 @@ fn decode_multi_byte_len(bytes: &[u8]) -> (usize, &[u8]) {
     (len, &bytes[len_len + 1..])
 }
 
+const DER_INTEGER_TAG: u8 = 0x02;
 const DER_OBJECT_ID_TAG: u8 = 0x06;
 const DER_BIT_STRING_TAG: u8 = 0x03;
 const DER_SEQUENCE_TAG: u8 = 0x30;
@@ -222,6 +352,10 @@ impl VerifyingKey for RsaPublicKey {
             Algorithm::Ed25519Sha256 => Err(Error::IncompatibleAlgorithms),
         }
     }
+
+    fn key_bits(&self) -> usize {
+        self.bits
+    }
 }
 
 pub(crate) struct Ed25519PublicKey {
@@ -256,6 +390,10 @@ impl VerifyingKey for Ed25519PublicKey {
             .verify(hasher.complete().as_ref(), signature)
             .map_err(|err| Error::CryptoError(err.to_string()))
     }
+
+    fn key_bits(&self) -> usize {
+        256
+    }
 }
 
 impl HashImpl for Sha1 {
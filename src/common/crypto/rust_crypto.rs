@@ -1,7 +1,12 @@
 use std::marker::PhantomData;
 
 use ed25519_dalek::Signer;
-use rsa::{pkcs1::DecodeRsaPrivateKey, PaddingScheme, PublicKey as _, RsaPrivateKey};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey},
+    pkcs8::{DecodePrivateKey, EncodePublicKey},
+    rand_core::{OsRng, RngCore},
+    PaddingScheme, PublicKey as _, PublicKeyParts, RsaPrivateKey,
+};
 use sha2::digest::Digest;
 
 use crate::{
@@ -10,7 +15,10 @@ use crate::{
     Error, Result,
 };
 
-use super::{Algorithm, HashContext, HashImpl, HashOutput, Sha1, Sha256, SigningKey, VerifyingKey};
+use super::{
+    ed25519_pkcs8_seed, Algorithm, HashContext, HashImpl, HashOutput, PrivateKeyBytes, Sha1,
+    Sha256, SigningKey, VerifyingKey, ED25519_KEY_FORMATS, RSA_KEY_FORMATS,
+};
 
 #[derive(Debug)]
 pub struct RsaKey<T> {
@@ -40,6 +48,55 @@ impl<T: HashImpl> RsaKey<T> {
             padding: PhantomData,
         })
     }
+
+    /// Creates a new RSA private key from PKCS1 or PKCS8 bytes, either PEM or DER encoded.
+    pub fn from_key_bytes(private_key: &[u8]) -> Result<Self> {
+        match PrivateKeyBytes::decode(private_key) {
+            Some(PrivateKeyBytes::Pkcs1(der)) => RsaPrivateKey::from_pkcs1_der(&der).ok(),
+            Some(PrivateKeyBytes::Pkcs8(der)) => RsaPrivateKey::from_pkcs8_der(&der).ok(),
+            Some(PrivateKeyBytes::Der(der)) => RsaPrivateKey::from_pkcs1_der(&der)
+                .ok()
+                .or_else(|| RsaPrivateKey::from_pkcs8_der(&der).ok()),
+            _ => None,
+        }
+        .map(|inner| RsaKey {
+            inner,
+            padding: PhantomData,
+        })
+        .ok_or(Error::UnsupportedKeyFormat(RSA_KEY_FORMATS))
+    }
+
+    /// Generates a new RSA private key with the given modulus size.
+    ///
+    /// Only available with the `rust-crypto` feature, since `ring` cannot
+    /// generate RSA keys.
+    pub fn generate(bits: usize) -> Result<Self> {
+        Ok(RsaKey {
+            inner: RsaPrivateKey::new(&mut OsRng, bits)?,
+            padding: PhantomData,
+        })
+    }
+
+    /// Returns the PKCS1 DER encoding of this private key.
+    pub fn to_pkcs1_der(&self) -> Result<Vec<u8>> {
+        Ok(self
+            .inner
+            .to_pkcs1_der()
+            .map_err(|err| Error::CryptoError(err.to_string()))?
+            .as_bytes()
+            .to_vec())
+    }
+
+    /// Returns the DER-encoded SubjectPublicKeyInfo of this key.
+    pub fn public_key(&self) -> Result<Vec<u8>> {
+        Ok(self
+            .inner
+            .to_public_key()
+            .to_public_key_der()
+            .map_err(|err| Error::CryptoError(err.to_string()))?
+            .as_bytes()
+            .to_vec())
+    }
 }
 
 impl SigningKey for RsaKey<Sha1> {
@@ -58,6 +115,10 @@ impl SigningKey for RsaKey<Sha1> {
     fn algorithm(&self) -> Algorithm {
         Algorithm::RsaSha1
     }
+
+    fn signature_len(&self) -> usize {
+        self.inner.size()
+    }
 }
 
 impl SigningKey for RsaKey<Sha256> {
@@ -76,6 +137,10 @@ impl SigningKey for RsaKey<Sha256> {
     fn algorithm(&self) -> Algorithm {
         Algorithm::RsaSha256
     }
+
+    fn signature_len(&self) -> usize {
+        self.inner.size()
+    }
 }
 
 pub struct Ed25519Key {
@@ -94,6 +159,55 @@ impl Ed25519Key {
             },
         })
     }
+
+    /// Creates an Ed25519 private key from PKCS8 bytes, either PEM or DER encoded,
+    /// a raw 32-byte seed or a raw 64-byte seed followed by the public key.
+    pub fn from_key_bytes(private_key: &[u8]) -> crate::Result<Self> {
+        match PrivateKeyBytes::decode(private_key) {
+            Some(PrivateKeyBytes::Pkcs8(der) | PrivateKeyBytes::Der(der)) => {
+                ed25519_pkcs8_seed(&der).and_then(|seed| Self::from_seed(seed).ok())
+            }
+            Some(PrivateKeyBytes::Raw(raw)) if raw.len() == 32 => Self::from_seed(&raw).ok(),
+            Some(PrivateKeyBytes::Raw(raw)) => Self::from_bytes(&raw[32..], &raw[..32]).ok(),
+            _ => None,
+        }
+        .ok_or(Error::UnsupportedKeyFormat(ED25519_KEY_FORMATS))
+    }
+
+    fn from_seed(seed: &[u8]) -> crate::Result<Self> {
+        let secret = ed25519_dalek::SecretKey::from_bytes(seed)?;
+
+        Ok(Self {
+            inner: ed25519_dalek::Keypair {
+                public: ed25519_dalek::PublicKey::from(&secret),
+                secret,
+            },
+        })
+    }
+
+    /// Generates a new Ed25519 private key.
+    pub fn generate() -> crate::Result<Self> {
+        let mut seed = [0u8; ed25519_dalek::SECRET_KEY_LENGTH];
+        OsRng.fill_bytes(&mut seed);
+        let secret = ed25519_dalek::SecretKey::from_bytes(&seed)?;
+
+        Ok(Self {
+            inner: ed25519_dalek::Keypair {
+                public: ed25519_dalek::PublicKey::from(&secret),
+                secret,
+            },
+        })
+    }
+
+    /// Returns the raw 32-byte private key.
+    pub fn private_key(&self) -> &[u8] {
+        self.inner.secret.as_bytes()
+    }
+
+    /// Returns the raw 32-byte public key.
+    pub fn public_key(&self) -> Vec<u8> {
+        self.inner.public.as_bytes().to_vec()
+    }
 }
 
 impl SigningKey for Ed25519Key {
@@ -107,6 +221,10 @@ impl SigningKey for Ed25519Key {
     fn algorithm(&self) -> Algorithm {
         Algorithm::Ed25519Sha256
     }
+
+    fn signature_len(&self) -> usize {
+        ed25519_dalek::SIGNATURE_LENGTH
+    }
 }
 
 pub(crate) struct RsaPublicKey {
@@ -163,6 +281,10 @@ impl VerifyingKey for RsaPublicKey {
             Algorithm::Ed25519Sha256 => Err(Error::IncompatibleAlgorithms),
         }
     }
+
+    fn key_bits(&self) -> usize {
+        self.inner.n().bits()
+    }
 }
 
 pub(crate) struct Ed25519PublicKey {
@@ -204,6 +326,10 @@ impl VerifyingKey for Ed25519PublicKey {
             )
             .map_err(|_| Error::FailedVerification)
     }
+
+    fn key_bits(&self) -> usize {
+        256
+    }
 }
 
 impl Writer for sha1::Sha1 {
@@ -51,6 +51,7 @@ pub(crate) struct HeaderParser<'x> {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum AuthenticatedHeader<'x> {
     Ds(&'x [u8]),
+    Dk(&'x [u8]),
     Aar(&'x [u8]),
     Ams(&'x [u8]),
     As(&'x [u8]),
@@ -305,6 +306,15 @@ impl<'x> Iterator for HeaderParser<'x> {
                     {
                         AuthenticatedHeader::Ds(header_name)
                     }
+                    DOMAINKEY
+                        if self
+                            .message
+                            .get(token_start + 8..token_end + 1)
+                            .unwrap_or_default()
+                            .eq_ignore_ascii_case(b"y-Signature") =>
+                    {
+                        AuthenticatedHeader::Dk(header_name)
+                    }
                     MSGID
                         if self
                             .message
@@ -377,6 +387,14 @@ const DKIM: u64 = (b'd' as u64)
     | (b's' as u64) << 40
     | (b'i' as u64) << 48
     | (b'g' as u64) << 56;
+const DOMAINKEY: u64 = (b'd' as u64)
+    | (b'o' as u64) << 8
+    | (b'm' as u64) << 16
+    | (b'a' as u64) << 24
+    | (b'i' as u64) << 32
+    | (b'n' as u64) << 40
+    | (b'k' as u64) << 48
+    | (b'e' as u64) << 56;
 const AAR: u64 = (b'a' as u64)
     | (b'r' as u64) << 8
     | (b'c' as u64) << 16
@@ -502,6 +520,8 @@ mod test {
             "ARC-Authentication-Results: i=1;\n",
             "ARC-Seal: i=1; a=rsa-sha256;\n",
             "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/simple;\n",
+            "DomainKey-Signature: a=rsa-sha1; c=nofws;\n",
+            "DomainKey-Sig: a=rsa-sha1;\n",
             "From: jdoe@domain\n",
             "F r o m : jane@domain.com\n",
             "ARC-Authentication: i=1;\n",
@@ -521,6 +541,8 @@ mod test {
                 AuthenticatedHeader::Aar(b"ARC-Authentication-Results"),
                 AuthenticatedHeader::As(b"ARC-Seal"),
                 AuthenticatedHeader::Ds(b"DKIM-Signature"),
+                AuthenticatedHeader::Dk(b"DomainKey-Signature"),
+                AuthenticatedHeader::Other(b"DomainKey-Sig"),
                 AuthenticatedHeader::From(b"From"),
                 AuthenticatedHeader::From(b"F r o m "),
                 AuthenticatedHeader::Other(b"ARC-Authentication"),
@@ -8,9 +8,16 @@
  * except according to those terms.
  */
 
+use std::borrow::Cow;
+
 use mail_parser::{parsers::MessageStream, HeaderValue};
 
-use crate::{arc, common::crypto::HashAlgorithm, dkim, AuthenticatedMessage};
+use crate::{
+    arc,
+    common::crypto::HashAlgorithm,
+    dkim::{self, canonicalize::BodyHasher, Canonicalization},
+    AuthenticatedMessage,
+};
 
 use super::headers::{AuthenticatedHeader, Header, HeaderParser};
 
@@ -23,10 +30,12 @@ impl<'x> AuthenticatedMessage<'x> {
             body_offset: 0,
             body_hashes: Vec::new(),
             dkim_headers: Vec::new(),
+            domainkeys_headers: Vec::new(),
             ams_headers: Vec::new(),
             as_headers: Vec::new(),
             aar_headers: Vec::new(),
             received_headers_count: 0,
+            from_headers_count: 0,
             date_header_present: false,
             message_id_header_present: false,
         };
@@ -39,22 +48,21 @@ impl<'x> AuthenticatedMessage<'x> {
                 AuthenticatedHeader::Ds(name) => {
                     let signature = dkim::Signature::parse(value);
                     if let Ok(signature) = &signature {
-                        let ha = HashAlgorithm::from(signature.a);
-                        if !message
-                            .body_hashes
-                            .iter()
-                            .any(|(c, h, l, _)| c == &signature.cb && h == &ha && l == &signature.l)
-                        {
-                            message
-                                .body_hashes
-                                .push((signature.cb, ha, signature.l, Vec::new()));
-                        }
+                        message.add_body_hash(signature.cb, signature.a.into(), signature.l);
                     }
                     message
                         .dkim_headers
                         .push(Header::new(name, value, signature));
                     name
                 }
+                AuthenticatedHeader::Dk(name) => {
+                    message.domainkeys_headers.push(Header::new(
+                        name,
+                        value,
+                        dkim::Signature::parse_domainkeys(value),
+                    ));
+                    name
+                }
                 AuthenticatedHeader::Aar(name) => {
                     let results = arc::Results::parse(value);
                     if !has_arc_errors {
@@ -67,16 +75,7 @@ impl<'x> AuthenticatedMessage<'x> {
                     let signature = arc::Signature::parse(value);
 
                     if let Ok(signature) = &signature {
-                        let ha = HashAlgorithm::from(signature.a);
-                        if !message
-                            .body_hashes
-                            .iter()
-                            .any(|(c, h, l, _)| c == &signature.cb && h == &ha && l == &signature.l)
-                        {
-                            message
-                                .body_hashes
-                                .push((signature.cb, ha, signature.l, Vec::new()));
-                        }
+                        message.add_body_hash(signature.cb, signature.a.into(), signature.l);
                     } else {
                         has_arc_errors = true;
                     }
@@ -95,6 +94,7 @@ impl<'x> AuthenticatedMessage<'x> {
                     name
                 }
                 AuthenticatedHeader::From(name) => {
+                    message.from_headers_count += 1;
                     match MessageStream::new(value).parse_address() {
                         HeaderValue::Address(addr) => {
                             if let Some(addr) = addr.address {
@@ -153,9 +153,13 @@ impl<'x> AuthenticatedMessage<'x> {
         }
         let body = raw_message.get(message.body_offset..).unwrap_or_default();
 
-        // Calculate body hashes
-        for (cb, ha, l, bh) in &mut message.body_hashes {
-            *bh = ha.hash(cb.canonical_body(body, *l)).as_ref().to_vec();
+        // Calculate body hashes and canonical body lengths in a single pass each
+        for (cb, ha, l, bh, len) in &mut message.body_hashes {
+            let mut hasher = BodyHasher::with_limit(*cb, *ha, *l);
+            hasher.update(body);
+            let body_hash = hasher.finish();
+            *bh = body_hash.hash;
+            *len = body_hash.len;
         }
 
         // Sort ARC headers
@@ -186,6 +190,45 @@ impl<'x> AuthenticatedMessage<'x> {
         message.into()
     }
 
+    /// Registers a body hash to compute once the body is parsed. DKIM and ARC
+    /// signatures with the same canonicalization, algorithm and length share it.
+    fn add_body_hash(&mut self, cb: Canonicalization, ha: HashAlgorithm, l: Option<u64>) {
+        if self.body_hash_for(cb, ha, l).is_none() {
+            self.body_hashes.push((cb, ha, l, Vec::new(), 0));
+        }
+    }
+
+    /// Returns the body hash computed for a canonicalization, hash algorithm and length.
+    pub(crate) fn body_hash_for(
+        &self,
+        cb: Canonicalization,
+        ha: HashAlgorithm,
+        l: Option<u64>,
+    ) -> Option<&[u8]> {
+        self.body_hashes
+            .iter()
+            .find(|(c, h, len, _, _)| *c == cb && *h == ha && *len == l)
+            .map(|(_, _, _, bh, _)| bh.as_slice())
+    }
+
+    /// Returns the canonical body length measured while computing the body hash
+    /// for a canonicalization, hash algorithm and length.
+    pub(crate) fn body_length_for(
+        &self,
+        cb: Canonicalization,
+        ha: HashAlgorithm,
+        l: Option<u64>,
+    ) -> Option<u64> {
+        self.body_hashes
+            .iter()
+            .find(|(c, h, len, _, _)| *c == cb && *h == ha && *len == l)
+            .map(|(_, _, _, _, body_length)| *body_length)
+    }
+
+    pub(crate) fn has_arc_headers(&self) -> bool {
+        !self.as_headers.is_empty() || !self.ams_headers.is_empty() || !self.aar_headers.is_empty()
+    }
+
     pub fn received_headers_count(&self) -> usize {
         self.received_headers_count
     }
@@ -213,4 +256,49 @@ impl<'x> AuthenticatedMessage<'x> {
     pub fn from(&self) -> &str {
         self.from.first().map_or("", |f| f.as_str())
     }
+
+    /// Returns the address of the first Sender header, or the first RFC5322.From
+    /// address if there is none, which DomainKeys verifies (RFC 4870 section 3.4).
+    pub(crate) fn domainkeys_sender(&self) -> Cow<'_, str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(b"Sender"))
+            .and_then(
+                |(_, value)| match MessageStream::new(value).parse_address() {
+                    HeaderValue::Address(addr) => addr.address.map(|a| a.to_lowercase()),
+                    _ => None,
+                },
+            )
+            .map_or(Cow::Borrowed(self.from()), Cow::Owned)
+    }
+
+    /// Returns the number of RFC5322.From headers.
+    pub fn from_headers_count(&self) -> usize {
+        self.from_headers_count
+    }
+
+    /// Returns the distinct domains of the RFC5322.From addresses, in order of appearance.
+    pub fn from_domains(&self) -> Vec<&str> {
+        let mut from_domains = Vec::new();
+        for (_, domain) in self.from.iter().filter_map(|from| from.rsplit_once('@')) {
+            if !from_domains.contains(&domain) {
+                from_domains.push(domain);
+            }
+        }
+        from_domains
+    }
+
+    /// Returns the domain of the RFC5322.From addresses, or `None` if there is none
+    /// or they belong to more than one domain, which DMARC exempts from checking.
+    pub fn from_domain(&self) -> Option<&str> {
+        let mut from_domain = None;
+        for (_, domain) in self.from.iter().filter_map(|from| from.rsplit_once('@')) {
+            match from_domain {
+                None => from_domain = Some(domain),
+                Some(from_domain) if from_domain != domain => return None,
+                _ => (),
+            }
+        }
+        from_domain
+    }
 }
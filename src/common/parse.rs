@@ -10,7 +10,7 @@
 
 use std::{borrow::Cow, slice::Iter};
 
-use mail_parser::decoders::quoted_printable::quoted_printable_decode_char;
+use crate::Error;
 
 pub(crate) const V: u64 = b'v' as u64;
 pub(crate) const A: u64 = b'a' as u64;
@@ -18,6 +18,7 @@ pub(crate) const B: u64 = b'b' as u64;
 pub(crate) const BH: u64 = (b'b' as u64) | ((b'h' as u64) << 8);
 pub(crate) const C: u64 = b'c' as u64;
 pub(crate) const D: u64 = b'd' as u64;
+pub(crate) const G: u64 = b'g' as u64;
 pub(crate) const H: u64 = b'h' as u64;
 pub(crate) const I: u64 = b'i' as u64;
 pub(crate) const K: u64 = b'k' as u64;
@@ -25,6 +26,7 @@ pub(crate) const L: u64 = b'l' as u64;
 pub(crate) const N: u64 = b'n' as u64;
 pub(crate) const O: u64 = b'o' as u64;
 pub(crate) const P: u64 = b'p' as u64;
+pub(crate) const Q: u64 = b'q' as u64;
 pub(crate) const R: u64 = b'r' as u64;
 pub(crate) const S: u64 = b's' as u64;
 pub(crate) const T: u64 = b't' as u64;
@@ -35,6 +37,19 @@ pub(crate) const Z: u64 = b'z' as u64;
 
 pub trait TxtRecordParser: Sized {
     fn parse(record: &[u8]) -> crate::Result<Self>;
+
+    /// Parses the TXT records published at a name, returning the first one
+    /// that parses successfully.
+    fn parse_records(records: impl IntoIterator<Item = impl AsRef<[u8]>>) -> crate::Result<Self> {
+        let mut result = Err(Error::InvalidRecordType);
+        for record in records {
+            result = Self::parse(record.as_ref());
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
 }
 
 pub(crate) trait TagParser: Sized {
@@ -43,7 +58,9 @@ pub(crate) trait TagParser: Sized {
     fn value(&mut self) -> u64;
     fn text(&mut self, to_lower: bool) -> String;
     fn text_qp(&mut self, base: Vec<u8>, to_lower: bool, stop_comma: bool) -> String;
+    fn text_qp_bytes(&mut self, base: Vec<u8>, stop_comma: bool) -> Vec<u8>;
     fn headers_qp<T: ItemParser>(&mut self) -> Vec<T>;
+    fn hex_octet(&mut self, tag: &mut Vec<u8>);
     fn number(&mut self) -> Option<u64>;
     fn items<T: ItemParser>(&mut self) -> Vec<T>;
     fn flag_value(&mut self) -> (u64, u8);
@@ -187,39 +204,29 @@ impl TagParser for Iter<'_, u8> {
 
     #[inline(always)]
     #[allow(clippy::while_let_on_iterator)]
-    fn text_qp(&mut self, mut tag: Vec<u8>, to_lower: bool, stop_comma: bool) -> String {
-        'outer: while let Some(&ch) = self.next() {
+    fn text_qp(&mut self, base: Vec<u8>, to_lower: bool, stop_comma: bool) -> String {
+        let tag = self.text_qp_bytes(base, stop_comma);
+        if to_lower {
+            String::from_utf8_lossy(&tag).to_lowercase()
+        } else {
+            String::from_utf8(tag)
+                .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+        }
+    }
+
+    #[inline(always)]
+    #[allow(clippy::while_let_on_iterator)]
+    fn text_qp_bytes(&mut self, mut tag: Vec<u8>, stop_comma: bool) -> Vec<u8> {
+        while let Some(&ch) = self.next() {
             if ch == b';' || (stop_comma && ch == b',') {
                 break;
             } else if ch == b'=' {
-                let mut hex1 = 0;
-
-                while let Some(&ch) = self.next() {
-                    if ch.is_ascii_hexdigit() {
-                        if hex1 != 0 {
-                            if let Some(ch) = quoted_printable_decode_char(hex1, ch) {
-                                tag.push(ch);
-                            }
-                            break;
-                        } else {
-                            hex1 = ch;
-                        }
-                    } else if ch == b';' {
-                        break 'outer;
-                    } else if !ch.is_ascii_whitespace() {
-                        break;
-                    }
-                }
+                self.hex_octet(&mut tag);
             } else if !ch.is_ascii_whitespace() {
                 tag.push(ch);
             }
         }
-        if to_lower {
-            String::from_utf8_lossy(&tag).to_lowercase()
-        } else {
-            String::from_utf8(tag)
-                .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
-        }
+        tag
     }
 
     #[inline(always)]
@@ -228,7 +235,7 @@ impl TagParser for Iter<'_, u8> {
         let mut tags = Vec::new();
         let mut tag = Vec::with_capacity(20);
 
-        'outer: while let Some(&ch) = self.next() {
+        while let Some(&ch) = self.next() {
             if ch == b';' {
                 break;
             } else if ch == b'|' {
@@ -240,32 +247,7 @@ impl TagParser for Iter<'_, u8> {
                     tag.clear();
                 }
             } else if ch == b'=' {
-                let mut hex1 = 0;
-
-                while let Some(&ch) = self.next() {
-                    if ch.is_ascii_hexdigit() {
-                        if hex1 != 0 {
-                            if let Some(ch) = quoted_printable_decode_char(hex1, ch) {
-                                tag.push(ch);
-                            }
-                            break;
-                        } else {
-                            hex1 = ch;
-                        }
-                    } else if ch == b'|' {
-                        if !tag.is_empty() {
-                            if let Some(tag) = T::parse(&tag) {
-                                tags.push(tag);
-                            }
-                            tag.clear();
-                        }
-                        break;
-                    } else if ch == b';' {
-                        break 'outer;
-                    } else if !ch.is_ascii_whitespace() {
-                        break;
-                    }
-                }
+                self.hex_octet(&mut tag);
             } else if !ch.is_ascii_whitespace() {
                 tag.push(ch);
             }
@@ -280,6 +262,26 @@ impl TagParser for Iter<'_, u8> {
         tags
     }
 
+    #[inline(always)]
+    fn hex_octet(&mut self, tag: &mut Vec<u8>) {
+        // Called after an '=' has been consumed
+        match self.as_slice() {
+            [hex1, hex2, ..] if hex1.is_ascii_hexdigit() && hex2.is_ascii_hexdigit() => {
+                tag.push((hex_value(*hex1) << 4) | hex_value(*hex2));
+                self.nth(1);
+            }
+            // Soft line break
+            [b'\r', b'\n', ..] => {
+                self.nth(1);
+            }
+            [b'\n', ..] => {
+                self.next();
+            }
+            // Invalid escapes are kept verbatim
+            _ => tag.push(b'='),
+        }
+    }
+
     #[inline(always)]
     fn number(&mut self) -> Option<u64> {
         let mut num: u64 = 0;
@@ -407,3 +409,12 @@ impl ItemParser for Cow<'_, str> {
         )
     }
 }
+
+#[inline(always)]
+fn hex_value(ch: u8) -> u8 {
+    match ch {
+        b'0'..=b'9' => ch - b'0',
+        b'a'..=b'f' => ch - b'a' + 10,
+        _ => ch - b'A' + 10,
+    }
+}
@@ -12,6 +12,7 @@ use std::{
     borrow::Cow,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     sync::Arc,
+    time::Duration,
 };
 
 use trust_dns_resolver::{
@@ -23,10 +24,16 @@ use trust_dns_resolver::{
 };
 
 use crate::{
-    dkim::{Atps, DomainKeyReport},
-    dmarc::Dmarc,
+    arc::{ArcStrictness, MAX_ARC_INSTANCES},
+    dkim::{
+        Atps, BodyLengthPolicy, DomainKeyReport, IdentityMatch, KeyFetcher, MinimumKeyStrength,
+    },
+    dmarc::{
+        psl::{DefaultSuffixList, SuffixListProvider},
+        Dmarc, DmarcDiscovery,
+    },
     mta_sts::{MtaSts, TlsRpt},
-    spf::{Macro, Spf},
+    spf::{Macro, Spf, SpfPolicy},
     Error, IpLookupStrategy, Resolver, Txt, MX,
 };
 
@@ -71,14 +78,9 @@ impl Resolver {
         options: ResolverOpts,
         capacity: usize,
     ) -> Result<Self, ResolveError> {
-        Ok(Self {
-            resolver: AsyncResolver::tokio(config, options)?,
-            cache_txt: LruCache::with_capacity(capacity),
-            cache_mx: LruCache::with_capacity(capacity),
-            cache_ipv4: LruCache::with_capacity(capacity),
-            cache_ipv6: LruCache::with_capacity(capacity),
-            cache_ptr: LruCache::with_capacity(capacity),
-        })
+        Self::with_capacities(
+            config, options, capacity, capacity, capacity, capacity, capacity,
+        )
     }
 
     pub fn with_capacities(
@@ -97,25 +99,202 @@ impl Resolver {
             cache_ipv4: LruCache::with_capacity(ipv4_capacity),
             cache_ipv6: LruCache::with_capacity(ipv6_capacity),
             cache_ptr: LruCache::with_capacity(ptr_capacity),
+            max_clock_skew: None,
+            body_length_policy: BodyLengthPolicy::default(),
+            max_signatures: 10,
+            max_arc_instances: MAX_ARC_INSTANCES as usize,
+            arc_strictness: ArcStrictness::default(),
+            min_key_strength: MinimumKeyStrength::default(),
+            verify_domainkeys: false,
+            identity_match: IdentityMatch::default(),
+            key_fetchers: Vec::new(),
+            spf_lookup_limit: 10,
+            spf_void_lookup_limit: 2,
+            spf_trace: false,
+            spf_best_guess: false,
+            spf_policy: SpfPolicy::default(),
+            spf_timeout: Duration::from_secs(20),
+            spf_query_timeout: None,
+            suffix_list: Arc::new(DefaultSuffixList),
+            psd_discovery: false,
+            dmarc_discovery: DmarcDiscovery::default(),
+            #[cfg(any(test, feature = "test"))]
+            mock_num_queries: Default::default(),
         })
     }
 
+    /// Tolerates signature timestamps up to this far in the future and
+    /// expirations up to this far in the past. By default, no timestamp
+    /// check is performed and expirations are enforced exactly.
+    pub fn with_max_clock_skew(mut self, max_clock_skew: Duration) -> Self {
+        self.max_clock_skew = Some(max_clock_skew.as_secs());
+        self
+    }
+
+    /// Sets how signatures with an l= tag shorter than the message body are treated.
+    pub fn with_body_length_policy(mut self, policy: BodyLengthPolicy) -> Self {
+        self.body_length_policy = policy;
+        self
+    }
+
+    /// Sets the maximum number of DKIM signatures evaluated per message (defaults to 10).
+    /// Signatures past the limit are returned as neutral. ARC chains are bounded by
+    /// [`Resolver::with_max_arc_instances`] instead.
+    pub fn with_max_signatures(mut self, max_signatures: usize) -> Self {
+        self.max_signatures = max_signatures;
+        self
+    }
+
+    /// Sets the maximum number of ARC sets accepted in a chain (defaults to 50).
+    /// Longer chains fail with `ArcChainTooLong`. The limit can only be lowered,
+    /// as RFC 8617 does not allow more than 50 instances.
+    pub fn with_max_arc_instances(mut self, max_arc_instances: usize) -> Self {
+        self.max_arc_instances = max_arc_instances.min(MAX_ARC_INSTANCES as usize);
+        self
+    }
+
+    /// Sets whether ARC headers have to appear in instance order (defaults to lenient).
+    /// Repeated or missing instances fail in either mode.
+    pub fn with_arc_strictness(mut self, arc_strictness: ArcStrictness) -> Self {
+        self.arc_strictness = arc_strictness;
+        self
+    }
+
+    /// Sets the minimum RSA key size and whether rsa-sha1 signatures are accepted.
+    /// Defaults to 1024 bits without rsa-sha1, as required by RFC 8301.
+    pub fn with_minimum_key_strength(mut self, min_key_strength: MinimumKeyStrength) -> Self {
+        self.min_key_strength = min_key_strength;
+        self
+    }
+
+    /// Also verifies historic DomainKey-Signature headers (RFC 4870), which are
+    /// returned after the DKIM results. These are always rsa-sha1, so they are
+    /// not subject to the rsa-sha1 restriction. Disabled by default.
+    pub fn with_domainkeys(mut self, verify_domainkeys: bool) -> Self {
+        self.verify_domainkeys = verify_domainkeys;
+        self
+    }
+
+    /// Requires the domain of the i= identity to equal the d= domain instead of
+    /// being one of its subdomains. Signatures that only satisfy RFC 6376 are
+    /// returned as neutral.
+    pub fn with_identity_match(mut self, identity_match: IdentityMatch) -> Self {
+        self.identity_match = identity_match;
+        self
+    }
+
+    /// Registers a key fetcher for a DKIM query method (q= tag) other than dns/txt.
+    /// Signatures listing no supported method are returned as neutral.
+    pub fn with_key_fetcher(
+        mut self,
+        method: impl Into<String>,
+        fetcher: impl KeyFetcher + 'static,
+    ) -> Self {
+        self.key_fetchers
+            .push((method.into().to_ascii_lowercase(), Arc::new(fetcher)));
+        self
+    }
+
+    /// Sets the provider of organizational domains used to locate DMARC records
+    /// and to check relaxed DKIM and SPF alignment. By default, DMARC records are
+    /// located by walking up the domain tree and subdomains are aligned.
+    pub fn with_suffix_list(mut self, suffix_list: impl SuffixListProvider + 'static) -> Self {
+        self.suffix_list = Arc::new(suffix_list);
+        self
+    }
+
+    /// Enables looking up the DMARC record of the public suffix when the
+    /// organizational domain has none, which is applied only when it has
+    /// `psd=y` (RFC 9091). Disabled by default.
+    pub fn with_psd_discovery(mut self, psd_discovery: bool) -> Self {
+        self.psd_discovery = psd_discovery;
+        self
+    }
+
+    /// Sets how DMARC records and organizational domains are discovered.
+    /// Defaults to `DmarcDiscovery::Rfc7489`.
+    pub fn with_dmarc_discovery(mut self, dmarc_discovery: DmarcDiscovery) -> Self {
+        self.dmarc_discovery = dmarc_discovery;
+        self
+    }
+
+    /// Sets the maximum number of DNS-querying terms evaluated during an SPF
+    /// evaluation, not counting the query for the SPF record itself (defaults
+    /// to 10). Records exceeding the limit return a permerror.
+    pub fn with_spf_lookup_limit(mut self, spf_lookup_limit: u32) -> Self {
+        self.spf_lookup_limit = spf_lookup_limit;
+        self
+    }
+
+    /// Sets the maximum number of SPF mechanism lookups that may return no
+    /// records (defaults to 2, as recommended by RFC 7208). Records exceeding
+    /// the limit return a permerror.
+    pub fn with_spf_void_lookup_limit(mut self, spf_void_lookup_limit: u32) -> Self {
+        self.spf_void_lookup_limit = spf_void_lookup_limit;
+        self
+    }
+
+    /// Sets the time budget for an SPF check (defaults to 20 seconds), covering
+    /// both identities when using `verify_spf`. Checks exceeding it return a
+    /// temperror.
+    pub fn with_spf_timeout(mut self, spf_timeout: Duration) -> Self {
+        self.spf_timeout = spf_timeout;
+        self
+    }
+
+    /// Sets the maximum time to wait for each DNS query issued during an SPF
+    /// check. Queries taking longer are treated as DNS errors.
+    pub fn with_spf_query_timeout(mut self, spf_query_timeout: Duration) -> Self {
+        self.spf_query_timeout = spf_query_timeout.into();
+        self
+    }
+
+    /// Records every term evaluated during an SPF check along with its outcome
+    /// and the DNS queries it issued, available from `SpfOutput::trace`
+    /// (disabled by default).
+    pub fn with_spf_trace(mut self, spf_trace: bool) -> Self {
+        self.spf_trace = spf_trace;
+        self
+    }
+
+    /// Evaluates `v=spf1 a mx ptr ?all` for domains that publish no SPF record,
+    /// flagging the result with `SpfOutput::is_best_guess` (disabled by default).
+    pub fn with_spf_best_guess(mut self, spf_best_guess: bool) -> Self {
+        self.spf_best_guess = spf_best_guess;
+        self
+    }
+
+    /// Sets the local policy used to treat softfail and neutral SPF results as
+    /// fail, available from `SpfOutput::effective_result`. The RFC 7208 result
+    /// returned by `SpfOutput::result` is not changed.
+    pub fn with_spf_policy(mut self, spf_policy: SpfPolicy) -> Self {
+        self.spf_policy = spf_policy;
+        self
+    }
+
     pub async fn txt_lookup<'x, T: TxtRecordParser + Into<Txt> + UnwrapTxtRecord>(
         &self,
         key: impl IntoFqdn<'x>,
     ) -> crate::Result<Arc<T>> {
         let key = key.into_fqdn();
+        if !key.is_ascii() {
+            return Err(Error::ParseError);
+        }
+        #[cfg(any(test, feature = "test"))]
+        self.mock_num_queries
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
         if let Some(value) = self.cache_txt.get(key.as_ref()) {
             return T::unwrap_txt(value);
         }
 
         #[cfg(any(test, feature = "test"))]
         if true {
+            mock_delay(key.as_ref()).await;
             return mock_resolve(key.as_ref());
         }
 
         let txt_lookup = self.resolver.txt_lookup(key.as_ref()).await?;
-        let mut result = Err(Error::InvalidRecordType);
         let records = txt_lookup.as_lookup().record_iter().filter_map(|r| {
             let txt_data = r.data()?.as_txt()?.txt_data();
             match txt_data.len() {
@@ -131,27 +310,29 @@ impl Resolver {
             }
         });
 
-        for record in records {
-            result = T::parse(record.as_ref());
-            if result.is_ok() {
-                break;
-            }
-        }
         T::unwrap_txt(self.cache_txt.insert(
             key.into_owned(),
-            result.into(),
+            T::parse_records(records).into(),
             txt_lookup.valid_until(),
         ))
     }
 
     pub async fn mx_lookup<'x>(&self, key: impl IntoFqdn<'x>) -> crate::Result<Arc<Vec<MX>>> {
         let key = key.into_fqdn();
+        if !key.is_ascii() {
+            return Err(Error::ParseError);
+        }
+        #[cfg(any(test, feature = "test"))]
+        self.mock_num_queries
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
         if let Some(value) = self.cache_mx.get(key.as_ref()) {
             return Ok(value);
         }
 
         #[cfg(any(test, feature = "test"))]
         if true {
+            mock_delay(key.as_ref()).await;
             return mock_resolve(key.as_ref());
         }
 
@@ -186,12 +367,20 @@ impl Resolver {
         key: impl IntoFqdn<'x>,
     ) -> crate::Result<Arc<Vec<Ipv4Addr>>> {
         let key = key.into_fqdn();
+        if !key.is_ascii() {
+            return Err(Error::ParseError);
+        }
+        #[cfg(any(test, feature = "test"))]
+        self.mock_num_queries
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
         if let Some(value) = self.cache_ipv4.get(key.as_ref()) {
             return Ok(value);
         }
 
         #[cfg(any(test, feature = "test"))]
         if true {
+            mock_delay(key.as_ref()).await;
             return mock_resolve(key.as_ref());
         }
 
@@ -212,12 +401,20 @@ impl Resolver {
         key: impl IntoFqdn<'x>,
     ) -> crate::Result<Arc<Vec<Ipv6Addr>>> {
         let key = key.into_fqdn();
+        if !key.is_ascii() {
+            return Err(Error::ParseError);
+        }
+        #[cfg(any(test, feature = "test"))]
+        self.mock_num_queries
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
         if let Some(value) = self.cache_ipv6.get(key.as_ref()) {
             return Ok(value);
         }
 
         #[cfg(any(test, feature = "test"))]
         if true {
+            mock_delay(key.as_ref()).await;
             return mock_resolve(key.as_ref());
         }
 
@@ -278,6 +475,10 @@ impl Resolver {
     }
 
     pub async fn ptr_lookup<'x>(&self, addr: IpAddr) -> crate::Result<Arc<Vec<String>>> {
+        #[cfg(any(test, feature = "test"))]
+        self.mock_num_queries
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
         if let Some(value) = self.cache_ptr.get(&addr) {
             return Ok(value);
         }
@@ -322,6 +523,9 @@ impl Resolver {
         }
 
         let key = key.into_fqdn();
+        if !key.is_ascii() {
+            return Err(Error::ParseError);
+        }
         match self.resolver.lookup_ip(key.as_ref()).await {
             Ok(result) => Ok(result.as_lookup().record_iter().any(|r| {
                 r.data().map_or(false, |d| {
@@ -546,34 +750,57 @@ pub trait IntoFqdn<'x> {
 
 impl<'x> IntoFqdn<'x> for String {
     fn into_fqdn(self) -> Cow<'x, str> {
-        if self.ends_with('.') {
-            self.to_lowercase().into()
-        } else {
-            format!("{}.", self.to_lowercase()).into()
-        }
+        to_fqdn(&self).into()
     }
 }
 
 impl<'x> IntoFqdn<'x> for &'x str {
     fn into_fqdn(self) -> Cow<'x, str> {
-        if self.ends_with('.') {
-            self.to_lowercase().into()
-        } else {
-            format!("{}.", self.to_lowercase()).into()
-        }
+        to_fqdn(self).into()
     }
 }
 
 impl<'x> IntoFqdn<'x> for &String {
     fn into_fqdn(self) -> Cow<'x, str> {
-        if self.ends_with('.') {
-            self.to_lowercase().into()
-        } else {
-            format!("{}.", self.to_lowercase()).into()
+        to_fqdn(self).into()
+    }
+}
+
+fn to_fqdn(name: &str) -> String {
+    // Invalid names are kept as they are and rejected by the lookups
+    let name = domain_to_ascii(name).unwrap_or(Cow::Borrowed(name));
+    if name.ends_with('.') {
+        name.to_lowercase()
+    } else {
+        format!("{}.", name.to_lowercase())
+    }
+}
+
+/// Converts the U-labels of a domain name to A-labels using UTS #46
+/// non-transitional processing. Returns `None` when the name is not valid,
+/// or when it is not ASCII and the `idna` feature is disabled.
+pub(crate) fn domain_to_ascii(name: &str) -> Option<Cow<'_, str>> {
+    if name.is_ascii() {
+        Some(Cow::Borrowed(name))
+    } else {
+        #[cfg(feature = "idna")]
+        {
+            idna::domain_to_ascii(name).ok().map(Cow::Owned)
+        }
+        #[cfg(not(feature = "idna"))]
+        {
+            None
         }
     }
 }
 
+#[cfg(any(test, feature = "test"))]
+pub async fn mock_delay(domain: &str) {
+    if domain.contains("_slow.") {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
 #[cfg(any(test, feature = "test"))]
 pub fn mock_resolve<T>(domain: &str) -> crate::Result<T> {
     Err(if domain.contains("_parse_error.") {
@@ -8,15 +8,26 @@
  * except according to those terms.
  */
 
-use std::net::IpAddr;
+use std::{borrow::Cow, fmt::Debug, net::IpAddr};
 
-use crate::{dkim::Canonicalization, Error, IprevOutput, IprevResult, Resolver};
+use trust_dns_resolver::Name;
 
-use super::crypto::{Algorithm, VerifyingKey};
+use crate::{
+    dkim::{Canonicalization, Flag},
+    Error, IprevOutput, IprevResult, Resolver,
+};
 
+use super::crypto::{
+    Algorithm, HashAlgorithm, VerifyingKey, VerifyingKeyType, R_HASH_SHA1, R_HASH_SHA256,
+};
+
+/// A parsed DKIM public key record (RFC 6376 section 3.6.1).
 pub struct DomainKey {
     pub(crate) p: Box<dyn VerifyingKey + Send + Sync>,
+    pub(crate) k: VerifyingKeyType,
+    pub(crate) public_key: Vec<u8>,
     pub(crate) f: u64,
+    pub(crate) g: Option<String>,
 }
 
 impl Resolver {
@@ -92,8 +103,92 @@ impl DomainKey {
             input.algorithm(),
         )
     }
+
+    /// Returns the size of the public key in bits.
+    pub fn key_bits(&self) -> usize {
+        self.p.key_bits()
+    }
+
+    /// Returns the type of the public key (k= tag).
+    pub fn key_type(&self) -> VerifyingKeyType {
+        self.k
+    }
+
+    /// Returns the decoded public key (p= tag).
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Returns true if the domain is testing DKIM (t=y flag). Verifiers
+    /// should not treat failed signatures differently from unsigned mail.
+    pub fn is_testing(&self) -> bool {
+        self.has_flag(Flag::Testing)
+    }
+
+    /// Returns true if the i= domain has to match the d= domain exactly (t=s flag).
+    pub fn is_strict(&self) -> bool {
+        self.has_flag(Flag::MatchDomain)
+    }
+
+    /// Returns the hash algorithms the key may be used with (h= tag),
+    /// which are all supported algorithms when the tag is absent.
+    pub fn hash_algorithms(&self) -> Vec<HashAlgorithm> {
+        [HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+            .into_iter()
+            .filter(|&algorithm| {
+                self.f & (R_HASH_SHA1 | R_HASH_SHA256) == 0 || self.has_flag(algorithm)
+            })
+            .collect()
+    }
+
+    /// Returns the local-part pattern this key is restricted to (g= tag).
+    pub fn granularity(&self) -> Option<&str> {
+        self.g.as_deref()
+    }
+
+    pub(crate) fn validate_granularity(&self, local_part: &str) -> bool {
+        // RFC 4871 section 3.6.1: a single "*" matches zero or more characters,
+        // an empty value matches nothing.
+        let pattern = match &self.g {
+            Some(pattern) if pattern == "*" => return true,
+            Some(pattern) => pattern.to_lowercase(),
+            None => return true,
+        };
+
+        if pattern.is_empty() {
+            false
+        } else if let Some((prefix, suffix)) = pattern.split_once('*') {
+            local_part.len() >= prefix.len() + suffix.len()
+                && local_part.starts_with(prefix)
+                && local_part.ends_with(suffix)
+        } else {
+            local_part == pattern
+        }
+    }
+}
+
+impl Debug for DomainKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DomainKey")
+            .field("k", &self.k)
+            .field("key_bits", &self.key_bits())
+            .field("f", &self.f)
+            .field("g", &self.g)
+            .finish()
+    }
+}
+
+impl PartialEq for DomainKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.k == other.k
+            && self.public_key == other.public_key
+            && self.f == other.f
+            && self.g == other.g
+    }
 }
 
+impl Eq for DomainKey {}
+
 pub trait VerifySignature {
     fn selector(&self) -> &str;
 
@@ -105,12 +200,35 @@ pub trait VerifySignature {
 
     fn domain_key(&self) -> String {
         let s = self.selector();
-        let d = self.domain();
+        let d = to_ascii_domain(self.domain());
         let mut key = String::with_capacity(s.len() + d.len() + 13);
         key.push_str(s);
         key.push_str("._domainkey.");
-        key.push_str(d);
+        key.push_str(&d);
         key.push('.');
         key
     }
 }
+
+/// Converts the U-labels of an internationalized domain name to A-labels
+/// (RFC 5890), leaving ASCII and invalid domains unchanged.
+pub(crate) fn to_ascii_domain(domain: &str) -> Cow<'_, str> {
+    if domain.is_ascii() {
+        domain.into()
+    } else {
+        Name::from_utf8(domain)
+            .map(|name| Cow::from(name.to_ascii()))
+            .unwrap_or_else(|_| domain.into())
+    }
+}
+
+/// Converts the A-labels of an internationalized domain name to U-labels.
+pub(crate) fn to_unicode_domain(domain: &str) -> Cow<'_, str> {
+    if !domain.to_ascii_lowercase().contains("xn--") {
+        domain.into()
+    } else {
+        Name::from_ascii(domain)
+            .map(|name| Cow::from(name.to_utf8()))
+            .unwrap_or_else(|_| domain.into())
+    }
+}
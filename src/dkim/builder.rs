@@ -8,9 +8,96 @@
  * except according to those terms.
  */
 
-use crate::common::crypto::{HashAlgorithm, SigningKey};
+use std::time::{Duration, SystemTime};
 
-use super::{Canonicalization, DkimSigner, Done, NeedDomain, NeedHeaders, NeedSelector, Signature};
+use mail_builder::encoders::base64::base64_encode;
+
+use crate::common::{
+    base32::Base32Writer,
+    crypto::{Algorithm, HashAlgorithm, SigningKey},
+    headers::Writer,
+    verify::{to_ascii_domain, to_unicode_domain},
+};
+
+use super::{
+    Atps, Canonicalization, DkimSigner, DomainKeyBuilder, Done, DualSigner, NeedDomain,
+    NeedHeaders, NeedSelector, RotatingSigner, Signature,
+};
+
+/// Presets for the headers to sign, for use with [`DkimSigner::headers`].
+pub struct Headers;
+
+impl Headers {
+    /// Only the From header, which is the minimum required by RFC 6376.
+    pub const MINIMAL: [&'static str; 1] = ["From"];
+
+    /// The headers RFC 6376 section 5.4.1 recommends signing, along with
+    /// the message identifier and MIME headers.
+    pub const RECOMMENDED: [&'static str; 24] = [
+        "From",
+        "Reply-To",
+        "Subject",
+        "Date",
+        "To",
+        "Cc",
+        "Resent-Date",
+        "Resent-From",
+        "Resent-Sender",
+        "Resent-To",
+        "Resent-Cc",
+        "In-Reply-To",
+        "References",
+        "List-Id",
+        "List-Help",
+        "List-Unsubscribe",
+        "List-Subscribe",
+        "List-Post",
+        "List-Owner",
+        "List-Archive",
+        "Message-ID",
+        "MIME-Version",
+        "Content-Type",
+        "Content-Transfer-Encoding",
+    ];
+
+    /// The recommended headers, with the ones most likely to be tampered with
+    /// over-signed so that adding another instance invalidates the signature.
+    pub const STRICT: [&'static str; 33] = [
+        "From",
+        "Reply-To",
+        "Subject",
+        "Date",
+        "To",
+        "Cc",
+        "Resent-Date",
+        "Resent-From",
+        "Resent-Sender",
+        "Resent-To",
+        "Resent-Cc",
+        "In-Reply-To",
+        "References",
+        "List-Id",
+        "List-Help",
+        "List-Unsubscribe",
+        "List-Subscribe",
+        "List-Post",
+        "List-Owner",
+        "List-Archive",
+        "Message-ID",
+        "MIME-Version",
+        "Content-Type",
+        "Content-Transfer-Encoding",
+        "From",
+        "Reply-To",
+        "Subject",
+        "Date",
+        "To",
+        "Cc",
+        "Message-ID",
+        "MIME-Version",
+        "Content-Type",
+    ];
+}
 
 impl<T: SigningKey> DkimSigner<T> {
     pub fn from_key(key: T) -> DkimSigner<T, NeedDomain> {
@@ -22,18 +109,24 @@ impl<T: SigningKey> DkimSigner<T> {
                 ..Default::default()
             },
             key,
+            strict_headers: false,
+            expires_at: None,
         }
     }
 }
 
 impl<T: SigningKey> DkimSigner<T, NeedDomain> {
-    /// Sets the domain to use for signing.
+    /// Sets the domain to use for signing. Internationalized domains are
+    /// converted to A-labels, see [`unicode_domain`](DkimSigner::unicode_domain).
     pub fn domain(mut self, domain: impl Into<String>) -> DkimSigner<T, NeedSelector> {
-        self.template.d = domain.into();
+        let domain = domain.into();
+        self.template.d = to_ascii_domain(&domain).into_owned();
         DkimSigner {
             _state: Default::default(),
             key: self.key,
             template: self.template,
+            strict_headers: self.strict_headers,
+            expires_at: self.expires_at,
         }
     }
 }
@@ -46,6 +139,8 @@ impl<T: SigningKey> DkimSigner<T, NeedSelector> {
             _state: Default::default(),
             key: self.key,
             template: self.template,
+            strict_headers: self.strict_headers,
+            expires_at: self.expires_at,
         }
     }
 }
@@ -61,6 +156,62 @@ impl<T: SigningKey> DkimSigner<T, NeedHeaders> {
             _state: Default::default(),
             key: self.key,
             template: self.template,
+            strict_headers: self.strict_headers,
+            expires_at: self.expires_at,
+        }
+    }
+
+    /// Sets the headers to sign to the [`Headers::STRICT`] preset along with
+    /// `headers`. Signing fails if any of them is a `Received` or `Return-Path`
+    /// trace header, which RFC 6376 section 5.4 says should not be signed.
+    pub fn headers_strict(
+        self,
+        headers: impl IntoIterator<Item = impl Into<String>>,
+    ) -> DkimSigner<T, Done> {
+        let recommended = Headers::RECOMMENDED.len();
+        let mut signer = self.headers(
+            Headers::STRICT[..recommended]
+                .iter()
+                .map(|h| h.to_string())
+                .chain(headers.into_iter().map(|h| h.into()))
+                .chain(Headers::STRICT[recommended..].iter().map(|h| h.to_string())),
+        );
+        signer.strict_headers = true;
+        signer
+    }
+
+    /// Sets the headers to sign, listing each one more time than it appears in
+    /// the message so that any added instance invalidates the signature.
+    pub fn headers_oversigned(
+        self,
+        headers: impl IntoIterator<Item = impl Into<String>>,
+    ) -> DkimSigner<T, Done> {
+        self.headers_repeated(headers.into_iter().map(|h| (h, 1)))
+    }
+
+    /// Sets the headers to sign, each one followed by the number of extra
+    /// occurrences to add to the h= tag.
+    pub fn headers_repeated(
+        mut self,
+        headers: impl IntoIterator<Item = (impl Into<String>, usize)>,
+    ) -> DkimSigner<T, Done> {
+        let mut oversigned = Vec::new();
+        for (header, count) in headers {
+            let header = header.into();
+            for _ in 0..count {
+                oversigned.push(header.clone());
+            }
+            self.template.h.push(header);
+        }
+        // Repeated names never match a header during canonicalization, so they
+        // end up in h= after the existing instances and hash as null strings.
+        self.template.h.extend(oversigned);
+        DkimSigner {
+            _state: Default::default(),
+            key: self.key,
+            template: self.template,
+            strict_headers: self.strict_headers,
+            expires_at: self.expires_at,
         }
     }
 }
@@ -78,6 +229,18 @@ impl<T: SigningKey> DkimSigner<T, Done> {
         self
     }
 
+    /// Writes internationalized signing domains to d= as U-labels rather than
+    /// A-labels (RFC 8616). The public key is looked up by its A-labels either way.
+    pub fn unicode_domain(mut self, unicode_domain: bool) -> Self {
+        self.template.d = if unicode_domain {
+            to_unicode_domain(&self.template.d)
+        } else {
+            to_ascii_domain(&self.template.d)
+        }
+        .into_owned();
+        self
+    }
+
     /// Sets the selector to use for signing.
     pub fn agent_user_identifier(mut self, auid: impl Into<String>) -> Self {
         self.template.i = auid.into();
@@ -87,12 +250,42 @@ impl<T: SigningKey> DkimSigner<T, Done> {
     /// Sets the number of seconds from now to use for the signature expiration.
     pub fn expiration(mut self, expiration: u64) -> Self {
         self.template.x = expiration;
+        self.expires_at = None;
+        self
+    }
+
+    /// Expires signatures at `expiration`, regardless of their signing timestamp.
+    /// Signing fails with `Error::SignatureExpired` once this time is reached.
+    pub fn expiration_at(mut self, expiration: SystemTime) -> Self {
+        self.template.x = 0;
+        self.expires_at = expiration
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+            .into();
         self
     }
 
+    /// Expires signatures `expiration` after their signing timestamp. Durations are
+    /// rounded up to whole seconds, and only a zero duration disables the x= tag.
+    pub fn expiration_after(self, expiration: Duration) -> Self {
+        self.expiration(expiration.as_secs() + u64::from(expiration.subsec_nanos() > 0))
+    }
+
     /// Include the body length in the signature.
     pub fn body_length(mut self, body_length: bool) -> Self {
-        self.template.l = u64::from(body_length);
+        self.template.l = if body_length { Some(0) } else { None };
+        self
+    }
+
+    /// Copy the signed headers into the signature (z= tag).
+    pub fn copy_headers(mut self, copy_headers: bool) -> Self {
+        // The copied values are filled in at signing time
+        self.template.z = if copy_headers {
+            vec![String::new()]
+        } else {
+            Vec::new()
+        };
         self
     }
 
@@ -113,4 +306,185 @@ impl<T: SigningKey> DkimSigner<T, Done> {
         self.template.cb = cb;
         self
     }
+
+    /// Sets the line length at which the generated header is folded.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.template.folding.max_line_length = max_line_length;
+        self
+    }
+
+    /// Sets the indentation of folded lines, which must consist of spaces or tabs.
+    pub fn indentation(mut self, indent: impl Into<String>) -> Self {
+        let indent = indent.into();
+        if !indent.is_empty() && indent.bytes().all(|ch| ch == b' ' || ch == b'\t') {
+            self.template.folding.indent = indent;
+        }
+        self
+    }
+
+    /// Whether the bh= and b= values may be broken across lines.
+    pub fn fold_base64(mut self, fold_base64: bool) -> Self {
+        self.template.folding.fold_base64 = fold_base64;
+        self
+    }
+}
+
+impl<T: SigningKey, U: SigningKey> DualSigner<T, U> {
+    /// Creates a signer that produces one signature for each of the two signers.
+    pub fn new(first: DkimSigner<T, Done>, second: DkimSigner<U, Done>) -> Self {
+        DualSigner { first, second }
+    }
+}
+
+impl<T: SigningKey> RotatingSigner<T> {
+    /// Creates an empty set of rotating signers.
+    pub fn new() -> Self {
+        RotatingSigner {
+            signers: Vec::new(),
+            overlap: 0,
+        }
+    }
+
+    /// Adds a signer that is valid from `not_before` until `not_after`, both in
+    /// seconds since the epoch. A `not_after` of 0 means the key never expires.
+    pub fn signer(mut self, signer: DkimSigner<T, Done>, not_before: u64, not_after: u64) -> Self {
+        self.signers.push((not_before, not_after, signer));
+        self
+    }
+
+    /// Keeps signing with the previous key, alongside the newest one, during the
+    /// first `overlap` after the newest key becomes valid.
+    pub fn overlap(mut self, overlap: Duration) -> Self {
+        self.overlap = overlap.as_secs();
+        self
+    }
+}
+
+impl<T: SigningKey> Default for RotatingSigner<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DomainKeyBuilder {
+    /// Creates a DomainKey record for a public key used with the given signing algorithm.
+    pub fn new(algorithm: Algorithm, public_key: impl Into<Vec<u8>>) -> Self {
+        DomainKeyBuilder {
+            k: algorithm,
+            p: public_key.into(),
+            h: Vec::new(),
+            email_only: false,
+            testing: false,
+            strict: false,
+        }
+    }
+
+    /// Restricts the hash algorithms that may be used with this key (h= tag).
+    pub fn hash_algorithms(mut self, h: impl IntoIterator<Item = HashAlgorithm>) -> Self {
+        self.h = h.into_iter().collect();
+        self
+    }
+
+    /// Restricts the key to the email service type (s=email).
+    pub fn email_only(mut self, email_only: bool) -> Self {
+        self.email_only = email_only;
+        self
+    }
+
+    /// Marks the domain as testing DKIM (t=y).
+    pub fn testing(mut self, testing: bool) -> Self {
+        self.testing = testing;
+        self
+    }
+
+    /// Requires the AUID domain to match the SDID exactly (t=s).
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Renders the TXT record value to publish at `<selector>._domainkey.<domain>`.
+    pub fn to_txt_record(&self) -> crate::Result<String> {
+        let mut record = String::with_capacity(self.p.len() * 2 + 32);
+        record.push_str("v=DKIM1; k=");
+        record.push_str(match self.k {
+            Algorithm::RsaSha1 | Algorithm::RsaSha256 => "rsa",
+            Algorithm::Ed25519Sha256 => "ed25519",
+        });
+        if !self.h.is_empty() {
+            record.push_str("; h=");
+            for (pos, h) in self.h.iter().enumerate() {
+                if pos > 0 {
+                    record.push(':');
+                }
+                record.push_str(match h {
+                    HashAlgorithm::Sha1 => "sha1",
+                    HashAlgorithm::Sha256 => "sha256",
+                });
+            }
+        }
+        if self.email_only {
+            record.push_str("; s=email");
+        }
+        match (self.testing, self.strict) {
+            (true, true) => record.push_str("; t=y:s"),
+            (true, false) => record.push_str("; t=y"),
+            (false, true) => record.push_str("; t=s"),
+            (false, false) => (),
+        }
+        record.push_str("; p=");
+        record.push_str(
+            std::str::from_utf8(&base64_encode(&self.p)?).map_err(|_| crate::Error::Base64)?,
+        );
+
+        Ok(record)
+    }
+
+    /// Renders the TXT record value in zone file form, split into quoted
+    /// character-strings of at most 255 octets as required for large RSA keys.
+    pub fn to_zone_txt_record(&self) -> crate::Result<String> {
+        let record = self.to_txt_record()?;
+        let mut zone_record = String::with_capacity(record.len() + 8);
+        for (pos, chunk) in record.as_bytes().chunks(255).enumerate() {
+            if pos > 0 {
+                zone_record.push(' ');
+            }
+            zone_record.push('"');
+            zone_record.push_str(&String::from_utf8_lossy(chunk));
+            zone_record.push('"');
+        }
+
+        Ok(zone_record)
+    }
+}
+
+impl Atps {
+    /// Computes the label under which the ATPS record of a third-party signing
+    /// domain is published (RFC 6541 section 4.4).
+    pub fn hash_label(signing_domain: &str, algorithm: HashAlgorithm) -> String {
+        let mut writer = Base32Writer::with_capacity(40);
+        writer.write(
+            algorithm
+                .hash(signing_domain.to_lowercase().as_bytes())
+                .as_ref(),
+        );
+        writer.finalize()
+    }
+
+    /// Renders the owner name and the TXT record value that authorize
+    /// `signing_domain` to sign messages on behalf of `domain`.
+    pub fn to_txt_record(
+        domain: &str,
+        signing_domain: &str,
+        algorithm: HashAlgorithm,
+    ) -> (String, String) {
+        (
+            format!(
+                "{}._atps.{}.",
+                Atps::hash_label(signing_domain, algorithm),
+                domain.trim_end_matches('.')
+            ),
+            format!("v=ATPS1; d={};", signing_domain.to_lowercase()),
+        )
+    }
 }
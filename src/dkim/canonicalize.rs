@@ -8,72 +8,246 @@
  * except according to those terms.
  */
 
-use crate::common::headers::{HeaderStream, Writable, Writer};
+use crate::common::{
+    crypto::{HashAlgorithm, HashContext, HashImpl, Sha1, Sha256},
+    headers::{HeaderStream, Writable, Writer},
+};
 
-use super::{Canonicalization, Signature};
+use super::{Canonicalization, DomainKeysCanonicalization, Signature};
+
+#[cfg(test)]
+thread_local!(pub(crate) static BODY_PASSES: std::cell::Cell<u64> = std::cell::Cell::new(0));
+
+/// Returns the body hash placed in the bh= tag of a signature. When `l` is set, only
+/// that many octets of the canonicalized body are hashed; `None` hashes all of it.
+pub fn body_hash(
+    body: &[u8],
+    canonicalization: Canonicalization,
+    algorithm: HashAlgorithm,
+    l: Option<u64>,
+) -> Vec<u8> {
+    algorithm
+        .hash(canonicalization.canonical_body_with_limit(body, l))
+        .as_ref()
+        .to_vec()
+}
 
 pub struct CanonicalBody<'a> {
     canonicalization: Canonicalization,
     body: &'a [u8],
+    l: Option<u64>,
 }
 
 impl Writable for CanonicalBody<'_> {
     fn write(self, hasher: &mut impl Writer) {
-        let mut crlf_seq = 0;
+        let mut canonicalizer = BodyCanonicalizer::new(self.canonicalization, self.l);
+        canonicalizer.write(self.body, hasher);
+        canonicalizer.finish(hasher);
+    }
+}
+
+impl CanonicalBody<'_> {
+    /// Returns the length of the canonicalized body, ignoring any length limit.
+    pub fn canonical_len(&self) -> u64 {
+        let mut canonicalizer = BodyCanonicalizer::new(self.canonicalization, None);
+        canonicalizer.write(self.body, &mut NullWriter);
+        canonicalizer.finish(&mut NullWriter);
+        canonicalizer.len
+    }
+}
+
+struct BodyCanonicalizer {
+    canonicalization: Canonicalization,
+    crlf_seq: usize,
+    last_ch: u8,
+    limit: Option<u64>,
+    len: u64,
+}
+
+impl BodyCanonicalizer {
+    fn new(canonicalization: Canonicalization, limit: Option<u64>) -> Self {
+        #[cfg(test)]
+        BODY_PASSES.with(|passes| passes.set(passes.get() + 1));
+
+        BodyCanonicalizer {
+            canonicalization,
+            crlf_seq: 0,
+            last_ch: 0,
+            limit,
+            len: 0,
+        }
+    }
 
+    fn write(&mut self, body: &[u8], hasher: &mut impl Writer) {
         match self.canonicalization {
             Canonicalization::Relaxed => {
-                let mut last_ch = 0;
-
-                for &ch in self.body {
+                for &ch in body {
                     match ch {
                         b' ' | b'\t' => {
-                            while crlf_seq > 0 {
-                                hasher.write(b"\r\n");
-                                crlf_seq -= 1;
-                            }
+                            // Whitespace is only emitted once followed by other
+                            // characters, so whitespace-only trailing lines are
+                            // dropped like empty ones (RFC 6376 section 3.4.4)
                         }
                         b'\n' => {
-                            crlf_seq += 1;
+                            self.crlf_seq += 1;
                         }
                         b'\r' => {}
                         _ => {
-                            while crlf_seq > 0 {
-                                hasher.write(b"\r\n");
-                                crlf_seq -= 1;
+                            while self.crlf_seq > 0 {
+                                self.emit(b"\r\n", hasher);
+                                self.crlf_seq -= 1;
                             }
 
-                            if last_ch == b' ' || last_ch == b'\t' {
-                                hasher.write(b" ");
+                            if self.last_ch == b' ' || self.last_ch == b'\t' {
+                                self.emit(b" ", hasher);
                             }
 
-                            hasher.write(&[ch]);
+                            self.emit(&[ch], hasher);
                         }
                     }
 
-                    last_ch = ch;
+                    self.last_ch = ch;
                 }
             }
             Canonicalization::Simple => {
-                for &ch in self.body {
+                for &ch in body {
                     match ch {
                         b'\n' => {
-                            crlf_seq += 1;
+                            self.crlf_seq += 1;
                         }
                         b'\r' => {}
                         _ => {
-                            while crlf_seq > 0 {
-                                hasher.write(b"\r\n");
-                                crlf_seq -= 1;
+                            while self.crlf_seq > 0 {
+                                self.emit(b"\r\n", hasher);
+                                self.crlf_seq -= 1;
                             }
-                            hasher.write(&[ch]);
+                            self.emit(&[ch], hasher);
                         }
                     }
                 }
             }
         }
+    }
+
+    fn finish(&mut self, hasher: &mut impl Writer) {
+        // Simple canonicalization turns an empty body into a single CRLF,
+        // relaxed canonicalization leaves it empty (RFC 6376 section 3.4.3-3.4.4)
+        if self.len > 0 || self.canonicalization == Canonicalization::Simple {
+            self.emit(b"\r\n", hasher);
+        }
+    }
+
+    #[inline(always)]
+    fn emit(&mut self, bytes: &[u8], hasher: &mut impl Writer) {
+        // Without a limit the whole body is hashed
+        match self.limit {
+            None => hasher.write(bytes),
+            Some(limit) if self.len < limit => {
+                let remaining = std::cmp::min(limit - self.len, bytes.len() as u64) as usize;
+                hasher.write(&bytes[..remaining]);
+            }
+            Some(_) => (),
+        }
+        self.len += bytes.len() as u64;
+    }
+}
+
+struct NullWriter;
+
+impl Writer for NullWriter {
+    fn write(&mut self, _: &[u8]) {}
+}
+
+/// Incrementally computes the hash of a message body.
+pub struct BodyHasher {
+    canonicalizer: BodyCanonicalizer,
+    context: BodyHashContext,
+    algorithm: HashAlgorithm,
+}
+
+/// A body hash computed by [`BodyHasher`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BodyHash {
+    pub(crate) hash: Vec<u8>,
+    pub(crate) canonicalization: Canonicalization,
+    pub(crate) algorithm: HashAlgorithm,
+    pub(crate) len: u64,
+}
+
+enum BodyHashContext {
+    Sha1(<Sha1 as HashImpl>::Context),
+    Sha256(<Sha256 as HashImpl>::Context),
+}
+
+impl BodyHasher {
+    pub fn new(canonicalization: Canonicalization, algorithm: HashAlgorithm) -> Self {
+        Self::with_limit(canonicalization, algorithm, None)
+    }
+
+    /// Hashes only the first `l` octets of the canonicalized body, while still
+    /// measuring its full length.
+    pub(crate) fn with_limit(
+        canonicalization: Canonicalization,
+        algorithm: HashAlgorithm,
+        l: Option<u64>,
+    ) -> Self {
+        BodyHasher {
+            canonicalizer: BodyCanonicalizer::new(canonicalization, l),
+            context: match algorithm {
+                HashAlgorithm::Sha1 => BodyHashContext::Sha1(Sha1::hasher()),
+                HashAlgorithm::Sha256 => BodyHashContext::Sha256(Sha256::hasher()),
+            },
+            algorithm,
+        }
+    }
+
+    /// Feeds a chunk of the raw message body.
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.canonicalizer.write(chunk, &mut self.context);
+    }
 
-        hasher.write(b"\r\n");
+    /// Completes the hash computation.
+    pub fn finish(mut self) -> BodyHash {
+        let canonicalization = self.canonicalizer.canonicalization;
+        self.canonicalizer.finish(&mut self.context);
+        BodyHash {
+            hash: match self.context {
+                BodyHashContext::Sha1(context) => context.complete().as_ref().to_vec(),
+                BodyHashContext::Sha256(context) => context.complete().as_ref().to_vec(),
+            },
+            canonicalization,
+            algorithm: self.algorithm,
+            len: self.canonicalizer.len,
+        }
+    }
+}
+
+impl Writer for BodyHashContext {
+    fn write(&mut self, buf: &[u8]) {
+        match self {
+            BodyHashContext::Sha1(context) => context.write(buf),
+            BodyHashContext::Sha256(context) => context.write(buf),
+        }
+    }
+}
+
+impl BodyHash {
+    /// Returns the raw body hash, as placed in the `bh=` tag.
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Returns the length of the canonicalized body that was hashed.
+    pub fn body_length(&self) -> u64 {
+        self.len
+    }
+
+    pub fn canonicalization(&self) -> Canonicalization {
+        self.canonicalization
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
     }
 }
 
@@ -132,13 +306,19 @@ impl Canonicalization {
     }
 
     pub fn canonical_body<'a>(&self, body: &'a [u8], l: u64) -> CanonicalBody<'a> {
+        self.canonical_body_with_limit(body, Some(l).filter(|&l| l > 0))
+    }
+
+    /// Canonicalizes the first `l` octets of the body, or all of it when `l` is `None`.
+    pub(crate) fn canonical_body_with_limit<'a>(
+        &self,
+        body: &'a [u8],
+        l: Option<u64>,
+    ) -> CanonicalBody<'a> {
         CanonicalBody {
             canonicalization: *self,
-            body: if l == 0 || body.is_empty() {
-                body
-            } else {
-                &body[..std::cmp::min(l as usize, body.len())]
-            },
+            body,
+            l,
         }
     }
 
@@ -150,6 +330,80 @@ impl Canonicalization {
     }
 }
 
+impl DomainKeysCanonicalization {
+    /// Canonicalizes the signed headers and the body of a DomainKey-Signature
+    /// (RFC 4870 section 3.4), which are hashed together.
+    pub fn canonicalize<'a>(
+        &self,
+        headers: impl Iterator<Item = (&'a [u8], &'a [u8])>,
+        body: &[u8],
+        writer: &mut impl Writer,
+    ) {
+        for (name, value) in headers {
+            match self {
+                DomainKeysCanonicalization::Simple => {
+                    writer.write(name);
+                    writer.write(b":");
+                    for &ch in value {
+                        match ch {
+                            b'\n' => writer.write(b"\r\n"),
+                            b'\r' => (),
+                            _ => writer.write(&[ch]),
+                        }
+                    }
+                    if !value.ends_with(b"\n") {
+                        writer.write(b"\r\n");
+                    }
+                }
+                DomainKeysCanonicalization::Nofws => {
+                    for &ch in name.iter().chain(b":").chain(value) {
+                        if !ch.is_ascii_whitespace() {
+                            writer.write(&[ch]);
+                        }
+                    }
+                    writer.write(b"\r\n");
+                }
+            }
+        }
+
+        // Trailing empty lines are ignored, along with the header/body
+        // separator when the body is empty.
+        let mut is_first = true;
+        let mut empty_lines = 0;
+        for line in body.split(|&ch| ch == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let is_empty = match self {
+                DomainKeysCanonicalization::Simple => line.is_empty(),
+                DomainKeysCanonicalization::Nofws => line.iter().all(|ch| ch.is_ascii_whitespace()),
+            };
+            if is_empty {
+                empty_lines += 1;
+                continue;
+            }
+
+            if is_first {
+                writer.write(b"\r\n");
+                is_first = false;
+            }
+            while empty_lines > 0 {
+                writer.write(b"\r\n");
+                empty_lines -= 1;
+            }
+            match self {
+                DomainKeysCanonicalization::Simple => writer.write(line),
+                DomainKeysCanonicalization::Nofws => {
+                    for &ch in line {
+                        if !ch.is_ascii_whitespace() {
+                            writer.write(&[ch]);
+                        }
+                    }
+                }
+            }
+            writer.write(b"\r\n");
+        }
+    }
+}
+
 impl Signature {
     pub fn canonicalize<'x>(
         &self,
@@ -174,7 +428,7 @@ impl Signature {
         let body = message.body();
         let body_len = body.len();
         let canonical_headers = self.ch.canonical_headers(headers);
-        let canonical_body = self.ch.canonical_body(body, u64::MAX);
+        let canonical_body = self.cb.canonical_body(body, u64::MAX);
 
         // Add any missing headers
         signed_headers.reverse();
@@ -193,6 +447,28 @@ pub struct CanonicalHeaders<'a> {
     headers: Vec<(&'a [u8], &'a [u8])>,
 }
 
+impl CanonicalHeaders<'_> {
+    /// Returns the unfolded headers in signing order, as used in the z= tag.
+    pub(crate) fn copied_headers(&self) -> Vec<String> {
+        self.headers
+            .iter()
+            .rev()
+            .map(|(name, value)| {
+                let value = value
+                    .iter()
+                    .copied()
+                    .filter(|&ch| ch != b'\r' && ch != b'\n')
+                    .collect::<Vec<_>>();
+                format!(
+                    "{}:{}",
+                    String::from_utf8_lossy(name).trim(),
+                    String::from_utf8_lossy(&value).trim()
+                )
+            })
+            .collect()
+    }
+}
+
 impl<'a> Writable for CanonicalHeaders<'a> {
     fn write(self, writer: &mut impl Writer) {
         self.canonicalization
@@ -202,9 +478,14 @@ impl<'a> Writable for CanonicalHeaders<'a> {
 
 #[cfg(test)]
 mod test {
-    use super::{CanonicalBody, CanonicalHeaders};
+    use mail_parser::decoders::base64::base64_decode;
+
+    use super::{body_hash, BodyHasher, CanonicalBody, CanonicalHeaders};
     use crate::{
-        common::headers::{HeaderIterator, Writable},
+        common::{
+            crypto::HashAlgorithm,
+            headers::{HeaderIterator, Writable},
+        },
         dkim::Canonicalization,
     };
 
@@ -249,7 +530,7 @@ mod test {
             ),
             (
                 concat!("H: value\t\r\n\r\n",),
-                (concat!("h:value\r\n"), concat!("\r\n")),
+                (concat!("h:value\r\n"), concat!("")),
                 (concat!("H: value\t\r\n"), concat!("\r\n")),
             ),
             (
@@ -281,10 +562,137 @@ mod test {
                 CanonicalBody {
                     canonicalization,
                     body: raw_body,
+                    l: None,
                 }
                 .write(&mut body);
                 assert_eq!(expected_body, String::from_utf8(body).unwrap());
             }
         }
     }
+
+    #[test]
+    fn dkim_canonicalize_body_edge_cases() {
+        for (body, relaxed_body, simple_body) in [
+            ("", "", "\r\n"),
+            ("\r\n", "", "\r\n"),
+            ("\r\n\r\n", "", "\r\n"),
+            (" \t\r\n\r\n", "", " \t\r\n"),
+            ("Hello world", "Hello world\r\n", "Hello world\r\n"),
+            ("Hello world \t", "Hello world\r\n", "Hello world \t\r\n"),
+            (
+                "Hello\r\nworld\n",
+                "Hello\r\nworld\r\n",
+                "Hello\r\nworld\r\n",
+            ),
+            ("Hello\n\n\n", "Hello\r\n", "Hello\r\n"),
+            ("abc\r\n  \r\n", "abc\r\n", "abc\r\n  \r\n"),
+            ("abc\r\n \t\r\n\r\n", "abc\r\n", "abc\r\n \t\r\n"),
+            (
+                "abc\r\n  \r\ndef\r\n",
+                "abc\r\n\r\ndef\r\n",
+                "abc\r\n  \r\ndef\r\n",
+            ),
+            ("abc\r\n \tdef\r\n", "abc\r\n def\r\n", "abc\r\n \tdef\r\n"),
+        ] {
+            for (canonicalization, expected_body) in [
+                (Canonicalization::Relaxed, relaxed_body),
+                (Canonicalization::Simple, simple_body),
+            ] {
+                let mut canonical_body = Vec::new();
+                canonicalization
+                    .canonical_body(body.as_bytes(), 0)
+                    .write(&mut canonical_body);
+                assert_eq!(
+                    String::from_utf8(canonical_body).unwrap(),
+                    expected_body,
+                    "{body:?} {canonicalization:?}"
+                );
+                assert_eq!(
+                    canonicalization
+                        .canonical_body(body.as_bytes(), 0)
+                        .canonical_len(),
+                    expected_body.len() as u64
+                );
+
+                for algorithm in [HashAlgorithm::Sha1, HashAlgorithm::Sha256] {
+                    assert_eq!(
+                        body_hash(body.as_bytes(), canonicalization, algorithm, None),
+                        algorithm.hash(expected_body.as_bytes()).as_ref(),
+                        "{body:?} {canonicalization:?} {algorithm:?}"
+                    );
+                }
+            }
+        }
+
+        // Well-known body hashes of an empty body
+        for (canonicalization, algorithm, expected_hash) in [
+            (
+                Canonicalization::Relaxed,
+                HashAlgorithm::Sha1,
+                "2jmj7l5rSw0yVb/vlWAYkK/YBwk=",
+            ),
+            (
+                Canonicalization::Relaxed,
+                HashAlgorithm::Sha256,
+                "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            ),
+            (
+                Canonicalization::Simple,
+                HashAlgorithm::Sha1,
+                "uoq1oCgLlTqpdDX/iUbLy7J1Wic=",
+            ),
+            (
+                Canonicalization::Simple,
+                HashAlgorithm::Sha256,
+                "frcCV1k9oG9oKj3dpUqdJg1PxRT2RSN/XKdLCPjaYaY=",
+            ),
+        ] {
+            assert_eq!(
+                body_hash(b"", canonicalization, algorithm, None),
+                base64_decode(expected_hash.as_bytes()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn dkim_body_hasher() {
+        for body in [
+            "",
+            "\r\n",
+            "\r\n\r\n\r\n",
+            "Hello world",
+            "Hello world\r\n",
+            "Hello  \t world \r\n\r\n\r\n",
+            " line 1\r\n\tline 2 \t\r\n\r\nline 3\r\n \r\n",
+            "bare\nnewlines\n\n",
+        ] {
+            for canonicalization in [Canonicalization::Relaxed, Canonicalization::Simple] {
+                for algorithm in [HashAlgorithm::Sha1, HashAlgorithm::Sha256] {
+                    let expected = algorithm
+                        .hash(canonicalization.canonical_body(body.as_bytes(), u64::MAX))
+                        .as_ref()
+                        .to_vec();
+
+                    for chunk_size in 1..=body.len().max(1) {
+                        let mut hasher = BodyHasher::new(canonicalization, algorithm);
+                        for chunk in body.as_bytes().chunks(chunk_size) {
+                            hasher.update(chunk);
+                        }
+                        let body_hash = hasher.finish();
+                        assert_eq!(
+                            body_hash.hash(),
+                            expected,
+                            "{body:?} {canonicalization:?} {algorithm:?} {chunk_size}"
+                        );
+                        assert_eq!(
+                            body_hash.body_length(),
+                            canonicalization
+                                .canonical_body(body.as_bytes(), 0)
+                                .canonical_len()
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
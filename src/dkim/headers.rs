@@ -8,17 +8,42 @@
  * except according to those terms.
  */
 
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    io,
+};
+
+use mail_builder::encoders::base64::base64_encode;
 
 use crate::common::headers::{HeaderWriter, Writer};
 
 use super::{Algorithm, Canonicalization, HashAlgorithm, Signature};
 
 impl Signature {
-    pub(crate) fn write(&self, writer: &mut impl Writer, as_header: bool) {
+    /// Renders the signature as a folded `DKIM-Signature` header, including the trailing CRLF.
+    pub fn to_header(&self) -> String {
+        HeaderWriter::to_header(self)
+    }
+
+    /// Writes the signature as a folded `DKIM-Signature` header, including the trailing CRLF.
+    pub fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.serialize(&mut buf, true);
+        writer.write_all(&buf)
+    }
+
+    pub(crate) fn serialize(&self, writer: &mut impl Writer, as_header: bool) {
+        let folding = &self.folding;
+        let max_len = folding.max_line_length;
+        let indent = folding.indent.len();
+        let mut folded_line = Vec::with_capacity(indent + 2);
         let (header, new_line) = match self.ch {
             Canonicalization::Relaxed if !as_header => (&b"dkim-signature:"[..], &b" "[..]),
-            _ => (&b"DKIM-Signature: "[..], &b"\r\n\t"[..]),
+            _ => {
+                folded_line.extend_from_slice(b"\r\n");
+                folded_line.extend_from_slice(folding.indent.as_bytes());
+                (&b"DKIM-Signature: "[..], &folded_line[..])
+            }
         };
         writer.write(header);
         writer.write(b"v=1; a=");
@@ -49,15 +74,24 @@ impl Signature {
         if self.r {
             writer.write(b"; r=y");
         }
+        if !self.q.is_empty() {
+            writer.write(b"; q=");
+            for (num, method) in self.q.iter().enumerate() {
+                if num > 0 {
+                    writer.write(b":");
+                }
+                writer.write(method.as_bytes());
+            }
+        }
 
         writer.write(b";");
         writer.write(new_line);
 
-        let mut bw = 1;
+        let mut bw = indent;
         for (num, h) in self.h.iter().enumerate() {
-            if bw + h.len() + 1 >= 76 {
+            if bw + h.len() + 1 >= max_len {
                 writer.write(new_line);
-                bw = 1;
+                bw = indent;
             }
             if num > 0 {
                 writer.write_len(b":", &mut bw);
@@ -67,43 +101,44 @@ impl Signature {
             writer.write_len(h.as_bytes(), &mut bw);
         }
 
-        if !self.i.is_empty() {
-            if bw + self.i.len() + 3 >= 76 {
+        let i = self.identity_bytes();
+        if !i.is_empty() {
+            if bw + i.len() + 3 >= max_len {
                 writer.write(b";");
                 writer.write(new_line);
-                bw = 1;
+                bw = indent;
             } else {
                 writer.write_len(b"; ", &mut bw);
             }
             writer.write_len(b"i=", &mut bw);
 
-            for &ch in self.i.as_bytes().iter() {
+            for &ch in i {
                 match ch {
-                    0..=0x20 | b';' | 0x7f..=u8::MAX => {
+                    0..=0x20 | b';' | b'=' | 0x7f..=u8::MAX => {
                         writer.write_len(format!("={ch:02X}").as_bytes(), &mut bw);
                     }
                     _ => {
                         writer.write_len(&[ch], &mut bw);
                     }
                 }
-                if bw >= 76 {
+                if bw >= max_len {
                     writer.write(new_line);
-                    bw = 1;
+                    bw = indent;
                 }
             }
         }
 
         for (tag, value) in [
-            (&b"t="[..], self.t),
-            (&b"x="[..], self.x),
+            (&b"t="[..], Some(self.t).filter(|&t| t > 0)),
+            (&b"x="[..], Some(self.x).filter(|&x| x > 0)),
             (&b"l="[..], self.l),
         ] {
-            if value > 0 {
+            if let Some(value) = value {
                 let value = value.to_string();
                 writer.write_len(b";", &mut bw);
-                if bw + tag.len() + value.len() >= 76 {
+                if bw + tag.len() + value.len() >= max_len {
                     writer.write(new_line);
-                    bw = 1;
+                    bw = indent;
                 } else {
                     writer.write_len(b" ", &mut bw);
                 }
@@ -113,15 +148,78 @@ impl Signature {
             }
         }
 
-        for (tag, value) in [(&b"; bh="[..], &self.bh), (&b"; b="[..], &self.b)] {
-            writer.write_len(tag, &mut bw);
-            for &byte in value {
-                writer.write_len(&[byte], &mut bw);
-                if bw >= 76 {
-                    writer.write(new_line);
-                    bw = 1;
+        if !self.z.is_empty() {
+            writer.write_len(b";", &mut bw);
+            if bw + 3 >= max_len {
+                writer.write(new_line);
+                bw = indent;
+            } else {
+                writer.write_len(b" ", &mut bw);
+            }
+            writer.write_len(b"z=", &mut bw);
+
+            for (num, header) in self.z.iter().enumerate() {
+                if num > 0 {
+                    writer.write_len(b"|", &mut bw);
+                }
+                for &ch in header.as_bytes() {
+                    match ch {
+                        0..=0x20 | b';' | b'|' | b'=' | 0x7f..=u8::MAX => {
+                            writer.write_len(format!("={ch:02X}").as_bytes(), &mut bw);
+                        }
+                        _ => {
+                            writer.write_len(&[ch], &mut bw);
+                        }
+                    }
+                    if bw >= max_len {
+                        writer.write(new_line);
+                        bw = indent;
+                    }
+                }
+            }
+        }
+
+        for (name, value) in &self.unknown_tags {
+            writer.write_len(b";", &mut bw);
+            if bw + name.len() + value.len() + 2 >= max_len {
+                writer.write(new_line);
+                bw = indent;
+            } else {
+                writer.write_len(b" ", &mut bw);
+            }
+            writer.write_len(name.as_bytes(), &mut bw);
+            writer.write_len(b"=", &mut bw);
+            writer.write_len(value.as_bytes(), &mut bw);
+        }
+
+        let bh = base64_encode(&self.bh).unwrap_or_default();
+        let b = base64_encode(&self.b).unwrap_or_default();
+        if folding.fold_base64 {
+            for (tag, value) in [(&b"; bh="[..], &bh), (&b"; b="[..], &b)] {
+                writer.write_len(tag, &mut bw);
+                for &byte in value {
+                    writer.write_len(&[byte], &mut bw);
+                    if bw >= max_len {
+                        writer.write(new_line);
+                        bw = indent;
+                    }
                 }
             }
+        } else {
+            // Keep base64 values unbroken. The b= tag always starts a new line
+            // since its value is empty while the header is being hashed.
+            writer.write_len(b";", &mut bw);
+            if bw + bh.len() + 4 >= max_len {
+                writer.write(new_line);
+            } else {
+                writer.write(b" ");
+            }
+            writer.write(b"bh=");
+            writer.write(&bh);
+            writer.write(b";");
+            writer.write(new_line);
+            writer.write(b"b=");
+            writer.write(&b);
         }
 
         writer.write(b";");
@@ -133,14 +231,14 @@ impl Signature {
 
 impl HeaderWriter for Signature {
     fn write_header(&self, writer: &mut impl Writer) {
-        self.write(writer, true);
+        self.serialize(writer, true);
     }
 }
 
 impl Display for Signature {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut buf = Vec::new();
-        self.write(&mut buf, false);
+        self.serialize(&mut buf, false);
         f.write_str(&String::from_utf8(buf).map_err(|_| std::fmt::Error)?)
     }
 }
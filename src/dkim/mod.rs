@@ -8,13 +8,23 @@
  * except according to those terms.
  */
 
+use std::{future::Future, ops::Range, pin::Pin, sync::Arc};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    arc::Set,
+    arc::{ArcInstanceResult, Set},
     common::{
         crypto::{Algorithm, HashAlgorithm, SigningKey},
-        verify::VerifySignature,
+        headers::Header,
+        verify::{DomainKey, VerifySignature},
+    },
+    dmarc::{
+        psl::{DefaultSuffixList, SuffixListProvider},
+        Alignment,
     },
-    ArcOutput, DkimOutput, DkimResult, Error, Version,
+    ArcOutput, AuthenticatedMessage, DkimOutput, DkimResult, Error, Version,
 };
 
 pub mod builder;
@@ -25,6 +35,7 @@ pub mod sign;
 pub mod verify;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Canonicalization {
     Relaxed,
     Simple,
@@ -35,6 +46,30 @@ pub struct DkimSigner<T: SigningKey, State = NeedDomain> {
     _state: std::marker::PhantomData<State>,
     pub key: T,
     pub template: Signature,
+    strict_headers: bool,
+    expires_at: Option<u64>,
+}
+
+pub struct DualSigner<T: SigningKey, U: SigningKey> {
+    pub first: DkimSigner<T, Done>,
+    pub second: DkimSigner<U, Done>,
+}
+
+/// A set of signers for rotating keys, each valid between its `not_before`
+/// and `not_after` timestamps.
+pub struct RotatingSigner<T: SigningKey> {
+    pub(crate) signers: Vec<(u64, u64, DkimSigner<T, Done>)>,
+    pub(crate) overlap: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DomainKeyBuilder {
+    pub(crate) k: Algorithm,
+    pub(crate) p: Vec<u8>,
+    pub(crate) h: Vec<HashAlgorithm>,
+    pub(crate) email_only: bool,
+    pub(crate) testing: bool,
+    pub(crate) strict: bool,
 }
 
 pub struct NeedDomain;
@@ -52,8 +87,10 @@ pub struct Signature {
     pub(crate) bh: Vec<u8>,
     pub(crate) h: Vec<String>,
     pub(crate) z: Vec<String>,
+    pub(crate) q: Vec<String>,
     pub(crate) i: String,
-    pub(crate) l: u64,
+    pub(crate) i_raw: Vec<u8>,
+    pub(crate) l: Option<u64>,
     pub(crate) x: u64,
     pub(crate) t: u64,
     pub(crate) r: bool,                      // RFC 6651
@@ -61,6 +98,94 @@ pub struct Signature {
     pub(crate) atpsh: Option<HashAlgorithm>, // RFC 6541
     pub(crate) ch: Canonicalization,
     pub(crate) cb: Canonicalization,
+    pub(crate) unknown_tags: Vec<(String, String)>,
+    pub(crate) folding: HeaderFolding,
+    pub(crate) domainkeys: Option<DomainKeysCanonicalization>, // RFC 4870
+}
+
+/// Controls when [`verify_dkim_with_strategy`](crate::Resolver::verify_dkim_with_strategy)
+/// stops evaluating signatures.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum DkimVerifyStrategy {
+    /// Verify every signature.
+    #[default]
+    Full,
+    /// Stop after the first passing signature.
+    FirstPass,
+    /// Stop after the first passing signature that is relaxed aligned with
+    /// this RFC5322.From domain.
+    FirstAlignedPass(String),
+}
+
+/// Retrieves public keys for a query method (q= tag) other than dns/txt, which
+/// is always handled by the [`Resolver`](crate::Resolver).
+pub trait KeyFetcher: Send + Sync {
+    fn fetch<'x>(
+        &'x self,
+        signature: &'x Signature,
+    ) -> Pin<Box<dyn Future<Output = crate::Result<Arc<DomainKey>>> + Send + 'x>>;
+}
+
+/// Canonicalization algorithms of historic DomainKey-Signature headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainKeysCanonicalization {
+    Simple,
+    Nofws,
+}
+
+/// Controls how generated DKIM-Signature headers are folded.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HeaderFolding {
+    pub(crate) max_line_length: usize,
+    pub(crate) indent: String,
+    pub(crate) fold_base64: bool,
+}
+
+impl Default for HeaderFolding {
+    fn default() -> Self {
+        HeaderFolding {
+            max_line_length: 76,
+            indent: "\t".to_string(),
+            fold_base64: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BodyLengthPolicy {
+    /// Accept signatures covering only part of the body.
+    #[default]
+    Allow,
+    /// Accept them, flagging the output as a partial body signature.
+    Warn,
+    /// Treat signatures that leave part of the body unsigned as neutral.
+    Reject,
+}
+
+/// How closely the domain of the i= identity has to match the d= domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdentityMatch {
+    /// The identity may use the signing domain or any of its subdomains (RFC 6376).
+    #[default]
+    Subdomain,
+    /// The identity has to use the signing domain itself.
+    Exact,
+}
+
+/// The weakest signatures accepted by the verifier (RFC 8301).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinimumKeyStrength {
+    pub min_rsa_bits: usize,
+    pub allow_sha1: bool,
+}
+
+impl Default for MinimumKeyStrength {
+    fn default() -> Self {
+        MinimumKeyStrength {
+            min_rsa_bits: 1024,
+            allow_sha1: false,
+        }
+    }
 }
 
 impl Default for Algorithm {
@@ -75,6 +200,15 @@ impl Default for Canonicalization {
     }
 }
 
+/// The data hashed while verifying a DKIM signature.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct DkimTrace {
+    pub(crate) canonical_headers: Vec<u8>,
+    pub(crate) signed_headers: Vec<String>,
+    pub(crate) body_hash: Vec<u8>,
+    pub(crate) expected_body_hash: Vec<u8>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct DomainKeyReport {
     pub(crate) ra: String,
@@ -162,58 +296,121 @@ impl VerifySignature for Signature {
 }
 
 impl Signature {
+    /// Returns the decoded agent or user identifier (i= tag).
     pub fn identity(&self) -> &str {
         &self.i
     }
 
+    /// Returns the decoded octets of the i= tag before UTF-8 validation.
+    pub fn identity_bytes(&self) -> &[u8] {
+        if self.i_raw.is_empty() {
+            self.i.as_bytes()
+        } else {
+            &self.i_raw
+        }
+    }
+
     pub fn body_hash(&self) -> &[u8] {
         &self.bh
     }
+
+    /// Returns the signing timestamp (t= tag) in seconds since the epoch, or 0 if not set.
+    pub fn timestamp(&self) -> u64 {
+        self.t
+    }
+
+    /// Returns the expiration (x= tag) in seconds since the epoch, or 0 if not set.
+    pub fn expiration(&self) -> u64 {
+        self.x
+    }
+
+    /// Returns the headers listed in the h= tag, in signing order.
+    pub fn signed_headers(&self) -> &[String] {
+        &self.h
+    }
+
+    /// Returns the names of the headers in `message` that are not covered by the signature,
+    /// in message order. Headers are signed from the bottom up, so when a header appears more
+    /// times than it is listed in h= its topmost instances are the unsigned ones.
+    pub fn unsigned_headers(&self, message: &AuthenticatedMessage<'_>) -> Vec<String> {
+        // The signature also covers its own DKIM-Signature header
+        let mut signed: Vec<(&str, usize)> = vec![("DKIM-Signature", 1)];
+        for h in &self.h {
+            match signed
+                .iter_mut()
+                .find(|(name, _)| name.eq_ignore_ascii_case(h))
+            {
+                Some((_, count)) => *count += 1,
+                None => signed.push((h.as_str(), 1)),
+            }
+        }
+
+        let mut unsigned = Vec::new();
+        for (name, _) in message.headers.iter().rev() {
+            let name = String::from_utf8_lossy(name);
+            let name = name.trim();
+            match signed
+                .iter_mut()
+                .find(|(h, _)| h.eq_ignore_ascii_case(name))
+            {
+                Some((_, count)) if *count > 0 => *count -= 1,
+                _ => unsigned.push(name.to_string()),
+            }
+        }
+        unsigned.reverse();
+        unsigned
+    }
+
+    /// Returns the query methods (q= tag) in order of preference, lowercased.
+    /// An empty list means the default dns/txt method.
+    pub fn query_methods(&self) -> &[String] {
+        &self.q
+    }
+
+    /// Returns the tags that were not recognized while parsing, as written in the header.
+    pub fn unknown_tags(&self) -> &[(String, String)] {
+        &self.unknown_tags
+    }
+
+    /// Returns the canonicalization of a DomainKey-Signature, or `None` for DKIM signatures.
+    pub fn domainkeys_canonicalization(&self) -> Option<DomainKeysCanonicalization> {
+        self.domainkeys
+    }
 }
 
 impl<'x> DkimOutput<'x> {
     pub(crate) fn pass() -> Self {
         DkimOutput {
             result: DkimResult::Pass,
-            signature: None,
-            report: None,
-            is_atps: false,
+            ..Default::default()
         }
     }
 
     pub(crate) fn perm_err(err: Error) -> Self {
         DkimOutput {
             result: DkimResult::PermError(err),
-            signature: None,
-            report: None,
-            is_atps: false,
+            ..Default::default()
         }
     }
 
     pub(crate) fn temp_err(err: Error) -> Self {
         DkimOutput {
             result: DkimResult::TempError(err),
-            signature: None,
-            report: None,
-            is_atps: false,
+            ..Default::default()
         }
     }
 
     pub(crate) fn fail(err: Error) -> Self {
         DkimOutput {
             result: DkimResult::Fail(err),
-            signature: None,
-            report: None,
-            is_atps: false,
+            ..Default::default()
         }
     }
 
     pub(crate) fn neutral(err: Error) -> Self {
         DkimOutput {
             result: DkimResult::Neutral(err),
-            signature: None,
-            report: None,
-            is_atps: false,
+            ..Default::default()
         }
     }
 
@@ -225,6 +422,18 @@ impl<'x> DkimOutput<'x> {
         }
     }
 
+    pub(crate) fn not_evaluated(header: &'x Header<'x, crate::Result<Signature>>) -> Self {
+        match &header.header {
+            Ok(signature) => DkimOutput::neutral(Error::NotEvaluated).with_signature(signature),
+            Err(_) => DkimOutput::neutral(Error::NotEvaluated),
+        }
+    }
+
+    pub(crate) fn with_result(mut self, result: DkimResult) -> Self {
+        self.result = result;
+        self
+    }
+
     pub(crate) fn with_signature(mut self, signature: &'x Signature) -> Self {
         self.signature = signature.into();
         self
@@ -235,6 +444,45 @@ impl<'x> DkimOutput<'x> {
         self
     }
 
+    pub(crate) fn with_clock_skew(mut self, is_clock_skewed: bool) -> Self {
+        self.is_clock_skewed = is_clock_skewed;
+        self
+    }
+
+    pub(crate) fn with_partial_body(mut self, is_partial_body: bool) -> Self {
+        self.is_partial_body = is_partial_body;
+        self
+    }
+
+    pub(crate) fn with_body_length(mut self, body_length: Option<u64>) -> Self {
+        self.body_length = body_length;
+        self
+    }
+
+    pub(crate) fn with_domainkeys(mut self) -> Self {
+        self.is_domainkeys = true;
+        self
+    }
+
+    pub(crate) fn with_record(mut self, record: Arc<DomainKey>) -> Self {
+        self.record = Some(record);
+        self
+    }
+
+    pub(crate) fn set_header<T>(
+        &mut self,
+        message: &AuthenticatedMessage<'x>,
+        index: usize,
+        header: &Header<'x, T>,
+    ) {
+        let base = message.raw_message.as_ptr() as usize;
+        let start = (header.name.as_ptr() as usize).saturating_sub(base);
+        let end = (header.value.as_ptr() as usize + header.value.len()).saturating_sub(base);
+        self.header_index = Some(index);
+        self.header_offset = start;
+        self.raw_header = message.raw_message.get(start..end);
+    }
+
     pub fn result(&self) -> &DkimResult {
         &self.result
     }
@@ -246,6 +494,126 @@ impl<'x> DkimOutput<'x> {
     pub fn failure_report_addr(&self) -> Option<&str> {
         self.report.as_deref()
     }
+
+    /// Returns whether the signature passed and its d= domain is aligned with
+    /// `from_domain`, either exactly (`strict`) or as DMARC relaxed alignment.
+    pub fn is_aligned(&self, from_domain: &str, strict: bool) -> bool {
+        self.is_aligned_with(from_domain, strict, &DefaultSuffixList)
+    }
+
+    /// Same as `is_aligned`, using the organizational domains computed by
+    /// `suffix_list` for relaxed alignment.
+    pub fn is_aligned_with(
+        &self,
+        from_domain: &str,
+        strict: bool,
+        suffix_list: &dyn SuffixListProvider,
+    ) -> bool {
+        let alignment = if strict {
+            Alignment::Strict
+        } else {
+            Alignment::Relaxed
+        };
+        self.result == DkimResult::Pass
+            && self.signature.as_ref().map_or(false, |signature| {
+                alignment.is_aligned(&signature.d, from_domain, suffix_list)
+            })
+    }
+
+    /// Returns true if the signature was authorized through an ATPS record (RFC 6541).
+    pub fn is_atps(&self) -> bool {
+        self.is_atps
+    }
+
+    /// Returns true if the signature was only accepted because of the configured clock skew tolerance.
+    pub fn is_clock_skewed(&self) -> bool {
+        self.is_clock_skewed
+    }
+
+    /// Returns true if the signature's l= tag leaves part of the message body unsigned.
+    pub fn is_partial_body(&self) -> bool {
+        self.is_partial_body
+    }
+
+    /// Returns the number of body octets covered by the signature's l= tag, if any.
+    pub fn body_length_covered(&self) -> Option<u64> {
+        self.signature.and_then(|signature| signature.l)
+    }
+
+    /// Returns the length of the canonicalized message body, which is only computed
+    /// for passing signatures with an l= tag under the `Warn` and `Reject` body length policies.
+    pub fn body_length_total(&self) -> Option<u64> {
+        self.body_length
+    }
+
+    /// Returns whether the signature covers the whole message body, or `None` if the
+    /// signature did not pass or its coverage was not computed.
+    pub fn is_body_fully_covered(&self) -> Option<bool> {
+        if self.result != DkimResult::Pass {
+            return None;
+        }
+        match (self.body_length_covered(), self.body_length) {
+            (None, _) => Some(true),
+            (Some(l), Some(body_length)) => Some(l >= body_length),
+            (Some(_), None) => None,
+        }
+    }
+
+    /// Returns true if the result is for a historic DomainKey-Signature header (RFC 4870).
+    pub fn is_domainkeys(&self) -> bool {
+        self.is_domainkeys
+    }
+
+    /// Returns the zero-based position of the signature header among the message's
+    /// DKIM-Signature headers, or among its DomainKey-Signature headers for
+    /// [`is_domainkeys`](Self::is_domainkeys) results, counting top-down.
+    pub fn header_index(&self) -> Option<usize> {
+        self.header_index
+    }
+
+    /// Returns the raw signature header, including its name and line ending.
+    pub fn raw_header(&self) -> Option<&'x [u8]> {
+        self.raw_header
+    }
+
+    /// Returns the byte range of the raw signature header within the message.
+    pub fn header_offsets(&self) -> Option<Range<usize>> {
+        self.raw_header
+            .map(|raw_header| self.header_offset..self.header_offset + raw_header.len())
+    }
+
+    /// Returns the public key record the signature was verified against, if it was retrieved.
+    pub fn record(&self) -> Option<&DomainKey> {
+        self.record.as_deref()
+    }
+
+    /// Returns true if the signing domain is testing DKIM (t=y flag), in which case
+    /// failures should be treated as if the message was unsigned (RFC 6376 section 6.3).
+    pub fn is_testing(&self) -> bool {
+        self.record().map_or(false, |record| record.is_testing())
+    }
+}
+
+impl DkimTrace {
+    /// Returns the canonicalized header block, including the stripped DKIM-Signature.
+    pub fn canonical_headers(&self) -> &[u8] {
+        &self.canonical_headers
+    }
+
+    /// Returns the names of the headers that were hashed, in hashing order.
+    pub fn signed_headers(&self) -> &[String] {
+        &self.signed_headers
+    }
+
+    /// Returns the body hash computed from the message.
+    pub fn body_hash(&self) -> &[u8] {
+        &self.body_hash
+    }
+
+    /// Returns the body hash stated in the bh= tag.
+    pub fn expected_body_hash(&self) -> &[u8] {
+        &self.expected_body_hash
+    }
 }
 
 impl<'x> ArcOutput<'x> {
@@ -253,9 +621,23 @@ impl<'x> ArcOutput<'x> {
         &self.result
     }
 
+    /// Returns the ARC sets found in the message. Sets are returned even when
+    /// the chain fails to validate, in which case their contents are untrusted.
     pub fn sets(&self) -> &[Set] {
         &self.set
     }
+
+    /// Returns the validation results of each ARC set, from which the chain
+    /// result is derived. Sets that failed to parse are not included.
+    pub fn instance_results(&self) -> &[ArcInstanceResult] {
+        &self.instances
+    }
+
+    /// Returns `true` if the chain validated, which is required before trusting
+    /// the contents of any of its sets.
+    pub fn is_validated(&self) -> bool {
+        self.result == DkimResult::Pass
+    }
 }
 
 impl From<Error> for DkimResult {
@@ -19,8 +19,8 @@ use crate::{
 };
 
 use super::{
-    Algorithm, Atps, Canonicalization, DomainKeyReport, Flag, HashAlgorithm, Service, Signature,
-    Version, RR_DNS, RR_OTHER, RR_POLICY,
+    Algorithm, Atps, Canonicalization, DomainKeyReport, DomainKeysCanonicalization, Flag,
+    HashAlgorithm, Service, Signature, Version, RR_DNS, RR_OTHER, RR_POLICY,
 };
 
 const ATPSH: u64 = (b'a' as u64)
@@ -52,11 +52,13 @@ impl Signature {
             d: "".into(),
             s: "".into(),
             i: "".into(),
+            i_raw: Vec::new(),
             b: Vec::with_capacity(0),
             bh: Vec::with_capacity(0),
             h: Vec::with_capacity(0),
             z: Vec::with_capacity(0),
-            l: 0,
+            q: Vec::with_capacity(0),
+            l: None,
             x: 0,
             t: 0,
             ch: Canonicalization::Simple,
@@ -64,14 +66,27 @@ impl Signature {
             r: false,
             atps: None,
             atpsh: None,
+            unknown_tags: Vec::new(),
+            folding: Default::default(),
+            domainkeys: None,
         };
         let header_len = header.len();
         let mut header = header.iter();
+        let mut tag_start = header.as_slice();
+        let mut seen_tags = Vec::with_capacity(12);
 
         while let Some(key) = header.key() {
+            // Repeated tags invalidate the whole tag-list (RFC 6376 section 3.2)
+            if let Some(name) = signature_tag_name(key) {
+                if seen_tags.contains(&key) {
+                    return Err(Error::InvalidTag(name));
+                }
+                seen_tags.push(key);
+            }
+
             match key {
                 V => {
-                    signature.v = header.number().unwrap_or(0) as u32;
+                    signature.v = header.number().ok_or(Error::InvalidTag("v"))? as u32;
                     if signature.v != 1 {
                         return Err(Error::UnsupportedVersion);
                     }
@@ -94,11 +109,15 @@ impl Signature {
                 }
                 D => signature.d = header.text(true),
                 H => signature.h = header.items(),
-                I => signature.i = header.text_qp(Vec::with_capacity(20), true, false),
-                L => signature.l = header.number().unwrap_or(0),
+                I => {
+                    let i = header.text_qp_bytes(Vec::with_capacity(20), false);
+                    signature.i = String::from_utf8_lossy(&i).to_lowercase();
+                    signature.i_raw = i;
+                }
+                L => signature.l = Some(header.number().ok_or(Error::InvalidTag("l"))?),
                 S => signature.s = header.text(true),
-                T => signature.t = header.number().unwrap_or(0),
-                X => signature.x = header.number().unwrap_or(0),
+                T => signature.t = header.number().ok_or(Error::InvalidTag("t"))?,
+                X => signature.x = header.number().ok_or(Error::InvalidTag("x"))?,
                 Z => signature.z = header.headers_qp(),
                 R => signature.r = header.value() == Y,
                 ATPS => {
@@ -117,8 +136,16 @@ impl Signature {
                         }
                     };
                 }
-                _ => header.ignore(),
+                Q => {
+                    signature.q = header
+                        .items::<String>()
+                        .into_iter()
+                        .map(|method| method.to_ascii_lowercase())
+                        .collect()
+                }
+                _ => signature.unknown_tags.push(header.unknown_tag(tag_start)),
             }
+            tag_start = header.as_slice();
         }
 
         if !signature.d.is_empty()
@@ -132,6 +159,86 @@ impl Signature {
             Err(Error::MissingParameters)
         }
     }
+
+    /// Parses a historic DomainKey-Signature header (RFC 4870).
+    #[allow(clippy::while_let_on_iterator)]
+    pub fn parse_domainkeys(header: &'_ [u8]) -> crate::Result<Self> {
+        let mut signature = Signature {
+            a: Algorithm::RsaSha1,
+            domainkeys: DomainKeysCanonicalization::Simple.into(),
+            ..Default::default()
+        };
+        let header_len = header.len();
+        let mut header = header.iter();
+
+        while let Some(key) = header.key() {
+            match key {
+                A => {
+                    if header.algorithm()? != Algorithm::RsaSha1 {
+                        return Err(Error::UnsupportedAlgorithm);
+                    }
+                }
+                B => {
+                    signature.b =
+                        base64_decode_stream(&mut header, header_len, b';').ok_or(Error::Base64)?
+                }
+                C => {
+                    signature.domainkeys = match header.text(true).as_str() {
+                        "simple" => DomainKeysCanonicalization::Simple,
+                        "nofws" => DomainKeysCanonicalization::Nofws,
+                        _ => return Err(Error::UnsupportedCanonicalization),
+                    }
+                    .into();
+                }
+                D => signature.d = header.text(true),
+                H => signature.h = header.items(),
+                S => signature.s = header.text(true),
+                _ => header.ignore(),
+            }
+        }
+
+        if !signature.d.is_empty() && !signature.s.is_empty() && !signature.b.is_empty() {
+            Ok(signature)
+        } else {
+            Err(Error::MissingParameters)
+        }
+    }
+}
+
+/// Returns the name of a DKIM-Signature tag defined by RFC 6376 or RFC 6541.
+fn signature_tag_name(key: u64) -> Option<&'static str> {
+    match key {
+        V => "v",
+        A => "a",
+        B => "b",
+        BH => "bh",
+        C => "c",
+        D => "d",
+        H => "h",
+        I => "i",
+        L => "l",
+        Q => "q",
+        S => "s",
+        T => "t",
+        X => "x",
+        Z => "z",
+        R => "r",
+        ATPS => "atps",
+        ATPSH => "atpsh",
+        _ => return None,
+    }
+    .into()
+}
+
+/// Returns the static name of a DKIM-Signature tag.
+#[cfg(feature = "serde")]
+pub(crate) fn static_tag_name(name: &str) -> Option<&'static str> {
+    format!("{name}=")
+        .as_bytes()
+        .iter()
+        .key()
+        .and_then(signature_tag_name)
+        .filter(|tag| *tag == name)
 }
 
 pub(crate) trait SignatureParser: Sized {
@@ -140,9 +247,33 @@ pub(crate) trait SignatureParser: Sized {
         default: Canonicalization,
     ) -> crate::Result<(Canonicalization, Canonicalization)>;
     fn algorithm(&mut self) -> crate::Result<Algorithm>;
+    fn unknown_tag(&mut self, tag_start: &[u8]) -> (String, String);
 }
 
 impl SignatureParser for Iter<'_, u8> {
+    fn unknown_tag(&mut self, tag_start: &[u8]) -> (String, String) {
+        // The tag name was consumed by key(), up to and including the '='
+        let name = &tag_start[..tag_start.len() - self.as_slice().len() - 1];
+        let name = name.rsplit(|&ch| ch == b';').next().unwrap_or_default();
+        let value_start = self.as_slice();
+        self.ignore();
+        let value = &value_start[..value_start.len() - self.as_slice().len()];
+        let value = value.strip_suffix(b";").unwrap_or(value);
+
+        let unfold = |bytes: &[u8]| {
+            String::from_utf8_lossy(
+                &bytes
+                    .iter()
+                    .copied()
+                    .filter(|&ch| ch != b'\r' && ch != b'\n')
+                    .collect::<Vec<_>>(),
+            )
+            .trim()
+            .to_string()
+        };
+        (unfold(name), unfold(value))
+    }
+
     fn canonicalization(
         &mut self,
         default: Canonicalization,
@@ -243,11 +374,17 @@ impl SignatureParser for Iter<'_, u8> {
 impl TxtRecordParser for DomainKey {
     #[allow(clippy::while_let_on_iterator)]
     fn parse(header: &[u8]) -> crate::Result<Self> {
+        // Records in zone file form are split into quoted character-strings
+        if let Some(record) = join_character_strings(header) {
+            return Self::parse(&record);
+        }
+
         let header_len = header.len();
         let mut header = header.iter();
         let mut flags = 0;
         let mut key_type = VerifyingKeyType::Rsa;
         let mut public_key = None;
+        let mut granularity = None;
 
         while let Some(key) = header.key() {
             match key {
@@ -262,6 +399,9 @@ impl TxtRecordParser for DomainKey {
                         public_key = Some(bytes);
                     }
                 }
+                G => {
+                    granularity = header.text_qp(Vec::with_capacity(20), false, false).into();
+                }
                 S => flags |= header.flags::<Service>(),
                 T => flags |= header.flags::<Flag>(),
                 K => {
@@ -297,13 +437,47 @@ impl TxtRecordParser for DomainKey {
         match public_key {
             Some(public_key) => Ok(DomainKey {
                 p: key_type.verifying_key(&public_key)?,
+                k: key_type,
+                public_key,
                 f: flags,
+                g: granularity,
             }),
             _ => Err(Error::InvalidRecordType),
         }
     }
 }
 
+/// Concatenates the quoted character-strings of a TXT record in zone file form
+/// (RFC 1035 section 5.1), returning `None` if the record is not quoted.
+#[allow(clippy::while_let_on_iterator)]
+pub(crate) fn join_character_strings(record: &[u8]) -> Option<Vec<u8>> {
+    let mut iter = record
+        .iter()
+        .skip_while(|ch| ch.is_ascii_whitespace())
+        .peekable();
+    if iter.peek() != Some(&&b'"') {
+        return None;
+    }
+
+    let mut joined = Vec::with_capacity(record.len());
+    let mut in_string = false;
+    while let Some(&ch) = iter.next() {
+        match ch {
+            b'"' => in_string = !in_string,
+            b'\\' if in_string => joined.push(*iter.next()?),
+            _ if in_string => joined.push(ch),
+            _ if ch.is_ascii_whitespace() => (),
+            _ => return None,
+        }
+    }
+
+    if !in_string {
+        Some(joined)
+    } else {
+        None
+    }
+}
+
 impl TxtRecordParser for DomainKeyReport {
     #[allow(clippy::while_let_on_iterator)]
     fn parse(header: &[u8]) -> crate::Result<Self> {
@@ -461,15 +635,16 @@ mod test {
 
     use crate::{
         common::{
-            crypto::{Algorithm, R_HASH_SHA1, R_HASH_SHA256},
+            crypto::{Algorithm, HashAlgorithm, VerifyingKeyType, R_HASH_SHA1, R_HASH_SHA256},
             parse::TxtRecordParser,
             verify::DomainKey,
         },
         dkim::{
-            Canonicalization, DomainKeyReport, Signature, RR_DNS, RR_EXPIRATION, RR_OTHER,
-            RR_POLICY, RR_SIGNATURE, RR_UNKNOWN_TAG, RR_VERIFICATION, R_FLAG_MATCH_DOMAIN,
-            R_FLAG_TESTING, R_SVC_ALL, R_SVC_EMAIL,
+            parse::join_character_strings, Canonicalization, DomainKeyReport, Signature, RR_DNS,
+            RR_EXPIRATION, RR_OTHER, RR_POLICY, RR_SIGNATURE, RR_UNKNOWN_TAG, RR_VERIFICATION,
+            R_FLAG_MATCH_DOMAIN, R_FLAG_TESTING, R_SVC_ALL, R_SVC_EMAIL,
         },
+        Error,
     };
 
     #[test]
@@ -502,7 +677,7 @@ mod test {
                     .unwrap(),
                     h: vec!["Subject".into(), "To".into(), "From".into()],
                     z: vec![],
-                    l: 0,
+                    l: None,
                     x: 0,
                     t: 311923920,
                     ch: Canonicalization::Relaxed,
@@ -510,6 +685,9 @@ mod test {
                     r: false,
                     atps: None,
                     atpsh: None,
+                    unknown_tags: vec![],
+                    folding: Default::default(),
+                    ..Default::default()
                 },
             ),
             (
@@ -545,7 +723,8 @@ mod test {
                         "Subject:demo run".into(),
                         "Date:July 5, 2005 3:44:08 PM -0700".into(),
                     ],
-                    l: 0,
+                    q: vec!["dns/txt".into()],
+                    l: None,
                     x: 1118006938,
                     t: 1117574938,
                     ch: Canonicalization::Simple,
@@ -553,6 +732,9 @@ mod test {
                     r: false,
                     atps: None,
                     atpsh: None,
+                    unknown_tags: vec![],
+                    folding: Default::default(),
+                    ..Default::default()
                 },
             ),
             (
@@ -593,7 +775,8 @@ mod test {
                         "Message-ID".into(),
                     ],
                     z: vec![],
-                    l: 123,
+                    q: vec!["dns/txt".into()],
+                    l: Some(123),
                     x: 0,
                     t: 0,
                     ch: Canonicalization::Simple,
@@ -601,6 +784,9 @@ mod test {
                     r: false,
                     atps: None,
                     atpsh: None,
+                    unknown_tags: vec![],
+                    folding: Default::default(),
+                    ..Default::default()
                 },
             ),
         ] {
@@ -622,6 +808,181 @@ mod test {
         }
     }
 
+    #[test]
+    fn dkim_signature_parse_qp() {
+        for (tag, expected_i, expected_bytes, expected_z) in [
+            // UTF-8 sequences, lowercase hex and soft line breaks
+            (
+                "i==E7=94=A8=e6=88=b7@exa=\r\n mple.jp; z=Subject:=C2=A1ol=C3=A9!|To:a=|b",
+                "用户@example.jp",
+                "用户@example.jp".as_bytes().to_vec(),
+                vec![
+                    "Subject:¡olé!".to_string(),
+                    "To:a=".to_string(),
+                    "b".to_string(),
+                ],
+            ),
+            // Invalid escapes are kept, invalid UTF-8 is replaced
+            (
+                "i=user=zz=4@ex=FFample.com",
+                "user=zz=4@ex\u{fffd}ample.com",
+                b"user=zz=4@ex\xffample.com".to_vec(),
+                vec![],
+            ),
+        ] {
+            let signature = Signature::parse(
+                format!("v=1; a=rsa-sha256; s=s; d=example.com; h=From; bh=YWJj; b=YWJj; {tag}")
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(signature.identity(), expected_i, "{tag}");
+            assert_eq!(signature.identity_bytes(), expected_bytes, "{tag}");
+            assert_eq!(signature.z, expected_z, "{tag}");
+        }
+
+        // Identities are encoded when written
+        let signature = Signature {
+            d: "example.com".to_string(),
+            s: "s".to_string(),
+            i: "用户=1@例え.jp".to_string(),
+            h: vec!["From".to_string()],
+            bh: b"YWJj".to_vec(),
+            b: b"YWJj".to_vec(),
+            ..Default::default()
+        };
+        let header = signature.to_string();
+        assert!(
+            header.contains("i==E7=94=A8=E6=88=B7=3D1@=E4=BE=8B=E3=81=88.jp"),
+            "{header}"
+        );
+        assert_eq!(
+            Signature::parse(header.split_once(':').unwrap().1.as_bytes())
+                .unwrap()
+                .identity(),
+            "用户=1@例え.jp"
+        );
+    }
+
+    #[test]
+    fn dkim_signature_to_header() {
+        for signature in [
+            concat!(
+                "v=1; a=rsa-sha256; s=default; d=stalw.art; c=relaxed/relaxed; ",
+                "bh=QoiUNYyUV+1tZ/xUPRcE+gST2zAStvJx1OK078Ylm5s=; ",
+                "b=Du0rvdzNodI6b5bhlUaZZ+gpXJi0VwjY/3qL7lS0wzKutNVCbvdJuZObGdAcv\n",
+                " eVI/RNQh2gxW4H2ynMS3B+Unse1YLJQwdjuGxsCEKBqReKlsEKT8JlO/7b2AvxR\n",
+                "\t9Q+M2aHD5kn9dbNIKnN/PKouutaXmm18QwL5EPEN9DHXSqQ=;",
+                "h=Subject:To:From; t=311923920",
+            ),
+            concat!(
+                "v=1; a=ed25519-sha256; q=dns/txt; c=simple/relaxed; d=example.com; ",
+                "s=brisbane; i=Joe.Smith=40@Football.example.com; h=From:To:Subject:Date; ",
+                "l=1234; t=1528637909; x=1528724309; r=y; atps=example.org; atpsh=sha256; ",
+                "z=From:foo@eng.example.net|To:joe@example.com|Subject:demo=20run; ",
+                "foo=bar; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; ",
+                "b=pKvURIxJVi2CgRXROh/M6pJ/UrTVRZKX+LQ+QtqJI4vBNibkPcs43bCCSIkn7JBPtCBXRDmD6IWFF51QVRr+Yg==",
+            ),
+            "v=1; a=rsa-sha1; d=example.net; s=brisbane; h=from; bh=YWJj; b=YWJj",
+        ] {
+            let parsed = Signature::parse(signature.as_bytes()).unwrap();
+            let header = parsed.to_header();
+            assert!(header.starts_with("DKIM-Signature: v=1; a="), "{header}");
+            assert!(header.ends_with(";\r\n"), "{header}");
+            for line in header.trim_end().split("\r\n").skip(1) {
+                // Tag separators may overrun the limit by a few characters
+                assert!(line.len() <= 76 + 6, "{header}");
+            }
+
+            let mut buf = Vec::new();
+            parsed.write(&mut buf).unwrap();
+            assert_eq!(buf, header.as_bytes());
+
+            assert_eq!(
+                Signature::parse(header.split_once(':').unwrap().1.as_bytes()).unwrap(),
+                parsed,
+                "{header}"
+            );
+        }
+
+        // Unset optional tags are omitted
+        let header = Signature::parse(
+            b"v=1; a=rsa-sha1; d=example.net; s=brisbane; h=from; bh=YWJj; b=YWJj",
+        )
+        .unwrap()
+        .to_header();
+        for tag in [" i=", " l=", " t=", " x=", " z=", " r=", " atps="] {
+            assert!(!header.contains(tag), "{header}");
+        }
+    }
+
+    #[test]
+    fn dkim_signature_parse_lenient() {
+        let base = "a=rsa-sha256; s=s; d=example.com; h=From; bh=YWJj; b=YWJj";
+        for (header, expected) in [
+            // FWS around '=' and ';', trailing ';'
+            (
+                format!("v = 1 ;\r\n\t{} ; l = 3 ;", base.replace("; ", " ;\r\n ")),
+                Ok(Some(3)),
+            ),
+            // Uppercase tag names
+            (
+                "V=1; A=rsa-sha256; S=s; D=example.com; H=From; BH=YWJj; B=YWJj; L=3".to_string(),
+                Ok(Some(3)),
+            ),
+            // Missing v= tag
+            (format!("{base}; l=3"), Ok(Some(3))),
+            // Unknown and vendor-specific tags are ignored
+            (
+                format!("v=1; {base}; xyz=; x-vendor=a=b;; l=3"),
+                Ok(Some(3)),
+            ),
+            // Duplicate tags
+            (
+                format!("v=1; {base}; d=example.org"),
+                Err(Error::InvalidTag("d")),
+            ),
+            (format!("v=1; {base}; V=1"), Err(Error::InvalidTag("v"))),
+            // Invalid values of known tags
+            (format!("v=1; {base}; l=abc"), Err(Error::InvalidTag("l"))),
+            (format!("v=1; {base}; t=-1"), Err(Error::InvalidTag("t"))),
+            (format!("v=1; {base}; x="), Err(Error::InvalidTag("x"))),
+            (format!("v=one; {base}"), Err(Error::InvalidTag("v"))),
+            (format!("v=2; {base}"), Err(Error::UnsupportedVersion)),
+        ] {
+            assert_eq!(
+                Signature::parse(header.as_bytes()).map(|signature| signature.l),
+                expected,
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn dkim_signature_query_methods() {
+        let base = "v=1; a=rsa-sha256; s=s; d=example.com; h=From; bh=YWJj; b=YWJj";
+        for (header, expected) in [
+            (base.to_string(), vec![]),
+            (format!("{base}; q=dns/txt"), vec!["dns/txt"]),
+            (
+                format!("{base}; q = DNS/HTTPS :\r\n dns/txt"),
+                vec!["dns/https", "dns/txt"],
+            ),
+        ] {
+            let signature = Signature::parse(header.as_bytes()).unwrap();
+            assert_eq!(signature.query_methods(), expected, "{header}");
+
+            // The list is kept when the signature is written back
+            let header = signature.to_string();
+            assert_eq!(
+                Signature::parse(header.split_once(':').unwrap().1.as_bytes())
+                    .unwrap()
+                    .query_methods(),
+                expected,
+                "{header}"
+            );
+        }
+    }
+
     #[test]
     fn dkim_record_parse() {
         for (record, expected_result) in [
@@ -672,6 +1033,70 @@ mod test {
         }
     }
 
+    #[test]
+    fn dkim_record_accessors() {
+        let record = DomainKey::parse(
+            concat!(
+                "v=DKIM1; k=rsa; t=y; h=sha256; g=bill*; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQ",
+                "KBgQDwIRP/UC3SBsEmGqZ9ZJW3/DkMoGeLnQg1fWn7/zYt",
+                "IxN2SnFCjxOCKG9v3b4jYfcTNh5ijSsq631uBItLa7od+v",
+                "/RtdC2UzJ1lWT947qR+Rcac2gbto/NMqJ0fzfVjH4OuKhi",
+                "tdY9tf6mcwGjaNBcWToIMmPSPDdQPNUYckcQ2QIDAQAB",
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(record.key_type(), VerifyingKeyType::Rsa);
+        assert_eq!(record.key_bits(), 1024);
+        assert!(record.is_testing());
+        assert!(!record.is_strict());
+        assert_eq!(record.hash_algorithms(), vec![HashAlgorithm::Sha256]);
+        assert_eq!(record.granularity(), Some("bill*"));
+
+        let record = DomainKey::parse(
+            b"v=DKIM1; k=ed25519; t=s; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=",
+        )
+        .unwrap();
+        assert_eq!(record.key_type(), VerifyingKeyType::Ed25519);
+        assert_eq!(record.key_bits(), 256);
+        assert_eq!(record.public_key().len(), 32);
+        assert!(!record.is_testing());
+        assert!(record.is_strict());
+        assert_eq!(
+            record.hash_algorithms(),
+            vec![HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+        );
+        assert_eq!(record.granularity(), None);
+    }
+
+    #[test]
+    fn dkim_record_character_strings() {
+        for (record, expected) in [
+            (r#""v=DKIM1" "; t=s""#, Some("v=DKIM1; t=s")),
+            (r#" "v=DKIM1;"  "t=\"s\"" "#, Some(r#"v=DKIM1;t="s""#)),
+            (r#""v=DKIM1" ; t=s"#, None),
+            (r#""v=DKIM1"#, None),
+            ("v=DKIM1; t=s", None),
+        ] {
+            assert_eq!(
+                join_character_strings(record.as_bytes()),
+                expected.map(|e| e.as_bytes().to_vec()),
+                "{record}"
+            );
+        }
+
+        assert_eq!(
+            DomainKey::parse(
+                br#""v=DKIM1; k=ed25519; t=s; " "p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=""#
+            )
+            .unwrap(),
+            DomainKey::parse(
+                b"v=DKIM1; k=ed25519; t=s; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+            )
+            .unwrap()
+        );
+    }
+
     #[test]
     fn dkim_report_record_parse() {
         for (record, expected_result) in [
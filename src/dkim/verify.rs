@@ -8,24 +8,29 @@
  * except according to those terms.
  */
 
-use std::time::SystemTime;
+use std::{sync::Arc, time::SystemTime};
 
 use crate::{
     common::{
-        base32::Base32Writer,
-        headers::Writer,
-        verify::{DomainKey, VerifySignature},
+        crypto::Algorithm,
+        headers::Header,
+        verify::{to_ascii_domain, DomainKey, VerifySignature},
     },
     is_within_pct, AuthenticatedMessage, DkimOutput, DkimResult, Error, Resolver,
 };
 
 use super::{
-    Atps, DomainKeyReport, Flag, HashAlgorithm, Signature, RR_DNS, RR_EXPIRATION, RR_OTHER,
-    RR_SIGNATURE, RR_VERIFICATION,
+    Atps, BodyLengthPolicy, Canonicalization, DkimTrace, DkimVerifyStrategy, DomainKeyReport,
+    DomainKeysCanonicalization, Flag, IdentityMatch, MinimumKeyStrength, Signature, RR_DNS,
+    RR_EXPIRATION, RR_OTHER, RR_POLICY, RR_SIGNATURE, RR_VERIFICATION,
 };
 
 impl Resolver {
     /// Verifies DKIM headers of an RFC5322 message.
+    ///
+    /// One output is returned per DKIM-Signature header, in the order the headers
+    /// appear in the message, followed by the DomainKey-Signature results if
+    /// [`with_domainkeys`](Resolver::with_domainkeys) is enabled.
     #[inline(always)]
     pub async fn verify_dkim<'x>(
         &self,
@@ -41,30 +46,122 @@ impl Resolver {
         .await
     }
 
+    /// Verifies DKIM headers of an RFC5322 message, stopping once `strategy` is
+    /// satisfied. Signatures after that point are returned as neutral with
+    /// [`Error::NotEvaluated`], so outputs still follow the order of the headers.
+    pub async fn verify_dkim_with_strategy<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+        strategy: &DkimVerifyStrategy,
+    ) -> Vec<DkimOutput<'x>> {
+        self.verify_dkim_traced_(
+            message,
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            None,
+            strategy,
+        )
+        .await
+    }
+
+    /// Verifies DKIM headers of an RFC5322 message, returning along with each result
+    /// the canonicalized data that was hashed.
+    pub async fn verify_dkim_traced<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+    ) -> Vec<(DkimOutput<'x>, DkimTrace)> {
+        let mut traces = vec![
+            DkimTrace::default();
+            message.dkim_headers.len()
+                + if self.verify_domainkeys {
+                    message.domainkeys_headers.len()
+                } else {
+                    0
+                }
+        ];
+        self.verify_dkim_traced_(
+            message,
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            Some(&mut traces),
+            &DkimVerifyStrategy::Full,
+        )
+        .await
+        .into_iter()
+        .zip(traces)
+        .collect()
+    }
+
+    #[inline(always)]
     pub(crate) async fn verify_dkim_<'x>(
         &self,
         message: &'x AuthenticatedMessage<'x>,
         now: u64,
+    ) -> Vec<DkimOutput<'x>> {
+        self.verify_dkim_traced_(message, now, None, &DkimVerifyStrategy::Full)
+            .await
+    }
+
+    async fn verify_dkim_traced_<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+        now: u64,
+        mut traces: Option<&mut Vec<DkimTrace>>,
+        strategy: &DkimVerifyStrategy,
     ) -> Vec<DkimOutput<'x>> {
         let mut output = Vec::with_capacity(message.dkim_headers.len());
         let mut report_requested = false;
+        let mut is_done = false;
 
         // Validate DKIM headers
-        for header in &message.dkim_headers {
-            // Validate body hash
-            let signature = match &header.header {
+        for (pos, header) in message.dkim_headers.iter().enumerate() {
+            // Skip the remaining signatures once the strategy is satisfied
+            is_done = is_done
+                || output
+                    .last()
+                    .map_or(false, |o| strategy.is_satisfied(o, self));
+            if is_done {
+                output.push(DkimOutput::not_evaluated(header));
+                continue;
+            }
+
+            // Enforce signature limit
+            if pos >= self.max_signatures {
+                output.push(match &header.header {
+                    Ok(signature) => {
+                        DkimOutput::neutral(Error::SignatureLimitExceeded).with_signature(signature)
+                    }
+                    Err(_) => DkimOutput::neutral(Error::SignatureLimitExceeded),
+                });
+                continue;
+            }
+
+            // Validate expiration
+            let (signature, is_clock_skewed) = match &header.header {
                 Ok(signature) => {
                     if signature.r {
                         report_requested = true;
                     }
 
-                    if signature.x == 0 || (signature.x > signature.t && signature.x > now) {
-                        signature
-                    } else {
-                        output.push(
-                            DkimOutput::neutral(Error::SignatureExpired).with_signature(signature),
-                        );
-                        continue;
+                    match signature.validate(
+                        self.max_clock_skew,
+                        &self.min_key_strength,
+                        self.identity_match,
+                        now,
+                    ) {
+                        Ok(is_clock_skewed) => (signature, is_clock_skewed),
+                        Err(result) => {
+                            output.push(
+                                DkimOutput::default()
+                                    .with_result(result)
+                                    .with_signature(signature),
+                            );
+                            continue;
+                        }
                     }
                 }
                 Err(err) => {
@@ -74,15 +171,29 @@ impl Resolver {
             };
 
             // Validate body hash
-            let ha = HashAlgorithm::from(signature.a);
-            let bh = &message
-                .body_hashes
-                .iter()
-                .find(|(c, h, l, _)| c == &signature.cb && h == &ha && l == &signature.l)
-                .unwrap()
-                .3;
+            let bh = message.signature_body_hash(signature);
+
+            if let Some(traces) = traces.as_deref_mut() {
+                let dkim_hdr_value = header.value.strip_signature();
+                let mut canonical_headers = Vec::new();
+                let mut signed_headers = Vec::new();
+                signature.ch.canonicalize_headers(
+                    message
+                        .signed_headers(&signature.h, header.name, &dkim_hdr_value)
+                        .inspect(|(name, _)| {
+                            signed_headers.push(String::from_utf8_lossy(name).trim().to_string())
+                        }),
+                    &mut canonical_headers,
+                );
+                traces[pos] = DkimTrace {
+                    canonical_headers,
+                    signed_headers,
+                    body_hash: bh.to_vec(),
+                    expected_body_hash: signature.bh.clone(),
+                };
+            }
 
-            if bh != &signature.bh {
+            if bh != signature.bh {
                 output.push(
                     DkimOutput::neutral(Error::FailedBodyHashMatch).with_signature(signature),
                 );
@@ -90,29 +201,45 @@ impl Resolver {
             }
 
             // Obtain ._domainkey TXT record
-            let record = match self.txt_lookup::<DomainKey>(signature.domain_key()).await {
+            let record = match self.fetch_domain_key(signature).await {
                 Ok(record) => record,
+                Err(Error::UnsupportedQueryMethod) => {
+                    output.push(
+                        DkimOutput::neutral(Error::UnsupportedQueryMethod)
+                            .with_signature(signature),
+                    );
+                    continue;
+                }
                 Err(err) => {
                     output.push(DkimOutput::dns_error(err).with_signature(signature));
                     continue;
                 }
             };
 
-            // Enforce t=s flag
-            if !signature.validate_auid(&record) {
-                output.push(DkimOutput::fail(Error::FailedAuidMatch).with_signature(signature));
-                continue;
-            }
-
-            // Hash headers
-            let dkim_hdr_value = header.value.strip_signature();
-            let mut headers = message.signed_headers(&signature.h, header.name, &dkim_hdr_value);
-
             // Verify signature
-            if let Err(err) = record.verify(&mut headers, signature, signature.ch) {
-                output.push(DkimOutput::fail(err).with_signature(signature));
-                continue;
-            }
+            let body_length = match signature.verify_with_key(
+                message,
+                header,
+                &record,
+                &self.min_key_strength,
+                self.body_length_policy,
+            ) {
+                Ok(body_length) => body_length,
+                Err(result) => {
+                    output.push(
+                        DkimOutput::default()
+                            .with_result(result)
+                            .with_record(record)
+                            .with_signature(signature),
+                    );
+                    continue;
+                }
+            };
+            let is_partial_body = self.body_length_policy == BodyLengthPolicy::Warn
+                && signature
+                    .l
+                    .zip(body_length)
+                    .map_or(false, |(l, body_length)| l < body_length);
 
             // Verify third-party signature, if any.
             if let Some(atps) = &signature.atps {
@@ -129,12 +256,7 @@ impl Resolver {
 
                 if found {
                     let mut query_domain = match &signature.atpsh {
-                        Some(algorithm) => {
-                            let mut writer = Base32Writer::with_capacity(40);
-                            let output = algorithm.hash(signature.d.as_bytes());
-                            writer.write(output.as_ref());
-                            writer.finalize()
-                        }
+                        Some(algorithm) => Atps::hash_label(&signature.d, *algorithm),
                         None => signature.d.to_string(),
                     };
                     query_domain.push_str("._atps.");
@@ -144,12 +266,21 @@ impl Resolver {
                     match self.txt_lookup::<Atps>(query_domain).await {
                         Ok(_) => {
                             // ATPS Verification successful
-                            output.push(DkimOutput::pass().with_atps().with_signature(signature));
+                            output.push(
+                                DkimOutput::pass()
+                                    .with_atps()
+                                    .with_clock_skew(is_clock_skewed)
+                                    .with_partial_body(is_partial_body)
+                                    .with_body_length(body_length)
+                                    .with_record(record)
+                                    .with_signature(signature),
+                            );
                         }
                         Err(err) => {
                             output.push(
                                 DkimOutput::dns_error(err)
                                     .with_atps()
+                                    .with_record(record)
                                     .with_signature(signature),
                             );
                         }
@@ -159,7 +290,19 @@ impl Resolver {
             }
 
             // Verification successful
-            output.push(DkimOutput::pass().with_signature(signature));
+            output.push(
+                DkimOutput::pass()
+                    .with_clock_skew(is_clock_skewed)
+                    .with_partial_body(is_partial_body)
+                    .with_body_length(body_length)
+                    .with_record(record)
+                    .with_signature(signature),
+            );
+        }
+
+        // Outputs follow the order of the DKIM-Signature headers in the message
+        for (pos, (dkim, header)) in output.iter_mut().zip(&message.dkim_headers).enumerate() {
+            dkim.set_header(message, pos, header);
         }
 
         // Handle reports
@@ -167,7 +310,11 @@ impl Resolver {
             for dkim in &mut output {
                 // Process signatures with errors that requested reports
                 let signature = if let Some(signature) = &dkim.signature {
-                    if signature.r && dkim.result != DkimResult::Pass {
+                    if signature.r
+                        && dkim.result != DkimResult::Pass
+                        && dkim.result != DkimResult::Neutral(Error::SignatureLimitExceeded)
+                        && dkim.result != DkimResult::Neutral(Error::NotEvaluated)
+                    {
                         signature
                     } else {
                         continue;
@@ -201,14 +348,23 @@ impl Resolver {
                             | Error::Io(_)
                             | Error::FailedVerification
                             | Error::FailedBodyHashMatch
-                            | Error::FailedAuidMatch => (record.rr & RR_VERIFICATION) != 0,
+                            | Error::FailedAuidMatch
+                            | Error::KeyGranularityMismatch
+                            | Error::AuidNotInDomain
+                            | Error::AuidNotExactDomain => (record.rr & RR_VERIFICATION) != 0,
                             Error::Base64
                             | Error::UnsupportedVersion
                             | Error::UnsupportedAlgorithm
                             | Error::UnsupportedCanonicalization
                             | Error::UnsupportedKeyType
-                            | Error::IncompatibleAlgorithms => (record.rr & RR_SIGNATURE) != 0,
-                            Error::SignatureExpired => (record.rr & RR_EXPIRATION) != 0,
+                            | Error::IncompatibleAlgorithms
+                            | Error::WeakCryptography
+                            | Error::UnsupportedKeyFormat(_)
+                            | Error::InvalidTag(_)
+                            | Error::UnsupportedQueryMethod => (record.rr & RR_SIGNATURE) != 0,
+                            Error::SignatureExpired | Error::FutureSignature => {
+                                (record.rr & RR_EXPIRATION) != 0
+                            }
                             Error::DnsError(_)
                             | Error::DnsRecordNotFound(_)
                             | Error::InvalidRecordType
@@ -218,10 +374,29 @@ impl Resolver {
                             | Error::NoHeadersFound
                             | Error::ArcChainTooLong
                             | Error::ArcInvalidInstance(_)
+                            | Error::ArcDuplicateInstance(_)
+                            | Error::ArcMissingInstance(_)
                             | Error::ArcInvalidCV
                             | Error::ArcHasHeaderTag
                             | Error::ArcBrokenChain
-                            | Error::NotAligned => (record.rr & RR_OTHER) != 0,
+                            | Error::NotAligned
+                            | Error::SignatureLimitExceeded
+                            | Error::InvalidSignedHeaders(_)
+                            | Error::NoValidSigner
+                            | Error::NotEvaluated
+                            | Error::ArcInvalidOrder
+                            | Error::ArcNotValidated
+                            | Error::LookupLimitExceeded
+                            | Error::VoidLookupLimitExceeded
+                            | Error::EvaluationLoop(_)
+                            | Error::DeadlineExceeded
+                            | Error::RecordTooLong(_)
+                            | Error::MultipleRecords
+                            | Error::MxLimitExceeded
+                            | Error::MultipleFrom
+                            | Error::MissingFrom
+                            | Error::UnusableRecord => (record.rr & RR_OTHER) != 0,
+                            Error::PartialBody => (record.rr & RR_POLICY) != 0,
                         };
 
                         if send_report {
@@ -235,11 +410,197 @@ impl Resolver {
             }
         }
 
+        // Verify historic DomainKey-Signature headers
+        if self.verify_domainkeys {
+            for (pos, header) in message.domainkeys_headers.iter().enumerate() {
+                is_done = is_done
+                    || output
+                        .last()
+                        .map_or(false, |o| strategy.is_satisfied(o, self));
+                let mut dkim = if is_done {
+                    DkimOutput::not_evaluated(header)
+                } else if output.len() >= self.max_signatures {
+                    match &header.header {
+                        Ok(signature) => DkimOutput::neutral(Error::SignatureLimitExceeded)
+                            .with_signature(signature),
+                        Err(_) => DkimOutput::neutral(Error::SignatureLimitExceeded),
+                    }
+                } else {
+                    self.verify_domainkeys_(message, header).await
+                };
+                dkim.set_header(message, pos, header);
+                output.push(dkim);
+            }
+        }
+
         output
     }
+
+    async fn verify_domainkeys_<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+        header: &'x Header<'x, crate::Result<Signature>>,
+    ) -> DkimOutput<'x> {
+        let signature = match &header.header {
+            Ok(signature) => signature,
+            Err(err) => return DkimOutput::neutral(err.clone()).with_domainkeys(),
+        };
+
+        // The sending domain has to be the signing domain or one of its subdomains
+        let sender = message.domainkeys_sender();
+        let (local_part, domain) = sender.rsplit_once('@').unwrap_or_default();
+        if domain != signature.d && !domain.ends_with(&format!(".{}", signature.d)) {
+            return DkimOutput::fail(Error::FailedAuidMatch)
+                .with_signature(signature)
+                .with_domainkeys();
+        }
+
+        // Obtain record
+        let record = match self.txt_lookup::<DomainKey>(signature.domain_key()).await {
+            Ok(record) => record,
+            Err(err) => {
+                return DkimOutput::dns_error(err)
+                    .with_signature(signature)
+                    .with_domainkeys();
+            }
+        };
+
+        // Reject undersized RSA keys
+        if record.key_bits() < self.min_key_strength.min_rsa_bits {
+            return DkimOutput::perm_err(Error::WeakCryptography)
+                .with_signature(signature)
+                .with_domainkeys();
+        }
+
+        // Enforce g= granularity
+        if !record.validate_granularity(local_part) {
+            return DkimOutput::fail(Error::KeyGranularityMismatch)
+                .with_signature(signature)
+                .with_domainkeys();
+        }
+
+        // Only the headers following the signature are signed, in message order
+        let headers = message
+            .headers
+            .iter()
+            .skip_while(|(_, value)| !std::ptr::eq(*value, header.value))
+            .skip(1)
+            .filter(|(name, _)| {
+                signature.h.is_empty()
+                    || signature
+                        .h
+                        .iter()
+                        .any(|h| h.as_bytes().eq_ignore_ascii_case(name))
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        let first_name = match headers.first() {
+            Some((name, _)) => *name,
+            None => {
+                return DkimOutput::fail(Error::NoHeadersFound)
+                    .with_signature(signature)
+                    .with_domainkeys();
+            }
+        };
+        let canonicalization = signature
+            .domainkeys
+            .unwrap_or(DomainKeysCanonicalization::Simple);
+        let mut data = Vec::with_capacity(256);
+        canonicalization.canonicalize(
+            headers.into_iter(),
+            message
+                .raw_message
+                .get(message.body_offset..)
+                .unwrap_or_default(),
+            &mut data,
+        );
+
+        // Headers and body are hashed together, so the canonicalized data is
+        // verified as if it were a single header named after the first signed one.
+        let name_len = match canonicalization {
+            DomainKeysCanonicalization::Simple => first_name.len(),
+            DomainKeysCanonicalization::Nofws => first_name
+                .iter()
+                .filter(|ch| !ch.is_ascii_whitespace())
+                .count(),
+        };
+        let (name, value) = (&data[..name_len], &data[name_len + 1..]);
+        if let Err(err) = record.verify(
+            &mut [(name, value)].into_iter(),
+            signature,
+            Canonicalization::Simple,
+        ) {
+            return DkimOutput::fail(err)
+                .with_record(record)
+                .with_signature(signature)
+                .with_domainkeys();
+        }
+
+        DkimOutput::pass()
+            .with_record(record)
+            .with_signature(signature)
+            .with_domainkeys()
+    }
+
+    /// Obtains the public key of a signature using the first of its query methods
+    /// that is either dns/txt or has a registered key fetcher.
+    async fn fetch_domain_key(&self, signature: &Signature) -> crate::Result<Arc<DomainKey>> {
+        if signature.q.is_empty() {
+            return self.txt_lookup::<DomainKey>(signature.domain_key()).await;
+        }
+        for method in &signature.q {
+            if method == "dns/txt" {
+                return self.txt_lookup::<DomainKey>(signature.domain_key()).await;
+            } else if let Some((_, fetcher)) = self.key_fetchers.iter().find(|(m, _)| m == method) {
+                return fetcher.fetch(signature).await;
+            }
+        }
+        Err(Error::UnsupportedQueryMethod)
+    }
+
+    /// Validates the t= and x= tags, returning whether clock skew tolerance was needed.
+    #[inline(always)]
+    pub(crate) fn validate_timestamps(&self, t: u64, x: u64, now: u64) -> crate::Result<bool> {
+        validate_timestamps(self.max_clock_skew, t, x, now)
+    }
+}
+
+pub(crate) fn validate_timestamps(
+    max_clock_skew: Option<u64>,
+    t: u64,
+    x: u64,
+    now: u64,
+) -> crate::Result<bool> {
+    let skew = max_clock_skew.unwrap_or(0);
+
+    if x != 0 && (x <= t || x.saturating_add(skew) <= now) {
+        Err(Error::SignatureExpired)
+    } else if max_clock_skew.is_some() && t > now.saturating_add(skew) {
+        Err(Error::FutureSignature)
+    } else {
+        Ok(max_clock_skew.is_some() && (t > now || (x != 0 && x <= now)))
+    }
+}
+
+impl DkimVerifyStrategy {
+    fn is_satisfied(&self, output: &DkimOutput<'_>, resolver: &Resolver) -> bool {
+        match self {
+            DkimVerifyStrategy::Full => false,
+            DkimVerifyStrategy::FirstPass => output.result == DkimResult::Pass,
+            DkimVerifyStrategy::FirstAlignedPass(from_domain) => {
+                output.is_aligned_with(from_domain, false, resolver.suffix_list.as_ref())
+            }
+        }
+    }
 }
 
 impl<'x> AuthenticatedMessage<'x> {
+    /// Returns the body hash computed for the canonicalization, algorithm and length of a signature.
+    pub(crate) fn signature_body_hash(&self, signature: &Signature) -> &[u8] {
+        self.body_hash_for(signature.cb, signature.a.into(), signature.l)
+            .unwrap_or_default()
+    }
+
     // Returns a vector representing the canonicalized headers, and the signed + hashed canonicalized headers
     pub fn get_canonicalized_header(&self) -> Result<(Vec<u8>, &[u8]), Error> {
         // Based on verify_dkim_ function
@@ -310,6 +671,168 @@ impl<'x> AuthenticatedMessage<'x> {
 }
 
 impl Signature {
+    /// Verifies the signature against a known public key without any DNS lookups.
+    ///
+    /// The same checks as [`Resolver::verify_dkim`] are performed using its default
+    /// settings, except for ATPS authorization which requires DNS. The signature
+    /// has to be one of the DKIM-Signature headers of `message`.
+    pub fn verify_offline(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        record: &DomainKey,
+    ) -> DkimResult {
+        self.verify_offline_(
+            message,
+            record,
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        )
+    }
+
+    pub(crate) fn verify_offline_(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        record: &DomainKey,
+        now: u64,
+    ) -> DkimResult {
+        let header = match message
+            .dkim_headers
+            .iter()
+            .find(|header| header.header.as_ref().map_or(false, |s| s == self))
+        {
+            Some(header) => header,
+            None => return DkimResult::PermError(Error::NoHeadersFound),
+        };
+        let min_key_strength = MinimumKeyStrength::default();
+
+        if let Err(result) = self.validate(None, &min_key_strength, IdentityMatch::default(), now) {
+            result
+        } else if message.signature_body_hash(self) != self.bh {
+            DkimResult::Neutral(Error::FailedBodyHashMatch)
+        } else if let Err(result) = self.verify_with_key(
+            message,
+            header,
+            record,
+            &min_key_strength,
+            BodyLengthPolicy::default(),
+        ) {
+            result
+        } else {
+            DkimResult::Pass
+        }
+    }
+
+    /// Validates the timestamps, algorithm and identity, returning whether clock skew tolerance was needed.
+    pub(crate) fn validate(
+        &self,
+        max_clock_skew: Option<u64>,
+        min_key_strength: &MinimumKeyStrength,
+        identity_match: IdentityMatch,
+        now: u64,
+    ) -> Result<bool, DkimResult> {
+        let is_clock_skewed = validate_timestamps(max_clock_skew, self.t, self.x, now)
+            .map_err(DkimResult::Neutral)?;
+
+        // Reject rsa-sha1 signatures (RFC 8301)
+        if self.a == Algorithm::RsaSha1 && !min_key_strength.allow_sha1 {
+            Err(DkimResult::PermError(Error::WeakCryptography))
+        } else {
+            self.validate_identity(identity_match)?;
+            Ok(is_clock_skewed)
+        }
+    }
+
+    /// Validates that the domain of the i= identity is the d= domain or, unless
+    /// an exact match is required, one of its subdomains.
+    pub(crate) fn validate_identity(
+        &self,
+        identity_match: IdentityMatch,
+    ) -> Result<(), DkimResult> {
+        // Internationalized domains are compared in their A-label form
+        let auid_domain = match self.i.rsplit_once('@') {
+            Some((_, domain)) => to_ascii_domain(domain.trim_end_matches(&['>', '.'][..])),
+            None => return Ok(()),
+        };
+        let domain = to_ascii_domain(self.d.trim_end_matches('.'));
+        let (auid_domain, domain) = (auid_domain.as_bytes(), domain.as_bytes());
+
+        if auid_domain.eq_ignore_ascii_case(domain) {
+            Ok(())
+        } else if auid_domain.len() > domain.len() && {
+            let (subdomain, parent) = auid_domain.split_at(auid_domain.len() - domain.len());
+            subdomain.ends_with(b".") && parent.eq_ignore_ascii_case(domain)
+        } {
+            if identity_match == IdentityMatch::Exact {
+                Err(DkimResult::Neutral(Error::AuidNotExactDomain))
+            } else {
+                Ok(())
+            }
+        } else {
+            Err(DkimResult::PermError(Error::AuidNotInDomain))
+        }
+    }
+
+    /// Verifies the signature against its public key, returning the canonicalized body
+    /// length when the signature has an l= tag that the body length policy has to enforce.
+    pub(crate) fn verify_with_key(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        header: &Header<'_, crate::Result<Signature>>,
+        record: &DomainKey,
+        min_key_strength: &MinimumKeyStrength,
+        body_length_policy: BodyLengthPolicy,
+    ) -> Result<Option<u64>, DkimResult> {
+        // Reject undersized RSA keys
+        if self.a != Algorithm::Ed25519Sha256 && record.key_bits() < min_key_strength.min_rsa_bits {
+            return Err(DkimResult::PermError(Error::WeakCryptography));
+        }
+
+        // Enforce t=s flag
+        if !self.validate_auid(record) {
+            return Err(DkimResult::Fail(Error::FailedAuidMatch));
+        }
+
+        // Enforce g= granularity
+        if !self.validate_granularity(record) {
+            return Err(DkimResult::Fail(Error::KeyGranularityMismatch));
+        }
+
+        // Hash headers
+        let dkim_hdr_value = header.value.strip_signature();
+        let mut headers = message.signed_headers(&self.h, header.name, &dkim_hdr_value);
+
+        // Verify signature
+        record
+            .verify(&mut headers, self, self.ch)
+            .map_err(DkimResult::Fail)?;
+
+        // Enforce body length policy
+        let l = match self.l {
+            Some(l) if body_length_policy != BodyLengthPolicy::Allow => l,
+            _ => return Ok(None),
+        };
+        let body_length = message
+            .body_length_for(self.cb, self.a.into(), self.l)
+            .unwrap_or_else(|| {
+                self.cb
+                    .canonical_body(
+                        message
+                            .raw_message
+                            .get(message.body_offset..)
+                            .unwrap_or_default(),
+                        0,
+                    )
+                    .canonical_len()
+            });
+        if body_length_policy == BodyLengthPolicy::Reject && l < body_length {
+            Err(DkimResult::Neutral(Error::PartialBody))
+        } else {
+            Ok(Some(body_length))
+        }
+    }
+
     #[allow(clippy::while_let_on_iterator)]
     pub(crate) fn validate_auid(&self, record: &DomainKey) -> bool {
         // Enforce t=s flag
@@ -337,6 +860,10 @@ impl Signature {
 
         true
     }
+
+    pub(crate) fn validate_granularity(&self, record: &DomainKey) -> bool {
+        record.validate_granularity(self.i.rsplit_once('@').map_or("", |(l, _)| l))
+    }
 }
 
 pub(crate) trait Verifier: Sized {
@@ -393,9 +920,12 @@ mod test {
     };
 
     use crate::{
-        common::{parse::TxtRecordParser, verify::DomainKey},
+        common::{
+            parse::TxtRecordParser,
+            verify::{DomainKey, VerifySignature},
+        },
         dkim::verify::Verifier,
-        AuthenticatedMessage, DkimResult, Resolver,
+        AuthenticatedMessage, DkimResult, Error, Resolver,
     };
 
     #[tokio::test]
@@ -423,6 +953,138 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn dkim_verify_offline() {
+        let mut test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_dir.push("resources");
+        test_dir.push("dkim");
+
+        for file_name in fs::read_dir(&test_dir).unwrap() {
+            let file_name = file_name.unwrap().path();
+            println!("DKIM verifying offline {}", file_name.to_str().unwrap());
+
+            let test = String::from_utf8(fs::read(&file_name).unwrap()).unwrap();
+            let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+            let resolver = new_resolver(dns_records);
+            let raw_message = raw_message.replace('\n', "\r\n");
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+
+            // Results match the online verifier
+            let dkim = resolver.verify_dkim_(&message, 1667843664).await;
+            let mut verified = 0;
+            for output in &dkim {
+                let signature = match output.signature() {
+                    Some(signature) => signature,
+                    None => continue,
+                };
+                let record = dns_records
+                    .split('\n')
+                    .filter_map(|r| r.split_once(' '))
+                    .find(|(key, _)| format!("{key}.") == signature.domain_key())
+                    .map(|(_, value)| DomainKey::parse(value.as_bytes()).unwrap());
+                if let Some(record) = record {
+                    assert_eq!(
+                        &signature.verify_offline_(&message, &record, 1667843664),
+                        output.result()
+                    );
+                    verified += 1;
+                }
+            }
+            assert!(verified > 0);
+
+            // The signature has to belong to the message
+            let other =
+                AuthenticatedMessage::parse(b"From: jdoe@example.com\r\n\r\nHi\r\n").unwrap();
+            let signature = dkim.last().unwrap().signature().unwrap();
+            assert_eq!(
+                signature.verify_offline(
+                    &other,
+                    &DomainKey::parse(dns_records.split_once(' ').unwrap().1.as_bytes()).unwrap()
+                ),
+                DkimResult::PermError(Error::NoHeadersFound)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn domainkeys_verify() {
+        let mut test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_dir.push("resources");
+        test_dir.push("domainkeys");
+
+        for file_name in fs::read_dir(&test_dir).unwrap() {
+            let file_name = file_name.unwrap().path();
+            println!("DomainKeys verifying {}", file_name.to_str().unwrap());
+
+            let test = String::from_utf8(fs::read(&file_name).unwrap()).unwrap();
+            let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+            let raw_message = raw_message.replace('\n', "\r\n");
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+
+            // Disabled by default
+            let resolver = new_resolver(dns_records);
+            assert!(resolver.verify_dkim(&message).await.is_empty());
+
+            let resolver = resolver.with_domainkeys(true);
+            let dkim = resolver.verify_dkim(&message).await;
+            assert_eq!(dkim.len(), 1);
+            assert_eq!(dkim[0].result(), &DkimResult::Pass);
+            assert!(dkim[0].is_domainkeys());
+
+            let raw_message = raw_message.replace("hungry", "thirsty");
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+            assert_eq!(
+                resolver.verify_dkim(&message).await[0].result(),
+                &DkimResult::Fail(Error::FailedVerification)
+            );
+        }
+
+        // 004.txt is sent by a list on the signing domain for another From domain,
+        // so it only passes because of its Sender header
+        let test = String::from_utf8(fs::read(test_dir.join("004.txt")).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let raw_message = raw_message
+            .replace("Sender: Mailing List <list@lists.example.net>\n", "")
+            .replace('\n', "\r\n");
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        assert_eq!(
+            new_resolver(dns_records)
+                .with_domainkeys(true)
+                .verify_dkim(&message)
+                .await[0]
+                .result(),
+            &DkimResult::Fail(Error::FailedAuidMatch)
+        );
+    }
+
+    #[test]
+    fn dkim_unsigned_headers() {
+        let raw_message = concat!(
+            "Subject: Buy now\r\n",
+            "DKIM-Signature: v=1; a=rsa-sha256; d=example.com; s=default;\r\n",
+            "  h=From:To:Subject:Date:Date; bh=YWJj; b=YWJj\r\n",
+            "Received: from mx.example.com\r\n",
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "subject: TPS Report\r\n",
+            "Reply-To: bill@example.com\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP.\r\n"
+        );
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let signature = message.dkim_headers[0].header.as_ref().unwrap();
+
+        assert_eq!(
+            signature.signed_headers(),
+            ["From", "To", "Subject", "Date", "Date"]
+        );
+        // Only the bottom Subject header is signed
+        assert_eq!(
+            signature.unsigned_headers(&message),
+            ["Subject", "Received", "Reply-To"]
+        );
+    }
+
     #[test]
     fn dkim_strip_signature() {
         for (value, stripped_value) in [
@@ -0,0 +1,451 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::fmt::{Display, Write};
+
+use crate::Error;
+
+use super::{
+    parse::{decode_address, parse_size},
+    Alignment, Dmarc, Policy, Psd, Report, ReportUri, URI,
+};
+
+/// Builds a DMARC record, which is rendered with `to_string`.
+#[derive(Debug, Clone)]
+pub struct DmarcBuilder {
+    dmarc: Dmarc,
+    error: Option<Error>,
+}
+
+impl Dmarc {
+    pub fn builder() -> DmarcBuilder {
+        DmarcBuilder {
+            dmarc: Dmarc::default(),
+            error: None,
+        }
+    }
+}
+
+impl DmarcBuilder {
+    /// Sets the policy (p= tag), which is required.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.dmarc.p = policy;
+        self
+    }
+
+    /// Sets the policy for subdomains (sp= tag), which defaults to `policy`.
+    pub fn subdomain_policy(mut self, policy: Policy) -> Self {
+        self.dmarc.sp = policy;
+        self
+    }
+
+    /// Sets the policy for non-existent subdomains (np= tag), which defaults
+    /// to `subdomain_policy`.
+    pub fn nonexistent_subdomain_policy(mut self, policy: Policy) -> Self {
+        self.dmarc.np = policy;
+        self.dmarc.has_np = true;
+        self
+    }
+
+    pub fn adkim(mut self, alignment: Alignment) -> Self {
+        self.dmarc.adkim = alignment;
+        self
+    }
+
+    pub fn aspf(mut self, alignment: Alignment) -> Self {
+        self.dmarc.aspf = alignment;
+        self
+    }
+
+    /// Sets the percentage of failing messages the policy applies to, from 0 to 100.
+    pub fn pct(mut self, pct: u8) -> Self {
+        if pct <= 100 {
+            self.dmarc.pct = pct;
+            self.dmarc.deprecated_tags.insert("pct");
+        } else {
+            self.error.get_or_insert(Error::InvalidTag("pct"));
+        }
+        self
+    }
+
+    /// Sets the failure reporting options (fo= tag).
+    pub fn fo(mut self, options: impl IntoIterator<Item = Report>) -> Self {
+        self.dmarc.fo = options.into_iter().fold(0, |fo, option| fo | option as u8);
+        if self.dmarc.fo == 0 {
+            self.error.get_or_insert(Error::InvalidTag("fo"));
+        }
+        self
+    }
+
+    /// Sets the aggregate report interval in seconds, which may not be zero.
+    pub fn ri(mut self, ri: u32) -> Self {
+        if ri > 0 {
+            self.dmarc.ri = ri;
+            self.dmarc.deprecated_tags.insert("ri");
+        } else {
+            self.error.get_or_insert(Error::InvalidTag("ri"));
+        }
+        self
+    }
+
+    /// Sets the aggregate report destinations, such as `mailto:dmarc@example.org`
+    /// optionally followed by a size limit like `!10m`. Characters such as `,`
+    /// and `!` may be written either as-is or percent-encoded.
+    pub fn rua<'x>(mut self, uris: impl IntoIterator<Item = &'x str>) -> Self {
+        match parse_uris("rua", uris) {
+            Ok(uris) => self.dmarc.rua = uris,
+            Err(err) => {
+                self.error.get_or_insert(err);
+            }
+        }
+        self
+    }
+
+    /// Sets the failure report destinations, in the same format as `rua`.
+    pub fn ruf<'x>(mut self, uris: impl IntoIterator<Item = &'x str>) -> Self {
+        match parse_uris("ruf", uris) {
+            Ok(uris) => self.dmarc.ruf = uris,
+            Err(err) => {
+                self.error.get_or_insert(err);
+            }
+        }
+        self
+    }
+
+    /// Sets whether the record is published by a public suffix domain (psd= tag).
+    pub fn psd(mut self, psd: Psd) -> Self {
+        self.dmarc.psd = psd;
+        self
+    }
+
+    /// Sets the testing flag (t= tag).
+    pub fn testing(mut self, testing: bool) -> Self {
+        self.dmarc.t = testing;
+        self
+    }
+
+    /// Validates the record, which requires a policy.
+    pub fn build(self) -> crate::Result<Dmarc> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        let mut dmarc = self.dmarc;
+        if dmarc.p == Policy::Unspecified {
+            return Err(Error::MissingParameters);
+        }
+        if dmarc.sp == Policy::Unspecified {
+            dmarc.sp = dmarc.p;
+        }
+        if dmarc.np == Policy::Unspecified {
+            dmarc.np = dmarc.sp;
+        }
+
+        Ok(dmarc)
+    }
+}
+
+/// Parses the destinations of the `rua` or `ruf` tags, failing with an
+/// `InvalidTag` error for anything other than a valid `mailto` address.
+fn parse_uris<'x>(
+    tag: &'static str,
+    uris: impl IntoIterator<Item = &'x str>,
+) -> crate::Result<Vec<ReportUri>> {
+    let mut result = Vec::new();
+    for uri in uris {
+        let uri = uri.trim();
+        if uri.contains(char::is_whitespace) {
+            return Err(Error::InvalidTag(tag));
+        }
+
+        // The size limit follows the last `!`, which is otherwise part of the address
+        let (uri, max_size) = match uri
+            .rsplit_once('!')
+            .and_then(|(uri, size)| Some((uri, parse_size(size)?)))
+        {
+            Some((uri, max_size)) => (uri, Some(max_size)),
+            None => (uri, None),
+        };
+        let uri = match uri.split_once(':') {
+            Some((scheme, address)) if scheme.eq_ignore_ascii_case("mailto") => ReportUri {
+                scheme: "mailto".to_string(),
+                address: decode_address(address).map_err(|_| Error::InvalidTag(tag))?,
+                max_size,
+            },
+            _ => return Err(Error::InvalidTag(tag)),
+        };
+        if uri.is_mailto() {
+            result.push(uri);
+        } else {
+            return Err(Error::InvalidTag(tag));
+        }
+    }
+    Ok(result)
+}
+
+impl Display for Dmarc {
+    /// Renders the record with `v=DMARC1` and `p=` first, omitting tags
+    /// that have their default value unless they are deprecated tags
+    /// present in the record.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("v=DMARC1")?;
+        if self.p != Policy::Unspecified {
+            write!(f, "; p={}", self.p)?;
+        }
+        if self.sp != self.p {
+            write!(f, "; sp={}", self.sp)?;
+        }
+        if self.np != self.sp || self.has_np {
+            write!(f, "; np={}", self.np)?;
+        }
+        if self.adkim == Alignment::Strict {
+            f.write_str("; adkim=s")?;
+        }
+        if self.aspf == Alignment::Strict {
+            f.write_str("; aspf=s")?;
+        }
+        if self.pct != 100 || self.deprecated_tags.contains("pct") {
+            write!(f, "; pct={}", self.pct)?;
+        }
+        if self.deprecated_tags.contains("rf") {
+            f.write_str("; rf=afrf")?;
+        }
+        if self.fo != Report::All as u8 {
+            for (pos, option) in self.fo_options().into_iter().enumerate() {
+                f.write_str(if pos > 0 { ":" } else { "; fo=" })?;
+                write!(f, "{option}")?;
+            }
+        }
+        if self.ri != 86400 || self.deprecated_tags.contains("ri") {
+            write!(f, "; ri={}", self.ri)?;
+        }
+        for (tag, uris) in [("rua", &self.rua), ("ruf", &self.ruf)] {
+            if !uris.is_empty() {
+                write!(f, "; {tag}=")?;
+                for (pos, uri) in uris.iter().enumerate() {
+                    if pos > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{uri}")?;
+                }
+            }
+        }
+        match self.psd {
+            Psd::Yes => f.write_str("; psd=y")?,
+            Psd::No => f.write_str("; psd=n")?,
+            Psd::Default => (),
+        }
+        if self.t {
+            f.write_str("; t=y")?;
+        }
+        for (name, value) in &self.unknown_tags {
+            write!(f, "; {name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for URI {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ReportUri::from(self).fmt(f)
+    }
+}
+
+impl Display for ReportUri {
+    /// Renders the destination with a size limit in the largest exact unit.
+    /// Addresses of `mailto` destinations are percent-encoded, while other
+    /// schemes are written verbatim.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.scheme == "mailto" {
+            f.write_str("mailto:")?;
+            for ch in self.address.chars() {
+                match ch {
+                    ',' | ';' | '!' | '%' => write!(f, "%{:02X}", ch as u32)?,
+                    _ => f.write_char(ch)?,
+                }
+            }
+        } else if self.scheme.is_empty() {
+            f.write_str(&self.address)?;
+        } else {
+            write!(f, "{}:{}", self.scheme, self.address)?;
+        }
+        match self.max_size {
+            None => Ok(()),
+            Some(0) => f.write_str("!0"),
+            Some(size) if size % (1024 * 1024 * 1024 * 1024) == 0 => {
+                write!(f, "!{}t", size / (1024 * 1024 * 1024 * 1024))
+            }
+            Some(size) if size % (1024 * 1024 * 1024) == 0 => {
+                write!(f, "!{}g", size / (1024 * 1024 * 1024))
+            }
+            Some(size) if size % (1024 * 1024) == 0 => write!(f, "!{}m", size / (1024 * 1024)),
+            Some(size) if size % 1024 == 0 => write!(f, "!{}k", size / 1024),
+            Some(size) => write!(f, "!{size}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use crate::{
+        dmarc::{Alignment, Dmarc, Policy, Psd, Report},
+        Error,
+    };
+
+    #[test]
+    fn dmarc_builder() {
+        let dmarc = Dmarc::builder()
+            .policy(Policy::Quarantine)
+            .subdomain_policy(Policy::Reject)
+            .adkim(Alignment::Strict)
+            .pct(50)
+            .rua([
+                "mailto:agg@example.com",
+                "MAILTO:Third@Party.example.net!10m",
+            ])
+            .ruf(["mailto:fail@example.com!512"])
+            .fo([Report::Any])
+            .ri(3600)
+            .build()
+            .unwrap();
+        let record = concat!(
+            "v=DMARC1; p=quarantine; sp=reject; adkim=s; pct=50; fo=1; ri=3600; ",
+            "rua=mailto:agg@example.com,mailto:Third@party.example.net!10m; ",
+            "ruf=mailto:fail@example.com!512"
+        );
+        assert_eq!(dmarc.to_string(), record);
+        assert_eq!(Dmarc::from_str(record).unwrap(), dmarc);
+
+        // Defaults are omitted
+        let dmarc = Dmarc::builder().policy(Policy::None).build().unwrap();
+        assert_eq!(dmarc.to_string(), "v=DMARC1; p=none");
+        assert_eq!(Dmarc::from_str("v=DMARC1; p=none").unwrap(), dmarc);
+        let dmarc = Dmarc::builder()
+            .policy(Policy::Reject)
+            .nonexistent_subdomain_policy(Policy::Quarantine)
+            .aspf(Alignment::Strict)
+            .fo([Report::Dkim, Report::Spf])
+            .psd(Psd::Yes)
+            .testing(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            dmarc.to_string(),
+            "v=DMARC1; p=reject; np=quarantine; aspf=s; fo=d:s; psd=y; t=y"
+        );
+        assert_eq!(Dmarc::from_str(&dmarc.to_string()).unwrap(), dmarc);
+
+        // Reserved characters are accepted as-is or percent-encoded
+        let dmarc = Dmarc::builder()
+            .policy(Policy::Reject)
+            .rua(["mailto:Ops,DMARC@example.com", "mailto:a!b@example.com!1k"])
+            .ruf([
+                "mailto:user%2Creports@example.com",
+                "mailto:100%25@example.com",
+            ])
+            .build()
+            .unwrap();
+        let record = concat!(
+            "v=DMARC1; p=reject; rua=mailto:Ops%2CDMARC@example.com,mailto:a%21b@example.com!1k; ",
+            "ruf=mailto:user%2Creports@example.com,mailto:100%25@example.com"
+        );
+        assert_eq!(dmarc.to_string(), record);
+        assert_eq!(Dmarc::from_str(record).unwrap(), dmarc);
+        assert_eq!(dmarc.rua_uris()[0].address(), "Ops,DMARC@example.com");
+        assert_eq!(dmarc.rua_uris()[1].max_size(), Some(1024));
+
+        // Invalid values
+        for (builder, expected_err) in [
+            (Dmarc::builder(), Error::MissingParameters),
+            (
+                Dmarc::builder().policy(Policy::Reject).pct(101),
+                Error::InvalidTag("pct"),
+            ),
+            (
+                Dmarc::builder().policy(Policy::Reject).ri(0),
+                Error::InvalidTag("ri"),
+            ),
+            (
+                Dmarc::builder()
+                    .policy(Policy::Reject)
+                    .fo(Vec::<Report>::new()),
+                Error::InvalidTag("fo"),
+            ),
+            (
+                Dmarc::builder()
+                    .policy(Policy::Reject)
+                    .rua(["agg@example.com"]),
+                Error::InvalidTag("rua"),
+            ),
+            (
+                Dmarc::builder()
+                    .policy(Policy::Reject)
+                    .rua(["https://example.com/reports"]),
+                Error::InvalidTag("rua"),
+            ),
+            (
+                Dmarc::builder()
+                    .policy(Policy::Reject)
+                    .rua(["mailto:example.com"]),
+                Error::InvalidTag("rua"),
+            ),
+            (
+                Dmarc::builder()
+                    .policy(Policy::Reject)
+                    .rua(["mailto:agg@example.com; p=none"]),
+                Error::InvalidTag("rua"),
+            ),
+            (
+                Dmarc::builder()
+                    .policy(Policy::Reject)
+                    .ruf(["mailto:agg%2@example.com"]),
+                Error::InvalidTag("ruf"),
+            ),
+            (
+                Dmarc::builder()
+                    .policy(Policy::Reject)
+                    .ruf(["mailto:agg@example.com!10x"]),
+                Error::InvalidTag("ruf"),
+            ),
+        ] {
+            assert_eq!(builder.build(), Err(expected_err));
+        }
+    }
+
+    #[test]
+    fn dmarc_to_string() {
+        for (record, expected_record) in [
+            (
+                "v=DMARC1;p=Reject;rua=mailto:A@Example.com!10K,mailto:b@example.com ; fo=s:d",
+                "v=DMARC1; p=reject; fo=d:s; rua=mailto:A@example.com!10k,mailto:b@example.com",
+            ),
+            (
+                "v=DMARC1; p=none; sp=none; np=none; adkim=r; aspf=r; fo=0",
+                "v=DMARC1; p=none; np=none",
+            ),
+            // Deprecated tags are kept, even with their default value
+            (
+                "v=DMARC1; p=none; pct=100; rf=AFRF; ri=86400",
+                "v=DMARC1; p=none; pct=100; rf=afrf; ri=86400",
+            ),
+            (
+                "v=DMARC1; p=reject; ruf=mailto:user%2Creports@example.com; psd=y; ext=1",
+                "v=DMARC1; p=reject; ruf=mailto:user%2Creports@example.com; psd=y; ext=1",
+            ),
+            ("v=DMARC1", "v=DMARC1"),
+        ] {
+            let dmarc = Dmarc::from_str(record).unwrap();
+            assert_eq!(dmarc.to_string(), expected_record, "{record}");
+            assert_eq!(Dmarc::from_str(expected_record).unwrap(), dmarc, "{record}");
+        }
+    }
+}
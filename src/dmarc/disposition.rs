@@ -0,0 +1,406 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::fmt::Write;
+
+use crate::{
+    report::{PolicyOverride, PolicyOverrideReason},
+    DmarcOutput, DmarcResult,
+};
+
+use super::Policy;
+
+/// Action to take on a message after applying `pct` sampling and local
+/// policy overrides, along with the reasons to include in aggregate reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmarcDisposition {
+    pub(crate) policy: Policy,
+    pub(crate) reasons: Vec<PolicyOverrideReason>,
+}
+
+impl DmarcOutput {
+    /// Returns the action to take on the message. Messages that pass DMARC get
+    /// `Policy::None`, and failing messages get the published policy after `pct`
+    /// sampling, or the next weaker policy for records in testing mode (`t=y`).
+    /// Trusted ARC results and the local `overrides` supplied by the receiver,
+    /// either here or with `add_override`, such as `PolicyOverride::MailingList`,
+    /// lift the policy.
+    pub fn disposition(
+        &self,
+        overrides: impl IntoIterator<Item = PolicyOverrideReason>,
+    ) -> DmarcDisposition {
+        let mut policy =
+            if self.dkim_result == DmarcResult::Pass || self.spf_result == DmarcResult::Pass {
+                Policy::None
+            } else {
+                match self.effective_policy() {
+                    Policy::Unspecified => Policy::None,
+                    policy => policy,
+                }
+            };
+        let mut reasons = Vec::new();
+
+        if self.is_sampled_out() {
+            let reason = PolicyOverrideReason::new(PolicyOverride::SampledOut);
+            reasons.push(if self.is_testing() {
+                reason.with_comment("t=y")
+            } else {
+                reason
+            });
+        }
+        if let Some(arc_override) = &self.arc_override {
+            let mut comment = "arc=pass".to_string();
+            for (i, sealer) in arc_override.sealers.iter().enumerate().rev() {
+                write!(&mut comment, " as[{}].d={}", i + 1, sealer).ok();
+            }
+            policy = Policy::None;
+            reasons
+                .push(PolicyOverrideReason::new(PolicyOverride::Forwarded).with_comment(comment));
+        }
+        for (reason, override_policy) in self
+            .overrides
+            .iter()
+            .cloned()
+            .chain(overrides.into_iter().map(|reason| (reason, Policy::None)))
+        {
+            if severity(override_policy) < severity(policy) {
+                policy = override_policy;
+            }
+            if !reasons.contains(&reason) {
+                reasons.push(reason);
+            }
+        }
+
+        DmarcDisposition { policy, reasons }
+    }
+
+    /// Records a local policy override, such as a known mailing list, which
+    /// lifts the policy and is reported as a `reason` in aggregate reports.
+    pub fn add_override(&mut self, reason: PolicyOverride, comment: Option<&str>) {
+        self.add_override_with_policy(reason, comment, Policy::None);
+    }
+
+    /// Records a local policy override that applies `policy` instead of the
+    /// published one. Overrides never make the disposition more severe, so
+    /// a `reject` override of a `quarantine` policy is only reported.
+    pub fn add_override_with_policy(
+        &mut self,
+        reason: PolicyOverride,
+        comment: Option<&str>,
+        policy: Policy,
+    ) {
+        let reason = match comment {
+            Some(comment) => PolicyOverrideReason::new(reason).with_comment(comment),
+            None => PolicyOverrideReason::new(reason),
+        };
+        self.overrides.push((reason, policy));
+    }
+
+    /// Returns the local policy overrides added with `add_override`.
+    pub fn overrides(&self) -> impl Iterator<Item = &PolicyOverrideReason> {
+        self.overrides.iter().map(|(reason, _)| reason)
+    }
+}
+
+fn severity(policy: Policy) -> u8 {
+    match policy {
+        Policy::None | Policy::Unspecified => 0,
+        Policy::Quarantine => 1,
+        Policy::Reject => 2,
+    }
+}
+
+impl DmarcDisposition {
+    /// Returns the final action, which is `none`, `quarantine` or `reject`.
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Returns the policy override reasons, ready to be added to an
+    /// aggregate report row.
+    pub fn reasons(&self) -> &[PolicyOverrideReason] {
+        &self.reasons
+    }
+
+    pub fn into_reasons(self) -> Vec<PolicyOverrideReason> {
+        self.reasons
+    }
+
+    pub fn is_overridden(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{str::FromStr, sync::Arc};
+
+    use crate::{
+        arc::{ArcOverride, Results},
+        dmarc::{Dmarc, Policy},
+        report::{ActionDisposition, PolicyOverride, PolicyOverrideReason, Record, Report},
+        DmarcOutput, DmarcResult, Error,
+    };
+
+    fn dmarc_output(record: &str, policy: Policy) -> DmarcOutput {
+        DmarcOutput {
+            policy,
+            dkim_result: DmarcResult::Fail(Error::NotAligned),
+            spf_result: DmarcResult::Fail(Error::NotAligned),
+            ..Default::default()
+        }
+        .with_domain("example.org")
+        .with_record(Arc::new(Dmarc::from_str(record).unwrap()), "example.org")
+    }
+
+    #[test]
+    fn dmarc_disposition() {
+        // Failing messages get the published policy
+        let output = dmarc_output("v=DMARC1; p=reject", Policy::Reject);
+        let disposition = output.disposition([]);
+        assert_eq!(disposition.policy(), Policy::Reject);
+        assert!(!disposition.is_overridden());
+
+        // Sampled out failures get the next weaker policy
+        let output = dmarc_output("v=DMARC1; p=reject; pct=0", Policy::Reject)
+            .with_sampled_policy("<1@example.org>");
+        let disposition = output.disposition([]);
+        assert_eq!(disposition.policy(), Policy::Quarantine);
+        assert_eq!(
+            disposition.reasons(),
+            &[PolicyOverrideReason::new(PolicyOverride::SampledOut)]
+        );
+
+        // Local overrides lift the policy
+        let disposition = output
+            .disposition([PolicyOverrideReason::new(PolicyOverride::MailingList)
+                .with_comment("list.example.org")]);
+        assert_eq!(disposition.policy(), Policy::None);
+        assert_eq!(
+            disposition.into_reasons(),
+            vec![
+                PolicyOverrideReason::new(PolicyOverride::SampledOut),
+                PolicyOverrideReason::new(PolicyOverride::MailingList)
+                    .with_comment("list.example.org")
+            ]
+        );
+
+        // Trusted ARC results lift the policy
+        let arc_output = dmarc_output("v=DMARC1; p=quarantine", Policy::Quarantine)
+            .with_arc_override(ArcOverride {
+                domain: "example.org".to_string(),
+                sealers: vec!["forwarder.org".to_string(), "list.example.net".to_string()],
+                results: Results {
+                    i: 1,
+                    authserv_id: "forwarder.org".to_string(),
+                    results: vec![],
+                    is_malformed: false,
+                },
+            });
+        let disposition =
+            arc_output.disposition([PolicyOverrideReason::new(PolicyOverride::TrustedForwarder)]);
+        assert_eq!(disposition.policy(), Policy::None);
+        assert_eq!(
+            disposition.reasons(),
+            &[
+                PolicyOverrideReason::new(PolicyOverride::Forwarded)
+                    .with_comment("arc=pass as[2].d=list.example.net as[1].d=forwarder.org"),
+                PolicyOverrideReason::new(PolicyOverride::TrustedForwarder)
+            ]
+        );
+        let record = Record::new().with_dmarc_output(&arc_output);
+        assert_eq!(record.action_disposition(), ActionDisposition::None);
+        assert_eq!(record.policy_override_reason(), &disposition.reasons()[..1]);
+
+        // Passing messages are delivered
+        let output = DmarcOutput {
+            dkim_result: DmarcResult::Pass,
+            ..dmarc_output("v=DMARC1; p=reject", Policy::Reject)
+        };
+        assert_eq!(output.disposition([]).policy(), Policy::None);
+        assert_eq!(
+            Record::new()
+                .with_dmarc_output(&output)
+                .action_disposition(),
+            ActionDisposition::Pass
+        );
+    }
+
+    #[test]
+    fn dmarc_local_overrides() {
+        // Overrides lift the policy and are reported
+        let mut output = dmarc_output("v=DMARC1; p=reject", Policy::Reject);
+        output.add_override(
+            PolicyOverride::MailingList,
+            Some("listserv.example.edu detected via List-Id"),
+        );
+        output.add_override(PolicyOverride::TrustedForwarder, None);
+        let disposition = output.disposition([]);
+        assert_eq!(disposition.policy(), Policy::None);
+        assert_eq!(
+            disposition.reasons(),
+            &[
+                PolicyOverrideReason::new(PolicyOverride::MailingList)
+                    .with_comment("listserv.example.edu detected via List-Id"),
+                PolicyOverrideReason::new(PolicyOverride::TrustedForwarder)
+            ]
+        );
+        assert_eq!(output.overrides().count(), 2);
+
+        let record = Record::new().with_dmarc_output(&output);
+        assert_eq!(record.action_disposition(), ActionDisposition::None);
+        assert_eq!(record.policy_override_reason(), disposition.reasons());
+        let xml = Report::new().with_record(record).to_xml();
+        for expected in [
+            concat!(
+                "\t\t\t\t<reason>\n",
+                "\t\t\t\t\t<type>mailing_list</type>\n",
+                "\t\t\t\t\t<comment>listserv.example.edu detected via List-Id</comment>\n",
+                "\t\t\t\t</reason>\n",
+            ),
+            concat!(
+                "\t\t\t\t<reason>\n",
+                "\t\t\t\t\t<type>trusted_forwarder</type>\n",
+                "\t\t\t\t</reason>\n",
+            ),
+        ] {
+            assert!(xml.contains(expected), "{xml}");
+        }
+
+        // Overrides may apply a weaker policy, but never a more severe one
+        for (record, policy, override_policy, expected_policy) in [
+            (
+                "v=DMARC1; p=reject",
+                Policy::Reject,
+                Policy::Quarantine,
+                Policy::Quarantine,
+            ),
+            (
+                "v=DMARC1; p=quarantine",
+                Policy::Quarantine,
+                Policy::Reject,
+                Policy::Quarantine,
+            ),
+            (
+                "v=DMARC1; p=none",
+                Policy::None,
+                Policy::Reject,
+                Policy::None,
+            ),
+        ] {
+            let mut output = dmarc_output(record, policy);
+            output.add_override_with_policy(
+                PolicyOverride::LocalPolicy,
+                Some("internal relay"),
+                override_policy,
+            );
+            let disposition = output.disposition([]);
+            assert_eq!(disposition.policy(), expected_policy, "{record}");
+            assert_eq!(
+                disposition.reasons(),
+                &[PolicyOverrideReason::new(PolicyOverride::LocalPolicy)
+                    .with_comment("internal relay")],
+                "{record}"
+            );
+        }
+
+        // Passing messages are never made more severe
+        let mut output = DmarcOutput {
+            dkim_result: DmarcResult::Pass,
+            ..dmarc_output("v=DMARC1; p=reject", Policy::Reject)
+        };
+        output.add_override_with_policy(PolicyOverride::LocalPolicy, None, Policy::Reject);
+        assert_eq!(output.disposition([]).policy(), Policy::None);
+    }
+
+    #[test]
+    fn dmarc_testing_mode() {
+        for (record, expected_testing) in [
+            ("v=DMARC1; p=reject; t=y", true),
+            ("v=DMARC1; p=reject; t=Y", true),
+            ("v=DMARC1; p=reject; t=n", false),
+            ("v=DMARC1; p=reject", false),
+        ] {
+            assert_eq!(
+                Dmarc::from_str(record).unwrap().is_testing(),
+                expected_testing,
+                "{record}"
+            );
+        }
+
+        // Testing mode is treated as pct=0, keeping the published policy
+        let output = dmarc_output("v=DMARC1; p=reject; t=y", Policy::Reject);
+        let disposition = output.disposition([]);
+        assert_eq!(output.policy(), Policy::Reject);
+        assert_eq!(disposition.policy(), Policy::Quarantine);
+        assert_eq!(
+            disposition.reasons(),
+            &[PolicyOverrideReason::new(PolicyOverride::SampledOut).with_comment("t=y")]
+        );
+        let record = Record::new().with_dmarc_output(&output);
+        assert_eq!(record.action_disposition(), ActionDisposition::Quarantine);
+        let output = dmarc_output("v=DMARC1; p=quarantine; t=y", Policy::Quarantine);
+        assert_eq!(output.disposition([]).policy(), Policy::None);
+
+        // t=y takes precedence over pct, which applies otherwise
+        for (record, policy, expected_policy) in [
+            (
+                "v=DMARC1; p=reject; pct=100; t=y",
+                Policy::Reject,
+                Policy::Quarantine,
+            ),
+            (
+                "v=DMARC1; p=reject; pct=0; t=n",
+                Policy::Reject,
+                Policy::Quarantine,
+            ),
+            (
+                "v=DMARC1; p=reject; pct=100; t=n",
+                Policy::Reject,
+                Policy::Reject,
+            ),
+        ] {
+            let output = dmarc_output(record, policy);
+            for seed in 0..100 {
+                assert_eq!(
+                    output.sampled_policy(seed.to_string()),
+                    expected_policy,
+                    "{record}"
+                );
+            }
+            assert_eq!(
+                output.with_random_sampled_policy().disposition([]).policy(),
+                expected_policy,
+                "{record}"
+            );
+        }
+
+        // Tags removed by DMARCbis are still parsed
+        for (record, expected_tags) in [
+            (
+                "v=DMARC1; p=reject; pct=50; rf=afrf; ri=3600",
+                vec!["pct", "rf", "ri"],
+            ),
+            // Tags are reported when present, even with their default value
+            (
+                "v=DMARC1; p=reject; ri=86400; pct=100; rf=afrf",
+                vec!["pct", "rf", "ri"],
+            ),
+            ("v=DMARC1; p=reject; rf=iodef", vec!["rf"]),
+            ("v=DMARC1; p=reject; t=y", vec![]),
+        ] {
+            assert_eq!(
+                Dmarc::from_str(record).unwrap().deprecated_tags(),
+                expected_tags,
+                "{record}"
+            );
+        }
+    }
+}
@@ -8,13 +8,23 @@
  * except according to those terms.
  */
 
-use std::{fmt::Display, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    sync::Arc,
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{DmarcOutput, DmarcResult, Error, Version};
+use crate::{
+    arc::ArcOverride, dkim::Signature, is_within_pct, AuthenticatedMessage, DkimOutput, DkimResult,
+    DmarcOutput, DmarcResult, Error, SpfOutput, SpfResult, Version,
+};
 
+pub mod builder;
+pub mod disposition;
 pub mod parse;
+pub mod psl;
 pub mod verify;
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
@@ -22,17 +32,20 @@ pub struct Dmarc {
     pub(crate) v: Version,
     pub(crate) adkim: Alignment,
     pub(crate) aspf: Alignment,
-    pub(crate) fo: Report,
+    pub(crate) fo: u8,
     pub(crate) np: Policy,
+    pub(crate) has_np: bool,
     pub(crate) p: Policy,
     pub(crate) psd: Psd,
     pub(crate) pct: u8,
     pub(crate) rf: u8,
     pub(crate) ri: u32,
-    pub(crate) rua: Vec<URI>,
-    pub(crate) ruf: Vec<URI>,
+    pub(crate) rua: Vec<ReportUri>,
+    pub(crate) ruf: Vec<ReportUri>,
     pub(crate) sp: Policy,
     pub(crate) t: bool,
+    pub(crate) unknown_tags: BTreeMap<String, String>,
+    pub(crate) deprecated_tags: BTreeSet<&'static str>,
 }
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -42,26 +55,109 @@ pub struct URI {
     pub max_size: usize,
 }
 
-#[derive(Debug, Hash, Clone, PartialEq, Eq)]
-pub(crate) enum Alignment {
+/// Report destination listed in the `rua=` or `ruf=` tags, with percent-encoding
+/// decoded. Destinations with schemes other than `mailto` are kept verbatim.
+#[derive(Debug, Hash, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportUri {
+    pub(crate) scheme: String,
+    pub(crate) address: String,
+    pub(crate) max_size: Option<u64>,
+}
+
+/// A passing DKIM signature and the alignment mode it was checked with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignedSignature {
+    domain: String,
+    selector: String,
+    mode: Alignment,
+}
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
     Relaxed,
     Strict,
 }
 
-#[derive(Debug, Hash, Clone, PartialEq, Eq)]
-pub(crate) enum Psd {
+/// Value of the `psd=` tag (RFC 9091).
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum Psd {
+    /// The record is published by a public suffix domain (`psd=y`).
     Yes,
+    /// The record is published by an organizational domain (`psd=n`).
     No,
+    /// The tag is absent or `psd=u`.
     Default,
 }
 
-#[derive(Debug, Hash, Clone, PartialEq, Eq)]
+/// Failure reporting option of the `fo=` tag, which may be combined.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
 pub enum Report {
-    All,
-    Any,
-    Dkim,
-    Spf,
-    DkimSpf,
+    /// Report when no underlying mechanism produces an aligned pass (`0`).
+    All = 1,
+    /// Report when any underlying mechanism fails to produce an aligned pass (`1`).
+    Any = 2,
+    /// Report DKIM signatures that fail verification, regardless of alignment (`d`).
+    Dkim = 4,
+    /// Report SPF failures, regardless of alignment (`s`).
+    Spf = 8,
+    /// Both the `d` and `s` options (`d:s`).
+    DkimSpf = 12,
+}
+
+/// Deviation from the DMARC record syntax, as reported by `Dmarc::lint`.
+/// Records with deviations are still used, as described for each of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxIssue {
+    /// The version is not written as `v=DMARC1`.
+    NonStandardVersion,
+    /// The tag is not defined by RFC 7489 or RFC 9091, and is ignored.
+    UnknownTag(String),
+    /// The tag appears more than once, and its last value is used.
+    DuplicateTag(String),
+    /// The tag has an invalid value, and its default value is used.
+    InvalidValue(String),
+    /// The report destination is not a valid `mailto` URI, and is ignored.
+    UnsupportedUri(String),
+    /// The `p=` tag is missing or invalid. Records with valid `rua=` destinations
+    /// are treated as `p=none`, while other records are unusable.
+    MissingPolicy,
+}
+
+/// Authentication mechanism used to evaluate a DMARC policy.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AuthMechanism {
+    Dkim = 1,
+    Spf = 2,
+}
+
+/// Authentication results to evaluate a DMARC policy with, for receivers
+/// that only run DKIM or SPF. Mechanisms that are not supplied cannot
+/// produce an aligned pass.
+#[derive(Debug, Clone)]
+pub struct DmarcInput<'x> {
+    pub(crate) from: DmarcFrom<'x>,
+    pub(crate) dkim_output: Option<&'x [DkimOutput<'x>]>,
+    pub(crate) spf_output: Option<(&'x str, &'x SpfOutput)>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum DmarcFrom<'x> {
+    Message(&'x AuthenticatedMessage<'x>),
+    Domain(&'x str),
+}
+
+/// Tag of the DMARC record that the policy of a message is taken from.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum PolicySource {
+    /// The record was found at the RFC5322.From domain, or no record was found.
+    P,
+    /// The record was found at a parent domain of the RFC5322.From domain.
+    Sp,
+    /// The record was found at a parent domain of an RFC5322.From domain
+    /// that does not exist (RFC 9091).
+    Np,
 }
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
@@ -72,12 +168,37 @@ pub enum Policy {
     Unspecified,
 }
 
+/// How DMARC records and organizational domains are discovered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DmarcDiscovery {
+    /// Records are queried at the RFC5322.From domain and then at its
+    /// organizational domain, which is given by the suffix list (RFC 7489).
+    #[default]
+    Rfc7489,
+    /// Records are queried by walking up the domain tree, and the organizational
+    /// domain is decided by the `psd=` tags of the records found (DMARCbis).
+    TreeWalk,
+}
+
+/// Whether a report destination outside the policy domain accepts its reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DestinationAuthorization {
+    Authorized,
+    Unauthorized,
+    /// The authorization record could not be retrieved due to a DNS error.
+    RetryLater,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(u8)]
 pub(crate) enum Format {
     Afrf = 1,
 }
 
+impl Report {
+    const ALL: [Report; 4] = [Report::All, Report::Any, Report::Dkim, Report::Spf];
+}
+
 impl From<Format> for u64 {
     fn from(f: Format) -> Self {
         f as u64
@@ -102,6 +223,85 @@ impl URI {
     }
 }
 
+impl ReportUri {
+    /// Creates a report destination from a scheme, a decoded address and an
+    /// optional size limit in bytes.
+    pub fn new(scheme: &str, address: &str, max_size: Option<u64>) -> Self {
+        ReportUri {
+            scheme: scheme.to_ascii_lowercase(),
+            address: address.to_string(),
+            max_size,
+        }
+    }
+
+    /// Returns the lowercase scheme, or an empty string when the URI has none.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Returns the email address of `mailto` destinations, or the rest of
+    /// the URI after the scheme otherwise.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the maximum report size in bytes, if any.
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    /// Returns whether this is a `mailto` destination with a valid address,
+    /// which is the only kind reports are sent to.
+    pub fn is_mailto(&self) -> bool {
+        self.scheme == "mailto" && is_valid_address(&self.address)
+    }
+}
+
+impl From<&URI> for ReportUri {
+    fn from(uri: &URI) -> Self {
+        ReportUri {
+            scheme: "mailto".to_string(),
+            address: uri.uri.clone(),
+            max_size: if uri.max_size > 0 {
+                Some(uri.max_size as u64)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl From<&ReportUri> for URI {
+    fn from(uri: &ReportUri) -> Self {
+        URI {
+            uri: uri.address.to_lowercase(),
+            max_size: uri
+                .max_size
+                .map_or(0, |size| usize::try_from(size).unwrap_or(usize::MAX)),
+        }
+    }
+}
+
+/// Returns whether an address has a local part and a domain made of
+/// letters, digits and hyphens.
+fn is_valid_address(address: &str) -> bool {
+    match address.rsplit_once('@') {
+        Some((local_part, domain)) => {
+            !local_part.is_empty()
+                && !local_part.contains(|ch: char| ch.is_whitespace() || ch == '@')
+                && domain.split('.').all(|label| {
+                    !label.is_empty()
+                        && !label.starts_with('-')
+                        && !label.ends_with('-')
+                        && label
+                            .chars()
+                            .all(|ch| ch.is_alphanumeric() || ch == '-' || ch == '_')
+                })
+        }
+        None => false,
+    }
+}
+
 impl From<Error> for DmarcResult {
     fn from(err: Error) -> Self {
         if matches!(&err, Error::DnsError(_)) {
@@ -118,8 +318,21 @@ impl Default for DmarcOutput {
             domain: String::new(),
             policy: Policy::None,
             record: None,
+            record_domain: None,
+            sampled_policy: None,
+            dkim_alignment: None,
+            spf_alignment: None,
+            dkim_misalignment: None,
+            spf_misalignment: None,
+            policy_source: PolicySource::P,
+            from_domains: Vec::new(),
             spf_result: DmarcResult::None,
             dkim_result: DmarcResult::None,
+            arc_override: None,
+            evaluated_with: AuthMechanism::Dkim as u8 | AuthMechanism::Spf as u8,
+            failed_with: 0,
+            is_psd_fallback: false,
+            overrides: Vec::new(),
         }
     }
 }
@@ -130,6 +343,27 @@ impl DmarcOutput {
         self
     }
 
+    pub(crate) fn with_from_domains(mut self, from_domains: &[&str]) -> Self {
+        self.from_domains = from_domains
+            .iter()
+            .map(|domain| domain.to_string())
+            .collect();
+        self
+    }
+
+    /// Returns a failing result with a `reject` policy for a message that
+    /// cannot be attributed to a single author domain.
+    pub(crate) fn unattributed(domain: &str, from_domains: &[&str], err: Error) -> Self {
+        DmarcOutput {
+            policy: Policy::Reject,
+            ..Default::default()
+        }
+        .with_domain(domain)
+        .with_from_domains(from_domains)
+        .with_dkim_result(DmarcResult::PermError(err.clone()))
+        .with_spf_result(DmarcResult::PermError(err))
+    }
+
     pub(crate) fn with_spf_result(mut self, result: DmarcResult) -> Self {
         self.spf_result = result;
         self
@@ -140,8 +374,19 @@ impl DmarcOutput {
         self
     }
 
-    pub(crate) fn with_record(mut self, record: Arc<Dmarc>) -> Self {
+    pub(crate) fn with_evaluated_with(mut self, evaluated_with: u8) -> Self {
+        self.evaluated_with = evaluated_with;
+        self
+    }
+
+    pub(crate) fn with_failed_with(mut self, failed_with: u8) -> Self {
+        self.failed_with = failed_with;
+        self
+    }
+
+    pub(crate) fn with_record(mut self, record: Arc<Dmarc>, record_domain: &str) -> Self {
         self.record = record.into();
+        self.record_domain = record_domain.to_string().into();
         self
     }
 
@@ -153,10 +398,103 @@ impl DmarcOutput {
         self.domain
     }
 
+    /// Returns the RFC5322.From domains the policy was evaluated for.
+    pub fn from_domains(&self) -> &[String] {
+        &self.from_domains
+    }
+
+    /// Returns the policy published for the RFC5322.From domain, taken from
+    /// the `p`, `sp` or `np` tag of the record as returned by `policy_source`.
     pub fn policy(&self) -> Policy {
         self.policy
     }
 
+    /// Returns the tag of the record that `policy` was taken from.
+    pub fn policy_source(&self) -> PolicySource {
+        self.policy_source
+    }
+
+    /// Returns whether the policy is the `np` policy of the record, which applies to
+    /// subdomains that do not exist (RFC 9091).
+    pub fn is_np_policy(&self) -> bool {
+        self.policy_source == PolicySource::Np
+    }
+
+    /// Returns the policy to apply after `pct` sampling, which is the published
+    /// policy unless sampling was applied with `with_sampled_policy` or
+    /// `with_random_sampled_policy`. Records in testing mode (`t=y`) are treated
+    /// as having `pct=0`, whether they were sampled or not. Messages that pass
+    /// DMARC are never sampled.
+    pub fn effective_policy(&self) -> Policy {
+        match self.sampled_policy {
+            _ if self.is_pass() => self.policy,
+            Some(policy) => policy,
+            None if self.is_testing() => self.weaker_policy(),
+            None => self.policy,
+        }
+    }
+
+    /// Returns whether `pct` sampling or testing mode downgraded the published policy.
+    pub fn is_sampled_out(&self) -> bool {
+        self.effective_policy() != self.policy
+    }
+
+    /// Returns whether the record is in testing mode (`t=y`).
+    pub fn is_testing(&self) -> bool {
+        self.record.as_ref().map_or(false, |record| record.t)
+    }
+
+    /// Applies the `pct` tag of the record to the published policy, deciding
+    /// deterministically from a seed such as the message or queue id, so that
+    /// retries of a message get the same decision. Messages outside the sample
+    /// get the next weaker policy.
+    pub fn sampled_policy(&self, seed: impl AsRef<[u8]>) -> Policy {
+        let bucket = (fnv1a(seed.as_ref()).wrapping_mul(11400714819323198485u64) >> 32) % 100;
+        self.apply_pct(|pct| bucket < pct as u64)
+    }
+
+    /// Same as `sampled_policy`, using a random decision.
+    pub fn random_sampled_policy(&self) -> Policy {
+        self.apply_pct(is_within_pct)
+    }
+
+    /// Records the policy returned by `sampled_policy` as the effective policy.
+    pub fn with_sampled_policy(mut self, seed: impl AsRef<[u8]>) -> Self {
+        self.sampled_policy = self.sampled_policy(seed).into();
+        self
+    }
+
+    /// Records the policy returned by `random_sampled_policy` as the effective policy.
+    pub fn with_random_sampled_policy(mut self) -> Self {
+        self.sampled_policy = self.random_sampled_policy().into();
+        self
+    }
+
+    /// Applies the `pct` tag, which is ignored with `t=y` since testing mode
+    /// takes precedence. Only messages failing DMARC are sampled.
+    fn apply_pct(&self, is_sampled: impl FnOnce(u8) -> bool) -> Policy {
+        match &self.record {
+            Some(record)
+                if !self.is_pass()
+                    && (record.t || (record.pct < 100 && !is_sampled(record.pct))) =>
+            {
+                self.weaker_policy()
+            }
+            _ => self.policy,
+        }
+    }
+
+    fn is_pass(&self) -> bool {
+        self.dkim_result == DmarcResult::Pass || self.spf_result == DmarcResult::Pass
+    }
+
+    fn weaker_policy(&self) -> Policy {
+        match self.policy {
+            Policy::Reject => Policy::Quarantine,
+            _ => Policy::None,
+        }
+    }
+
     pub fn dkim_result(&self) -> &DmarcResult {
         &self.dkim_result
     }
@@ -165,6 +503,16 @@ impl DmarcOutput {
         &self.spf_result
     }
 
+    /// Returns the `AuthMechanism` flags of the mechanisms whose results were
+    /// supplied, which are DKIM and SPF unless evaluated with a `DmarcInput`.
+    pub fn evaluated_with(&self) -> u8 {
+        self.evaluated_with
+    }
+
+    pub fn is_evaluated_with(&self, mechanism: AuthMechanism) -> bool {
+        self.evaluated_with & mechanism as u8 != 0
+    }
+
     pub fn dmarc_record(&self) -> Option<&Dmarc> {
         self.record.as_deref()
     }
@@ -173,44 +521,377 @@ impl DmarcOutput {
         self.record.clone()
     }
 
+    /// Returns the domain at which the DMARC record was found, which is either
+    /// the RFC5322.From domain or one of its parent domains.
+    pub fn record_domain(&self) -> Option<&str> {
+        self.record_domain.as_deref()
+    }
+
+    /// Returns whether the DMARC record was found at a parent domain rather
+    /// than at the RFC5322.From domain.
+    pub fn is_inherited_record(&self) -> bool {
+        self.record_domain.as_ref().map_or(false, |record_domain| {
+            !record_domain.eq_ignore_ascii_case(&self.domain)
+        })
+    }
+
+    /// Returns whether the policy was taken from the record of a public suffix
+    /// domain with `psd=y`, in the absence of a record for the organizational
+    /// domain (RFC 9091).
+    pub fn is_psd_policy(&self) -> bool {
+        self.is_psd_fallback
+    }
+
+    /// Returns whether the `sp` or `np` policy of the record was applied rather
+    /// than `p`, which happens when the record was found at a parent domain.
+    pub fn is_subdomain_policy(&self) -> bool {
+        self.policy_source != PolicySource::P
+    }
+
+    /// Overrides a DMARC failure with the results recorded by a trusted ARC sealer,
+    /// provided they are for the same RFC5322.From domain. Overrides are reported
+    /// as `forwarded` in aggregate reports.
+    pub fn with_arc_override(mut self, arc_override: ArcOverride) -> Self {
+        if self.dkim_result != DmarcResult::Pass
+            && self.spf_result != DmarcResult::Pass
+            && self.domain.eq_ignore_ascii_case(&arc_override.domain)
+        {
+            self.arc_override = arc_override.into();
+        }
+        self
+    }
+
+    /// Returns the ARC override applied to this result, if any.
+    pub fn arc_override(&self) -> Option<&ArcOverride> {
+        self.arc_override.as_ref()
+    }
+
+    /// Returns the signature that provided the aligned DKIM pass, where a `Strict`
+    /// mode means that its domain is the RFC5322.From domain.
+    pub fn dkim_alignment(&self) -> Option<&AlignedSignature> {
+        self.dkim_alignment.as_ref()
+    }
+
+    /// Returns how the SPF domain was aligned, where `Strict` means that it is
+    /// the RFC5322.From domain.
+    pub fn spf_alignment(&self) -> Option<Alignment> {
+        self.spf_alignment
+    }
+
+    /// Returns the passing DKIM signature that failed alignment, along with the
+    /// mode it failed, when no signature was aligned.
+    pub fn dkim_misalignment(&self) -> Option<&AlignedSignature> {
+        self.dkim_misalignment.as_ref()
+    }
+
+    /// Returns the passing SPF domain that failed alignment.
+    pub fn spf_misalignment(&self) -> Option<&str> {
+        self.spf_misalignment.as_deref()
+    }
+
+    /// Describes why passing DKIM and SPF results were not aligned, such as
+    /// `DKIM passed for esp.example but relaxed alignment with example.org failed`.
+    pub fn alignment_failure(&self) -> Option<String> {
+        let mut failures = Vec::new();
+        if let Some(signature) = &self.dkim_misalignment {
+            failures.push(format!(
+                "DKIM passed for {} but {} alignment with {} failed",
+                signature.domain, signature.mode, self.domain
+            ));
+        }
+        if let (Some(domain), Some(record)) = (&self.spf_misalignment, &self.record) {
+            failures.push(format!(
+                "SPF passed for {} but {} alignment with {} failed",
+                domain, record.aspf, self.domain
+            ));
+        }
+        if !failures.is_empty() {
+            Some(failures.join("; "))
+        } else {
+            None
+        }
+    }
+
     pub fn requested_reports(&self) -> bool {
         self.record
             .as_ref()
-            .map_or(false, |r| !r.rua.is_empty() || !r.ruf.is_empty())
+            .map_or(false, |r| has_mailto(&r.rua) || has_mailto(&r.ruf))
     }
 
-    /// Returns the failure reporting options
+    /// Returns the first failure reporting option triggered by the results, with
+    /// the `d` and `s` options checked against the DKIM and SPF results the
+    /// message was verified with, regardless of alignment.
     pub fn failure_report(&self) -> Option<Report> {
-        // Send failure reports
-        match &self.record {
-            Some(record)
-                if !record.ruf.is_empty()
-                    && ((self.dkim_result != DmarcResult::Pass
-                        && matches!(record.fo, Report::Any | Report::Dkim | Report::DkimSpf))
-                        || (self.spf_result != DmarcResult::Pass
-                            && matches!(
-                                record.fo,
-                                Report::Any | Report::Spf | Report::DkimSpf
-                            ))
-                        || (self.dkim_result != DmarcResult::Pass
-                            && self.spf_result != DmarcResult::Pass
-                            && record.fo == Report::All)) =>
-            {
-                Some(record.fo.clone())
-            }
-            _ => None,
+        self.triggered_failure_report(self.failed_with)
+    }
+
+    /// Returns whether a failure report should be sent to the `ruf=` destinations
+    /// of the record, following the `fo=` options (RFC 7489, Section 6.3). The `d`
+    /// and `s` options are checked against the DKIM and SPF results regardless
+    /// of alignment.
+    pub fn should_send_failure_report(&self, dkim: &[DkimOutput], spf: &SpfOutput) -> bool {
+        self.triggered_failure_report(failed_with(dkim, spf))
+            .is_some()
+    }
+
+    fn triggered_failure_report(&self, failed_with: u8) -> Option<Report> {
+        let record = self
+            .record
+            .as_ref()
+            .filter(|record| has_mailto(&record.ruf))?;
+        let dkim_failed = self.dkim_result != DmarcResult::Pass;
+        let spf_failed = self.spf_result != DmarcResult::Pass;
+        record.fo_options().into_iter().find(|option| match option {
+            Report::All => dkim_failed && spf_failed,
+            Report::Any => dkim_failed || spf_failed,
+            Report::Dkim | Report::Spf | Report::DkimSpf => failed_with & *option as u8 != 0,
+        })
+    }
+}
+
+/// Returns the mechanisms that failed regardless of alignment, as a bitmask of
+/// `Report::Dkim` and `Report::Spf`. Temporary errors and neutral results are
+/// not failures for either mechanism.
+pub(crate) fn failed_with(dkim: &[DkimOutput], spf: &SpfOutput) -> u8 {
+    let dkim_failed = dkim.iter().any(|output| {
+        matches!(
+            output.result,
+            DkimResult::Fail(_) | DkimResult::PermError(_)
+        )
+    });
+    let spf_failed = matches!(
+        spf.result,
+        SpfResult::Fail | SpfResult::SoftFail | SpfResult::PermError
+    );
+    (if dkim_failed { Report::Dkim as u8 } else { 0 })
+        | (if spf_failed { Report::Spf as u8 } else { 0 })
+}
+
+impl Default for Dmarc {
+    fn default() -> Self {
+        Dmarc {
+            v: Version::V1,
+            adkim: Alignment::Relaxed,
+            aspf: Alignment::Relaxed,
+            fo: Report::All as u8,
+            np: Policy::Unspecified,
+            has_np: false,
+            p: Policy::Unspecified,
+            psd: Psd::Default,
+            pct: 100,
+            rf: Format::Afrf as u8,
+            ri: 86400,
+            rua: vec![],
+            ruf: vec![],
+            sp: Policy::Unspecified,
+            t: false,
+            unknown_tags: BTreeMap::new(),
+            deprecated_tags: BTreeSet::new(),
         }
     }
 }
 
 impl Dmarc {
-    pub fn ruf(&self) -> &[URI] {
-        &self.ruf
+    /// Returns every deviation from the DMARC syntax found in a record, which
+    /// the parser tolerates as described in RFC 7489, Section 6.3. Fails only
+    /// when the record is not a DMARC record.
+    pub fn lint(record: &str) -> crate::Result<Vec<SyntaxIssue>> {
+        let mut issues = Vec::new();
+        Dmarc::parse_record(record.as_bytes(), &mut issues).map(|_| issues)
+    }
+
+    /// Returns whether the record has a policy to apply. Records without a
+    /// valid `p=` tag or with an invalid `sp=` tag are unusable, unless they
+    /// have report destinations (RFC 7489, Section 6.6.3).
+    pub fn is_usable(&self) -> bool {
+        self.p != Policy::Unspecified
     }
 
-    pub fn rua(&self) -> &[URI] {
+    pub fn p(&self) -> Policy {
+        self.p
+    }
+
+    /// Returns the policy for subdomains, which defaults to `p`.
+    pub fn sp(&self) -> Policy {
+        self.sp
+    }
+
+    /// Returns the policy for non-existent subdomains, which defaults to `sp`.
+    pub fn np(&self) -> Policy {
+        self.np
+    }
+
+    /// Returns whether the record has an `np=` tag, without which the
+    /// policy of non-existent subdomains is not applied.
+    pub fn has_np(&self) -> bool {
+        self.has_np
+    }
+
+    /// Returns the policy published with the tag `source`.
+    pub fn policy_for(&self, source: PolicySource) -> Policy {
+        match source {
+            PolicySource::P => self.p,
+            PolicySource::Sp => self.sp,
+            PolicySource::Np => self.np,
+        }
+    }
+
+    pub fn adkim(&self) -> Alignment {
+        self.adkim
+    }
+
+    pub fn aspf(&self) -> Alignment {
+        self.aspf
+    }
+
+    pub fn pct(&self) -> u8 {
+        self.pct
+    }
+
+    /// Returns the failure reporting option of the `fo=` tag, with `d` and `s`
+    /// combined into `DkimSpf`. Use `fo_options` for tags listing other options.
+    pub fn fo(&self) -> &Report {
+        match (self.has_fo(Report::Dkim), self.has_fo(Report::Spf)) {
+            (true, true) => &Report::DkimSpf,
+            (true, false) => &Report::Dkim,
+            (false, true) => &Report::Spf,
+            _ if self.has_fo(Report::Any) => &Report::Any,
+            _ => &Report::All,
+        }
+    }
+
+    /// Returns the failure reporting options listed in the `fo=` tag.
+    pub fn fo_options(&self) -> Vec<Report> {
+        Report::ALL
+            .into_iter()
+            .filter(|option| self.has_fo(*option))
+            .collect()
+    }
+
+    /// Returns whether the `fo=` tag includes a failure reporting option.
+    pub fn has_fo(&self, option: Report) -> bool {
+        self.fo & option as u8 == option as u8
+    }
+
+    /// Returns the requested failure report formats as a bitmask,
+    /// where `1` stands for AFRF.
+    pub fn rf(&self) -> u8 {
+        self.rf
+    }
+
+    /// Returns the requested aggregate report interval in seconds.
+    pub fn ri(&self) -> u32 {
+        self.ri
+    }
+
+    /// Returns the tags removed by DMARCbis (`pct`, `rf` and `ri`) that are
+    /// present in the record, even with their default value. Their values
+    /// are still honored.
+    pub fn deprecated_tags(&self) -> Vec<&'static str> {
+        self.deprecated_tags.iter().copied().collect()
+    }
+
+    /// Returns the `mailto` destinations of the `ruf=` tag.
+    pub fn ruf(&self) -> Vec<URI> {
+        mailto_uris(&self.ruf)
+    }
+
+    /// Returns the `mailto` destinations of the `rua=` tag.
+    pub fn rua(&self) -> Vec<URI> {
+        mailto_uris(&self.rua)
+    }
+
+    /// Returns every destination of the `rua=` tag, including those with
+    /// unsupported schemes, which are not listed by `rua`.
+    pub fn rua_uris(&self) -> &[ReportUri] {
         &self.rua
     }
+
+    /// Returns every destination of the `ruf=` tag, including those with
+    /// unsupported schemes, which are not listed by `ruf`.
+    pub fn ruf_uris(&self) -> &[ReportUri] {
+        &self.ruf
+    }
+
+    pub fn psd(&self) -> Psd {
+        self.psd
+    }
+
+    /// Returns whether the record is in testing mode (`t=y`), which takes
+    /// precedence over `pct` when both are present.
+    pub fn is_testing(&self) -> bool {
+        self.t
+    }
+
+    /// Returns the tags not defined by DMARC, keyed by their lowercase name.
+    pub fn unknown_tags(&self) -> &BTreeMap<String, String> {
+        &self.unknown_tags
+    }
+}
+
+/// Returns whether any of the destinations is a valid `mailto` URI.
+fn has_mailto(uris: &[ReportUri]) -> bool {
+    uris.iter().any(ReportUri::is_mailto)
+}
+
+/// Returns the valid `mailto` destinations, which are the only ones reports
+/// are sent to.
+fn mailto_uris(uris: &[ReportUri]) -> Vec<URI> {
+    uris.iter()
+        .filter(|uri| uri.is_mailto())
+        .map(URI::from)
+        .collect()
+}
+
+/// 64-bit FNV-1a, which unlike the standard library hasher is stable
+/// across releases and platforms.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl AlignedSignature {
+    pub(crate) fn new(signature: &Signature, mode: Alignment) -> Self {
+        AlignedSignature {
+            domain: signature.d.to_string(),
+            selector: signature.s.to_string(),
+            mode,
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    pub fn mode(&self) -> Alignment {
+        self.mode
+    }
+}
+
+impl Display for Alignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Alignment::Relaxed => "relaxed",
+            Alignment::Strict => "strict",
+        })
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Report::All => "0",
+            Report::Any => "1",
+            Report::Dkim => "d",
+            Report::Spf => "s",
+            Report::DkimSpf => "d:s",
+        })
+    }
 }
 
 impl Display for Policy {
@@ -222,3 +903,273 @@ impl Display for Policy {
         })
     }
 }
+
+impl Display for SyntaxIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyntaxIssue::NonStandardVersion => f.write_str("version is not v=DMARC1"),
+            SyntaxIssue::UnknownTag(tag) => write!(f, "unknown tag {tag}"),
+            SyntaxIssue::DuplicateTag(tag) => write!(f, "duplicate tag {tag}"),
+            SyntaxIssue::InvalidValue(tag) => write!(f, "invalid value for tag {tag}"),
+            SyntaxIssue::UnsupportedUri(uri) => write!(f, "unsupported report URI {uri}"),
+            SyntaxIssue::MissingPolicy => f.write_str("missing or invalid p= tag"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{str::FromStr, sync::Arc};
+
+    use crate::{
+        report::{ActionDisposition, PolicyOverride, Record},
+        DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error, SpfOutput, SpfResult,
+    };
+
+    use super::{Dmarc, Policy, Report};
+
+    fn dmarc_output(record: &str, policy: Policy) -> DmarcOutput {
+        DmarcOutput {
+            policy,
+            dkim_result: DmarcResult::Fail(Error::NotAligned),
+            spf_result: DmarcResult::Fail(Error::NotAligned),
+            ..Default::default()
+        }
+        .with_domain("example.org")
+        .with_record(Arc::new(Dmarc::from_str(record).unwrap()), "example.org")
+    }
+
+    #[test]
+    fn dmarc_sampled_policy() {
+        for (record, policy, pct) in [
+            ("v=DMARC1; p=reject; pct=25", Policy::Reject, 25),
+            ("v=DMARC1; p=quarantine; pct=50", Policy::Quarantine, 50),
+            ("v=DMARC1; p=reject; pct=90", Policy::Reject, 90),
+            ("v=DMARC1; p=reject; pct=0", Policy::Reject, 0),
+        ] {
+            let output = dmarc_output(record, policy);
+            let weaker_policy = if policy == Policy::Reject {
+                Policy::Quarantine
+            } else {
+                Policy::None
+            };
+
+            let mut sampled = 0;
+            for seed in 0..10000 {
+                let seed = format!("<{seed}@example.org>");
+                let sampled_policy = output.sampled_policy(&seed);
+                assert_eq!(sampled_policy, output.sampled_policy(&seed), "{record}");
+                if sampled_policy == policy {
+                    sampled += 1;
+                } else {
+                    assert_eq!(sampled_policy, weaker_policy, "{record}");
+                }
+            }
+            assert!(
+                (sampled - pct * 100_i32).abs() <= 300,
+                "{record}: {sampled} of 10000 sampled"
+            );
+
+            let mut sampled = 0;
+            for _ in 0..10000 {
+                if output.random_sampled_policy() == policy {
+                    sampled += 1;
+                }
+            }
+            assert!(
+                (sampled - pct * 100_i32).abs() <= 500,
+                "{record}: {sampled} of 10000 sampled"
+            );
+        }
+
+        // Records without pct are always applied
+        let output = dmarc_output("v=DMARC1; p=reject", Policy::Reject);
+        for seed in 0..1000 {
+            assert_eq!(output.sampled_policy(seed.to_string()), Policy::Reject);
+        }
+        let output = output.with_random_sampled_policy();
+        assert_eq!(output.effective_policy(), Policy::Reject);
+        assert!(!output.is_sampled_out());
+
+        // Sampled out results are reported as overridden
+        let output = dmarc_output("v=DMARC1; p=reject; pct=0", Policy::Reject)
+            .with_sampled_policy("<1@example.org>");
+        assert_eq!(output.policy(), Policy::Reject);
+        assert_eq!(output.effective_policy(), Policy::Quarantine);
+        assert!(output.is_sampled_out());
+        let record = Record::new().with_dmarc_output(&output);
+        assert_eq!(record.action_disposition(), ActionDisposition::Quarantine);
+        assert_eq!(
+            record
+                .policy_override_reason()
+                .iter()
+                .map(|reason| reason.policy_override())
+                .collect::<Vec<_>>(),
+            vec![PolicyOverride::SampledOut]
+        );
+
+        // Messages passing DMARC are not sampled
+        for record in ["v=DMARC1; p=reject; pct=0", "v=DMARC1; p=reject; t=y"] {
+            let mut output = dmarc_output(record, Policy::Reject);
+            output.dkim_result = DmarcResult::Pass;
+            assert_eq!(output.sampled_policy("<1@example.org>"), Policy::Reject);
+            let output = output.with_sampled_policy("<1@example.org>");
+            assert_eq!(output.effective_policy(), Policy::Reject);
+            assert!(!output.is_sampled_out(), "{record}");
+            assert!(output.disposition([]).reasons().is_empty(), "{record}");
+        }
+
+        // Seeds are hashed as bytes, independently of the platform
+        assert_eq!(super::fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(super::fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn dmarc_failure_report_options() {
+        for (record, expected_fo, expected_options) in [
+            ("v=DMARC1; p=reject", Report::All, vec![Report::All]),
+            ("v=DMARC1; p=reject; fo=0", Report::All, vec![Report::All]),
+            ("v=DMARC1; p=reject; fo=1", Report::Any, vec![Report::Any]),
+            ("v=DMARC1; p=reject; fo=d", Report::Dkim, vec![Report::Dkim]),
+            (
+                "v=DMARC1; p=reject; fo=s:d",
+                Report::DkimSpf,
+                vec![Report::Dkim, Report::Spf],
+            ),
+            (
+                "v=DMARC1; p=reject; fo=1:d:s",
+                Report::DkimSpf,
+                vec![Report::Any, Report::Dkim, Report::Spf],
+            ),
+            (
+                "v=DMARC1; p=reject; fo = S : 0",
+                Report::Spf,
+                vec![Report::All, Report::Spf],
+            ),
+        ] {
+            let dmarc = Dmarc::from_str(record).unwrap();
+            assert_eq!(dmarc.fo(), &expected_fo, "{record}");
+            assert_eq!(dmarc.fo_options(), expected_options, "{record}");
+        }
+        // Invalid options are replaced by the default
+        for record in ["v=DMARC1; p=reject; fo=x", "v=DMARC1; p=reject; fo=1:"] {
+            let dmarc = Dmarc::from_str(record).unwrap();
+            assert_eq!(dmarc.fo(), &Report::All, "{record}");
+            assert_eq!(dmarc.fo_options(), vec![Report::All], "{record}");
+        }
+
+        let aligned = DmarcResult::Pass;
+        let unaligned = DmarcResult::Fail(Error::NotAligned);
+        let dkim_pass = DkimResult::Pass;
+        let dkim_fail = DkimResult::Fail(Error::FailedBodyHashMatch);
+        let dkim_temperror = DkimResult::TempError(Error::DnsError("timeout".to_string()));
+        let dkim_neutral = DkimResult::Neutral(Error::SignatureExpired);
+        // DMARC DKIM and SPF results, DKIM and SPF results regardless of alignment,
+        // followed by the expected results of fo=0, fo=1, fo=d and fo=s
+        for (dkim_dmarc, spf_dmarc, dkim, spf, expected_results) in [
+            (
+                &aligned,
+                &aligned,
+                &dkim_pass,
+                SpfResult::Pass,
+                [false, false, false, false],
+            ),
+            (
+                &unaligned,
+                &aligned,
+                &dkim_pass,
+                SpfResult::Pass,
+                [false, true, false, false],
+            ),
+            (
+                &unaligned,
+                &unaligned,
+                &dkim_pass,
+                SpfResult::Pass,
+                [true, true, false, false],
+            ),
+            (
+                &aligned,
+                &unaligned,
+                &dkim_pass,
+                SpfResult::SoftFail,
+                [false, true, false, true],
+            ),
+            (
+                &unaligned,
+                &aligned,
+                &dkim_fail,
+                SpfResult::Pass,
+                [false, true, true, false],
+            ),
+            (
+                &unaligned,
+                &unaligned,
+                &dkim_fail,
+                SpfResult::Fail,
+                [true, true, true, true],
+            ),
+            // Temporary errors and neutral results are not failures of either mechanism
+            (
+                &unaligned,
+                &unaligned,
+                &dkim_temperror,
+                SpfResult::TempError,
+                [true, true, false, false],
+            ),
+            (
+                &unaligned,
+                &unaligned,
+                &dkim_neutral,
+                SpfResult::Neutral,
+                [true, true, false, false],
+            ),
+        ] {
+            let dkim = [DkimOutput {
+                result: dkim.clone(),
+                ..Default::default()
+            }];
+            let spf = SpfOutput {
+                result: spf,
+                ..Default::default()
+            };
+            for (fo, expected_result) in ["0", "1", "d", "s"].into_iter().zip(expected_results) {
+                let record = format!("v=DMARC1; p=reject; ruf=mailto:ruf@example.org; fo={fo}");
+                let output = dmarc_output(&record, Policy::Reject)
+                    .with_dkim_result(dkim_dmarc.clone())
+                    .with_spf_result(spf_dmarc.clone());
+                assert_eq!(
+                    output.should_send_failure_report(&dkim, &spf),
+                    expected_result,
+                    "fo={fo} {dkim_dmarc:?} {spf_dmarc:?} {dkim:?} {spf:?}"
+                );
+
+                // The results the message was verified with are used otherwise
+                let output = output.with_failed_with(super::failed_with(&dkim, &spf));
+                assert_eq!(
+                    output.failure_report().is_some(),
+                    expected_result,
+                    "fo={fo} {dkim_dmarc:?} {spf_dmarc:?} {dkim:?} {spf:?}"
+                );
+
+                // Failure reports require a ruf destination
+                let output = dmarc_output(&format!("v=DMARC1; p=reject; fo={fo}"), Policy::Reject)
+                    .with_dkim_result(dkim_dmarc.clone())
+                    .with_spf_result(spf_dmarc.clone());
+                assert!(!output.should_send_failure_report(&dkim, &spf));
+            }
+
+            // Combined options request a report when any of them applies
+            let output = dmarc_output(
+                "v=DMARC1; p=reject; ruf=mailto:ruf@example.org; fo=d:s",
+                Policy::Reject,
+            )
+            .with_dkim_result(dkim_dmarc.clone())
+            .with_spf_result(spf_dmarc.clone());
+            assert_eq!(
+                output.should_send_failure_report(&dkim, &spf),
+                expected_results[2] || expected_results[3]
+            );
+        }
+    }
+}
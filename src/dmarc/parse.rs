@@ -8,95 +8,179 @@
  * except according to those terms.
  */
 
-use std::slice::Iter;
+use std::{slice::Iter, str::FromStr};
 
 use mail_parser::decoders::quoted_printable::quoted_printable_decode_char;
 
 use crate::{
     common::parse::{ItemParser, TagParser, TxtRecordParser, N, T, V, Y},
-    Error, Version,
+    Error,
 };
 
-use super::{Alignment, Dmarc, Format, Policy, Psd, Report, URI};
+use super::{Alignment, Dmarc, Format, Policy, Psd, Report, ReportUri, SyntaxIssue};
 
 impl TxtRecordParser for Dmarc {
     fn parse(bytes: &[u8]) -> crate::Result<Self> {
+        Dmarc::parse_record(bytes, &mut Vec::new())
+    }
+}
+
+impl FromStr for Dmarc {
+    type Err = Error;
+
+    /// Parses a DMARC record such as `v=DMARC1; p=reject`.
+    fn from_str(record: &str) -> Result<Self, Self::Err> {
+        Dmarc::parse_record(record.as_bytes(), &mut Vec::new())
+    }
+}
+
+impl Dmarc {
+    /// Parses a record, replacing invalid tag values with their defaults and
+    /// adding every deviation from the DMARC syntax to `issues`.
+    pub(crate) fn parse_record(bytes: &[u8], issues: &mut Vec<SyntaxIssue>) -> crate::Result<Self> {
         let mut record = bytes.iter();
-        if record.key().unwrap_or(0) != V
-            || !record.match_bytes(b"DMARC1")
-            || !record.seek_tag_end()
-        {
+        if record.key().unwrap_or(0) != V {
             return Err(Error::InvalidRecordType);
         }
+        let version = String::from_utf8_lossy(tag_value(&mut record));
+        if version.trim() != "DMARC1" {
+            if version
+                .split_whitespace()
+                .collect::<String>()
+                .eq_ignore_ascii_case("DMARC1")
+            {
+                issues.push(SyntaxIssue::NonStandardVersion);
+            } else {
+                return Err(Error::InvalidRecordType);
+            }
+        }
 
-        let mut dmarc = Dmarc {
-            adkim: Alignment::Relaxed,
-            aspf: Alignment::Relaxed,
-            fo: Report::All,
-            np: Policy::Unspecified,
-            p: Policy::Unspecified,
-            pct: 100,
-            rf: Format::Afrf as u8,
-            ri: 86400,
-            rua: vec![],
-            ruf: vec![],
-            sp: Policy::Unspecified,
-            v: Version::V1,
-            psd: Psd::Default,
-            t: false,
-        };
+        let mut dmarc = Dmarc::default();
+        let mut tags = Vec::new();
+        let mut has_invalid_sp = false;
 
-        while let Some(key) = record.key() {
-            match key {
-                ADKIM => {
-                    dmarc.adkim = record.alignment()?;
-                }
-                ASPF => {
-                    dmarc.aspf = record.alignment()?;
-                }
-                FO => {
-                    dmarc.fo = record.report()?;
-                }
-                NP => {
-                    dmarc.np = record.policy()?;
-                }
-                P => {
-                    dmarc.p = record.policy()?;
-                }
-                PCT => {
-                    dmarc.pct = std::cmp::min(100, record.number().ok_or(Error::ParseError)?) as u8;
-                }
-                RF => {
-                    dmarc.rf = record.flags::<Format>() as u8;
-                }
-                RI => {
-                    dmarc.ri = record.number().ok_or(Error::ParseError)? as u32;
-                }
+        loop {
+            let tag = record.as_slice();
+            let key = match record.key() {
+                Some(key) => key,
+                None => break,
+            };
+            // Obtain the tag name, skipping empty terms before it
+            let name = tag[..tag.len() - record.as_slice().len() - 1]
+                .rsplit(|&ch| ch == b';')
+                .next()
+                .unwrap_or_default();
+            let name = String::from_utf8_lossy(name).trim().to_lowercase();
+            let raw_value = tag_value(&mut record);
+            let mut value = raw_value.iter();
+            if tags.contains(&name) {
+                issues.push(SyntaxIssue::DuplicateTag(name.clone()));
+            } else if !name.is_empty() {
+                tags.push(name.clone());
+            }
+            if let Some(tag) = ["pct", "rf", "ri"].into_iter().find(|tag| *tag == name) {
+                dmarc.deprecated_tags.insert(tag);
+            }
+
+            let result = match key {
+                ADKIM => value.alignment().map(|adkim| dmarc.adkim = adkim),
+                ASPF => value.alignment().map(|aspf| dmarc.aspf = aspf),
+                FO => value.report().map(|fo| dmarc.fo = fo),
+                NP => value.policy().map(|np| {
+                    dmarc.np = np;
+                    dmarc.has_np = true;
+                }),
+                P => value.policy().map(|p| dmarc.p = p),
+                PCT => match value.number() {
+                    Some(pct) if pct <= 100 => {
+                        dmarc.pct = pct as u8;
+                        Ok(())
+                    }
+                    _ => Err(Error::ParseError),
+                },
+                RF => match value.flags::<Format>() {
+                    0 => Err(Error::ParseError),
+                    rf => {
+                        dmarc.rf = rf as u8;
+                        Ok(())
+                    }
+                },
+                RI => match value.number().and_then(|ri| u32::try_from(ri).ok()) {
+                    Some(ri) => {
+                        dmarc.ri = ri;
+                        Ok(())
+                    }
+                    None => Err(Error::ParseError),
+                },
                 RUA => {
-                    dmarc.rua = record.uris()?;
+                    let (uris, result) = report_uris(value.uris(), issues);
+                    dmarc.rua = uris;
+                    result
                 }
                 RUF => {
-                    dmarc.ruf = record.uris()?;
+                    let (uris, result) = report_uris(value.uris(), issues);
+                    dmarc.ruf = uris;
+                    result
                 }
                 SP => {
-                    dmarc.sp = record.policy()?;
-                }
-                PSD => {
-                    dmarc.psd = match record.value() {
-                        Y => Psd::Yes,
-                        N => Psd::No,
-                        _ => Psd::Default,
-                    };
-                }
-                T => {
-                    dmarc.t = record.value() == Y;
+                    let result = value.policy().map(|sp| dmarc.sp = sp);
+                    has_invalid_sp = result.is_err();
+                    result
                 }
+                PSD => match value.value() {
+                    Y => {
+                        dmarc.psd = Psd::Yes;
+                        Ok(())
+                    }
+                    N => {
+                        dmarc.psd = Psd::No;
+                        Ok(())
+                    }
+                    _ => Err(Error::ParseError),
+                },
+                T => match value.value() {
+                    Y => {
+                        dmarc.t = true;
+                        Ok(())
+                    }
+                    N => {
+                        dmarc.t = false;
+                        Ok(())
+                    }
+                    _ => Err(Error::ParseError),
+                },
                 _ => {
-                    record.ignore();
+                    // Preserve unknown tags
+                    if !name.is_empty() {
+                        dmarc.unknown_tags.insert(
+                            name.clone(),
+                            String::from_utf8_lossy(raw_value).trim().to_string(),
+                        );
+                        issues.push(SyntaxIssue::UnknownTag(name.clone()));
+                    }
+                    Ok(())
                 }
+            };
+            if result.is_err() {
+                issues.push(SyntaxIssue::InvalidValue(name));
             }
         }
 
+        // Records without a valid policy are used for reporting only when they
+        // have report destinations (RFC 7489, Section 6.6.3)
+        if dmarc.p == Policy::Unspecified {
+            issues.push(SyntaxIssue::MissingPolicy);
+        }
+        if dmarc.p == Policy::Unspecified || has_invalid_sp {
+            if dmarc.rua.iter().any(ReportUri::is_mailto) {
+                dmarc.p = Policy::None;
+                dmarc.sp = Policy::Unspecified;
+                dmarc.np = Policy::Unspecified;
+                dmarc.has_np = false;
+            } else {
+                dmarc.p = Policy::Unspecified;
+            }
+        }
         if dmarc.sp == Policy::Unspecified {
             dmarc.sp = dmarc.p;
         }
@@ -108,11 +192,42 @@ impl TxtRecordParser for Dmarc {
     }
 }
 
+/// Returns the value of the current tag and moves to the next one.
+fn tag_value<'x>(record: &mut Iter<'x, u8>) -> &'x [u8] {
+    let rest = record.as_slice();
+    let len = rest.iter().position(|&ch| ch == b';').unwrap_or(rest.len());
+    *record = rest.get(len + 1..).unwrap_or_default().iter();
+    &rest[..len]
+}
+
+/// Returns the destinations that could be parsed, adding those reports are not
+/// sent to to `issues`. Destinations with an invalid size or encoding are
+/// skipped and make the result an error, without discarding the other ones.
+fn report_uris(
+    uris: Vec<crate::Result<ReportUri>>,
+    issues: &mut Vec<SyntaxIssue>,
+) -> (Vec<ReportUri>, crate::Result<()>) {
+    let mut result = Ok(());
+    let mut valid_uris = Vec::with_capacity(uris.len());
+    for uri in uris {
+        match uri {
+            Ok(uri) => {
+                if !uri.is_mailto() {
+                    issues.push(SyntaxIssue::UnsupportedUri(uri.to_string()));
+                }
+                valid_uris.push(uri);
+            }
+            Err(err) => result = Err(err),
+        }
+    }
+    (valid_uris, result)
+}
+
 pub(crate) trait DMARCParser: Sized {
     fn alignment(&mut self) -> crate::Result<Alignment>;
-    fn report(&mut self) -> crate::Result<Report>;
+    fn report(&mut self) -> crate::Result<u8>;
     fn policy(&mut self) -> crate::Result<Policy>;
-    fn uris(&mut self) -> crate::Result<Vec<URI>>;
+    fn uris(&mut self) -> Vec<crate::Result<ReportUri>>;
 }
 
 impl DMARCParser for Iter<'_, u8> {
@@ -129,29 +244,17 @@ impl DMARCParser for Iter<'_, u8> {
         }
     }
 
-    fn report(&mut self) -> crate::Result<Report> {
-        let mut r = Report::All;
+    fn report(&mut self) -> crate::Result<u8> {
+        let mut r = 0;
 
         loop {
-            r = match self.next_skip_whitespaces().unwrap_or(0) {
+            r |= match self.next_skip_whitespaces().unwrap_or(0) {
                 b'0' => Report::All,
                 b'1' => Report::Any,
-                b'd' | b'D' => {
-                    if r == Report::Spf {
-                        Report::DkimSpf
-                    } else {
-                        Report::Dkim
-                    }
-                }
-                b's' | b'S' => {
-                    if r == Report::Dkim {
-                        Report::DkimSpf
-                    } else {
-                        Report::Spf
-                    }
-                }
+                b'd' | b'D' => Report::Dkim,
+                b's' | b'S' => Report::Spf,
                 _ => return Err(Error::ParseError),
-            };
+            } as u8;
             match self.next_skip_whitespaces().unwrap_or(0) {
                 b':' => (),
                 b';' | 0 => return Ok(r),
@@ -175,139 +278,106 @@ impl DMARCParser for Iter<'_, u8> {
     }
 
     #[allow(clippy::while_let_on_iterator)]
-    fn uris(&mut self) -> crate::Result<Vec<URI>> {
-        let mut uris = Vec::new();
-        let mut uri = Vec::with_capacity(16);
-        let mut found_uri = false;
-        let mut found_at = false;
-        let mut size: usize = 0;
-
-        'outer: while let Some(&ch) = self.next() {
+    fn uris(&mut self) -> Vec<crate::Result<ReportUri>> {
+        let mut value = Vec::with_capacity(32);
+
+        while let Some(&ch) = self.next() {
             match ch {
-                b'%' => {
-                    let mut hex1 = 0;
-
-                    while let Some(&ch) = self.next() {
-                        if ch.is_ascii_hexdigit() {
-                            if hex1 != 0 {
-                                if let Some(ch) = quoted_printable_decode_char(hex1, ch) {
-                                    match ch {
-                                        b'@' => {
-                                            found_at = true;
-                                            uri.push(ch);
-                                        }
-                                        _ => {
-                                            if !ch.is_ascii_whitespace() {
-                                                uri.push(ch);
-                                            }
-                                        }
-                                    }
-                                }
-                                break;
-                            } else {
-                                hex1 = ch;
-                            }
-                        } else if ch == b';' {
-                            break 'outer;
-                        } else if !ch.is_ascii_whitespace() {
-                            return Err(Error::ParseError);
-                        }
-                    }
-                }
-                b'!' => {
-                    let mut has_digits = false;
-                    let mut has_units = false;
-
-                    while let Some(&ch) = self.next() {
-                        match ch {
-                            b'0'..=b'9' if !has_units => {
-                                size =
-                                    (size.saturating_mul(10)).saturating_add((ch - b'0') as usize);
-                                has_digits = true;
-                            }
-                            b'k' | b'K' if !has_units && has_digits => {
-                                size = size.saturating_mul(1024);
-                                has_units = true;
-                            }
-                            b'm' | b'M' if !has_units && has_digits => {
-                                size = size.saturating_mul(1024 * 1024);
-                                has_units = true;
-                            }
-                            b'g' | b'G' if !has_units && has_digits => {
-                                size = size.saturating_mul(1024 * 1024 * 1024);
-                                has_units = true;
-                            }
-                            b't' | b'T' if !has_units && has_digits => {
-                                size = size.saturating_mul(1024 * 1024 * 1024 * 1024);
-                                has_units = true;
-                            }
-                            b';' => {
-                                break 'outer;
-                            }
-                            b',' => {
-                                if !uri.is_empty() {
-                                    if found_uri && found_at {
-                                        uris.push(URI {
-                                            uri: String::from_utf8_lossy(&uri).to_lowercase(),
-                                            max_size: size,
-                                        });
-                                    }
-                                    found_uri = false;
-                                    found_at = false;
-                                    uri.clear();
-                                }
-                                size = 0;
-                                break;
-                            }
-                            _ => {
-                                if !ch.is_ascii_whitespace() {
-                                    return Err(Error::ParseError);
-                                }
-                            }
-                        }
-                    }
-                }
-                b',' => {
-                    if !uri.is_empty() {
-                        if found_uri && found_at {
-                            uris.push(URI {
-                                uri: String::from_utf8_lossy(&uri).to_lowercase(),
-                                max_size: size,
-                            });
-                        }
-                        found_uri = false;
-                        found_at = false;
-                        uri.clear();
-                    }
-                    size = 0;
-                }
-                b':' if !found_uri => {
-                    found_uri = uri.eq_ignore_ascii_case(b"mailto");
-                    uri.clear();
-                }
-                b';' => {
-                    break;
-                }
-                b'@' => {
-                    found_at = true;
-                    uri.push(ch);
-                }
+                b';' => break,
                 _ => {
                     if !ch.is_ascii_whitespace() {
-                        uri.push(ch);
+                        value.push(ch);
                     }
                 }
             }
         }
 
-        if !uri.is_empty() && found_uri && found_at {
-            uris.push(URI {
-                uri: String::from_utf8_lossy(&uri).to_lowercase(),
-                max_size: size,
-            })
+        String::from_utf8_lossy(&value)
+            .split(',')
+            .filter(|uri| !uri.is_empty())
+            .map(ReportUri::parse)
+            .collect()
+    }
+}
+
+impl ReportUri {
+    /// Parses a report destination such as `mailto:user@example.org!10m`,
+    /// decoding percent-encoded characters in `mailto` addresses.
+    pub(crate) fn parse(uri: &str) -> crate::Result<Self> {
+        let (uri, max_size) = match uri.split_once('!') {
+            Some((uri, size)) => (uri, Some(parse_size(size).ok_or(Error::ParseError)?)),
+            None => (uri, None),
+        };
+        let (scheme, address) = uri.split_once(':').unwrap_or(("", uri));
+        let scheme = scheme.to_ascii_lowercase();
+        let address = if scheme == "mailto" {
+            decode_address(address)?
+        } else {
+            address.to_string()
+        };
+
+        Ok(ReportUri {
+            scheme,
+            address,
+            max_size,
+        })
+    }
+}
+
+/// Decodes the percent-encoded characters of a `mailto` address, removing any
+/// whitespace. The domain is lowercased while the local part keeps its case.
+pub(crate) fn decode_address(address: &str) -> crate::Result<String> {
+    let mut decoded = Vec::with_capacity(address.len());
+    let mut bytes = address.bytes();
+    while let Some(ch) = bytes.next() {
+        let ch = if ch == b'%' {
+            match (bytes.next(), bytes.next()) {
+                (Some(hex1), Some(hex2)) => {
+                    quoted_printable_decode_char(hex1, hex2).ok_or(Error::ParseError)?
+                }
+                _ => return Err(Error::ParseError),
+            }
+        } else {
+            ch
+        };
+        if !ch.is_ascii_whitespace() {
+            decoded.push(ch);
         }
+    }
 
-        Ok(uris)
+    let address = String::from_utf8_lossy(&decoded);
+    Ok(match address.rsplit_once('@') {
+        Some((local_part, domain)) => format!("{local_part}@{}", domain.to_lowercase()),
+        None => address.into_owned(),
+    })
+}
+
+/// Parses a size limit such as `100`, `10k` or `1m` into bytes.
+pub(crate) fn parse_size(size: &str) -> Option<u64> {
+    let (number, multiplier) = match size.char_indices().last()? {
+        (pos, ch) if ch.is_ascii_alphabetic() => (
+            &size[..pos],
+            match ch.to_ascii_lowercase() {
+                'k' => 1024,
+                'm' => 1024 * 1024,
+                'g' => 1024 * 1024 * 1024,
+                't' => 1024 * 1024 * 1024 * 1024,
+                _ => return None,
+            },
+        ),
+        _ => (size, 1),
+    };
+    if !number.is_empty() && number.bytes().all(|ch| ch.is_ascii_digit()) {
+        Some(
+            number
+                .bytes()
+                .fold(0u64, |size, ch| {
+                    size.saturating_mul(10).saturating_add((ch - b'0') as u64)
+                })
+                .saturating_mul(multiplier),
+        )
+    } else {
+        None
     }
 }
 
@@ -340,9 +410,15 @@ const PSD: u64 = (b'p' as u64) | (b's' as u64) << 8 | (b'd' as u64) << 16;
 
 #[cfg(test)]
 mod test {
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        fs,
+        path::PathBuf,
+        str::FromStr,
+    };
+
     use crate::{
-        common::parse::TxtRecordParser,
-        dmarc::{Alignment, Dmarc, Format, Policy, Psd, Report, URI},
+        dmarc::{Alignment, Dmarc, Format, Policy, Psd, Report, ReportUri, SyntaxIssue, URI},
         Version,
     };
 
@@ -354,18 +430,21 @@ mod test {
                 Dmarc {
                     adkim: Alignment::Relaxed,
                     aspf: Alignment::Relaxed,
-                    fo: Report::All,
+                    fo: Report::All as u8,
                     np: Policy::None,
+                    has_np: false,
                     p: Policy::None,
                     pct: 100,
                     rf: Format::Afrf as u8,
                     ri: 86400,
-                    rua: vec![URI::new("dmarc-feedback@example.com", 0)],
+                    rua: vec![ReportUri::new("mailto", "dmarc-feedback@example.com", None)],
                     ruf: vec![],
                     sp: Policy::None,
                     psd: Psd::Default,
                     t: false,
                     v: Version::V1,
+                    unknown_tags: BTreeMap::new(),
+                    deprecated_tags: BTreeSet::new(),
                 },
             ),
             (
@@ -376,18 +455,21 @@ mod test {
                 Dmarc {
                     adkim: Alignment::Relaxed,
                     aspf: Alignment::Relaxed,
-                    fo: Report::All,
+                    fo: Report::All as u8,
                     np: Policy::None,
+                    has_np: false,
                     p: Policy::None,
                     pct: 100,
                     rf: Format::Afrf as u8,
                     ri: 86400,
-                    rua: vec![URI::new("dmarc-feedback@example.com", 0)],
-                    ruf: vec![URI::new("auth-reports@example.com", 0)],
+                    rua: vec![ReportUri::new("mailto", "dmarc-feedback@example.com", None)],
+                    ruf: vec![ReportUri::new("mailto", "auth-reports@example.com", None)],
                     sp: Policy::None,
                     psd: Psd::Default,
                     t: false,
                     v: Version::V1,
+                    unknown_tags: BTreeMap::new(),
+                    deprecated_tags: BTreeSet::new(),
                 },
             ),
             (
@@ -398,21 +480,28 @@ mod test {
                 Dmarc {
                     adkim: Alignment::Relaxed,
                     aspf: Alignment::Relaxed,
-                    fo: Report::DkimSpf,
+                    fo: Report::Dkim as u8 | Report::Spf as u8,
                     np: Policy::Quarantine,
+                    has_np: false,
                     p: Policy::Quarantine,
                     pct: 25,
                     rf: Format::Afrf as u8,
                     ri: 86400,
                     ruf: vec![],
                     rua: vec![
-                        URI::new("dmarc-feedback@example.com", 0),
-                        URI::new("tld-test@thirdparty.example.net", 10 * 1024 * 1024),
+                        ReportUri::new("mailto", "dmarc-feedback@example.com", None),
+                        ReportUri::new(
+                            "mailto",
+                            "tld-test@thirdparty.example.net",
+                            Some(10 * 1024 * 1024),
+                        ),
                     ],
                     sp: Policy::Quarantine,
                     psd: Psd::Default,
                     t: false,
                     v: Version::V1,
+                    unknown_tags: BTreeMap::new(),
+                    deprecated_tags: BTreeSet::from(["pct"]),
                 },
             ),
             (
@@ -423,18 +512,21 @@ mod test {
                 Dmarc {
                     adkim: Alignment::Strict,
                     aspf: Alignment::Strict,
-                    fo: Report::Any,
+                    fo: Report::Any as u8,
                     np: Policy::None,
+                    has_np: true,
                     p: Policy::Reject,
                     pct: 100,
                     rf: Format::Afrf as u8,
                     ri: 86400,
-                    rua: vec![URI::new("dmarc-feedback@example.com", 0)],
+                    rua: vec![ReportUri::new("mailto", "dmarc-feedback@example.com", None)],
                     ruf: vec![],
                     sp: Policy::Quarantine,
                     psd: Psd::Default,
                     t: false,
                     v: Version::V1,
+                    unknown_tags: BTreeMap::new(),
+                    deprecated_tags: BTreeSet::new(),
                 },
             ),
             (
@@ -446,21 +538,24 @@ mod test {
                 Dmarc {
                     adkim: Alignment::Relaxed,
                     aspf: Alignment::Relaxed,
-                    fo: Report::Spf,
+                    fo: Report::Spf as u8,
                     np: Policy::Reject,
+                    has_np: false,
                     p: Policy::Reject,
                     pct: 100,
                     rf: Format::Afrf as u8,
                     ri: 3600,
                     rua: vec![
-                        URI::new("dmarc-feedback@example.com", 10 * 1024),
-                        URI::new("user@example.com", 2 * 1024 * 1024 * 1024),
+                        ReportUri::new("mailto", "dmarc-feedback@example.com", Some(10 * 1024)),
+                        ReportUri::new("mailto", "user@example.com", Some(2 * 1024 * 1024 * 1024)),
                     ],
                     ruf: vec![],
                     sp: Policy::Reject,
                     psd: Psd::Default,
                     t: false,
                     v: Version::V1,
+                    unknown_tags: BTreeMap::from([("ignore_me".to_string(), "true".to_string())]),
+                    deprecated_tags: BTreeSet::from(["rf", "ri"]),
                 },
             ),
             (
@@ -471,30 +566,154 @@ mod test {
                 Dmarc {
                     adkim: Alignment::Relaxed,
                     aspf: Alignment::Relaxed,
-                    fo: Report::DkimSpf,
+                    fo: Report::Dkim as u8 | Report::Spf as u8,
                     np: Policy::Quarantine,
+                    has_np: false,
                     p: Policy::Quarantine,
                     pct: 100,
                     rf: Format::Afrf as u8,
                     ri: 86400,
                     rua: vec![
-                        URI::new("dmarc-feedback@example.com", 0),
-                        URI::new("tld-test@thirdparty.example.net", 0),
+                        ReportUri::new("mailto", "dmarc-feedback@example.com", None),
+                        ReportUri::new("mailto", "tld-test@thirdparty.example.net", None),
                     ],
                     ruf: vec![],
                     sp: Policy::Quarantine,
                     psd: Psd::Yes,
                     t: true,
                     v: Version::V1,
+                    unknown_tags: BTreeMap::new(),
+                    deprecated_tags: BTreeSet::new(),
                 },
             ),
         ] {
             assert_eq!(
-                Dmarc::parse(record.as_bytes())
-                    .unwrap_or_else(|err| panic!("{record:?} : {err:?}")),
+                Dmarc::from_str(record).unwrap_or_else(|err| panic!("{record:?} : {err:?}")),
                 expected_result,
                 "{record}"
             );
         }
     }
+
+    #[test]
+    fn dmarc_malformed_records() {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources");
+        test_file.push("dmarc");
+        test_file.push("malformed.txt");
+
+        for test in String::from_utf8(fs::read(&test_file).unwrap())
+            .unwrap()
+            .split("\n\n")
+        {
+            let mut record = "";
+            let mut expected_policy = "";
+            let mut expected_issues = Vec::new();
+            for line in test.lines() {
+                match line.split_once(": ") {
+                    _ if line.starts_with('#') => (),
+                    Some(("record", value)) => record = value,
+                    Some(("policy", value)) => expected_policy = value,
+                    Some(("issue", value)) => expected_issues.push(value),
+                    _ => panic!("Invalid line {line:?}"),
+                }
+            }
+            if record.is_empty() {
+                continue;
+            }
+
+            let (policy, issues) = match (Dmarc::from_str(record), Dmarc::lint(record)) {
+                (Ok(dmarc), Ok(issues)) if dmarc.is_usable() => (dmarc.p().to_string(), issues),
+                (Ok(_), Ok(issues)) => ("unusable".to_string(), issues),
+                (Err(_), Err(_)) => ("invalid".to_string(), vec![]),
+                result => panic!("Unexpected result {result:?} for {record:?}"),
+            };
+            assert_eq!(policy, expected_policy, "{record}");
+            assert_eq!(
+                issues
+                    .iter()
+                    .map(|issue| issue.to_string())
+                    .collect::<Vec<_>>(),
+                expected_issues,
+                "{record}"
+            );
+        }
+    }
+
+    #[test]
+    fn dmarc_report_uris() {
+        let dmarc = Dmarc::from_str(concat!(
+            "v=DMARC1; p=reject; rua=mailto:Agg%2Breports@Example.org!1t,",
+            "https://reports.example.org/dmarc!50m, mailto:not-an-address!100; ",
+            "ruf=mailto:fail@example.org!2048"
+        ))
+        .unwrap();
+        assert_eq!(
+            dmarc.rua_uris(),
+            &[
+                ReportUri::new(
+                    "mailto",
+                    "Agg+reports@example.org",
+                    Some(1024 * 1024 * 1024 * 1024)
+                ),
+                ReportUri::new(
+                    "https",
+                    "//reports.example.org/dmarc",
+                    Some(50 * 1024 * 1024)
+                ),
+                ReportUri::new("mailto", "not-an-address", Some(100)),
+            ]
+        );
+        assert_eq!(
+            dmarc.rua(),
+            vec![URI::new(
+                "agg+reports@example.org",
+                1024 * 1024 * 1024 * 1024
+            )]
+        );
+        assert_eq!(
+            dmarc.ruf_uris(),
+            &[ReportUri::new("mailto", "fail@example.org", Some(2048))]
+        );
+        assert!(dmarc.ruf_uris()[0].is_mailto());
+        assert!(!dmarc.rua_uris()[1].is_mailto());
+        assert!(!dmarc.rua_uris()[2].is_mailto());
+        assert_eq!(
+            dmarc.to_string(),
+            concat!(
+                "v=DMARC1; p=reject; rua=mailto:Agg+reports@example.org!1t,",
+                "https://reports.example.org/dmarc!50m,mailto:not-an-address!100; ",
+                "ruf=mailto:fail@example.org!2k"
+            )
+        );
+
+        // Destinations with invalid sizes or percent-encoding are discarded
+        for record in [
+            "v=DMARC1; p=reject; rua=mailto:agg@example.org!10x",
+            "v=DMARC1; p=reject; rua=mailto:agg@example.org!k",
+            "v=DMARC1; p=reject; rua=mailto:agg%2@example.org",
+            "v=DMARC1; p=reject; rua=mailto:agg@example.org%",
+        ] {
+            let dmarc = Dmarc::from_str(record).unwrap();
+            assert!(dmarc.rua_uris().is_empty(), "{record}");
+            assert_eq!(
+                Dmarc::lint(record).unwrap(),
+                vec![SyntaxIssue::InvalidValue("rua".to_string())],
+                "{record}"
+            );
+        }
+
+        // Other destinations of the same tag are kept
+        let record = "v=DMARC1; p=reject; rua=mailto:agg%2@example.org,mailto:agg@example.net";
+        let dmarc = Dmarc::from_str(record).unwrap();
+        assert_eq!(
+            dmarc.rua_uris(),
+            &[ReportUri::new("mailto", "agg@example.net", None)]
+        );
+        assert_eq!(dmarc.rua(), vec![URI::new("agg@example.net", 0)]);
+        assert_eq!(
+            Dmarc::lint(record).unwrap(),
+            vec![SyntaxIssue::InvalidValue("rua".to_string())]
+        );
+    }
 }
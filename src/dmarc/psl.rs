@@ -0,0 +1,471 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{borrow::Cow, collections::HashSet, path::Path};
+
+use crate::common::resolver::domain_to_ascii;
+
+/// Computes organizational domains, which are used to locate DMARC records
+/// and to check relaxed alignment.
+pub trait SuffixListProvider: Send + Sync {
+    /// Returns the public suffix of `domain` plus one label, or `None` when
+    /// it is not known or `domain` is itself a public suffix.
+    fn effective_tld_plus_one(&self, domain: &str) -> Option<String>;
+
+    /// Returns the organizational domain of `domain` (RFC 7489, Section 3.2),
+    /// or `None` when it is not known to the provider, in which case DMARC
+    /// discovery and relaxed alignment fall back to walking the domain tree.
+    /// Defaults to `effective_tld_plus_one`.
+    fn organizational_domain<'x>(&self, domain: &'x str) -> Option<Cow<'x, str>> {
+        self.effective_tld_plus_one(domain).map(Cow::Owned)
+    }
+}
+
+/// Provider without a suffix list. DMARC records are located by walking up
+/// the domain tree, and domains are aligned in relaxed mode when either one
+/// is a subdomain of the other.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultSuffixList;
+
+/// Public suffix list in the `public_suffix_list.dat` format published at
+/// <https://publicsuffix.org/list/>.
+#[derive(Debug, Default, Clone)]
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl SuffixListProvider for DefaultSuffixList {
+    fn effective_tld_plus_one(&self, _domain: &str) -> Option<String> {
+        None
+    }
+}
+
+impl PublicSuffixList {
+    /// Parses a list, optionally skipping the entries in its private section.
+    pub fn parse(list: &str, include_private: bool) -> Self {
+        let mut psl = PublicSuffixList::default();
+
+        for line in list.lines() {
+            let line = line.trim();
+            if line.starts_with("// ===BEGIN PRIVATE DOMAINS===") && !include_private {
+                break;
+            }
+            let rule = match line.split_whitespace().next() {
+                Some(rule) if !rule.starts_with("//") => rule.to_lowercase(),
+                _ => continue,
+            };
+
+            if let Some(rule) = rule.strip_prefix('!') {
+                if let Some(rule) = domain_to_ascii(rule) {
+                    psl.exceptions.insert(rule.into_owned());
+                }
+            } else if let Some(rule) = rule.strip_prefix("*.") {
+                if let Some(rule) = domain_to_ascii(rule) {
+                    psl.wildcards.insert(rule.into_owned());
+                }
+            } else if let Some(rule) = domain_to_ascii(&rule) {
+                psl.rules.insert(rule.into_owned());
+            }
+        }
+
+        psl
+    }
+
+    /// Reads a list from a file, optionally skipping the entries in its private section.
+    pub fn from_file(path: impl AsRef<Path>, include_private: bool) -> std::io::Result<Self> {
+        std::fs::read_to_string(path).map(|list| PublicSuffixList::parse(&list, include_private))
+    }
+
+    /// Returns the number of labels of the public suffix of a domain, which is
+    /// decided by the longest matching rule, or by an exception rule. Domains
+    /// matching no rule have a single-label suffix.
+    fn suffix_len(&self, labels: &[&str]) -> usize {
+        for pos in 0..labels.len() {
+            let suffix = labels[pos..].join(".");
+            let parent = labels[pos + 1..].join(".");
+            if self.exceptions.contains(&suffix) {
+                return labels.len() - pos - 1;
+            } else if self.rules.contains(&suffix) || self.wildcards.contains(&parent) {
+                return labels.len() - pos;
+            }
+        }
+
+        1
+    }
+}
+
+impl SuffixListProvider for PublicSuffixList {
+    fn effective_tld_plus_one(&self, domain: &str) -> Option<String> {
+        let domain = normalize_domain(domain);
+        let labels = domain.split('.').collect::<Vec<_>>();
+        if !domain.is_ascii() || labels.iter().any(|label| label.is_empty()) {
+            return None;
+        }
+
+        let suffix_len = self.suffix_len(&labels);
+        if suffix_len < labels.len() {
+            Some(labels[labels.len() - suffix_len - 1..].join("."))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the organizational domain of `domain`, which is lowercased,
+    /// converted to A-labels and stripped of trailing dots. Public suffixes
+    /// and single-label hosts are their own organizational domain.
+    fn organizational_domain<'x>(&self, domain: &'x str) -> Option<Cow<'x, str>> {
+        let domain = normalize_domain(domain);
+        if !domain.is_ascii() || domain.split('.').any(|label| label.is_empty()) {
+            return None;
+        }
+        Some(match self.effective_tld_plus_one(&domain) {
+            Some(org_domain) if org_domain != domain => Cow::Owned(org_domain),
+            _ => domain,
+        })
+    }
+}
+
+/// Lowercases a domain, removes its trailing dots and converts its U-labels
+/// to A-labels. Invalid names are only lowercased.
+pub(crate) fn normalize_domain(domain: &str) -> Cow<'_, str> {
+    let domain = domain.trim_end_matches('.');
+    let domain = if domain.bytes().any(|ch| ch.is_ascii_uppercase()) {
+        Cow::Owned(domain.to_lowercase())
+    } else {
+        Cow::Borrowed(domain)
+    };
+    if domain.is_ascii() {
+        domain
+    } else {
+        domain_to_ascii(&domain)
+            .map(|ascii_domain| Cow::Owned(ascii_domain.into_owned()))
+            .unwrap_or(domain)
+    }
+}
+
+/// Returns whether `domain` is `parent` or one of its subdomains.
+pub(crate) fn is_subdomain(domain: &str, parent: &str) -> bool {
+    domain == parent || domain.ends_with(&format!(".{parent}"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use crate::{
+        dkim::Signature,
+        dmarc::{Alignment, Policy},
+        AuthenticatedMessage, DkimOutput, DkimResult, DmarcResult, Resolver, SpfOutput,
+    };
+
+    use super::{DefaultSuffixList, PublicSuffixList, SuffixListProvider};
+
+    const LIST: &str = concat!(
+        "// This Source Code Form is subject to the terms of the Mozilla Public\n",
+        "// License, v. 2.0.\n",
+        "\n",
+        "// ===BEGIN ICANN DOMAINS===\n",
+        "uk\n",
+        "co.uk\n",
+        "// ck : https://en.wikipedia.org/wiki/.ck\n",
+        "*.ck\n",
+        "!www.ck\n",
+        "公司.cn\n",
+        "cn\n",
+        "// ===END ICANN DOMAINS===\n",
+        "// ===BEGIN PRIVATE DOMAINS===\n",
+        "blogspot.co.uk   // trailing text is ignored\n",
+        "// ===END PRIVATE DOMAINS===\n",
+    );
+
+    #[test]
+    fn psl_effective_tld_plus_one() {
+        let psl = PublicSuffixList::parse(LIST, true);
+        for (domain, expected_result) in [
+            ("example.co.uk", Some("example.co.uk")),
+            ("mail.example.co.uk", Some("example.co.uk")),
+            ("MAIL.Example.CO.UK.", Some("example.co.uk")),
+            ("example.uk", Some("example.uk")),
+            ("co.uk", None),
+            ("uk", None),
+            // Wildcard rules make every label under ck a public suffix
+            ("example.ck", None),
+            ("mail.example.ck", Some("mail.example.ck")),
+            ("a.mail.example.ck", Some("mail.example.ck")),
+            // Exception rules override wildcards
+            ("www.ck", Some("www.ck")),
+            ("mail.www.ck", Some("www.ck")),
+            // Domains matching no rule use their top-level domain
+            ("mail.example.org", Some("example.org")),
+            ("org", None),
+            ("example.blogspot.co.uk", Some("example.blogspot.co.uk")),
+            ("a..example.org", None),
+        ] {
+            assert_eq!(
+                psl.effective_tld_plus_one(domain).as_deref(),
+                expected_result,
+                "{domain}"
+            );
+        }
+
+        // Internationalized names are converted to A-labels
+        #[cfg(feature = "idna")]
+        assert_eq!(
+            psl.effective_tld_plus_one("测试.公司.cn").as_deref(),
+            Some("xn--0zwm56d.xn--55qx5d.cn")
+        );
+
+        // Private entries can be excluded
+        let psl = PublicSuffixList::parse(LIST, false);
+        assert_eq!(
+            psl.effective_tld_plus_one("example.blogspot.co.uk")
+                .as_deref(),
+            Some("blogspot.co.uk")
+        );
+        assert_eq!(
+            DefaultSuffixList.effective_tld_plus_one("example.co.uk"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn psl_organizational_domain() {
+        // Cases from https://raw.githubusercontent.com/publicsuffix/list/master/tests/test_psl.txt,
+        // where public suffixes are expected to be returned unchanged
+        let psl = PublicSuffixList::parse(
+            concat!(
+                "com\nbiz\nac\n*.mm\njp\nac.jp\nkyoto.jp\nide.kyoto.jp\n*.kobe.jp\n",
+                "!city.kobe.jp\n*.ck\n!www.ck\nus\nak.us\nk12.ak.us\ncn\ncom.cn\n",
+                "公司.cn\n中国\n// ===BEGIN PRIVATE DOMAINS===\nuk.com\n"
+            ),
+            true,
+        );
+        for (domain, expected_org_domain) in [
+            // Mixed case and trailing dots
+            ("COM", "com"),
+            ("example.COM", "example.com"),
+            ("WwW.example.COM", "example.com"),
+            ("www.example.com.", "example.com"),
+            // Unlisted TLD
+            ("example", "example"),
+            ("example.example", "example.example"),
+            ("b.example.example", "example.example"),
+            ("a.b.example.example", "example.example"),
+            // TLD with only 1 rule
+            ("biz", "biz"),
+            ("domain.biz", "domain.biz"),
+            ("b.domain.biz", "domain.biz"),
+            ("a.b.domain.biz", "domain.biz"),
+            // TLD with some 2-level rules
+            ("example.com", "example.com"),
+            ("b.example.com", "example.com"),
+            ("a.b.example.com", "example.com"),
+            ("uk.com", "uk.com"),
+            ("example.uk.com", "example.uk.com"),
+            ("b.example.uk.com", "example.uk.com"),
+            ("a.b.example.uk.com", "example.uk.com"),
+            ("test.ac", "test.ac"),
+            // TLD with only 1 (wildcard) rule
+            ("mm", "mm"),
+            ("c.mm", "c.mm"),
+            ("b.c.mm", "b.c.mm"),
+            ("a.b.c.mm", "b.c.mm"),
+            // More complex TLD
+            ("jp", "jp"),
+            ("test.jp", "test.jp"),
+            ("www.test.jp", "test.jp"),
+            ("ac.jp", "ac.jp"),
+            ("test.ac.jp", "test.ac.jp"),
+            ("www.test.ac.jp", "test.ac.jp"),
+            ("kyoto.jp", "kyoto.jp"),
+            ("test.kyoto.jp", "test.kyoto.jp"),
+            ("ide.kyoto.jp", "ide.kyoto.jp"),
+            ("b.ide.kyoto.jp", "b.ide.kyoto.jp"),
+            ("a.b.ide.kyoto.jp", "b.ide.kyoto.jp"),
+            ("c.kobe.jp", "c.kobe.jp"),
+            ("b.c.kobe.jp", "b.c.kobe.jp"),
+            ("a.b.c.kobe.jp", "b.c.kobe.jp"),
+            ("city.kobe.jp", "city.kobe.jp"),
+            ("www.city.kobe.jp", "city.kobe.jp"),
+            // TLD with a wildcard rule and exceptions
+            ("ck", "ck"),
+            ("test.ck", "test.ck"),
+            ("b.test.ck", "b.test.ck"),
+            ("a.b.test.ck", "b.test.ck"),
+            ("www.ck", "www.ck"),
+            ("www.www.ck", "www.ck"),
+            // US K12
+            ("us", "us"),
+            ("test.us", "test.us"),
+            ("www.test.us", "test.us"),
+            ("ak.us", "ak.us"),
+            ("test.ak.us", "test.ak.us"),
+            ("www.test.ak.us", "test.ak.us"),
+            ("k12.ak.us", "k12.ak.us"),
+            ("test.k12.ak.us", "test.k12.ak.us"),
+            ("www.test.k12.ak.us", "test.k12.ak.us"),
+        ] {
+            assert_eq!(
+                psl.organizational_domain(domain).as_deref(),
+                Some(expected_org_domain),
+                "{domain}"
+            );
+        }
+
+        // IDN labels, which are returned as A-labels
+        #[cfg(feature = "idna")]
+        for (domain, expected_org_domain) in [
+            ("食狮.com.cn", "xn--85x722f.com.cn"),
+            ("食狮.公司.cn", "xn--85x722f.xn--55qx5d.cn"),
+            ("www.食狮.公司.cn", "xn--85x722f.xn--55qx5d.cn"),
+            ("shishi.公司.cn", "shishi.xn--55qx5d.cn"),
+            ("公司.cn", "xn--55qx5d.cn"),
+            ("食狮.中国", "xn--85x722f.xn--fiqs8s"),
+            ("www.食狮.中国", "xn--85x722f.xn--fiqs8s"),
+            ("shishi.中国", "shishi.xn--fiqs8s"),
+            ("中国", "xn--fiqs8s"),
+        ] {
+            assert_eq!(
+                psl.organizational_domain(domain).as_deref(),
+                Some(expected_org_domain),
+                "{domain}"
+            );
+        }
+        for (domain, expected_org_domain) in [
+            ("xn--85x722f.com.cn", "xn--85x722f.com.cn"),
+            ("xn--85x722f.xn--55qx5d.cn", "xn--85x722f.xn--55qx5d.cn"),
+            ("www.xn--85x722f.xn--55qx5d.cn", "xn--85x722f.xn--55qx5d.cn"),
+            ("shishi.xn--55qx5d.cn", "shishi.xn--55qx5d.cn"),
+            ("xn--55qx5d.cn", "xn--55qx5d.cn"),
+            ("xn--85x722f.xn--fiqs8s", "xn--85x722f.xn--fiqs8s"),
+            ("www.xn--85x722f.xn--fiqs8s", "xn--85x722f.xn--fiqs8s"),
+            ("shishi.xn--fiqs8s", "shishi.xn--fiqs8s"),
+            ("xn--fiqs8s", "xn--fiqs8s"),
+        ] {
+            assert_eq!(
+                psl.organizational_domain(domain).as_deref(),
+                Some(expected_org_domain),
+                "{domain}"
+            );
+        }
+        assert_eq!(psl.organizational_domain("a..example.com"), None);
+        assert_eq!(DefaultSuffixList.organizational_domain("example.com"), None);
+
+        // Without a suffix list, organizational domains are found with the
+        // DMARCbis tree walk, and domains without records are only normalized
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (name, record) in [
+            ("_dmarc.example.com.", "v=DMARC1; p=reject"),
+            ("_dmarc.psd.example.", "v=DMARC1; p=reject; psd=y"),
+        ] {
+            resolver.txt_add(
+                name,
+                crate::dmarc::Dmarc::from_str(record),
+                std::time::Instant::now() + std::time::Duration::new(3200, 0),
+            );
+        }
+        for (domain, expected_org_domain) in [
+            ("Mail.Example.COM.", "example.com"),
+            ("example.com", "example.com"),
+            ("www.owner.psd.example", "owner.psd.example"),
+            ("mail.example.org", "mail.example.org"),
+            ("localhost", "localhost"),
+        ] {
+            assert_eq!(
+                resolver.organizational_domain(domain).await.unwrap(),
+                expected_org_domain,
+                "{domain}"
+            );
+        }
+        #[cfg(feature = "idna")]
+        assert_eq!(
+            resolver.organizational_domain("食狮.中国").await.unwrap(),
+            "xn--85x722f.xn--fiqs8s"
+        );
+
+        // The suffix list takes precedence
+        let resolver = resolver.with_suffix_list(psl);
+        for (domain, expected_org_domain) in [
+            ("www.city.kobe.jp.", "city.kobe.jp"),
+            ("Mail.Example.COM.", "example.com"),
+            ("www.owner.psd.example", "psd.example"),
+        ] {
+            assert_eq!(
+                resolver.organizational_domain(domain).await.unwrap(),
+                expected_org_domain,
+                "{domain}"
+            );
+        }
+    }
+
+    #[test]
+    fn psl_alignment() {
+        let psl = PublicSuffixList::parse(LIST, true);
+        for (domain, from_domain, expected_default, expected_psl) in [
+            ("example.co.uk", "example.co.uk", true, true),
+            ("mail.example.co.uk", "example.co.uk", true, true),
+            ("example.co.uk", "news.example.co.uk", true, true),
+            // Siblings only share an organizational domain
+            ("mail.example.co.uk", "news.example.co.uk", false, true),
+            ("example.co.uk", "co.uk", true, false),
+            ("a.example.ck", "b.example.ck", false, false),
+            ("a.www.ck", "b.www.ck", false, true),
+            ("example.co.uk", "example.org", false, false),
+        ] {
+            assert_eq!(
+                Alignment::Relaxed.is_aligned(domain, from_domain, &DefaultSuffixList),
+                expected_default,
+                "{domain} {from_domain}"
+            );
+            assert_eq!(
+                Alignment::Relaxed.is_aligned(domain, from_domain, &psl),
+                expected_psl,
+                "{domain} {from_domain}"
+            );
+            assert_eq!(
+                Alignment::Strict.is_aligned(domain, from_domain, &psl),
+                domain == from_domain,
+                "{domain} {from_domain}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn psl_dmarc_verify() {
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_suffix_list(PublicSuffixList::parse(LIST, true));
+        resolver.txt_add(
+            "_dmarc.example.co.uk.",
+            crate::dmarc::Dmarc::from_str("v=DMARC1; p=reject; sp=quarantine"),
+            std::time::Instant::now() + std::time::Duration::new(3200, 0),
+        );
+
+        let message =
+            AuthenticatedMessage::parse(b"From: hello@news.example.co.uk\r\n\r\n").unwrap();
+        let signature = Signature {
+            d: "mail.example.co.uk".into(),
+            ..Default::default()
+        };
+        let dkim = DkimOutput {
+            result: DkimResult::Pass,
+            signature: (&signature).into(),
+            ..Default::default()
+        };
+        let result = resolver
+            .verify_dmarc(&message, &[dkim], "example.com", &SpfOutput::default())
+            .await;
+        assert_eq!(result.dkim_result(), &DmarcResult::Pass);
+        assert_eq!(result.policy(), Policy::Quarantine);
+        assert_eq!(result.record_domain(), Some("example.co.uk"));
+    }
+}
@@ -15,10 +15,52 @@ use crate::{
     SpfOutput, SpfResult,
 };
 
-use super::{Alignment, Dmarc, URI};
+use super::{
+    failed_with,
+    psl::{is_subdomain, normalize_domain, SuffixListProvider},
+    AlignedSignature, Alignment, AuthMechanism, DestinationAuthorization, Dmarc, DmarcDiscovery,
+    DmarcFrom, DmarcInput, PolicySource, Psd, ReportUri,
+};
+
+impl<'x> DmarcInput<'x> {
+    /// Evaluates the RFC5322.From domains of a message, with neither DKIM
+    /// nor SPF results.
+    pub fn new(message: &'x AuthenticatedMessage<'x>) -> Self {
+        DmarcInput {
+            from: DmarcFrom::Message(message),
+            dkim_output: None,
+            spf_output: None,
+        }
+    }
+
+    /// Evaluates an RFC5322.From domain that was extracted by the caller.
+    pub fn from_domain(domain: &'x str) -> Self {
+        DmarcInput {
+            from: DmarcFrom::Domain(domain),
+            dkim_output: None,
+            spf_output: None,
+        }
+    }
+
+    pub fn with_dkim_output(mut self, dkim_output: &'x [DkimOutput<'x>]) -> Self {
+        self.dkim_output = Some(dkim_output);
+        self
+    }
+
+    pub fn with_spf_output(mut self, mail_from_domain: &'x str, spf_output: &'x SpfOutput) -> Self {
+        self.spf_output = Some((mail_from_domain, spf_output));
+        self
+    }
+}
 
 impl Resolver {
-    /// Verifies the DMARC policy of an RFC5322.From domain
+    /// Verifies the DMARC policy of an RFC5322.From domain.
+    ///
+    /// Messages that cannot be attributed to a single author domain, since they have
+    /// several From headers, no From address or addresses in unrelated domains, fail
+    /// with a `reject` policy and a `MultipleFrom` or `MissingFrom` permerror (RFC 7489,
+    /// Section 6.6.1). Addresses in domains with the same organizational domain are
+    /// evaluated one by one, returning the first failing result.
     pub async fn verify_dmarc(
         &self,
         message: &AuthenticatedMessage<'_>,
@@ -26,26 +68,163 @@ impl Resolver {
         mail_from_domain: &str,
         spf_output: &SpfOutput,
     ) -> DmarcOutput {
+        self.verify_dmarc_input(
+            &DmarcInput::new(message)
+                .with_dkim_output(dkim_output)
+                .with_spf_output(mail_from_domain, spf_output),
+        )
+        .await
+    }
+
+    /// Verifies the DMARC policy of an RFC5322.From domain using the results of
+    /// the mechanisms supplied in `input`, as `verify_dmarc` does. A missing SPF
+    /// result is treated as SPF `none` and missing DKIM results as no signatures,
+    /// which is recorded in `DmarcOutput::evaluated_with`.
+    pub async fn verify_dmarc_input(&self, input: &DmarcInput<'_>) -> DmarcOutput {
+        self.verify_dmarc_from(input, None).await
+    }
+
+    /// Verifies `input` against a DMARC record supplied by the caller instead of
+    /// the one published in DNS, which is useful for simulating policy changes.
+    /// The subdomain policies apply when `record_domain` differs from the
+    /// RFC5322.From domain, as if the record had been found at `record_domain`.
+    pub async fn verify_dmarc_with_record(
+        &self,
+        input: &DmarcInput<'_>,
+        record: &Dmarc,
+        record_domain: &str,
+    ) -> DmarcOutput {
+        self.verify_dmarc_from(
+            input,
+            Some((
+                &Arc::new(record.clone()),
+                record_domain.trim_end_matches('.'),
+            )),
+        )
+        .await
+    }
+
+    async fn verify_dmarc_from(
+        &self,
+        input: &DmarcInput<'_>,
+        record: Option<(&Arc<Dmarc>, &str)>,
+    ) -> DmarcOutput {
+        let evaluated_with = input.dkim_output.map_or(0, |_| AuthMechanism::Dkim as u8)
+            | input.spf_output.map_or(0, |_| AuthMechanism::Spf as u8);
+        let dkim_output = input.dkim_output.unwrap_or(&[]);
+        let no_spf_output = SpfOutput::new(String::new());
+        let (mail_from_domain, spf_output) = input.spf_output.unwrap_or(("", &no_spf_output));
+
         // Extract RFC5322.From
-        let mut from_domain = "";
-        for from in &message.from {
-            if let Some((_, domain)) = from.rsplit_once('@') {
-                if from_domain.is_empty() {
-                    from_domain = domain;
-                } else if from_domain != domain {
-                    // Multi-valued RFC5322.From header fields with multiple
-                    // domains MUST be exempt from DMARC checking.
-                    return DmarcOutput::default();
+        let (from_domains, from_headers_count) = match &input.from {
+            DmarcFrom::Message(message) => (message.from_domains(), message.from_headers_count()),
+            DmarcFrom::Domain(domain) if !domain.is_empty() => (vec![*domain], 1),
+            DmarcFrom::Domain(_) => (vec![], 0),
+        };
+        let first_domain = match from_domains.first() {
+            Some(first_domain) => *first_domain,
+            None => {
+                return DmarcOutput::unattributed("", &[], Error::MissingFrom)
+                    .with_evaluated_with(evaluated_with)
+            }
+        };
+        if from_headers_count > 1 {
+            return DmarcOutput::unattributed(first_domain, &from_domains, Error::MultipleFrom)
+                .with_evaluated_with(evaluated_with);
+        }
+
+        // Addresses have to share the same organizational domain
+        if from_domains.len() > 1 {
+            let mut org_domains = Vec::with_capacity(from_domains.len());
+            for domain in &from_domains {
+                match self.organizational_domain(domain).await {
+                    Ok(org_domain) => org_domains.push(org_domain),
+                    Err(err) => {
+                        let err = DmarcResult::from(err);
+                        return DmarcOutput::default()
+                            .with_domain(first_domain)
+                            .with_dkim_result(err.clone())
+                            .with_spf_result(err)
+                            .with_from_domains(&from_domains)
+                            .with_evaluated_with(evaluated_with);
+                    }
                 }
             }
+            if org_domains
+                .iter()
+                .any(|org_domain| org_domain != &org_domains[0])
+            {
+                return DmarcOutput::unattributed(first_domain, &from_domains, Error::MultipleFrom)
+                    .with_evaluated_with(evaluated_with);
+            }
         }
-        if from_domain.is_empty() {
-            return DmarcOutput::default();
+
+        let mut result = None;
+        for from_domain in &from_domains {
+            let output = self
+                .verify_dmarc_domain(
+                    from_domain,
+                    dkim_output,
+                    mail_from_domain,
+                    spf_output,
+                    record,
+                )
+                .await;
+            let has_failed =
+                output.dkim_result != DmarcResult::Pass && output.spf_result != DmarcResult::Pass;
+            if result.is_none() || has_failed {
+                result = output.into();
+                if has_failed {
+                    break;
+                }
+            }
         }
 
-        // Obtain DMARC policy
-        let dmarc = match self.dmarc_tree_walk(from_domain).await {
-            Ok(Some(dmarc)) => dmarc,
+        result
+            .unwrap_or_default()
+            .with_from_domains(&from_domains)
+            .with_evaluated_with(evaluated_with)
+            .with_failed_with(failed_with(dkim_output, spf_output))
+    }
+
+    async fn verify_dmarc_domain(
+        &self,
+        from_domain: &str,
+        dkim_output: &[DkimOutput<'_>],
+        mail_from_domain: &str,
+        spf_output: &SpfOutput,
+        record: Option<(&Arc<Dmarc>, &str)>,
+    ) -> DmarcOutput {
+        // Obtain DMARC policy, unless it was supplied by the caller
+        let result = match (record, self.dmarc_discovery) {
+            (Some((dmarc, record_domain)), _) => {
+                Ok(Some((dmarc.clone(), record_domain.to_string(), None)))
+            }
+            (None, DmarcDiscovery::Rfc7489) => {
+                self.dmarc_tree_walk(from_domain).await.map(|result| {
+                    result.map(|(dmarc, record_domain, is_psd_fallback)| {
+                        (dmarc, record_domain, None, is_psd_fallback)
+                    })
+                })
+            }
+            (None, DmarcDiscovery::TreeWalk) => {
+                self.dmarc_bis_tree_walk(from_domain).await.map(|result| {
+                    result.map(|(dmarc, record_domain, org_domain)| {
+                        // Records of public suffixes apply when no record was found below them
+                        let is_psd_fallback =
+                            dmarc.psd == Psd::Yes && record_domain != normalize_domain(from_domain);
+                        (
+                            dmarc,
+                            record_domain,
+                            Some(OrganizationalDomain(org_domain)),
+                            is_psd_fallback,
+                        )
+                    })
+                })
+            }
+        };
+        let (dmarc, record_domain, org_domain, is_psd_fallback) = match result {
+            Ok(Some(result)) => result,
             Ok(None) => return DmarcOutput::default().with_domain(from_domain),
             Err(err) => {
                 let err = DmarcResult::from(err);
@@ -56,105 +235,336 @@ impl Resolver {
             }
         };
 
+        // Records without a usable policy disable DMARC processing, which is
+        // reported as a permerror rather than as a missing record
+        if !dmarc.is_usable() {
+            let err = DmarcResult::PermError(Error::UnusableRecord);
+            return DmarcOutput::default()
+                .with_domain(from_domain)
+                .with_dkim_result(err.clone())
+                .with_spf_result(err)
+                .with_record(dmarc, &record_domain);
+        }
+
+        // The p policy only applies to the domain the record was found at, so
+        // records inherited from a parent domain always use sp
+        let policy_source = if from_domain.eq_ignore_ascii_case(&record_domain) {
+            PolicySource::P
+        } else {
+            PolicySource::Sp
+        };
         let mut output = DmarcOutput {
             spf_result: DmarcResult::None,
             dkim_result: DmarcResult::None,
             domain: from_domain.to_string(),
-            policy: dmarc.p,
+            policy: dmarc.policy_for(policy_source),
             record: None,
+            record_domain: None,
+            sampled_policy: None,
+            dkim_alignment: None,
+            spf_alignment: None,
+            dkim_misalignment: None,
+            spf_misalignment: None,
+            policy_source,
+            from_domains: Vec::new(),
+            arc_override: None,
+            evaluated_with: AuthMechanism::Dkim as u8 | AuthMechanism::Spf as u8,
+            failed_with: 0,
+            is_psd_fallback,
+            overrides: Vec::new(),
         };
 
+        // Best-guess SPF results do not come from a published record
+        let has_spf_pass = spf_output.result == SpfResult::Pass && !spf_output.best_guess;
         let has_dkim_pass = dkim_output.iter().any(|o| o.result == DkimResult::Pass);
-        if spf_output.result == SpfResult::Pass || has_dkim_pass {
-            // Check SPF alignment
-            let from_subdomain = format!(".{from_domain}");
-            if spf_output.result == SpfResult::Pass {
-                output.spf_result = if mail_from_domain == from_domain {
-                    DmarcResult::Pass
-                } else if dmarc.aspf == Alignment::Relaxed
-                    && mail_from_domain.ends_with(&from_subdomain)
-                    || from_domain.ends_with(&format!(".{mail_from_domain}"))
-                {
-                    output.policy = dmarc.sp;
-                    DmarcResult::Pass
-                } else {
-                    DmarcResult::Fail(Error::NotAligned)
-                };
+        if has_spf_pass || has_dkim_pass {
+            // Check SPF alignment, using the organizational domain found by
+            // the tree walk instead of the suffix list when available
+            let suffix_list = match &org_domain {
+                Some(org_domain) => org_domain as &dyn SuffixListProvider,
+                None => self.suffix_list.as_ref(),
+            };
+            if has_spf_pass {
+                output.spf_result =
+                    if Alignment::Strict.is_aligned(mail_from_domain, from_domain, suffix_list) {
+                        output.spf_alignment = Alignment::Strict.into();
+                        DmarcResult::Pass
+                    } else if dmarc
+                        .aspf
+                        .is_aligned(mail_from_domain, from_domain, suffix_list)
+                    {
+                        output.spf_alignment = Alignment::Relaxed.into();
+                        DmarcResult::Pass
+                    } else {
+                        output.spf_misalignment = mail_from_domain.to_string().into();
+                        DmarcResult::Fail(Error::NotAligned)
+                    };
             }
 
             // Check DKIM alignment
             if has_dkim_pass {
-                output.dkim_result = if dkim_output.iter().any(|o| {
-                    o.result == DkimResult::Pass && o.signature.as_ref().unwrap().d.eq(from_domain)
-                }) {
-                    DmarcResult::Pass
-                } else if dmarc.adkim == Alignment::Relaxed
-                    && dkim_output.iter().any(|o| {
-                        o.result == DkimResult::Pass
-                            && (o.signature.as_ref().unwrap().d.ends_with(&from_subdomain)
-                                || from_domain
-                                    .ends_with(&format!(".{}", o.signature.as_ref().unwrap().d)))
-                    })
+                output.dkim_result = if let Some(signature) = dkim_output
+                    .iter()
+                    .find(|o| o.is_aligned_with(from_domain, true, suffix_list))
+                    .and_then(|o| o.signature)
                 {
-                    output.policy = dmarc.sp;
+                    output.dkim_alignment =
+                        AlignedSignature::new(signature, Alignment::Strict).into();
                     DmarcResult::Pass
-                } else {
-                    if dkim_output.iter().any(|o| {
-                        o.result == DkimResult::Pass
-                            && (o.signature.as_ref().unwrap().d.ends_with(&from_subdomain)
-                                || from_domain
-                                    .ends_with(&format!(".{}", o.signature.as_ref().unwrap().d)))
-                    }) {
-                        output.policy = dmarc.sp;
+                } else if let Some(signature) = dkim_output
+                    .iter()
+                    .find(|o| o.is_aligned_with(from_domain, false, suffix_list))
+                    .and_then(|o| o.signature)
+                {
+                    if dmarc.adkim == Alignment::Relaxed {
+                        output.dkim_alignment =
+                            AlignedSignature::new(signature, Alignment::Relaxed).into();
+                        DmarcResult::Pass
+                    } else {
+                        output.dkim_misalignment =
+                            AlignedSignature::new(signature, Alignment::Strict).into();
+                        DmarcResult::Fail(Error::NotAligned)
                     }
+                } else {
+                    // Report the first passing signature as the nearest miss
+                    output.dkim_misalignment = dkim_output
+                        .iter()
+                        .filter(|o| o.result == DkimResult::Pass)
+                        .find_map(|o| o.signature)
+                        .map(|signature| AlignedSignature::new(signature, dmarc.adkim));
                     DmarcResult::Fail(Error::NotAligned)
                 };
             }
         }
 
-        output.with_record(dmarc)
+        // Apply the policy for non-existent subdomains (RFC 9091), which is
+        // only done when np= is present
+        if output.dkim_result != DmarcResult::Pass
+            && output.spf_result != DmarcResult::Pass
+            && dmarc.has_np
+            && policy_source == PolicySource::Sp
+            && !self.domain_exists(from_domain).await
+        {
+            output.policy_source = PolicySource::Np;
+            output.policy = dmarc.policy_for(PolicySource::Np);
+        }
+
+        output.with_record(dmarc, &record_domain)
+    }
+
+    /// Returns whether a domain has MX, A or AAAA records. DNS errors are treated
+    /// as existing domains, so that they never lead to a harsher policy.
+    async fn domain_exists(&self, domain: &str) -> bool {
+        match self.mx_lookup(domain).await {
+            Ok(mxs) if !mxs.is_empty() => return true,
+            Ok(_) | Err(Error::DnsRecordNotFound(_)) => (),
+            Err(_) => return true,
+        }
+        match self.ipv4_lookup(domain).await {
+            Ok(ips) if !ips.is_empty() => return true,
+            Ok(_) | Err(Error::DnsRecordNotFound(_)) => (),
+            Err(_) => return true,
+        }
+        match self.ipv6_lookup(domain).await {
+            Ok(ips) => !ips.is_empty(),
+            Err(Error::DnsRecordNotFound(_)) => false,
+            Err(_) => true,
+        }
     }
 
-    /// Validates the external report e-mail addresses of a DMARC record
+    /// Validates the external report e-mail addresses of a DMARC record, returning
+    /// the authorized ones or `None` when verification has to be retried later.
+    /// Destinations other than valid `mailto` URIs are skipped.
     pub async fn verify_dmarc_report_address<'x>(
         &self,
         domain: &str,
-        addresses: &'x [URI],
-    ) -> Option<Vec<&'x URI>> {
+        addresses: &'x [ReportUri],
+    ) -> Option<Vec<&'x ReportUri>> {
         let mut result = Vec::with_capacity(addresses.len());
-        for address in addresses {
-            if address.uri.ends_with(domain)
-                || match self
-                    .txt_lookup::<Dmarc>(format!(
-                        "{}._report._dmarc.{}.",
-                        domain,
-                        address
-                            .uri
-                            .rsplit_once('@')
-                            .map(|(_, d)| d)
-                            .unwrap_or_default()
-                    ))
-                    .await
-                {
-                    Ok(_) => true,
-                    Err(Error::DnsError(_)) => return None,
-                    _ => false,
-                }
+        for address in addresses.iter().filter(|address| address.is_mailto()) {
+            match self
+                .verify_report_destination(domain, address.address())
+                .await
             {
-                result.push(address);
+                DestinationAuthorization::Authorized => result.push(address),
+                DestinationAuthorization::Unauthorized => (),
+                DestinationAuthorization::RetryLater => return None,
             }
         }
 
         result.into()
     }
 
-    async fn dmarc_tree_walk(&self, domain: &str) -> crate::Result<Option<Arc<Dmarc>>> {
+    /// Verifies that the owner of a report destination outside the policy domain
+    /// accepts reports for it, by looking up a `v=DMARC1` record at
+    /// `<policy-domain>._report._dmarc.<destination-domain>` or at the wildcard
+    /// `*._report._dmarc.<destination-domain>` (RFC 7489, Section 7.1).
+    pub async fn verify_report_destination(
+        &self,
+        policy_domain: &str,
+        destination: &str,
+    ) -> DestinationAuthorization {
+        let policy_domain = policy_domain.trim_end_matches('.').to_lowercase();
+        let destination_domain = destination
+            .rsplit_once('@')
+            .map_or(destination, |(_, domain)| domain)
+            .trim_end_matches('.')
+            .to_lowercase();
+
+        // Destinations within the policy domain need no authorization
+        if destination_domain == policy_domain
+            || destination_domain.ends_with(&format!(".{policy_domain}"))
+        {
+            return DestinationAuthorization::Authorized;
+        }
+
+        for name in [
+            format!("{policy_domain}._report._dmarc.{destination_domain}."),
+            format!("*._report._dmarc.{destination_domain}."),
+        ] {
+            match self.txt_lookup::<Dmarc>(name).await {
+                Ok(_) => return DestinationAuthorization::Authorized,
+                Err(Error::DnsError(_)) => return DestinationAuthorization::RetryLater,
+                Err(_) => (),
+            }
+        }
+
+        DestinationAuthorization::Unauthorized
+    }
+
+    /// Returns the organizational domain of `domain`, as used by DMARC discovery
+    /// and alignment. It is given by the suffix list set with `with_suffix_list`
+    /// or, for domains the list does not know, by the `psd=` tags of the DMARC
+    /// records found with the DMARCbis tree walk. Domains without any record
+    /// are returned lowercased, converted to A-labels and without trailing dots.
+    pub async fn organizational_domain(&self, domain: &str) -> crate::Result<String> {
+        match self.suffix_list.organizational_domain(domain) {
+            Some(org_domain) => Ok(org_domain.into_owned()),
+            None => self.dmarc_bis_tree_walk(domain).await.map(|result| {
+                result.map_or_else(
+                    || normalize_domain(domain).into_owned(),
+                    |(_, _, org_domain)| org_domain,
+                )
+            }),
+        }
+    }
+
+    /// Looks up the DMARC record of a domain with the DMARCbis tree walk, returning
+    /// the first record found along with its domain and the organizational domain.
+    /// The walk continues past the first record until a record with a `psd=` tag is
+    /// found. The organizational domain is then the domain with `psd=n`, or the one
+    /// just below the domain with `psd=y`. Otherwise, it is the domain with the
+    /// fewest labels that has a record.
+    async fn dmarc_bis_tree_walk(
+        &self,
+        domain: &str,
+    ) -> crate::Result<Option<(Arc<Dmarc>, String, String)>> {
+        let domain = normalize_domain(domain);
+        let labels = domain.split('.').collect::<Vec<_>>();
+        let mut policy = None;
+        let mut org_domain = None;
+        let mut x = labels.len();
+
+        while x != 0 {
+            let name = labels[labels.len() - x..].join(".");
+            match self.txt_lookup::<Dmarc>(format!("_dmarc.{name}.")).await {
+                // Public suffixes have to opt in with psd=y (RFC 9091)
+                Ok(dmarc) if x > 1 || dmarc.psd == Psd::Yes => {
+                    let psd = dmarc.psd;
+                    if policy.is_none() {
+                        policy = Some((dmarc, name.clone()));
+                    }
+                    match psd {
+                        Psd::No => {
+                            org_domain = Some(name);
+                            break;
+                        }
+                        Psd::Yes => {
+                            org_domain = Some(if x < labels.len() {
+                                labels[labels.len() - x - 1..].join(".")
+                            } else {
+                                name
+                            });
+                            break;
+                        }
+                        Psd::Default => {
+                            org_domain = Some(name);
+                        }
+                    }
+                }
+                Ok(_)
+                | Err(Error::DnsRecordNotFound(_))
+                | Err(Error::InvalidRecordType)
+                | Err(Error::ParseError) => (),
+                Err(err) => return Err(err),
+            }
+
+            // Names with more than 4 labels are shortened to 4 labels
+            if x < 5 {
+                x -= 1;
+            } else {
+                x = 4;
+            }
+        }
+
+        Ok(policy.map(|(dmarc, record_domain)| {
+            (
+                dmarc,
+                record_domain,
+                org_domain.unwrap_or_else(|| domain.into_owned()),
+            )
+        }))
+    }
+
+    /// Looks up the DMARC record of a domain, falling back to its parent domains.
+    /// Returns the record along with the domain at which it was found, and whether
+    /// it is the record of a public suffix used in place of a missing one.
+    async fn dmarc_tree_walk(
+        &self,
+        domain: &str,
+    ) -> crate::Result<Option<(Arc<Dmarc>, String, bool)>> {
+        // Query the organizational domain when known (RFC 7489, Section 6.6.3)
+        if let Some(org_domain) = self.suffix_list.organizational_domain(domain) {
+            let org_domain = org_domain.into_owned();
+            let mut names = vec![normalize_domain(domain).into_owned()];
+            if names[0] != org_domain {
+                names.push(org_domain);
+            }
+            for name in names {
+                match self.txt_lookup::<Dmarc>(format!("_dmarc.{name}.")).await {
+                    Ok(dmarc) => return Ok(Some((dmarc, name, false))),
+                    // Invalid records are discarded
+                    Err(Error::DnsRecordNotFound(_))
+                    | Err(Error::InvalidRecordType)
+                    | Err(Error::ParseError) => (),
+                    Err(err) => return Err(err),
+                }
+            }
+
+            // Fall back to the record of the public suffix (RFC 9091)
+            if let Some((_, psd)) = org_domain.split_once('.').filter(|_| self.psd_discovery) {
+                match self.txt_lookup::<Dmarc>(format!("_dmarc.{psd}.")).await {
+                    Ok(dmarc) if dmarc.psd == Psd::Yes => {
+                        return Ok(Some((dmarc, psd.to_string(), true)));
+                    }
+                    Ok(_)
+                    | Err(Error::DnsRecordNotFound(_))
+                    | Err(Error::InvalidRecordType)
+                    | Err(Error::ParseError) => (),
+                    Err(err) => return Err(err),
+                }
+            }
+            return Ok(None);
+        }
+
         let labels = domain.split('.').collect::<Vec<_>>();
         let mut x = labels.len();
         if x == 1 {
             return Ok(None);
         }
-        while x != 0 {
+        // Single labels are only queried for public suffix records
+        let min_labels = if self.psd_discovery { 1 } else { 2 };
+        while x >= min_labels {
             // Build query domain
             let mut domain = String::with_capacity(domain.len() + 8);
             domain.push_str("_dmarc");
@@ -165,11 +575,21 @@ impl Resolver {
             domain.push('.');
 
             // Query DMARC
-            match self.txt_lookup::<Dmarc>(domain).await {
-                Ok(dmarc) => {
-                    return Ok(Some(dmarc));
+            match self.txt_lookup::<Dmarc>(domain.as_str()).await {
+                Ok(dmarc) if x > 1 || dmarc.psd == Psd::Yes => {
+                    let record_domain = domain
+                        .strip_prefix("_dmarc.")
+                        .unwrap_or_default()
+                        .trim_end_matches('.')
+                        .to_string();
+                    let is_psd_fallback = dmarc.psd == Psd::Yes && x < labels.len();
+                    return Ok(Some((dmarc, record_domain, is_psd_fallback)));
                 }
-                Err(Error::DnsRecordNotFound(_)) | Err(Error::InvalidRecordType) => (),
+                // Public suffixes have to opt in with psd=y (RFC 9091)
+                Ok(_) => (),
+                Err(Error::DnsRecordNotFound(_))
+                | Err(Error::InvalidRecordType)
+                | Err(Error::ParseError) => (),
                 Err(err) => return Err(err),
             }
 
@@ -187,17 +607,63 @@ impl Resolver {
     }
 }
 
+/// Organizational domain found by the DMARCbis tree walk, which is shared by
+/// all of its subdomains.
+struct OrganizationalDomain(String);
+
+impl SuffixListProvider for OrganizationalDomain {
+    fn effective_tld_plus_one(&self, domain: &str) -> Option<String> {
+        if is_subdomain(&normalize_domain(domain), &self.0) {
+            Some(self.0.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl Alignment {
+    /// Returns whether an authenticated domain is aligned with the RFC5322.From domain.
+    /// Relaxed alignment holds when both domains have the same organizational domain,
+    /// or when neither has a known one and either domain is a subdomain of the other.
+    pub(crate) fn is_aligned(
+        &self,
+        domain: &str,
+        from_domain: &str,
+        suffix_list: &dyn SuffixListProvider,
+    ) -> bool {
+        let (domain, from_domain) = (normalize_domain(domain), normalize_domain(from_domain));
+        domain == from_domain
+            || (*self == Alignment::Relaxed
+                && match (
+                    suffix_list.organizational_domain(&domain),
+                    suffix_list.organizational_domain(&from_domain),
+                ) {
+                    (Some(domain), Some(from_domain)) => domain == from_domain,
+                    (None, None) => {
+                        is_subdomain(&domain, &from_domain) || is_subdomain(&from_domain, &domain)
+                    }
+                    _ => false,
+                })
+    }
+}
+
 #[cfg(test)]
 #[allow(unused)]
 mod test {
-    use std::time::{Duration, Instant};
+    use std::{
+        str::FromStr,
+        time::{Duration, Instant},
+    };
 
     use crate::{
-        common::parse::TxtRecordParser,
         dkim::Signature,
-        dmarc::{Dmarc, Policy, URI},
+        dmarc::{
+            psl::PublicSuffixList, Alignment, AuthMechanism, DestinationAuthorization, Dmarc,
+            DmarcDiscovery, DmarcInput, Policy, PolicySource, ReportUri,
+        },
+        report::{ActionDisposition, Record, SPFDomainScope},
         AuthenticatedMessage, DkimOutput, DkimResult, DmarcResult, Error, Resolver, SpfOutput,
-        SpfResult,
+        SpfResult, MX,
     };
 
     #[tokio::test]
@@ -246,7 +712,7 @@ mod test {
                 SpfResult::Pass,
                 DmarcResult::Pass,
                 DmarcResult::Pass,
-                Policy::Quarantine,
+                Policy::Reject,
             ),
             // Strict - Fail
             (
@@ -262,7 +728,7 @@ mod test {
                 SpfResult::Pass,
                 DmarcResult::Fail(Error::NotAligned),
                 DmarcResult::Fail(Error::NotAligned),
-                Policy::Quarantine,
+                Policy::Reject,
             ),
             // Strict - Pass with tree walk
             (
@@ -278,7 +744,7 @@ mod test {
                 SpfResult::Pass,
                 DmarcResult::Pass,
                 DmarcResult::Pass,
-                Policy::Reject,
+                Policy::Quarantine,
             ),
             // Relaxed - Pass with tree walk
             (
@@ -316,7 +782,7 @@ mod test {
             #[cfg(any(test, feature = "test"))]
             resolver.txt_add(
                 dmarc_dns,
-                Dmarc::parse(dmarc.as_bytes()).unwrap(),
+                Dmarc::from_str(dmarc).unwrap(),
                 Instant::now() + Duration::new(3200, 0),
             );
 
@@ -328,14 +794,12 @@ mod test {
             let dkim = DkimOutput {
                 result: dkim,
                 signature: (&signature).into(),
-                report: None,
-                is_atps: false,
+                ..Default::default()
             };
             let spf = SpfOutput {
                 result: spf,
                 domain: mail_from_domain.to_string(),
-                report: None,
-                explanation: None,
+                ..Default::default()
             };
             let result = resolver
                 .verify_dmarc(&auth_message, &[dkim], mail_from_domain, &spf)
@@ -343,6 +807,1092 @@ mod test {
             assert_eq!(result.dkim_result, expect_dkim);
             assert_eq!(result.spf_result, expect_spf);
             assert_eq!(result.policy, policy);
+
+            let record_domain = dmarc_dns
+                .strip_prefix("_dmarc.")
+                .unwrap()
+                .trim_end_matches('.');
+            assert_eq!(result.record_domain(), Some(record_domain));
+            assert_eq!(
+                result.is_inherited_record(),
+                result.domain() != record_domain
+            );
+            assert_eq!(
+                result.dmarc_record().unwrap(),
+                &Dmarc::from_str(dmarc).unwrap()
+            );
+        }
+
+        // Best-guess SPF results are not used
+        let auth_message = AuthenticatedMessage::parse(b"From: hello@example.org\r\n\r\n").unwrap();
+        let spf = SpfOutput {
+            result: SpfResult::Pass,
+            domain: "example.org".to_string(),
+            best_guess: true,
+            ..Default::default()
+        };
+        let result = resolver
+            .verify_dmarc(&auth_message, &[], "example.org", &spf)
+            .await;
+        assert_eq!(result.spf_result, DmarcResult::None);
+    }
+
+    #[tokio::test]
+    async fn dmarc_verify_alignment_details() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::from_str("v=DMARC1; p=reject; aspf=s"),
+            Instant::now() + Duration::new(3200, 0),
+        );
+        let message = AuthenticatedMessage::parse(b"From: hello@example.org\r\n\r\n").unwrap();
+        let signatures = [
+            Signature {
+                d: "esp.example".into(),
+                s: "esp".into(),
+                ..Default::default()
+            },
+            Signature {
+                d: "mail.example.org".into(),
+                s: "relaxed".into(),
+                ..Default::default()
+            },
+            Signature {
+                d: "example.org".into(),
+                s: "strict".into(),
+                ..Default::default()
+            },
+        ];
+        let dkim = |pos: usize| DkimOutput {
+            result: DkimResult::Pass,
+            signature: (&signatures[pos]).into(),
+            ..Default::default()
+        };
+        let spf = |domain: &str| SpfOutput {
+            result: SpfResult::Pass,
+            domain: domain.to_string(),
+            ..Default::default()
+        };
+
+        // Strict and relaxed passes
+        let result = resolver
+            .verify_dmarc(
+                &message,
+                &[dkim(0), dkim(1), dkim(2)],
+                "example.org",
+                &spf("example.org"),
+            )
+            .await;
+        let signature = result.dkim_alignment().unwrap();
+        assert_eq!(
+            (signature.domain(), signature.selector(), signature.mode()),
+            ("example.org", "strict", Alignment::Strict)
+        );
+        assert_eq!(result.spf_alignment(), Some(Alignment::Strict));
+        assert_eq!(result.alignment_failure(), None);
+
+        let result = resolver
+            .verify_dmarc(
+                &message,
+                &[dkim(0), dkim(1)],
+                "example.org",
+                &spf("example.org"),
+            )
+            .await;
+        let signature = result.dkim_alignment().unwrap();
+        assert_eq!(
+            (signature.domain(), signature.selector(), signature.mode()),
+            ("mail.example.org", "relaxed", Alignment::Relaxed)
+        );
+        assert_eq!(result.dkim_misalignment(), None);
+
+        // Nearest misses
+        let result = resolver
+            .verify_dmarc(
+                &message,
+                &[dkim(0)],
+                "bounce.example.org",
+                &spf("bounce.example.org"),
+            )
+            .await;
+        assert_eq!(result.dkim_alignment(), None);
+        assert_eq!(result.spf_alignment(), None);
+        assert_eq!(result.dkim_misalignment().unwrap().domain(), "esp.example");
+        assert_eq!(result.spf_misalignment(), Some("bounce.example.org"));
+        assert_eq!(
+            result.alignment_failure().unwrap(),
+            concat!(
+                "DKIM passed for esp.example but relaxed alignment with example.org failed; ",
+                "SPF passed for bounce.example.org but strict alignment with example.org failed"
+            )
+        );
+
+        // Aligned signatures are included in aggregate reports
+        let record = Record::new().with_dmarc_output(&result);
+        assert_eq!(
+            record
+                .dkim_auth_result()
+                .iter()
+                .map(|result| (result.domain(), result.selector(), result.human_result()))
+                .collect::<Vec<_>>(),
+            vec![(
+                "esp.example",
+                "esp",
+                Some("relaxed alignment with example.org failed")
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn dmarc_verify_partial_input() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::from_str("v=DMARC1; p=reject"),
+            Instant::now() + Duration::new(3200, 0),
+        );
+        let message = AuthenticatedMessage::parse(b"From: hello@example.org\r\n\r\n").unwrap();
+        let signature = Signature {
+            d: "example.org".into(),
+            s: "default".into(),
+            ..Default::default()
+        };
+        let dkim = [DkimOutput {
+            result: DkimResult::Pass,
+            signature: (&signature).into(),
+            ..Default::default()
+        }];
+        let spf = SpfOutput {
+            result: SpfResult::Pass,
+            domain: "example.org".to_string(),
+            ..Default::default()
+        };
+
+        // DKIM only
+        let result = resolver
+            .verify_dmarc_input(&DmarcInput::new(&message).with_dkim_output(&dkim))
+            .await;
+        assert_eq!(result.dkim_result(), &DmarcResult::Pass);
+        assert_eq!(result.spf_result(), &DmarcResult::None);
+        assert_eq!(result.evaluated_with(), AuthMechanism::Dkim as u8);
+        assert!(!result.is_evaluated_with(AuthMechanism::Spf));
+        let record = Record::new()
+            .with_dkim_output(&dkim)
+            .with_dmarc_output(&result);
+        assert_eq!(record.action_disposition(), ActionDisposition::Pass);
+        assert_eq!(record.dmarc_spf_result(), crate::report::DmarcResult::Fail);
+        assert_eq!(
+            record
+                .spf_auth_result()
+                .iter()
+                .map(|result| (result.domain(), result.result(), result.human_result()))
+                .collect::<Vec<_>>(),
+            vec![(
+                "example.org",
+                crate::report::SpfResult::None,
+                Some("spf not evaluated")
+            )]
+        );
+
+        // SPF only, with a From domain extracted by the caller
+        let result = resolver
+            .verify_dmarc_input(
+                &DmarcInput::from_domain("example.org").with_spf_output("example.org", &spf),
+            )
+            .await;
+        assert_eq!(result.dkim_result(), &DmarcResult::None);
+        assert_eq!(result.spf_result(), &DmarcResult::Pass);
+        assert_eq!(result.evaluated_with(), AuthMechanism::Spf as u8);
+        assert_eq!(result.from_domains(), &["example.org".to_string()]);
+        let record = Record::new()
+            .with_spf_output(&spf, SPFDomainScope::MailFrom)
+            .with_dmarc_output(&result);
+        assert_eq!(record.dmarc_dkim_result(), crate::report::DmarcResult::Fail);
+        assert!(record.dkim_auth_result().is_empty());
+        assert_eq!(record.spf_auth_result().len(), 1);
+
+        // Neither mechanism
+        let result = resolver
+            .verify_dmarc_input(&DmarcInput::from_domain("example.org"))
+            .await;
+        assert_eq!(result.policy(), Policy::Reject);
+        assert_eq!(result.evaluated_with(), 0);
+        assert_eq!(result.disposition([]).policy(), Policy::Reject);
+        let result = resolver
+            .verify_dmarc_input(&DmarcInput::from_domain("").with_dkim_output(&dkim))
+            .await;
+        assert_eq!(
+            result.dkim_result(),
+            &DmarcResult::PermError(Error::MissingFrom)
+        );
+
+        // Full inputs evaluate both mechanisms
+        let result = resolver
+            .verify_dmarc(&message, &dkim, "example.org", &spf)
+            .await;
+        assert_eq!(
+            result.evaluated_with(),
+            AuthMechanism::Dkim as u8 | AuthMechanism::Spf as u8
+        );
+    }
+
+    #[tokio::test]
+    async fn dmarc_verify_from_headers() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::from_str("v=DMARC1; p=none"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc.example.net.",
+            Dmarc::from_str("v=DMARC1; p=quarantine; adkim=s"),
+            valid_until,
+        );
+
+        for (message, signature_domain, expected_dkim, expected_policy, expected_domains) in [
+            // Multiple From headers, even in the same domain
+            (
+                "From: a@example.org\r\nFrom: b@example.org\r\n\r\n",
+                "example.org",
+                DmarcResult::PermError(Error::MultipleFrom),
+                Policy::Reject,
+                vec!["example.org"],
+            ),
+            // Addresses in unrelated domains
+            (
+                "From: a@example.org, b@example.com\r\n\r\n",
+                "example.org",
+                DmarcResult::PermError(Error::MultipleFrom),
+                Policy::Reject,
+                vec!["example.org", "example.com"],
+            ),
+            // Missing or empty From
+            (
+                "From: undisclosed-recipients:;\r\n\r\n",
+                "example.org",
+                DmarcResult::PermError(Error::MissingFrom),
+                Policy::Reject,
+                vec![],
+            ),
+            (
+                "Subject: no From\r\n\r\n",
+                "example.org",
+                DmarcResult::PermError(Error::MissingFrom),
+                Policy::Reject,
+                vec![],
+            ),
+            // Group syntax with addresses in a single domain
+            (
+                "From: Team: a@example.org, b@example.org;\r\n\r\n",
+                "example.org",
+                DmarcResult::Pass,
+                Policy::None,
+                vec!["example.org"],
+            ),
+            // Addresses with the same organizational domain are evaluated one by one
+            (
+                "From: a@example.org, b@mail.example.org\r\n\r\n",
+                "example.org",
+                DmarcResult::Pass,
+                Policy::None,
+                vec!["example.org", "mail.example.org"],
+            ),
+            (
+                "From: a@example.net, b@mail.example.net\r\n\r\n",
+                "example.net",
+                DmarcResult::Fail(Error::NotAligned),
+                Policy::Quarantine,
+                vec!["example.net", "mail.example.net"],
+            ),
+            // Sibling subdomains share the organizational domain of the record and
+            // are evaluated one by one
+            (
+                "From: a@mail.example.org, b@news.example.org\r\n\r\n",
+                "mail.example.org",
+                DmarcResult::Fail(Error::NotAligned),
+                Policy::None,
+                vec!["mail.example.org", "news.example.org"],
+            ),
+        ] {
+            let auth_message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+            let signature = Signature {
+                d: signature_domain.into(),
+                ..Default::default()
+            };
+            let dkim = DkimOutput {
+                result: DkimResult::Pass,
+                signature: (&signature).into(),
+                ..Default::default()
+            };
+            let result = resolver
+                .verify_dmarc(&auth_message, &[dkim], "example.com", &SpfOutput::default())
+                .await;
+            assert_eq!(result.dkim_result(), &expected_dkim, "{message}");
+            assert_eq!(result.policy(), expected_policy, "{message}");
+            assert_eq!(result.from_domains(), expected_domains, "{message}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dmarc_record_discovery() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        for (name, record) in [
+            ("_dmarc.example.com.", "v=DMARC1; p=reject; sp=quarantine"),
+            ("_dmarc.sub.example.com.", "v=DMARC1; p=none"),
+            ("_dmarc.example.net.", "v=DMARC1; p=reject"),
+            ("_dmarc.broken.org.", "v=DMARC1; p=bogus"),
+            (
+                "_dmarc.monitor.org.",
+                "v=DMARC1; rua=mailto:dmarc@monitor.org",
+            ),
+            ("_dmarc.org.", "v=DMARC1; p=reject"),
+            ("_dmarc.psd.", "v=DMARC1; p=reject; sp=quarantine; psd=y"),
+        ] {
+            resolver.txt_add(name, Dmarc::from_str(record), valid_until);
+        }
+
+        for (from, expected_record_domain, expected_subdomain_policy, expected_policy) in [
+            // Record at the From domain
+            (
+                "sub.example.com",
+                Some("sub.example.com"),
+                false,
+                Policy::None,
+            ),
+            ("example.com", Some("example.com"), false, Policy::Reject),
+            // Record at the organizational domain, where sp defaults to p
+            (
+                "mail.example.com",
+                Some("example.com"),
+                true,
+                Policy::Quarantine,
+            ),
+            (
+                "a.b.sub.example.com",
+                Some("sub.example.com"),
+                true,
+                Policy::None,
+            ),
+            (
+                "mail.example.net",
+                Some("example.net"),
+                true,
+                Policy::Reject,
+            ),
+            // Records without a policy but with report destinations are monitored
+            ("monitor.org", Some("monitor.org"), false, Policy::None),
+            // Public suffixes are not queried by default
+            ("example.psd", None, false, Policy::None),
+        ] {
+            let message =
+                AuthenticatedMessage::parse(format!("From: hello@{from}\r\n\r\n").as_bytes())
+                    .unwrap();
+            let result = resolver
+                .verify_dmarc(&message, &[], from, &SpfOutput::default())
+                .await;
+            assert_eq!(result.record_domain(), expected_record_domain, "{from}");
+            assert_eq!(
+                result.is_subdomain_policy(),
+                expected_subdomain_policy,
+                "{from}"
+            );
+            assert_eq!(result.policy(), expected_policy, "{from}");
+            assert_eq!(result.dkim_result(), &DmarcResult::None, "{from}");
+        }
+
+        // Records without a usable policy stop discovery and fail with a permerror
+        for from in ["broken.org", "mail.broken.org"] {
+            let message =
+                AuthenticatedMessage::parse(format!("From: hello@{from}\r\n\r\n").as_bytes())
+                    .unwrap();
+            let result = resolver
+                .verify_dmarc(&message, &[], from, &SpfOutput::default())
+                .await;
+            assert_eq!(result.record_domain(), Some("broken.org"), "{from}");
+            assert_eq!(result.policy(), Policy::None, "{from}");
+            assert_eq!(
+                result.dkim_result(),
+                &DmarcResult::PermError(Error::UnusableRecord),
+                "{from}"
+            );
+            assert_eq!(
+                result.spf_result(),
+                &DmarcResult::PermError(Error::UnusableRecord),
+                "{from}"
+            );
+        }
+
+        // Organizational domains with an unusable record do not fall back to their suffix
+        let resolver = resolver.with_suffix_list(PublicSuffixList::parse("org\n", true));
+        resolver
+            .mock_num_queries
+            .store(0, std::sync::atomic::Ordering::Relaxed);
+        let message = AuthenticatedMessage::parse(b"From: hello@broken.org\r\n\r\n").unwrap();
+        let result = resolver
+            .verify_dmarc(&message, &[], "broken.org", &SpfOutput::default())
+            .await;
+        assert_eq!(result.record_domain(), Some("broken.org"));
+        assert_eq!(
+            resolver
+                .mock_num_queries
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn dmarc_psd_discovery() {
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        let psl = PublicSuffixList::parse("psd\nnopsd\n", true);
+        for (suffix_list, psd_discovery) in [(None, false), (None, true), (Some(psl), true)] {
+            let mut resolver = Resolver::new_system_conf()
+                .unwrap()
+                .with_psd_discovery(psd_discovery);
+            if let Some(suffix_list) = suffix_list {
+                resolver = resolver.with_suffix_list(suffix_list);
+            }
+            for (name, record) in [
+                (
+                    "_dmarc.psd.",
+                    "v=DMARC1; p=reject; sp=quarantine; np=reject; psd=y",
+                ),
+                ("_dmarc.nopsd.", "v=DMARC1; p=reject; psd=n"),
+                ("_dmarc.owner.psd.", "v=DMARC1; p=none"),
+                ("_dmarc.self.psd.", "v=DMARC1; p=quarantine; psd=y"),
+            ] {
+                resolver.txt_add(name, Dmarc::from_str(record), valid_until);
+            }
+            resolver.ipv4_add(
+                "example.psd.",
+                vec!["192.0.2.1".parse().unwrap()],
+                valid_until,
+            );
+
+            for (from, expected_record_domain, expected_policy) in [
+                // Organizational domains without a record get the sp policy of the
+                // public suffix, or its np policy when they do not exist
+                ("example.psd", Some("psd"), Policy::Quarantine),
+                ("ghost.psd", Some("psd"), Policy::Reject),
+                // Records of organizational domains take precedence
+                ("owner.psd", Some("owner.psd"), Policy::None),
+                ("mail.owner.psd", Some("owner.psd"), Policy::None),
+                // Records with psd=y found at the RFC5322.From domain are not a fallback
+                ("self.psd", Some("self.psd"), Policy::Quarantine),
+                // Public suffixes have to opt in with psd=y
+                ("example.nopsd", None, Policy::None),
+            ] {
+                let message =
+                    AuthenticatedMessage::parse(format!("From: hello@{from}\r\n\r\n").as_bytes())
+                        .unwrap();
+                let result = resolver
+                    .verify_dmarc(&message, &[], from, &SpfOutput::default())
+                    .await;
+                let (expected_record_domain, expected_policy) =
+                    if psd_discovery || expected_record_domain != Some("psd") {
+                        (expected_record_domain, expected_policy)
+                    } else {
+                        (None, Policy::None)
+                    };
+                assert_eq!(
+                    result.record_domain(),
+                    expected_record_domain,
+                    "{from} {psd_discovery}"
+                );
+                assert_eq!(result.policy(), expected_policy, "{from} {psd_discovery}");
+                assert_eq!(
+                    result.is_psd_policy(),
+                    expected_record_domain == Some("psd"),
+                    "{from} {psd_discovery}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn dmarc_discovery_modes() {
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        let psl = PublicSuffixList::parse(
+            "com\nnet\n// ===BEGIN PRIVATE DOMAINS===\nblogspot.com\n",
+            true,
+        );
+        let rfc7489 = Resolver::new_system_conf()
+            .unwrap()
+            .with_suffix_list(psl.clone());
+        let tree_walk = Resolver::new_system_conf()
+            .unwrap()
+            .with_suffix_list(psl)
+            .with_dmarc_discovery(DmarcDiscovery::TreeWalk);
+        for resolver in [&rfc7489, &tree_walk] {
+            for (name, record) in [
+                ("_dmarc.blogspot.com.", "v=DMARC1; p=reject"),
+                (
+                    "_dmarc.psd.example.",
+                    "v=DMARC1; p=reject; sp=quarantine; psd=y",
+                ),
+                ("_dmarc.example.com.", "v=DMARC1; p=none"),
+                ("_dmarc.dept.example.com.", "v=DMARC1; p=reject; psd=n"),
+                ("_dmarc.example.net.", "v=DMARC1; p=quarantine"),
+            ] {
+                resolver.txt_add(name, Dmarc::from_str(record), valid_until);
+            }
+        }
+
+        let not_aligned = DmarcResult::Fail(Error::NotAligned);
+        for (from, signature_domain, expected_rfc7489, expected_tree_walk) in [
+            // Private registries are organizational domains in the suffix list,
+            // while the tree walk finds the record of the registry
+            (
+                "alice.blogspot.com",
+                "bob.blogspot.com",
+                (None, &DmarcResult::None),
+                (Some("blogspot.com"), &DmarcResult::Pass),
+            ),
+            // Domains just below a psd=y record are organizational domains
+            (
+                "mail.shop.psd.example",
+                "shop.psd.example",
+                (Some("psd.example"), &DmarcResult::Pass),
+                (Some("psd.example"), &DmarcResult::Pass),
+            ),
+            (
+                "mail.shop.psd.example",
+                "other.psd.example",
+                (Some("psd.example"), &DmarcResult::Pass),
+                (Some("psd.example"), &not_aligned),
+            ),
+            // Domains with a psd=n record are organizational domains
+            (
+                "mail.dept.example.com",
+                "example.com",
+                (Some("example.com"), &DmarcResult::Pass),
+                (Some("dept.example.com"), &not_aligned),
+            ),
+            (
+                "mail.dept.example.com",
+                "dept.example.com",
+                (Some("example.com"), &DmarcResult::Pass),
+                (Some("dept.example.com"), &DmarcResult::Pass),
+            ),
+        ] {
+            let message =
+                AuthenticatedMessage::parse(format!("From: hello@{from}\r\n\r\n").as_bytes())
+                    .unwrap();
+            let signature = Signature {
+                d: signature_domain.into(),
+                ..Default::default()
+            };
+            let dkim = DkimOutput {
+                result: DkimResult::Pass,
+                signature: (&signature).into(),
+                ..Default::default()
+            };
+            for (resolver, (expected_record_domain, expected_result)) in [
+                (&rfc7489, expected_rfc7489),
+                (&tree_walk, expected_tree_walk),
+            ] {
+                let result = resolver
+                    .verify_dmarc(
+                        &message,
+                        std::slice::from_ref(&dkim),
+                        from,
+                        &SpfOutput::default(),
+                    )
+                    .await;
+                assert_eq!(
+                    result.record_domain(),
+                    expected_record_domain,
+                    "{from} {signature_domain} {:?}",
+                    resolver.dmarc_discovery
+                );
+                assert_eq!(
+                    result.dkim_result(),
+                    expected_result,
+                    "{from} {signature_domain} {:?}",
+                    resolver.dmarc_discovery
+                );
+            }
+        }
+
+        // Policies of inherited records are taken from sp
+        let message =
+            AuthenticatedMessage::parse(b"From: hello@mail.shop.psd.example\r\n\r\n").unwrap();
+        let result = tree_walk
+            .verify_dmarc(
+                &message,
+                &[],
+                "mail.shop.psd.example",
+                &SpfOutput::default(),
+            )
+            .await;
+        assert_eq!(result.policy(), Policy::Quarantine);
+
+        // The tree walk shortens long names to 4 labels and continues to the
+        // top-level domain, while RFC 7489 queries the organizational domain
+        let message =
+            AuthenticatedMessage::parse(b"From: hello@a.b.c.d.e.example.net\r\n\r\n").unwrap();
+        for (resolver, expected_queries) in [(&rfc7489, 2), (&tree_walk, 5)] {
+            resolver
+                .mock_num_queries
+                .store(0, std::sync::atomic::Ordering::Relaxed);
+            let result = resolver
+                .verify_dmarc(
+                    &message,
+                    &[],
+                    "a.b.c.d.e.example.net",
+                    &SpfOutput::default(),
+                )
+                .await;
+            assert_eq!(result.record_domain(), Some("example.net"));
+            assert_eq!(result.policy(), Policy::Quarantine);
+            assert_eq!(
+                resolver
+                    .mock_num_queries
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                expected_queries
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn dmarc_policy_source() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        for (name, record) in [
+            ("_dmarc.example.com.", "v=DMARC1; p=reject; sp=quarantine"),
+            ("_dmarc.own.example.com.", "v=DMARC1; p=none"),
+            ("_dmarc.example.net.", "v=DMARC1; p=quarantine"),
+            (
+                "_dmarc.example.org.",
+                "v=DMARC1; p=reject; sp=none; np=quarantine",
+            ),
+        ] {
+            resolver.txt_add(name, Dmarc::from_str(record), valid_until);
+        }
+        resolver.ipv4_add(
+            "www.example.org.",
+            vec!["192.0.2.1".parse().unwrap()],
+            valid_until,
+        );
+
+        for (from, expected_record_domain, expected_source, expected_policy) in [
+            // The From domain is the organizational domain, so sp is ignored
+            (
+                "example.com",
+                "example.com",
+                PolicySource::P,
+                Policy::Reject,
+            ),
+            // Subdomains at any depth get sp
+            (
+                "shop.example.com",
+                "example.com",
+                PolicySource::Sp,
+                Policy::Quarantine,
+            ),
+            (
+                "news.shop.example.com",
+                "example.com",
+                PolicySource::Sp,
+                Policy::Quarantine,
+            ),
+            // Subdomains with their own record do not use the sp of the parent record
+            (
+                "own.example.com",
+                "own.example.com",
+                PolicySource::P,
+                Policy::None,
+            ),
+            (
+                "mail.own.example.com",
+                "own.example.com",
+                PolicySource::Sp,
+                Policy::None,
+            ),
+            // Absent sp falls back to p
+            (
+                "mail.example.net",
+                "example.net",
+                PolicySource::Sp,
+                Policy::Quarantine,
+            ),
+            // Non-existent subdomains get np
+            (
+                "www.example.org",
+                "example.org",
+                PolicySource::Sp,
+                Policy::None,
+            ),
+            (
+                "ghost.example.org",
+                "example.org",
+                PolicySource::Np,
+                Policy::Quarantine,
+            ),
+            // Without np, non-existent subdomains get sp
+            (
+                "ghost.example.net",
+                "example.net",
+                PolicySource::Sp,
+                Policy::Quarantine,
+            ),
+        ] {
+            let message =
+                AuthenticatedMessage::parse(format!("From: hello@{from}\r\n\r\n").as_bytes())
+                    .unwrap();
+            let result = resolver
+                .verify_dmarc(&message, &[], from, &SpfOutput::default())
+                .await;
+            assert_eq!(
+                result.record_domain(),
+                Some(expected_record_domain),
+                "{from}"
+            );
+            assert_eq!(result.policy_source(), expected_source, "{from}");
+            assert_eq!(result.policy(), expected_policy, "{from}");
+            assert_eq!(
+                result.dmarc_record().unwrap().policy_for(expected_source),
+                expected_policy,
+                "{from}"
+            );
+            assert_eq!(
+                result.is_subdomain_policy(),
+                expected_source != PolicySource::P,
+                "{from}"
+            );
+        }
+
+        // Passing messages from non-existent subdomains keep sp
+        let message =
+            AuthenticatedMessage::parse(b"From: hello@ghost.example.org\r\n\r\n").unwrap();
+        let result = resolver
+            .verify_dmarc(
+                &message,
+                &[],
+                "ghost.example.org",
+                &SpfOutput {
+                    result: SpfResult::Pass,
+                    domain: "ghost.example.org".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert_eq!(result.spf_result(), &DmarcResult::Pass);
+        assert_eq!(result.policy_source(), PolicySource::Sp);
+    }
+
+    #[tokio::test]
+    async fn dmarc_verify_np() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::from_str("v=DMARC1; p=quarantine; sp=none; np=reject"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc.example.com.",
+            Dmarc::from_str("v=DMARC1; p=quarantine; sp=none"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc.example.net.",
+            Dmarc::from_str("v=DMARC1; p=quarantine; sp=none; np=none"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc._dns_error.example.org.",
+            Err::<Dmarc, _>(Error::DnsRecordNotFound(
+                trust_dns_resolver::proto::op::ResponseCode::NXDomain,
+            )),
+            valid_until,
+        );
+        resolver.mx_add(
+            "mail.example.org.",
+            vec![MX {
+                exchanges: vec!["mx.example.org.".to_string()],
+                preference: 10,
+            }],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "www.example.org.",
+            vec!["192.0.2.1".parse().unwrap()],
+            valid_until,
+        );
+        resolver.ipv6_add(
+            "ipv6.example.org.",
+            vec!["2001:db8::1".parse().unwrap()],
+            valid_until,
+        );
+
+        for (from, expected_policy, expected_np, expected_queries) in [
+            // Non-existent subdomains get the np policy after three lookups
+            ("ghost.example.org", Policy::Reject, true, 5),
+            // Any positive answer means that the subdomain exists
+            ("mail.example.org", Policy::None, false, 3),
+            ("www.example.org", Policy::None, false, 4),
+            ("ipv6.example.org", Policy::None, false, 5),
+            // DNS errors never lead to the np policy
+            ("_dns_error.example.org", Policy::None, false, 3),
+            // An np equal to sp is still applied when present
+            ("ghost.example.net", Policy::None, true, 5),
+            // No existence checks for the organizational domain or without np
+            ("example.org", Policy::Quarantine, false, 1),
+            ("ghost.example.com", Policy::None, false, 2),
+        ] {
+            resolver
+                .mock_num_queries
+                .store(0, std::sync::atomic::Ordering::Relaxed);
+            let message =
+                AuthenticatedMessage::parse(format!("From: hello@{from}\r\n\r\n").as_bytes())
+                    .unwrap();
+            let result = resolver
+                .verify_dmarc(&message, &[], from, &SpfOutput::default())
+                .await;
+            assert_eq!(result.policy(), expected_policy, "{from}");
+            assert_eq!(result.is_np_policy(), expected_np, "{from}");
+            assert_eq!(
+                resolver
+                    .mock_num_queries
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                expected_queries,
+                "{from}"
+            );
+        }
+
+        // Passing messages are not checked
+        resolver
+            .mock_num_queries
+            .store(0, std::sync::atomic::Ordering::Relaxed);
+        let message =
+            AuthenticatedMessage::parse(b"From: hello@ghost.example.org\r\n\r\n").unwrap();
+        let signature = Signature {
+            d: "example.org".into(),
+            ..Default::default()
+        };
+        let dkim = DkimOutput {
+            result: DkimResult::Pass,
+            signature: (&signature).into(),
+            ..Default::default()
+        };
+        let result = resolver
+            .verify_dmarc(
+                &message,
+                &[dkim],
+                "ghost.example.org",
+                &SpfOutput::default(),
+            )
+            .await;
+        assert_eq!(result.dkim_result(), &DmarcResult::Pass);
+        assert!(!result.is_np_policy());
+        assert_eq!(
+            resolver
+                .mock_num_queries
+                .load(std::sync::atomic::Ordering::Relaxed),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn dmarc_verify_with_record() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::from_str("v=DMARC1; p=none"),
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "www.example.org.",
+            vec!["192.0.2.1".parse().unwrap()],
+            valid_until,
+        );
+        let record =
+            Dmarc::from_str("v=DMARC1; p=reject; sp=quarantine; np=reject; adkim=s").unwrap();
+        let signature = Signature {
+            d: "example.org".into(),
+            s: "default".into(),
+            ..Default::default()
+        };
+        let dkim = [DkimOutput {
+            result: DkimResult::Pass,
+            signature: (&signature).into(),
+            ..Default::default()
+        }];
+
+        for (from, record_domain, expected_policy, expected_np, expected_queries) in [
+            ("example.org", "example.org", Policy::Reject, false, 0),
+            (
+                "www.example.org",
+                "example.org",
+                Policy::Quarantine,
+                false,
+                2,
+            ),
+            ("ghost.example.org", "example.org", Policy::Reject, true, 3),
+            (
+                "www.example.org",
+                "www.example.org.",
+                Policy::Reject,
+                false,
+                0,
+            ),
+        ] {
+            resolver
+                .mock_num_queries
+                .store(0, std::sync::atomic::Ordering::Relaxed);
+            let result = resolver
+                .verify_dmarc_with_record(&DmarcInput::from_domain(from), &record, record_domain)
+                .await;
+            assert_eq!(result.policy(), expected_policy, "{from}");
+            assert_eq!(result.is_np_policy(), expected_np, "{from}");
+            assert_eq!(result.dmarc_record(), Some(&record), "{from}");
+            assert_eq!(
+                result.record_domain(),
+                Some(record_domain.trim_end_matches('.')),
+                "{from}"
+            );
+            assert_eq!(
+                resolver
+                    .mock_num_queries
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                expected_queries,
+                "{from}"
+            );
+        }
+
+        // Alignment follows the supplied record
+        let result = resolver
+            .verify_dmarc_with_record(
+                &DmarcInput::from_domain("www.example.org").with_dkim_output(&dkim),
+                &record,
+                "example.org",
+            )
+            .await;
+        assert_eq!(result.dkim_result(), &DmarcResult::Fail(Error::NotAligned));
+        let result = resolver
+            .verify_dmarc_with_record(
+                &DmarcInput::from_domain("example.org").with_dkim_output(&dkim),
+                &record,
+                "example.org",
+            )
+            .await;
+        assert_eq!(result.dkim_result(), &DmarcResult::Pass);
+    }
+
+    #[test]
+    fn dmarc_dkim_alignment() {
+        for (from, signature_domain, result, expect_strict, expect_relaxed) in [
+            (
+                "From: a@example.org\r\n\r\n",
+                "example.org",
+                DkimResult::Pass,
+                true,
+                true,
+            ),
+            // Subdomain signers, in either direction
+            (
+                "From: a@example.org\r\n\r\n",
+                "mail.example.org",
+                DkimResult::Pass,
+                false,
+                true,
+            ),
+            (
+                "From: a@news.example.org\r\n\r\n",
+                "example.org",
+                DkimResult::Pass,
+                false,
+                true,
+            ),
+            // Domains are compared ignoring case and trailing dots
+            (
+                "From: x@example.com\r\n\r\n",
+                "Example.COM",
+                DkimResult::Pass,
+                true,
+                true,
+            ),
+            (
+                "From: x@example.com\r\n\r\n",
+                "example.com.",
+                DkimResult::Pass,
+                true,
+                true,
+            ),
+            (
+                "From: x@news.example.com\r\n\r\n",
+                "EXAMPLE.com",
+                DkimResult::Pass,
+                false,
+                true,
+            ),
+            // Third-party and look-alike domains
+            (
+                "From: a@example.org\r\n\r\n",
+                "esp.net",
+                DkimResult::Pass,
+                false,
+                false,
+            ),
+            (
+                "From: a@example.org\r\n\r\n",
+                "badexample.org",
+                DkimResult::Pass,
+                false,
+                false,
+            ),
+            // Only passing signatures are aligned
+            (
+                "From: a@example.org\r\n\r\n",
+                "example.org",
+                DkimResult::Fail(Error::FailedVerification),
+                false,
+                false,
+            ),
+            // Multi-valued From headers within a single domain
+            (
+                "From: a@example.org, b@example.org\r\n\r\n",
+                "example.org",
+                DkimResult::Pass,
+                true,
+                true,
+            ),
+        ] {
+            let message = AuthenticatedMessage::parse(from.as_bytes()).unwrap();
+            let from_domain = message.from_domain().unwrap();
+            let signature = Signature {
+                d: signature_domain.into(),
+                ..Default::default()
+            };
+            let dkim = DkimOutput {
+                result,
+                signature: (&signature).into(),
+                ..Default::default()
+            };
+            assert_eq!(
+                dkim.is_aligned(from_domain, true),
+                expect_strict,
+                "{from} {signature_domain}"
+            );
+            assert_eq!(
+                dkim.is_aligned(from_domain, false),
+                expect_relaxed,
+                "{from} {signature_domain}"
+            );
+        }
+
+        // Multiple From domains and addresses without a domain
+        for from in [
+            "From: a@example.org, b@example.com\r\n\r\n",
+            "From: undisclosed-recipients:;\r\n\r\n",
+            "Subject: no From\r\n\r\n",
+        ] {
+            let message = AuthenticatedMessage::parse(from.as_bytes()).unwrap();
+            assert_eq!(message.from_domain(), None, "{from}");
         }
     }
 
@@ -352,13 +1902,14 @@ mod test {
         #[cfg(any(test, feature = "test"))]
         resolver.txt_add(
             "example.org._report._dmarc.external.org.",
-            Dmarc::parse(b"v=DMARC1").unwrap(),
+            Dmarc::from_str("v=DMARC1").unwrap(),
             Instant::now() + Duration::new(3200, 0),
         );
         let uris = vec![
-            URI::new("dmarc@example.org", 0),
-            URI::new("dmarc@external.org", 0),
-            URI::new("domain@other.org", 0),
+            ReportUri::new("mailto", "dmarc@example.org", None),
+            ReportUri::new("mailto", "dmarc@external.org", Some(1024)),
+            ReportUri::new("mailto", "domain@other.org", None),
+            ReportUri::new("https", "//example.org/dmarc", None),
         ];
 
         assert_eq!(
@@ -366,10 +1917,82 @@ mod test {
                 .verify_dmarc_report_address("example.org", &uris)
                 .await
                 .unwrap(),
+            vec![&uris[0], &uris[1]]
+        );
+    }
+
+    #[tokio::test]
+    async fn dmarc_verify_report_destination() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        resolver.txt_add(
+            "example.org._report._dmarc.reports.net.",
+            Dmarc::from_str("v=DMARC1"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "*._report._dmarc.wildcard.net.",
+            Dmarc::from_str("v=DMARC1;"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "example.org._report._dmarc.invalid.net.",
+            Dmarc::from_str("v=spf1 -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "example.org._report._dmarc.timeout.net.",
+            Err::<Dmarc, _>(Error::DnsError("timed out".to_string())),
+            valid_until,
+        );
+
+        for (destination, expected_authorization) in [
+            ("dmarc@example.org", DestinationAuthorization::Authorized),
+            (
+                "dmarc@reports.example.org",
+                DestinationAuthorization::Authorized,
+            ),
+            ("dmarc@reports.net", DestinationAuthorization::Authorized),
+            ("dmarc@REPORTS.NET", DestinationAuthorization::Authorized),
+            ("dmarc@wildcard.net", DestinationAuthorization::Authorized),
+            ("dmarc@invalid.net", DestinationAuthorization::Unauthorized),
+            ("dmarc@unknown.net", DestinationAuthorization::Unauthorized),
+            (
+                "dmarc@badexample.org",
+                DestinationAuthorization::Unauthorized,
+            ),
+            ("dmarc@timeout.net", DestinationAuthorization::RetryLater),
+        ] {
+            assert_eq!(
+                resolver
+                    .verify_report_destination("example.org", destination)
+                    .await,
+                expected_authorization,
+                "{destination}"
+            );
+        }
+
+        // Records pointing at unauthorized destinations are filtered out
+        let dmarc = Dmarc::from_str(concat!(
+            "v=DMARC1; p=reject; rua=mailto:dmarc@example.org,mailto:dmarc@wildcard.net,",
+            "mailto:dmarc@unknown.net; ruf=mailto:dmarc@timeout.net"
+        ))
+        .unwrap();
+        assert_eq!(
+            resolver
+                .verify_dmarc_report_address("example.org", dmarc.rua_uris())
+                .await
+                .unwrap(),
             vec![
-                &URI::new("dmarc@example.org", 0),
-                &URI::new("dmarc@external.org", 0),
+                &ReportUri::new("mailto", "dmarc@example.org", None),
+                &ReportUri::new("mailto", "dmarc@wildcard.net", None),
             ]
         );
+        assert_eq!(
+            resolver
+                .verify_dmarc_report_address("example.org", dmarc.ruf_uris())
+                .await,
+            None
+        );
     }
 }
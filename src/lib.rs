@@ -263,15 +263,17 @@ use std::{
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     sync::Arc,
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
-use arc::Set;
+use arc::{ArcInstanceResult, Set};
 use common::{crypto::HashAlgorithm, headers::Header, lru::LruCache, verify::DomainKey};
 use dkim::{Atps, Canonicalization, DomainKeyReport};
 use dmarc::Dmarc;
 use mta_sts::{MtaSts, TlsRpt};
-use spf::{Macro, Spf};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use spf::{Macro, Spf, SpfTraceEntry};
 use trust_dns_resolver::{proto::op::ResponseCode, TokioAsyncResolver};
 
 pub mod arc;
@@ -293,6 +295,27 @@ pub struct Resolver {
     pub(crate) cache_ipv4: LruCache<String, Arc<Vec<Ipv4Addr>>>,
     pub(crate) cache_ipv6: LruCache<String, Arc<Vec<Ipv6Addr>>>,
     pub(crate) cache_ptr: LruCache<IpAddr, Arc<Vec<String>>>,
+    pub(crate) max_clock_skew: Option<u64>,
+    pub(crate) body_length_policy: dkim::BodyLengthPolicy,
+    pub(crate) max_signatures: usize,
+    pub(crate) max_arc_instances: usize,
+    pub(crate) arc_strictness: arc::ArcStrictness,
+    pub(crate) min_key_strength: dkim::MinimumKeyStrength,
+    pub(crate) verify_domainkeys: bool,
+    pub(crate) identity_match: dkim::IdentityMatch,
+    pub(crate) key_fetchers: Vec<(String, Arc<dyn dkim::KeyFetcher>)>,
+    pub(crate) spf_lookup_limit: u32,
+    pub(crate) spf_void_lookup_limit: u32,
+    pub(crate) spf_trace: bool,
+    pub(crate) spf_best_guess: bool,
+    pub(crate) spf_timeout: Duration,
+    pub(crate) spf_query_timeout: Option<Duration>,
+    pub(crate) spf_policy: spf::SpfPolicy,
+    pub(crate) suffix_list: Arc<dyn dmarc::psl::SuffixListProvider>,
+    pub(crate) psd_discovery: bool,
+    pub(crate) dmarc_discovery: dmarc::DmarcDiscovery,
+    #[cfg(any(test, feature = "test"))]
+    pub(crate) mock_num_queries: std::sync::atomic::AtomicU32,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -335,12 +358,14 @@ pub struct AuthenticatedMessage<'x> {
     pub from: Vec<String>,
     pub raw_message: &'x [u8],
     pub body_offset: usize,
-    pub body_hashes: Vec<(Canonicalization, HashAlgorithm, u64, Vec<u8>)>,
+    pub body_hashes: Vec<(Canonicalization, HashAlgorithm, Option<u64>, Vec<u8>, u64)>,
     pub dkim_headers: Vec<Header<'x, crate::Result<dkim::Signature>>>,
+    pub domainkeys_headers: Vec<Header<'x, crate::Result<dkim::Signature>>>,
     pub ams_headers: Vec<Header<'x, crate::Result<arc::Signature>>>,
     pub as_headers: Vec<Header<'x, crate::Result<arc::Seal>>>,
     pub aar_headers: Vec<Header<'x, crate::Result<arc::Results>>>,
     pub received_headers_count: usize,
+    pub(crate) from_headers_count: usize,
     pub date_header_present: bool,
     pub message_id_header_present: bool,
 }
@@ -359,6 +384,7 @@ pub struct ReceivedSpf {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DkimResult {
     Pass,
     Neutral(crate::Error),
@@ -374,12 +400,21 @@ pub struct DkimOutput<'x> {
     signature: Option<&'x dkim::Signature>,
     report: Option<String>,
     is_atps: bool,
+    is_clock_skewed: bool,
+    is_partial_body: bool,
+    is_domainkeys: bool,
+    body_length: Option<u64>,
+    record: Option<Arc<DomainKey>>,
+    header_index: Option<usize>,
+    header_offset: usize,
+    raw_header: Option<&'x [u8]>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ArcOutput<'x> {
     result: DkimResult,
     set: Vec<Set<'x>>,
+    instances: Vec<ArcInstanceResult>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -393,12 +428,31 @@ pub enum SpfResult {
     None,
 }
 
+/// The identity an SPF check was performed on.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SpfIdentity {
+    Helo,
+    MailFrom,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct SpfOutput {
     result: SpfResult,
+    identity: SpfIdentity,
+    client_ip: Option<IpAddr>,
+    helo_domain: String,
+    sender: String,
     domain: String,
+    matched: Option<(String, String)>,
+    trace: Option<Vec<SpfTraceEntry>>,
+    ptr_names: Option<Vec<String>>,
+    escalated: bool,
+    best_guess: bool,
     report: Option<String>,
     explanation: Option<String>,
+    error: Option<Error>,
+    num_lookups: u32,
+    elapsed: Duration,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -408,6 +462,19 @@ pub struct DmarcOutput {
     domain: String,
     policy: dmarc::Policy,
     record: Option<Arc<Dmarc>>,
+    record_domain: Option<String>,
+    sampled_policy: Option<dmarc::Policy>,
+    dkim_alignment: Option<dmarc::AlignedSignature>,
+    spf_alignment: Option<dmarc::Alignment>,
+    dkim_misalignment: Option<dmarc::AlignedSignature>,
+    spf_misalignment: Option<String>,
+    policy_source: dmarc::PolicySource,
+    from_domains: Vec<String>,
+    arc_override: Option<arc::ArcOverride>,
+    evaluated_with: u8,
+    failed_with: u8,
+    is_psd_fallback: bool,
+    overrides: Vec<(report::PolicyOverrideReason, dmarc::Policy)>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -440,6 +507,7 @@ pub(crate) enum Version {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Error {
     ParseError,
     MissingParameters,
@@ -458,14 +526,56 @@ pub enum Error {
     IncompatibleAlgorithms,
     SignatureExpired,
     DnsError(String),
-    DnsRecordNotFound(ResponseCode),
+    DnsRecordNotFound(
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "serialize_response_code",
+                deserialize_with = "deserialize_response_code"
+            )
+        )]
+        ResponseCode,
+    ),
     ArcChainTooLong,
     ArcInvalidInstance(u32),
+    ArcDuplicateInstance(u32),
+    ArcMissingInstance(u32),
     ArcInvalidCV,
     ArcHasHeaderTag,
     ArcBrokenChain,
     NotAligned,
     InvalidRecordType,
+    FutureSignature,
+    PartialBody,
+    SignatureLimitExceeded,
+    WeakCryptography,
+    KeyGranularityMismatch,
+    UnsupportedKeyFormat(
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_key_formats"))]
+        &'static [&'static str],
+    ),
+    InvalidSignedHeaders(String),
+    AuidNotInDomain,
+    AuidNotExactDomain,
+    InvalidTag(
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_tag_name"))]
+        &'static str,
+    ),
+    NoValidSigner,
+    UnsupportedQueryMethod,
+    NotEvaluated,
+    ArcInvalidOrder,
+    ArcNotValidated,
+    LookupLimitExceeded,
+    VoidLookupLimitExceeded,
+    EvaluationLoop(String),
+    DeadlineExceeded,
+    RecordTooLong(usize),
+    MultipleRecords,
+    MxLimitExceeded,
+    MultipleFrom,
+    MissingFrom,
+    UnusableRecord,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -501,6 +611,8 @@ impl Display for Error {
             Error::ArcInvalidInstance(i) => {
                 write!(f, "Invalid 'i={i}' value found in ARC header")
             }
+            Error::ArcDuplicateInstance(i) => write!(f, "Duplicate ARC set with 'i={i}'"),
+            Error::ArcMissingInstance(i) => write!(f, "Missing ARC set with 'i={i}'"),
             Error::ArcInvalidCV => write!(f, "Invalid 'cv=' value found in ARC header"),
             Error::ArcHasHeaderTag => write!(f, "Invalid 'h=' tag present in ARC-Seal"),
             Error::ArcBrokenChain => write!(f, "Broken or missing ARC chain"),
@@ -509,10 +621,79 @@ impl Display for Error {
             Error::DnsError(err) => write!(f, "DNS resolution error: {err}"),
             Error::DnsRecordNotFound(code) => write!(f, "DNS record not found: {code}"),
             Error::NotAligned => write!(f, "Policy not aligned"),
+            Error::UnusableRecord => write!(f, "DMARC record has no usable policy"),
+            Error::MissingFrom => write!(f, "Missing RFC5322.From address"),
+            Error::MultipleFrom => write!(f, "Multiple RFC5322.From domains or headers"),
+            Error::MxLimitExceeded => write!(f, "Too many MX records"),
+            Error::MultipleRecords => write!(f, "Multiple records found"),
+            Error::RecordTooLong(len) => write!(f, "Record too long ({len} bytes)"),
+            Error::DeadlineExceeded => write!(f, "SPF evaluation deadline exceeded"),
+            Error::EvaluationLoop(domain) => write!(f, "SPF evaluation loop at {domain}"),
+            Error::LookupLimitExceeded => write!(f, "DNS lookup limit exceeded"),
+            Error::VoidLookupLimitExceeded => write!(f, "Void DNS lookup limit exceeded"),
+            Error::ArcNotValidated => write!(f, "ARC chain of the message was not validated"),
+            Error::ArcInvalidOrder => write!(f, "ARC headers are not in instance order"),
+            Error::NotEvaluated => write!(f, "Signature not evaluated"),
+            Error::UnsupportedQueryMethod => write!(f, "No supported query method"),
+            Error::NoValidSigner => write!(f, "No signing key is valid at this time"),
+            Error::InvalidTag(tag) => write!(f, "Invalid value for the {tag}= tag"),
+            Error::AuidNotExactDomain => {
+                write!(f, "AUID domain does not exactly match the signing domain")
+            }
+            Error::AuidNotInDomain => write!(f, "AUID is not within the signing domain"),
+            Error::InvalidSignedHeaders(reason) => write!(f, "Invalid signed headers: {reason}"),
+            Error::UnsupportedKeyFormat(formats) => write!(
+                f,
+                "Unsupported private key format, tried: {}",
+                formats.join(", ")
+            ),
+            Error::KeyGranularityMismatch => {
+                write!(f, "AUID local-part does not match key granularity")
+            }
+            Error::WeakCryptography => write!(f, "Signature uses a weak algorithm or key"),
+            Error::SignatureLimitExceeded => write!(f, "Too many signatures"),
+            Error::PartialBody => write!(f, "Signature does not cover the entire message body"),
+            Error::FutureSignature => write!(f, "Signature timestamp is in the future"),
         }
     }
 }
 
+#[cfg(feature = "serde")]
+fn serialize_response_code<S: serde::Serializer>(
+    code: &ResponseCode,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_u16((*code).into())
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_response_code<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<ResponseCode, D::Error> {
+    u16::deserialize(deserializer).map(ResponseCode::from)
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_key_formats<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<&'static [&'static str], D::Error> {
+    use common::crypto::{ED25519_KEY_FORMATS, RSA_KEY_FORMATS};
+
+    let formats = Vec::<String>::deserialize(deserializer)?;
+    [RSA_KEY_FORMATS, ED25519_KEY_FORMATS]
+        .into_iter()
+        .find(|known| formats == *known)
+        .ok_or_else(|| serde::de::Error::custom("unknown key formats"))
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_tag_name<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<&'static str, D::Error> {
+    dkim::parse::static_tag_name(&String::deserialize(deserializer)?)
+        .ok_or_else(|| serde::de::Error::custom("unknown tag"))
+}
+
 impl Display for SpfResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
@@ -588,9 +769,40 @@ impl Default for SpfOutput {
     fn default() -> Self {
         Self {
             result: SpfResult::None,
+            identity: SpfIdentity::MailFrom,
+            client_ip: Default::default(),
+            helo_domain: Default::default(),
+            sender: Default::default(),
             domain: Default::default(),
+            matched: Default::default(),
+            trace: Default::default(),
+            ptr_names: Default::default(),
+            escalated: Default::default(),
+            best_guess: Default::default(),
             report: Default::default(),
             explanation: Default::default(),
+            error: Default::default(),
+            num_lookups: Default::default(),
+            elapsed: Default::default(),
+        }
+    }
+}
+
+impl Default for DkimOutput<'_> {
+    fn default() -> Self {
+        Self {
+            result: DkimResult::None,
+            signature: Default::default(),
+            report: Default::default(),
+            is_atps: Default::default(),
+            is_clock_skewed: Default::default(),
+            is_partial_body: Default::default(),
+            is_domainkeys: Default::default(),
+            body_length: Default::default(),
+            record: Default::default(),
+            header_index: Default::default(),
+            header_offset: Default::default(),
+            raw_header: Default::default(),
         }
     }
 }
@@ -11,15 +11,179 @@
 use std::{fmt::Write, io, time::SystemTime};
 
 use mail_builder::{
+    encoders::base64::base64_encode,
     headers::{address::Address, content_type::ContentType, HeaderType},
     mime::{make_boundary, BodyPart, MimePart},
     MessageBuilder,
 };
 use mail_parser::DateTime;
 
-use crate::report::{AuthFailureType, DeliveryResult, Feedback, FeedbackType, IdentityAlignment};
+use crate::{
+    common::headers::Writable,
+    dkim::verify::Verifier,
+    report::{AuthFailureType, DeliveryResult, Feedback, FeedbackType, IdentityAlignment},
+    AuthenticatedMessage, AuthenticationResults, DkimOutput, DkimResult, DmarcOutput, DmarcResult,
+    Error, SpfOutput,
+};
 
 impl<'x> Feedback<'x> {
+    /// Builds an RFC 6651 failure report for a DKIM signature that requested
+    /// reports (r=y). Returns `None` if the signer's reporting record does not
+    /// ask for reports about this kind of failure.
+    pub fn from_dkim_failure(
+        output: &DkimOutput<'_>,
+        message: &'x AuthenticatedMessage<'x>,
+        reporting_domain: &str,
+    ) -> Option<Self> {
+        let err = match output.result() {
+            DkimResult::Neutral(err)
+            | DkimResult::Fail(err)
+            | DkimResult::PermError(err)
+            | DkimResult::TempError(err) => err,
+            DkimResult::Pass | DkimResult::None => return None,
+        };
+        output.failure_report_addr()?;
+        let signature = output.signature()?;
+
+        let mut feedback = Feedback::new(FeedbackType::AuthFailure)
+            .with_auth_failure(match err {
+                Error::FailedBodyHashMatch => AuthFailureType::BodyHash,
+                Error::RevokedPublicKey => AuthFailureType::Revoked,
+                _ => AuthFailureType::Signature,
+            })
+            .with_arrival_date(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0) as i64,
+            )
+            .with_authentication_results(
+                AuthenticationResults::new(reporting_domain)
+                    .with_dkim_result(output, message.from())
+                    .to_string(),
+            )
+            .with_reporting_mta(reporting_domain.to_string())
+            .with_dkim_domain(signature.d.clone())
+            .with_dkim_selector(signature.s.clone());
+        if !signature.i.is_empty() {
+            feedback = feedback.with_dkim_identity(signature.i.clone());
+        }
+
+        // Include the canonicalized data that failed to verify
+        match err {
+            Error::FailedVerification => {
+                if let Some(header) = message
+                    .dkim_headers
+                    .iter()
+                    .find(|h| matches!(&h.header, Ok(s) if std::ptr::eq(s, signature)))
+                {
+                    let dkim_hdr_value = header.value.strip_signature();
+                    let mut data = Vec::with_capacity(256);
+                    signature.ch.canonicalize_headers(
+                        message.signed_headers(&signature.h, header.name, &dkim_hdr_value),
+                        &mut data,
+                    );
+                    feedback = feedback.with_dkim_canonicalized_header(fold_base64(&data)?);
+                }
+            }
+            Error::FailedBodyHashMatch => {
+                let mut data = Vec::with_capacity(256);
+                signature
+                    .cb
+                    .canonical_body_with_limit(
+                        message
+                            .raw_message
+                            .get(message.body_offset..)
+                            .unwrap_or_default(),
+                        signature.l,
+                    )
+                    .write(&mut data);
+                feedback = feedback.with_dkim_canonicalized_body(fold_base64(&data)?);
+            }
+            _ => (),
+        }
+
+        Some(if std::str::from_utf8(message.raw_message).is_ok() {
+            feedback.with_message(message.raw_message)
+        } else {
+            feedback.with_headers(message.raw_headers())
+        })
+    }
+
+    /// Builds an RFC 7489 failure report for a message whose DMARC results trigger
+    /// one of the `fo=` options of the record, to be sent to its `ruf=` destinations.
+    /// Returns `None` if the record does not request a report for these results.
+    pub fn from_dmarc_failure(
+        output: &DmarcOutput,
+        dkim: &[DkimOutput<'_>],
+        spf: &SpfOutput,
+        message: &'x AuthenticatedMessage<'x>,
+        reporting_domain: &str,
+    ) -> Option<Self> {
+        if !output.should_send_failure_report(dkim, spf) {
+            return None;
+        }
+
+        let feedback = Feedback::new(FeedbackType::AuthFailure)
+            .with_auth_failure(AuthFailureType::Dmarc)
+            .with_arrival_date(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0) as i64,
+            )
+            .with_authentication_results(
+                AuthenticationResults::new(reporting_domain)
+                    .with_dkim_results(dkim, message.from())
+                    .with_dmarc_result(output)
+                    .to_string(),
+            )
+            .with_reporting_mta(reporting_domain.to_string())
+            .with_reported_domain(output.domain().to_string())
+            .with_identity_alignment(
+                match (
+                    output.dkim_result() == &DmarcResult::Pass,
+                    output.spf_result() == &DmarcResult::Pass,
+                ) {
+                    (true, true) => IdentityAlignment::DkimSpf,
+                    (true, false) => IdentityAlignment::Dkim,
+                    (false, true) => IdentityAlignment::Spf,
+                    (false, false) => IdentityAlignment::None,
+                },
+            );
+
+        Some(if std::str::from_utf8(message.raw_message).is_ok() {
+            feedback.with_message(message.raw_message)
+        } else {
+            feedback.with_headers(message.raw_headers())
+        })
+    }
+
+    /// Generates a ready to send RFC 6651 failure report for a DKIM signature,
+    /// addressed to the signer's reporting address.
+    pub fn dkim_failure_report(
+        output: &DkimOutput<'_>,
+        message: &'x AuthenticatedMessage<'x>,
+        reporting_domain: &str,
+    ) -> Option<Vec<u8>> {
+        let feedback = Feedback::from_dkim_failure(output, message, reporting_domain)?;
+        let from = format!("noreply-dkim@{reporting_domain}");
+        let subject = format!(
+            "DKIM failure report for {}",
+            output.signature().map_or("", |s| s.d.as_str())
+        );
+        let mut buf = Vec::new();
+        feedback
+            .write_rfc5322(
+                from.as_str(),
+                output.failure_report_addr()?,
+                &subject,
+                &mut buf,
+            )
+            .ok()?;
+        Some(buf)
+    }
+
     pub fn write_rfc5322(
         &self,
         from: impl Into<Address<'x>>,
@@ -249,9 +413,30 @@ impl<'x> Feedback<'x> {
     }
 }
 
+fn fold_base64(data: &[u8]) -> Option<String> {
+    let encoded = base64_encode(data).ok()?;
+    let mut folded = String::with_capacity(encoded.len() + encoded.len() / 32);
+    for (pos, chunk) in encoded.chunks(76).enumerate() {
+        if pos > 0 {
+            folded.push_str("\r\n\t");
+        }
+        folded.push_str(std::str::from_utf8(chunk).ok()?);
+    }
+    Some(folded)
+}
+
 #[cfg(test)]
 mod test {
-    use crate::report::{AuthFailureType, Feedback, FeedbackType, IdentityAlignment};
+    use std::{str::FromStr, sync::Arc};
+
+    use mail_parser::decoders::base64::base64_decode;
+
+    use crate::{
+        dmarc::Dmarc,
+        report::{AuthFailureType, Feedback, FeedbackType, IdentityAlignment},
+        AuthenticatedMessage, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error, SpfOutput,
+        SpfResult,
+    };
 
     #[test]
     fn arf_report_generate() {
@@ -295,4 +480,146 @@ mod test {
 
         assert_eq!(feedback, parsed_feedback);
     }
+
+    #[test]
+    fn arf_dkim_failure_report() {
+        let message = concat!(
+            "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n",
+            "\ts=default; r=y; h=From:Subject; bh=YWJj; b=YWJj\r\n",
+            "From: bill@example.com\r\n",
+            "Subject: TPS Report\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP.\r\n"
+        );
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let signature = message.dkim_headers[0].header.as_ref().unwrap();
+
+        for (err, auth_failure, canonical_header, canonical_body) in [
+            (
+                Error::FailedVerification,
+                AuthFailureType::Signature,
+                Some(concat!(
+                    "from:bill@example.com\r\n",
+                    "subject:TPS Report\r\n",
+                    "dkim-signature:v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; ",
+                    "s=default; r=y; h=From:Subject; bh=YWJj; b="
+                )),
+                None,
+            ),
+            (
+                Error::FailedBodyHashMatch,
+                AuthFailureType::BodyHash,
+                None,
+                Some("I'm going to need those TPS reports ASAP.\r\n"),
+            ),
+            (
+                Error::RevokedPublicKey,
+                AuthFailureType::Revoked,
+                None,
+                None,
+            ),
+        ] {
+            let output = DkimOutput {
+                result: DkimResult::Fail(err),
+                signature: signature.into(),
+                report: Some("dkim-failures@example.com".to_string()),
+                ..Default::default()
+            };
+            let report =
+                Feedback::dkim_failure_report(&output, &message, "mx.example.org").unwrap();
+            let report = std::str::from_utf8(&report).unwrap();
+            assert!(
+                report.contains("To: <dkim-failures@example.com>"),
+                "{report}"
+            );
+
+            let feedback = Feedback::parse_rfc5322(report.as_bytes()).unwrap();
+            assert_eq!(feedback.feedback_type(), FeedbackType::AuthFailure);
+            assert_eq!(feedback.auth_failure(), auth_failure);
+            assert_eq!(feedback.dkim_domain(), Some("example.com"));
+            assert_eq!(feedback.dkim_selector(), Some("default"));
+            assert_eq!(feedback.reporting_mta(), Some("mx.example.org"));
+            assert_eq!(feedback.message(), Some(message.raw_message));
+            for (value, expected) in [
+                (feedback.dkim_canonicalized_header(), canonical_header),
+                (feedback.dkim_canonicalized_body(), canonical_body),
+            ] {
+                assert_eq!(
+                    value.map(|v| {
+                        let v = v
+                            .chars()
+                            .filter(|ch| !ch.is_whitespace())
+                            .collect::<String>();
+                        String::from_utf8(base64_decode(v.as_bytes()).unwrap()).unwrap()
+                    }),
+                    expected.map(|v| v.to_string())
+                );
+            }
+        }
+
+        // No report without a reporting address or for passing signatures
+        let output = DkimOutput {
+            result: DkimResult::Fail(Error::FailedVerification),
+            signature: signature.into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Feedback::from_dkim_failure(&output, &message, "mx.example.org"),
+            None
+        );
+        let output = DkimOutput {
+            result: DkimResult::Pass,
+            signature: signature.into(),
+            report: Some("dkim-failures@example.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Feedback::from_dkim_failure(&output, &message, "mx.example.org"),
+            None
+        );
+    }
+
+    #[test]
+    fn arf_dmarc_failure_report() {
+        let message = AuthenticatedMessage::parse(
+            b"From: bill@example.com\r\nSubject: TPS Report\r\n\r\nI'm going to need those TPS reports ASAP.\r\n",
+        )
+        .unwrap();
+        let output = DmarcOutput {
+            domain: "example.com".to_string(),
+            record: Some(Arc::new(
+                Dmarc::from_str("v=DMARC1; p=reject; ruf=mailto:ruf@example.com; fo=d:s").unwrap(),
+            )),
+            ..Default::default()
+        }
+        .with_dkim_result(DmarcResult::Fail(Error::NotAligned))
+        .with_spf_result(DmarcResult::Pass);
+        let spf = SpfOutput {
+            result: SpfResult::Pass,
+            ..Default::default()
+        };
+
+        // Signatures that verify do not trigger the d option
+        let dkim = [DkimOutput {
+            result: DkimResult::Pass,
+            ..Default::default()
+        }];
+        assert!(
+            Feedback::from_dmarc_failure(&output, &dkim, &spf, &message, "mx.example.org")
+                .is_none()
+        );
+
+        let dkim = [DkimOutput {
+            result: DkimResult::Fail(Error::FailedVerification),
+            ..Default::default()
+        }];
+        let feedback =
+            Feedback::from_dmarc_failure(&output, &dkim, &spf, &message, "mx.example.org").unwrap();
+        assert_eq!(feedback.feedback_type(), FeedbackType::AuthFailure);
+        assert_eq!(feedback.auth_failure(), AuthFailureType::Dmarc);
+        assert_eq!(feedback.identity_alignment(), IdentityAlignment::Spf);
+        assert_eq!(feedback.reported_domain(), ["example.com"]);
+        assert_eq!(feedback.reporting_mta(), Some("mx.example.org"));
+        assert_eq!(feedback.message(), Some(message.raw_message));
+    }
 }
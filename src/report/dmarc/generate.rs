@@ -100,7 +100,8 @@ impl Report {
             writeln!(&mut xml, "\t<version>{}</version>", self.version).ok();
         }
         self.report_metadata.to_xml(&mut xml);
-        self.policy_published.to_xml(&mut xml);
+        self.policy_published
+            .to_xml(&mut xml, ReportFormat::from_version(self.version));
         for record in &self.record {
             record.to_xml(&mut xml);
         }
@@ -141,18 +142,41 @@ impl ReportMetadata {
     }
 }
 
+/// Schema followed by the generated report XML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReportFormat {
+    /// RFC 7489 Appendix C, for reports with no version or a version below 2.
+    Rfc7489,
+    /// DMARCbis, which adds the `version_published` and `testing` elements.
+    DmarcBis,
+}
+
+impl ReportFormat {
+    fn from_version(version: f32) -> Self {
+        if version < 2.0 {
+            ReportFormat::Rfc7489
+        } else {
+            ReportFormat::DmarcBis
+        }
+    }
+}
+
 impl PolicyPublished {
-    pub(crate) fn to_xml(&self, xml: &mut String) {
+    /// Writes the published policy, leaving out the elements that are not
+    /// part of the schema of `format`.
+    pub(crate) fn to_xml(&self, xml: &mut String, format: ReportFormat) {
+        let rfc7489 = format == ReportFormat::Rfc7489;
         writeln!(xml, "\t<policy_published>").ok();
         writeln!(xml, "\t\t<domain>{}</domain>", escape_xml(&self.domain)).ok();
-        if let Some(vp) = &self.version_published {
+        if let Some(vp) = self.version_published.filter(|_| !rfc7489) {
             writeln!(xml, "\t\t<version_published>{vp}</version_published>").ok();
         }
         writeln!(xml, "\t\t<adkim>{}</adkim>", &self.adkim).ok();
         writeln!(xml, "\t\t<aspf>{}</aspf>", &self.aspf).ok();
         writeln!(xml, "\t\t<p>{}</p>", &self.p).ok();
         writeln!(xml, "\t\t<sp>{}</sp>", &self.sp).ok();
-        if self.testing {
+        writeln!(xml, "\t\t<pct>{}</pct>", self.pct).ok();
+        if self.testing && !rfc7489 {
             writeln!(xml, "\t\t<testing>y</testing>").ok();
         }
         if let Some(fo) = &self.fo {
@@ -395,7 +419,9 @@ impl Display for SpfResult {
 
 fn escape_xml(text: &str) -> Cow<'_, str> {
     for ch in text.as_bytes() {
-        if [b'"', b'\'', b'<', b'>', b'&'].contains(ch) {
+        if [b'"', b'\'', b'<', b'>', b'&'].contains(ch)
+            || (*ch < 0x20 && ![b'\t', b'\n', b'\r'].contains(ch))
+        {
             let mut escaped = String::with_capacity(text.len());
             for ch in text.chars() {
                 match ch {
@@ -414,6 +440,11 @@ fn escape_xml(text: &str) -> Cow<'_, str> {
                     '&' => {
                         escaped.push_str("&amp;");
                     }
+                    // Control characters are not allowed in XML 1.0
+                    '\t' | '\n' | '\r' => {
+                        escaped.push(ch);
+                    }
+                    _ if ch < ' ' => (),
                     _ => {
                         escaped.push(ch);
                     }
@@ -428,12 +459,19 @@ fn escape_xml(text: &str) -> Cow<'_, str> {
 
 #[cfg(test)]
 mod test {
-    use crate::report::{
-        ActionDisposition, Alignment, DKIMAuthResult, Disposition, DkimResult, DmarcResult,
-        PolicyOverride, PolicyOverrideReason, Record, Report, SPFAuthResult, SPFDomainScope,
-        SpfResult,
+    use std::str::FromStr;
+
+    use crate::{
+        dmarc::Dmarc,
+        report::{
+            ActionDisposition, Alignment, DKIMAuthResult, Disposition, DkimResult, DmarcResult,
+            PolicyOverride, PolicyOverrideReason, PolicyPublished, Record, Report, SPFAuthResult,
+            SPFDomainScope, SpfResult,
+        },
     };
 
+    use super::escape_xml;
+
     #[test]
     fn dmarc_report_generate() {
         let report = Report::new()
@@ -530,4 +568,109 @@ mod test {
 
         assert_eq!(report, parsed_report);
     }
+
+    #[test]
+    fn dmarc_report_roundtrip() {
+        let dmarc = Dmarc::from_str(
+            "v=DMARC1; p=quarantine; sp=reject; pct=50; adkim=s; fo=1:d; rua=mailto:dmarc@example.org",
+        )
+        .unwrap();
+        let record = Record::new()
+            .with_source_ip("192.0.2.1".parse().unwrap())
+            .with_action_disposition(ActionDisposition::Quarantine)
+            .with_dmarc_dkim_result(DmarcResult::Fail)
+            .with_dmarc_spf_result(DmarcResult::Fail)
+            .with_policy_override_reason(
+                PolicyOverrideReason::new(PolicyOverride::LocalPolicy)
+                    .with_comment("list <owner> & \"friends\""),
+            )
+            .with_envelope_from("example.org")
+            .with_header_from("example.org")
+            .with_dkim_auth_result(
+                DKIMAuthResult::new()
+                    .with_domain("example.org")
+                    .with_selector("default")
+                    .with_result(DkimResult::Fail)
+                    .with_human_result("body hash did not verify"),
+            )
+            .with_dkim_auth_result(
+                DKIMAuthResult::new()
+                    .with_domain("esp.example.net")
+                    .with_selector("s1")
+                    .with_result(DkimResult::Pass),
+            )
+            .with_spf_auth_result(
+                SPFAuthResult::new()
+                    .with_domain("example.org")
+                    .with_scope(SPFDomainScope::MailFrom)
+                    .with_result(SpfResult::SoftFail),
+            );
+
+        let mut report = Report::new()
+            .with_org_name("Initech & Co")
+            .with_email("dmarc@initech.net")
+            .with_report_id("day-12345")
+            .with_date_range_begin(1680307200)
+            .with_date_range_end(1680393599)
+            .with_policy_published(PolicyPublished::from_record("example.org", &dmarc));
+        // Records are only grouped on request
+        let mut ungrouped = report.clone();
+        for _ in 0..3 {
+            ungrouped.add_record(record.clone());
+        }
+        assert_eq!(ungrouped.records().len(), 3);
+
+        // Identical results are grouped into a single row
+        for _ in 0..3 {
+            report.add_grouped_record(record.clone());
+        }
+        report.add_grouped_record(
+            record
+                .clone()
+                .with_source_ip("192.0.2.2".parse().unwrap())
+                .with_count(2),
+        );
+        assert_eq!(report.records().len(), 2);
+        assert_eq!(report.records()[0].count(), 3);
+        assert_eq!(report.records()[1].count(), 2);
+        assert_eq!(report.pct(), 50);
+        assert_eq!(report.adkim(), Alignment::Strict);
+        assert_eq!(report.p(), Disposition::Quarantine);
+        assert_eq!(report.sp(), Disposition::Reject);
+        assert_eq!(report.fo(), Some("1:d"));
+
+        let xml = report.to_xml();
+        for expected in [
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" ?>",
+            "<org_name>Initech &amp; Co</org_name>",
+            "<pct>50</pct>",
+            "<result>softfail</result>",
+            "<scope>mfrom</scope>",
+            "<comment>list &lt;owner&gt; &amp; &quot;friends&quot;</comment>",
+        ] {
+            assert!(xml.contains(expected), "{expected}\n{xml}");
+        }
+        assert_eq!(Report::parse_xml(xml.as_bytes()).unwrap(), report);
+
+        // Elements outside of the RFC 7489 schema are only written to newer reports
+        let report = report.with_version_published(1.0).with_testing(true);
+        let xml = report.to_xml();
+        assert!(!xml.contains("<version_published>"), "{xml}");
+        assert!(!xml.contains("<testing>"), "{xml}");
+        let xml = report.with_version(2.0).to_xml();
+        assert!(
+            xml.contains("<version_published>1</version_published>"),
+            "{xml}"
+        );
+        assert!(xml.contains("<testing>y</testing>"), "{xml}");
+
+        // Policies without pct= apply to every message
+        let xml = Report::new().to_xml();
+        assert!(xml.contains("<pct>100</pct>"), "{xml}");
+        assert_eq!(Report::parse_xml(xml.as_bytes()).unwrap().pct(), 100);
+
+        // Characters not allowed in XML are removed
+        assert_eq!(escape_xml("line\u{1}feed\u{1b}"), "linefeed");
+        assert_eq!(escape_xml("tab\tnewline\n"), "tab\tnewline\n");
+    }
 }
@@ -15,7 +15,7 @@ use std::fmt::Write;
 use std::net::IpAddr;
 
 use crate::{
-    dmarc::Dmarc,
+    dmarc::{AlignedSignature, AuthMechanism, Dmarc},
     report::{
         ActionDisposition, Alignment, DKIMAuthResult, Disposition, DkimResult, DmarcResult,
         PolicyOverride, PolicyOverrideReason, Record, Report, SPFAuthResult, SPFDomainScope,
@@ -166,6 +166,15 @@ impl Report {
         self
     }
 
+    pub fn pct(&self) -> u8 {
+        self.policy_published.pct
+    }
+
+    pub fn with_pct(mut self, pct: u8) -> Self {
+        self.policy_published.pct = pct;
+        self
+    }
+
     pub fn testing(&self) -> bool {
         self.policy_published.testing
     }
@@ -188,6 +197,23 @@ impl Report {
         self.record.push(record);
     }
 
+    /// Adds a record to the report, merging it into an existing row with the same
+    /// source IP, policy evaluation, identifiers and authentication results by
+    /// adding up their message counts.
+    pub fn add_grouped_record(&mut self, mut record: Record) {
+        record.row.count = record.row.count.max(1);
+        match self
+            .record
+            .iter_mut()
+            .find(|existing| existing.is_same_group(&record))
+        {
+            Some(existing) => {
+                existing.row.count = existing.row.count.saturating_add(record.row.count);
+            }
+            None => self.record.push(record),
+        }
+    }
+
     pub fn with_policy_published(mut self, policy_published: PolicyPublished) -> Self {
         self.policy_published = policy_published;
         self
@@ -247,13 +273,15 @@ impl Record {
     }
 
     pub fn with_dmarc_output(mut self, dmarc_output: &DmarcOutput) -> Self {
-        self.row.policy_evaluated.disposition = if dmarc_output.dkim_result
+        let disposition = dmarc_output.disposition([]);
+        self.row.policy_evaluated.disposition = if (dmarc_output.dkim_result
             == crate::DmarcResult::Pass
-            || dmarc_output.spf_result == crate::DmarcResult::Pass
+            || dmarc_output.spf_result == crate::DmarcResult::Pass)
+            && dmarc_output.arc_override.is_none()
         {
             ActionDisposition::Pass
         } else {
-            match dmarc_output.policy {
+            match disposition.policy() {
                 crate::dmarc::Policy::None => ActionDisposition::None,
                 crate::dmarc::Policy::Quarantine => ActionDisposition::Quarantine,
                 crate::dmarc::Policy::Reject => ActionDisposition::Reject,
@@ -262,7 +290,59 @@ impl Record {
         };
         self.row.policy_evaluated.dkim = (&dmarc_output.dkim_result).into();
         self.row.policy_evaluated.spf = (&dmarc_output.spf_result).into();
-        self
+        if let Some(signature) = &dmarc_output.dkim_alignment {
+            self.add_aligned_signature(signature, None);
+        }
+        if let Some(signature) = &dmarc_output.dkim_misalignment {
+            self.add_aligned_signature(
+                signature,
+                format!(
+                    "{} alignment with {} failed",
+                    signature.mode(),
+                    dmarc_output.domain
+                )
+                .into(),
+            );
+        }
+        // Record mechanisms that were not run, since aggregate reports require
+        // an SPF result
+        if !dmarc_output.is_evaluated_with(AuthMechanism::Spf) && self.auth_results.spf.is_empty() {
+            self.auth_results.spf.push(SPFAuthResult {
+                domain: dmarc_output.domain.to_string(),
+                scope: SPFDomainScope::Unspecified,
+                result: SpfResult::None,
+                human_result: "spf not evaluated".to_string().into(),
+            });
+        }
+        self.row
+            .policy_evaluated
+            .reason
+            .extend(disposition.into_reasons());
+        self
+    }
+
+    /// Adds a passing signature used for DMARC alignment to the DKIM results,
+    /// unless it was already added by `with_dkim_output`.
+    fn add_aligned_signature(
+        &mut self,
+        signature: &AlignedSignature,
+        human_result: Option<String>,
+    ) {
+        match self.auth_results.dkim.iter_mut().find(|result| {
+            result.domain == signature.domain() && result.selector == signature.selector()
+        }) {
+            Some(result) => {
+                if result.human_result.is_none() {
+                    result.human_result = human_result;
+                }
+            }
+            None => self.auth_results.dkim.push(DKIMAuthResult {
+                domain: signature.domain().to_string(),
+                selector: signature.selector().to_string(),
+                result: DkimResult::Pass,
+                human_result,
+            }),
+        }
     }
 
     pub fn with_arc_output(mut self, arc_output: &ArcOutput) -> Self {
@@ -285,6 +365,14 @@ impl Record {
         self
     }
 
+    fn is_same_group(&self, other: &Record) -> bool {
+        self.row.source_ip == other.row.source_ip
+            && self.row.policy_evaluated == other.row.policy_evaluated
+            && self.identifiers == other.identifiers
+            && self.auth_results == other.auth_results
+            && self.extensions == other.extensions
+    }
+
     pub fn source_ip(&self) -> Option<IpAddr> {
         self.row.source_ip
     }
@@ -393,16 +481,15 @@ impl PolicyPublished {
             aspf: (&dmarc.aspf).into(),
             p: (&dmarc.p).into(),
             sp: (&dmarc.sp).into(),
+            pct: dmarc.pct,
             testing: dmarc.t,
-            fo: match &dmarc.fo {
-                crate::dmarc::Report::All => "0",
-                crate::dmarc::Report::Any => "1",
-                crate::dmarc::Report::Dkim => "d",
-                crate::dmarc::Report::Spf => "s",
-                crate::dmarc::Report::DkimSpf => "d:s",
-            }
-            .to_string()
-            .into(),
+            fo: dmarc
+                .fo_options()
+                .iter()
+                .map(|option| option.to_string())
+                .collect::<Vec<_>>()
+                .join(":")
+                .into(),
             version_published: None,
         }
     }
@@ -281,6 +281,9 @@ impl PolicyPublished {
                 b"sp" => {
                     p.sp = reader.next_value(buf)?.unwrap_or_default();
                 }
+                b"pct" => {
+                    p.pct = reader.next_value(buf)?.unwrap_or(100);
+                }
                 b"testing" => {
                     p.testing = reader
                         .next_value::<String>(buf)?
@@ -56,7 +56,7 @@ pub enum ActionDisposition {
     Unspecified,
 }
 
-#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PolicyPublished {
     pub domain: String,
     pub version_published: Option<f32>,
@@ -64,6 +64,8 @@ pub struct PolicyPublished {
     pub aspf: Alignment,
     pub p: Disposition,
     pub sp: Disposition,
+    #[serde(default = "default_pct")]
+    pub pct: u8,
     pub testing: bool,
     pub fo: Option<String>,
 }
@@ -239,6 +241,27 @@ impl Default for SPFDomainScope {
     }
 }
 
+impl Default for PolicyPublished {
+    fn default() -> Self {
+        PolicyPublished {
+            domain: Default::default(),
+            version_published: Default::default(),
+            adkim: Default::default(),
+            aspf: Default::default(),
+            p: Default::default(),
+            sp: Default::default(),
+            pct: default_pct(),
+            testing: Default::default(),
+            fo: Default::default(),
+        }
+    }
+}
+
+/// Reports without a `pct` element apply the policy to all messages.
+fn default_pct() -> u8 {
+    100
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     MailParseError,
@@ -0,0 +1,425 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::{common::parse::TxtRecordParser, Error};
+
+use super::{flatten::split_records, Directive, Macro, Mechanism, Qualifier, Spf, SpfWarning};
+
+/// Practical length limit of an SPF record, which keeps DNS responses
+/// within 512 bytes (RFC 7208, Section 3.4).
+const MAX_RECORD_LEN: usize = 450;
+
+/// An SPF record assembled with `SpfRecord::builder`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpfRecord {
+    pub(crate) directives: Vec<Directive>,
+    pub(crate) redirect: Option<Macro>,
+    pub(crate) exp: Option<Macro>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SpfRecordBuilder {
+    directives: Vec<Directive>,
+    redirect: Option<Macro>,
+    exp: Option<Macro>,
+    split: bool,
+    error: Option<Error>,
+}
+
+impl SpfRecord {
+    pub fn builder() -> SpfRecordBuilder {
+        SpfRecordBuilder::default()
+    }
+
+    /// Renders the record as one or more TXT records of at most 450 bytes,
+    /// returned as (name, value) pairs with the record for `domain` first.
+    /// Records are only split when enabled with `SpfRecordBuilder::split`, and
+    /// fail with `Error::RecordTooLong` when the record for `domain` does not
+    /// fit even after moving the networks out.
+    pub fn to_records(&self, domain: &str) -> crate::Result<Vec<(String, String)>> {
+        split_records(
+            domain,
+            &self.directives.iter().collect::<Vec<_>>(),
+            self.redirect.as_ref(),
+            self.exp.as_ref(),
+            MAX_RECORD_LEN,
+        )
+    }
+
+    pub fn directives(&self) -> &[Directive] {
+        &self.directives
+    }
+
+    pub fn redirect(&self) -> Option<&Macro> {
+        self.redirect.as_ref()
+    }
+
+    pub fn exp(&self) -> Option<&Macro> {
+        self.exp.as_ref()
+    }
+
+    /// Returns the issues found in the records published for `domain`, such
+    /// as exceeding the limit of 10 DNS lookups once the networks moved out
+    /// by splitting are included.
+    pub fn lint(&self, domain: &str) -> crate::Result<Vec<SpfWarning>> {
+        let mut warnings = Vec::new();
+        for (_, value) in self.to_records(domain)? {
+            warnings.extend(Spf::parse(value.as_bytes())?.lint());
+        }
+        Ok(warnings)
+    }
+}
+
+impl SpfRecordBuilder {
+    /// Adds an `ip4` mechanism for a network such as `192.0.2.0/24`.
+    pub fn ip4(self, net: &str) -> Self {
+        if is_network::<Ipv4Addr>(net, 32) {
+            self.term(format!("ip4:{net}"))
+        } else {
+            self.invalid()
+        }
+    }
+
+    /// Adds an `ip6` mechanism for a network such as `2001:db8::/32`.
+    pub fn ip6(self, net: &str) -> Self {
+        if is_network::<Ipv6Addr>(net, 128) {
+            self.term(format!("ip6:{net}"))
+        } else {
+            self.invalid()
+        }
+    }
+
+    pub fn include(self, domain: &str) -> Self {
+        self.term(format!("include:{domain}"))
+    }
+
+    /// Adds an `a` mechanism for `domain`, or for the current domain when
+    /// `None`, optionally followed by an IPv4 prefix length.
+    pub fn a(self, domain: Option<&str>, cidr: Option<u32>) -> Self {
+        if cidr.map_or(true, |cidr| cidr <= 32) {
+            self.term(host_term("a", domain, cidr))
+        } else {
+            self.invalid()
+        }
+    }
+
+    /// Adds an `mx` mechanism for `domain`, or for the current domain when
+    /// `None`, optionally followed by an IPv4 prefix length.
+    pub fn mx(self, domain: Option<&str>, cidr: Option<u32>) -> Self {
+        if cidr.map_or(true, |cidr| cidr <= 32) {
+            self.term(host_term("mx", domain, cidr))
+        } else {
+            self.invalid()
+        }
+    }
+
+    pub fn ptr(self, domain: Option<&str>) -> Self {
+        self.term(host_term("ptr", domain, None))
+    }
+
+    pub fn exists(self, macro_string: &str) -> Self {
+        self.term(format!("exists:{macro_string}"))
+    }
+
+    pub fn all(self, qualifier: Qualifier) -> Self {
+        self.term(format!("{qualifier}all"))
+    }
+
+    /// Adds a directive with a qualifier other than pass.
+    pub fn directive(self, directive: Directive) -> Self {
+        self.term(directive.to_string())
+    }
+
+    pub fn redirect(self, domain: &str) -> Self {
+        self.term(format!("redirect={domain}"))
+    }
+
+    pub fn exp(self, domain: &str) -> Self {
+        self.term(format!("exp={domain}"))
+    }
+
+    /// Allows records longer than 450 bytes, which are then split by
+    /// `SpfRecord::to_records`.
+    pub fn split(mut self, split: bool) -> Self {
+        self.split = split;
+        self
+    }
+
+    /// Validates the added terms and returns the record. Terms with invalid
+    /// syntax, mechanisms after `all` and records longer than 450 bytes
+    /// that cannot be split are rejected.
+    pub fn build(self) -> crate::Result<SpfRecord> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        let record = SpfRecord {
+            directives: self.directives,
+            redirect: self.redirect,
+            exp: self.exp,
+        };
+        let len = record.to_string().len();
+        if len > MAX_RECORD_LEN && !self.split {
+            return Err(Error::RecordTooLong(len));
+        }
+
+        Ok(record)
+    }
+
+    fn term(mut self, term: String) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = self.add_term(&term) {
+                self.error = err.into();
+            }
+        }
+        self
+    }
+
+    fn invalid(mut self) -> Self {
+        self.error.get_or_insert(Error::ParseError);
+        self
+    }
+
+    fn add_term(&mut self, term: &str) -> crate::Result<()> {
+        // Values may not smuggle in additional terms
+        if term.is_empty() || term.bytes().any(|ch| ch.is_ascii_whitespace()) {
+            return Err(Error::ParseError);
+        }
+        let spf = Spf::parse(format!("v=spf1 {term}").as_bytes())?;
+        if spf.directives.len()
+            + usize::from(spf.redirect.is_some())
+            + usize::from(spf.exp.is_some())
+            != 1
+        {
+            return Err(Error::ParseError);
+        }
+
+        if let Some(directive) = spf.directives.into_iter().next() {
+            if self
+                .directives
+                .iter()
+                .any(|directive| directive.mechanism == Mechanism::All)
+            {
+                return Err(Error::ParseError);
+            }
+            self.directives.push(directive);
+        } else if let Some(redirect) = spf.redirect {
+            if self.redirect.is_some() {
+                return Err(Error::ParseError);
+            }
+            self.redirect = redirect.into();
+        } else if let Some(exp) = spf.exp {
+            if self.exp.is_some() {
+                return Err(Error::ParseError);
+            }
+            self.exp = exp.into();
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether `net` is an address optionally followed by a prefix length
+/// of at most `max_len`, which the lenient record parser would otherwise clamp.
+fn is_network<T: FromStr>(net: &str, max_len: u32) -> bool {
+    let (addr, cidr) = net.split_once('/').unwrap_or((net, "0"));
+    addr.parse::<T>().is_ok()
+        && !cidr.is_empty()
+        && cidr.bytes().all(|ch| ch.is_ascii_digit())
+        && cidr.parse::<u32>().map_or(false, |cidr| cidr <= max_len)
+}
+
+fn host_term(mechanism: &str, domain: Option<&str>, cidr: Option<u32>) -> String {
+    let mut term = mechanism.to_string();
+    if let Some(domain) = domain {
+        term = format!("{term}:{domain}");
+    }
+    if let Some(cidr) = cidr {
+        term = format!("{term}/{cidr}");
+    }
+    term
+}
+
+impl Display for SpfRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("v=spf1")?;
+        for directive in &self.directives {
+            write!(f, " {directive}")?;
+        }
+        if let Some(redirect) = &self.redirect {
+            write!(f, " redirect={redirect}")?;
+        }
+        if let Some(exp) = &self.exp {
+            write!(f, " exp={exp}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::parse::TxtRecordParser,
+        spf::{Qualifier, Spf, SpfWarning},
+        Error,
+    };
+
+    use super::SpfRecord;
+
+    #[test]
+    fn spf_builder() {
+        let record = SpfRecord::builder()
+            .ip4("192.0.2.0/24")
+            .ip6("2001:db8::/32")
+            .include("_spf.example.com")
+            .a(Some("mail.example.com"), Some(28))
+            .mx(None, None)
+            .exists("%{i}._spf.%{d}")
+            .all(Qualifier::Fail)
+            .build()
+            .unwrap();
+        let value = concat!(
+            "v=spf1 ip4:192.0.2.0/24 ip6:2001:db8::/32 include:_spf.example.com ",
+            "a:mail.example.com/28 mx exists:%{i}._spf.%{d} -all"
+        );
+        assert_eq!(record.to_string(), value);
+        assert!(record.lint("example.com").unwrap().is_empty());
+        assert_eq!(
+            record.to_records("example.com").unwrap(),
+            vec![("example.com".to_string(), value.to_string())]
+        );
+
+        // Round-trip through the parser
+        let spf = Spf::parse(value.as_bytes()).unwrap();
+        assert_eq!(spf.directives(), record.directives());
+
+        let record = SpfRecord::builder()
+            .ip4("192.0.2.1")
+            .redirect("_spf.example.com")
+            .exp("explain.example.com")
+            .build()
+            .unwrap();
+        assert_eq!(
+            record.to_string(),
+            "v=spf1 ip4:192.0.2.1 redirect=_spf.example.com exp=explain.example.com"
+        );
+        assert!(record.redirect().is_some() && record.exp().is_some());
+
+        // Lookup count warnings
+        let mut builder = SpfRecord::builder();
+        for n in 0..11 {
+            builder = builder.include(&format!("_spf{n}.example.com"));
+        }
+        assert_eq!(
+            builder
+                .all(Qualifier::SoftFail)
+                .build()
+                .unwrap()
+                .lint("example.com")
+                .unwrap(),
+            vec![
+                SpfWarning::TooManyLookups(11),
+                SpfWarning::RecordTooLong(298)
+            ]
+        );
+
+        // Invalid terms
+        for builder in [
+            SpfRecord::builder().ip4("192.0.2.300"),
+            SpfRecord::builder().ip4("192.0.2.0/33"),
+            SpfRecord::builder().ip4("192.0.2.0/"),
+            SpfRecord::builder().ip6("192.0.2.0"),
+            SpfRecord::builder().ip6("2001:db8::/129"),
+            SpfRecord::builder().include(""),
+            SpfRecord::builder().include("a.org +all"),
+            SpfRecord::builder().a(Some("a.org"), Some(40)),
+            SpfRecord::builder().exists("%{z}.a.org"),
+            SpfRecord::builder().redirect("a.org").redirect("b.org"),
+            SpfRecord::builder().all(Qualifier::Fail).ip4("192.0.2.1"),
+            SpfRecord::builder()
+                .all(Qualifier::Fail)
+                .all(Qualifier::Neutral),
+        ] {
+            assert_eq!(builder.build(), Err(Error::ParseError));
+        }
+
+        // Records longer than 450 bytes are rejected unless split
+        let mut builder = SpfRecord::builder();
+        for n in 0..40 {
+            builder = builder.ip4(&format!("198.51.100.{n}"));
+        }
+        let builder = builder.include("_spf.example.com").all(Qualifier::Fail);
+        assert!(matches!(
+            builder.clone().build(),
+            Err(Error::RecordTooLong(len)) if len > 450
+        ));
+        let records = builder
+            .split(true)
+            .build()
+            .unwrap()
+            .to_records("example.com")
+            .unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["example.com", "spf1.example.com", "spf2.example.com"]
+        );
+        assert_eq!(
+            records[0].1,
+            concat!(
+                "v=spf1 include:spf1.example.com include:spf2.example.com ",
+                "include:_spf.example.com -all"
+            )
+        );
+        for (_, value) in &records {
+            assert!(value.len() <= 450);
+            Spf::parse(value.as_bytes()).unwrap();
+        }
+
+        // The includes added by splitting count towards the lookup limit
+        let mut builder = SpfRecord::builder();
+        for n in 0..9 {
+            builder = builder.include(&format!("_spf{n}.example.com"));
+        }
+        for n in 0..40 {
+            builder = builder.ip4(&format!("198.51.100.{n}"));
+        }
+        let record = builder.all(Qualifier::Fail).split(true).build().unwrap();
+        let too_many_lookups =
+            |warnings: &[SpfWarning]| warnings.contains(&SpfWarning::TooManyLookups(11));
+        assert!(!too_many_lookups(
+            &Spf::parse(record.to_string().as_bytes()).unwrap().lint()
+        ));
+        assert!(too_many_lookups(&record.lint("example.com").unwrap()));
+
+        // Split records whose first record still does not fit are rejected
+        let mut builder = SpfRecord::builder();
+        for n in 0..40 {
+            builder = builder.include(&format!("_spf{n}.long-subdomain.example.com"));
+        }
+        let record = builder.all(Qualifier::Fail).split(true).build().unwrap();
+        assert!(matches!(
+            record.to_records("example.com"),
+            Err(Error::RecordTooLong(len)) if len > 450
+        ));
+        assert!(matches!(
+            record.lint("example.com"),
+            Err(Error::RecordTooLong(_))
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::sync::Arc;
+
+use crate::Resolver;
+
+use super::{flatten::literal_domain, Mechanism, Qualifier, Spf};
+
+/// Maximum number of redirects followed by `Spf::resolve_disposition`.
+const MAX_REDIRECTS: usize = 10;
+
+/// What an SPF record does to hosts that are not matched by any of its
+/// other mechanisms.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RecordDisposition {
+    /// Hosts are matched by an `all` mechanism with this qualifier.
+    All(Qualifier),
+    /// The record has neither `all` nor `redirect`, so hosts get a neutral
+    /// result.
+    NoAll,
+    /// Hosts are evaluated by the record of the redirect target.
+    Redirect {
+        target: String,
+        disposition: Box<RecordDisposition>,
+    },
+    /// The record redirects to a target that was not followed.
+    Unknown(String),
+}
+
+impl Spf {
+    /// Returns what the record does to hosts not matched by any other
+    /// mechanism, without following redirects. The `redirect` modifier
+    /// is ignored when the record has an `all` mechanism.
+    pub fn default_disposition(&self) -> RecordDisposition {
+        if let Some(directive) = self
+            .directives
+            .iter()
+            .find(|directive| directive.mechanism == Mechanism::All)
+        {
+            RecordDisposition::All(directive.qualifier.clone())
+        } else if let Some(redirect) = &self.redirect {
+            RecordDisposition::Unknown(redirect.to_string())
+        } else {
+            RecordDisposition::NoAll
+        }
+    }
+
+    /// Returns the disposition of the record published at `domain`, following
+    /// redirects to targets without macros. Targets that cannot be retrieved
+    /// or that were already visited are reported as `Unknown`.
+    pub async fn resolve_disposition(
+        &self,
+        resolver: &Resolver,
+        domain: &str,
+    ) -> RecordDisposition {
+        let mut visited = vec![domain.trim_end_matches('.').to_lowercase()];
+        let mut redirect_spf: Option<Arc<Spf>> = None;
+
+        let disposition = loop {
+            let spf = redirect_spf.as_deref().unwrap_or(self);
+            let disposition = spf.default_disposition();
+            let target = match (&disposition, &spf.redirect) {
+                (RecordDisposition::Unknown(_), Some(macro_string)) => {
+                    literal_domain(macro_string, &visited[visited.len() - 1])
+                }
+                _ => None,
+            };
+
+            match target {
+                Some(target) if visited.len() <= MAX_REDIRECTS && !visited.contains(&target) => {
+                    match resolver.txt_lookup::<Spf>(target.as_str()).await {
+                        Ok(spf) => {
+                            redirect_spf = spf.into();
+                            visited.push(target);
+                        }
+                        Err(_) => break RecordDisposition::Unknown(target),
+                    }
+                }
+                Some(target) => break RecordDisposition::Unknown(target),
+                None => break disposition,
+            }
+        };
+
+        visited
+            .into_iter()
+            .skip(1)
+            .rev()
+            .fold(disposition, |disposition, target| {
+                RecordDisposition::Redirect {
+                    target,
+                    disposition: Box::new(disposition),
+                }
+            })
+    }
+}
+
+impl RecordDisposition {
+    /// Returns the qualifier applied to hosts not matched by any other
+    /// mechanism, or `None` when it depends on a redirect that was not
+    /// followed.
+    pub fn qualifier(&self) -> Option<Qualifier> {
+        match self {
+            RecordDisposition::All(qualifier) => Some(qualifier.clone()),
+            RecordDisposition::NoAll => Some(Qualifier::Neutral),
+            RecordDisposition::Redirect { disposition, .. } => disposition.qualifier(),
+            RecordDisposition::Unknown(_) => None,
+        }
+    }
+
+    /// Returns the target of the first redirect, if any.
+    pub fn redirect_target(&self) -> Option<&str> {
+        match self {
+            RecordDisposition::Redirect { target, .. } | RecordDisposition::Unknown(target) => {
+                Some(target)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns whether the record authorizes any host with `+all`, or gives
+    /// them a neutral result with `?all` or by having no `all` mechanism.
+    pub fn is_open(&self) -> bool {
+        match self {
+            RecordDisposition::All(qualifier) => {
+                matches!(qualifier, Qualifier::Pass | Qualifier::Neutral)
+            }
+            RecordDisposition::NoAll => true,
+            RecordDisposition::Redirect { disposition, .. } => disposition.is_open(),
+            RecordDisposition::Unknown(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        common::parse::TxtRecordParser,
+        spf::{Qualifier, Spf},
+        Resolver,
+    };
+
+    use super::RecordDisposition;
+
+    #[tokio::test]
+    async fn spf_disposition() {
+        for (record, expected_disposition, expected_open) in [
+            (
+                "v=spf1 mx -all",
+                RecordDisposition::All(Qualifier::Fail),
+                false,
+            ),
+            (
+                "v=spf1 mx ~all",
+                RecordDisposition::All(Qualifier::SoftFail),
+                false,
+            ),
+            (
+                "v=spf1 mx ?all",
+                RecordDisposition::All(Qualifier::Neutral),
+                true,
+            ),
+            (
+                "v=spf1 mx +all",
+                RecordDisposition::All(Qualifier::Pass),
+                true,
+            ),
+            ("v=spf1 mx", RecordDisposition::NoAll, true),
+            (
+                "v=spf1 mx redirect=_spf.example.org",
+                RecordDisposition::Unknown("_spf.example.org".to_string()),
+                false,
+            ),
+            // Redirects are ignored when the record has an all mechanism
+            (
+                "v=spf1 redirect=_spf.example.org mx ~all",
+                RecordDisposition::All(Qualifier::SoftFail),
+                false,
+            ),
+            // Mechanisms after the first all are never reached
+            (
+                "v=spf1 -all +all",
+                RecordDisposition::All(Qualifier::Fail),
+                false,
+            ),
+        ] {
+            let disposition = Spf::parse(record.as_bytes()).unwrap().default_disposition();
+            assert_eq!(disposition, expected_disposition, "{record}");
+            assert_eq!(disposition.is_open(), expected_open, "{record}");
+        }
+
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (domain, record) in [
+            ("example.org", "v=spf1 mx redirect=_spf.example.org"),
+            ("_spf.example.org", "v=spf1 redirect=_spf2.example.org"),
+            ("_spf2.example.org", "v=spf1 ip4:192.0.2.0/24 +all"),
+            ("macro.org", "v=spf1 redirect=_spf.%{d}"),
+            ("missing.org", "v=spf1 redirect=_spf.missing.org"),
+            ("loop.org", "v=spf1 redirect=_spf.loop.org"),
+            ("_spf.loop.org", "v=spf1 redirect=loop.org"),
+            ("all.org", "v=spf1 redirect=_spf.missing.org -all"),
+        ] {
+            resolver.txt_add(domain, Spf::parse(record.as_bytes()), valid_until);
+        }
+
+        for (domain, expected_disposition) in [
+            (
+                "example.org",
+                RecordDisposition::Redirect {
+                    target: "_spf.example.org".to_string(),
+                    disposition: Box::new(RecordDisposition::Redirect {
+                        target: "_spf2.example.org".to_string(),
+                        disposition: Box::new(RecordDisposition::All(Qualifier::Pass)),
+                    }),
+                },
+            ),
+            (
+                "macro.org",
+                RecordDisposition::Unknown("_spf.%{d}".to_string()),
+            ),
+            (
+                "missing.org",
+                RecordDisposition::Unknown("_spf.missing.org".to_string()),
+            ),
+            (
+                "loop.org",
+                RecordDisposition::Redirect {
+                    target: "_spf.loop.org".to_string(),
+                    disposition: Box::new(RecordDisposition::Unknown("loop.org".to_string())),
+                },
+            ),
+            ("all.org", RecordDisposition::All(Qualifier::Fail)),
+        ] {
+            let spf = resolver.txt_lookup::<Spf>(domain).await.unwrap();
+            let disposition = spf.resolve_disposition(&resolver, domain).await;
+            assert_eq!(disposition, expected_disposition, "{domain}");
+        }
+
+        let disposition = RecordDisposition::Redirect {
+            target: "_spf.example.org".to_string(),
+            disposition: Box::new(RecordDisposition::All(Qualifier::Pass)),
+        };
+        assert_eq!(disposition.qualifier(), Some(Qualifier::Pass));
+        assert_eq!(disposition.redirect_target(), Some("_spf.example.org"));
+        assert!(disposition.is_open());
+        assert_eq!(
+            RecordDisposition::Unknown("_spf.%{d}".to_string()).qualifier(),
+            None
+        );
+        assert_eq!(
+            RecordDisposition::NoAll.qualifier(),
+            Some(Qualifier::Neutral)
+        );
+    }
+}
@@ -0,0 +1,972 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    future::Future,
+    net::{Ipv4Addr, Ipv6Addr},
+    pin::Pin,
+};
+
+use crate::{Error, Resolver};
+
+use super::{verify::MAX_MX_NAMES, Directive, Macro, Mechanism, Qualifier, Spf, SpfWarning};
+
+const MAX_RECORD_LEN: usize = 512;
+
+/// Maximum number of nested include and redirect records that are followed,
+/// which bounds the recursion of hostile records.
+const MAX_DEPTH: usize = 10;
+
+/// An SPF record with its include, redirect, a and mx mechanisms replaced by
+/// the networks they resolved to.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FlattenedSpf {
+    pub(crate) directives: Vec<FlattenedDirective>,
+    pub(crate) redirect: Option<Macro>,
+    pub(crate) exp: Option<Macro>,
+    pub(crate) warnings: Vec<SpfWarning>,
+}
+
+/// A directive of a flattened record along with the mechanisms it was
+/// obtained from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FlattenedDirective {
+    pub(crate) directive: Directive,
+    pub(crate) sources: Vec<FlattenSource>,
+}
+
+/// A mechanism and the domain whose SPF record contained it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FlattenSource {
+    pub(crate) domain: String,
+    pub(crate) directive: Directive,
+}
+
+struct FlattenedRecord {
+    directives: Vec<FlattenedDirective>,
+    redirect: Option<Macro>,
+}
+
+#[derive(Clone, Copy)]
+struct Network {
+    addr: u128,
+    len: u32,
+    width: u32,
+}
+
+impl Spf {
+    /// Resolves the include, redirect, a and mx mechanisms of the SPF record
+    /// published at `domain` into a list of ip4 and ip6 networks. Mechanisms
+    /// using macros, as well as exists and ptr, are kept as they are, and so are
+    /// includes and redirects of records containing ptr or macros, and mx
+    /// mechanisms with more than 10 names.
+    pub async fn flatten(&self, resolver: &Resolver, domain: &str) -> crate::Result<FlattenedSpf> {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        let mut warnings = Vec::new();
+        let record = resolver
+            .flatten_record(self, domain.clone(), vec![domain], &mut warnings)
+            .await?;
+
+        Ok(FlattenedSpf {
+            directives: aggregate(record.directives),
+            redirect: record.redirect,
+            exp: self.exp.clone(),
+            warnings,
+        })
+    }
+}
+
+impl Resolver {
+    fn flatten_record<'x>(
+        &'x self,
+        spf: &'x Spf,
+        domain: String,
+        stack: Vec<String>,
+        warnings: &'x mut Vec<SpfWarning>,
+    ) -> Pin<Box<dyn Future<Output = crate::Result<FlattenedRecord>> + Send + 'x>> {
+        Box::pin(async move {
+            let mut directives = Vec::new();
+            let mut has_all = false;
+
+            for directive in &spf.directives {
+                let source = FlattenSource {
+                    domain: domain.clone(),
+                    directive: directive.clone(),
+                };
+                let target = match &directive.mechanism {
+                    Mechanism::A { macro_string, .. }
+                    | Mechanism::Mx { macro_string, .. }
+                    | Mechanism::Include { macro_string } => literal_domain(macro_string, &domain),
+                    _ => None,
+                };
+
+                match (&directive.mechanism, target) {
+                    (Mechanism::All | Mechanism::Ip4 { .. } | Mechanism::Ip6 { .. }, _) => {
+                        has_all = directive.mechanism == Mechanism::All;
+                        directives.push(FlattenedDirective {
+                            directive: directive.clone(),
+                            sources: vec![source],
+                        });
+                        if has_all {
+                            break;
+                        }
+                    }
+                    (
+                        Mechanism::A {
+                            ip4_mask, ip6_mask, ..
+                        },
+                        Some(target),
+                    ) => {
+                        self.flatten_host(
+                            &target,
+                            &directive.qualifier,
+                            *ip4_mask,
+                            *ip6_mask,
+                            &source,
+                            &mut directives,
+                        )
+                        .await?;
+                    }
+                    (
+                        Mechanism::Mx {
+                            ip4_mask, ip6_mask, ..
+                        },
+                        Some(target),
+                    ) => match self.mx_lookup(target.as_str()).await {
+                        // Evaluating more than 10 names is a permerror (RFC 7208, Section 4.6.4)
+                        Ok(records)
+                            if records.iter().flat_map(|mx| mx.exchanges.iter()).count()
+                                > MAX_MX_NAMES =>
+                        {
+                            warnings.push(SpfWarning::NotFlattened(directive.to_string()));
+                            directives.push(FlattenedDirective {
+                                directive: directive.clone(),
+                                sources: vec![source],
+                            });
+                        }
+                        Ok(records) => {
+                            for exchange in records.iter().flat_map(|mx| mx.exchanges.iter()) {
+                                self.flatten_host(
+                                    exchange,
+                                    &directive.qualifier,
+                                    *ip4_mask,
+                                    *ip6_mask,
+                                    &source,
+                                    &mut directives,
+                                )
+                                .await?;
+                            }
+                        }
+                        Err(Error::DnsRecordNotFound(_)) => (),
+                        Err(err) => return Err(err),
+                    },
+                    (Mechanism::Include { .. }, Some(target)) => {
+                        if stack.contains(&target) {
+                            return Err(Error::EvaluationLoop(target));
+                        } else if stack.len() > MAX_DEPTH {
+                            return Err(Error::LookupLimitExceeded);
+                        }
+                        let included_spf = self.txt_lookup::<Spf>(target.as_str()).await?;
+                        let mut included_stack = stack.clone();
+                        included_stack.push(target.clone());
+                        let included = self
+                            .flatten_record(&included_spf, target, included_stack, warnings)
+                            .await?;
+
+                        if let Some(included) = include_directives(included, &directive.qualifier) {
+                            directives.extend(included);
+                        } else {
+                            warnings.push(SpfWarning::NotFlattened(directive.to_string()));
+                            directives.push(FlattenedDirective {
+                                directive: directive.clone(),
+                                sources: vec![source],
+                            });
+                        }
+                    }
+                    _ => {
+                        warnings.push(SpfWarning::NotFlattened(directive.to_string()));
+                        directives.push(FlattenedDirective {
+                            directive: directive.clone(),
+                            sources: vec![source],
+                        });
+                    }
+                }
+            }
+
+            // Redirects are ignored when the record has an all mechanism
+            let mut redirect = None;
+            match &spf.redirect {
+                Some(macro_string) if !has_all => {
+                    if let Some(target) = literal_domain(macro_string, &domain) {
+                        if stack.contains(&target) {
+                            return Err(Error::EvaluationLoop(target));
+                        } else if stack.len() > MAX_DEPTH {
+                            return Err(Error::LookupLimitExceeded);
+                        }
+                        let redirect_spf = self.txt_lookup::<Spf>(target.as_str()).await?;
+                        let mut redirect_stack = stack.clone();
+                        redirect_stack.push(target.clone());
+                        let redirected = self
+                            .flatten_record(&redirect_spf, target, redirect_stack, warnings)
+                            .await?;
+                        if redirected
+                            .directives
+                            .iter()
+                            .any(|directive| depends_on_domain(&directive.directive.mechanism))
+                        {
+                            warnings
+                                .push(SpfWarning::NotFlattened(format!("redirect={macro_string}")));
+                            redirect = macro_string.clone().into();
+                        } else {
+                            directives.extend(redirected.directives);
+                            redirect = redirected.redirect;
+                        }
+                    } else {
+                        warnings.push(SpfWarning::NotFlattened(format!("redirect={macro_string}")));
+                        redirect = macro_string.clone().into();
+                    }
+                }
+                _ => (),
+            }
+
+            Ok(FlattenedRecord {
+                directives,
+                redirect,
+            })
+        })
+    }
+
+    async fn flatten_host(
+        &self,
+        host: &str,
+        qualifier: &Qualifier,
+        ip4_mask: u32,
+        ip6_mask: u128,
+        source: &FlattenSource,
+        directives: &mut Vec<FlattenedDirective>,
+    ) -> crate::Result<()> {
+        match self.ipv4_lookup(host).await {
+            Ok(addrs) => {
+                for addr in addrs.iter() {
+                    directives.push(FlattenedDirective {
+                        directive: Directive::new(
+                            qualifier.clone(),
+                            Mechanism::Ip4 {
+                                addr: Ipv4Addr::from(u32::from(*addr) & ip4_mask),
+                                mask: ip4_mask,
+                            },
+                        ),
+                        sources: vec![source.clone()],
+                    });
+                }
+            }
+            Err(Error::DnsRecordNotFound(_)) => (),
+            Err(err) => return Err(err),
+        }
+        match self.ipv6_lookup(host).await {
+            Ok(addrs) => {
+                for addr in addrs.iter() {
+                    directives.push(FlattenedDirective {
+                        directive: Directive::new(
+                            qualifier.clone(),
+                            Mechanism::Ip6 {
+                                addr: Ipv6Addr::from(u128::from(*addr) & ip6_mask),
+                                mask: ip6_mask,
+                            },
+                        ),
+                        sources: vec![source.clone()],
+                    });
+                }
+            }
+            Err(Error::DnsRecordNotFound(_)) => (),
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+}
+
+impl FlattenedSpf {
+    /// Renders the flattened record as one or more TXT records of at most 512
+    /// bytes, returned as (name, value) pairs with the record for `domain`
+    /// first. Networks that do not fit are moved to records named
+    /// `spf1.<domain>`, `spf2.<domain>`, etc., which are then included. Fails
+    /// with `Error::RecordTooLong` when the record for `domain` does not fit
+    /// even after moving the networks out.
+    pub fn to_records(&self, domain: &str) -> crate::Result<Vec<(String, String)>> {
+        split_records(
+            domain,
+            &self
+                .directives
+                .iter()
+                .map(|directive| &directive.directive)
+                .collect::<Vec<_>>(),
+            self.redirect.as_ref(),
+            self.exp.as_ref(),
+            MAX_RECORD_LEN,
+        )
+    }
+
+    pub fn directives(&self) -> &[FlattenedDirective] {
+        &self.directives
+    }
+
+    pub fn redirect(&self) -> Option<&Macro> {
+        self.redirect.as_ref()
+    }
+
+    pub fn warnings(&self) -> &[SpfWarning] {
+        &self.warnings
+    }
+}
+
+impl FlattenedDirective {
+    pub fn directive(&self) -> &Directive {
+        &self.directive
+    }
+
+    pub fn sources(&self) -> &[FlattenSource] {
+        &self.sources
+    }
+}
+
+impl FlattenSource {
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn directive(&self) -> &Directive {
+        &self.directive
+    }
+}
+
+/// Renders a record as one or more TXT records of at most `max_len` bytes,
+/// moving networks that do not fit to included records. Fails with
+/// `Error::RecordTooLong` when the remaining terms still exceed `max_len`.
+pub(crate) fn split_records(
+    domain: &str,
+    directives: &[&Directive],
+    redirect: Option<&Macro>,
+    exp: Option<&Macro>,
+    max_len: usize,
+) -> crate::Result<Vec<(String, String)>> {
+    let domain = domain.trim_end_matches('.');
+    let mut tail = String::new();
+    for directive in directives {
+        if directive.mechanism == Mechanism::All {
+            tail = format!(" {directive}");
+        }
+    }
+    if let Some(redirect) = redirect {
+        tail = format!("{tail} redirect={redirect}");
+    }
+    if let Some(exp) = exp {
+        tail = format!("{tail} exp={exp}");
+    }
+
+    let mut record = String::from("v=spf1");
+    for directive in directives {
+        if directive.mechanism != Mechanism::All {
+            record = format!("{record} {directive}");
+        }
+    }
+    record.push_str(&tail);
+    if record.len() <= max_len {
+        return Ok(vec![(domain.to_string(), record)]);
+    }
+
+    // Move runs of networks with the same qualifier to included records
+    let mut records = vec![(domain.to_string(), String::from("v=spf1"))];
+    let mut run_qualifier = None;
+    for directive in directives {
+        match directive.mechanism {
+            Mechanism::Ip4 { .. } | Mechanism::Ip6 { .. } => {
+                let term = format!(" {}", directive.mechanism);
+                let last = &records[records.len() - 1].1;
+                if run_qualifier.as_ref() != Some(&directive.qualifier)
+                    || last.len() + term.len() > max_len
+                {
+                    let name = format!("spf{}.{domain}", records.len());
+                    records[0].1 = format!(
+                        "{} {}",
+                        records[0].1,
+                        Directive::new(
+                            directive.qualifier.clone(),
+                            Mechanism::Include {
+                                macro_string: Macro::Literal(name.as_bytes().to_vec()),
+                            },
+                        )
+                    );
+                    records.push((name, String::from("v=spf1")));
+                    run_qualifier = directive.qualifier.clone().into();
+                }
+                records.last_mut().unwrap().1.push_str(&term);
+            }
+            Mechanism::All => (),
+            _ => {
+                records[0].1 = format!("{} {directive}", records[0].1);
+                run_qualifier = None;
+            }
+        }
+    }
+    records[0].1.push_str(&tail);
+
+    match records.iter().find(|(_, record)| record.len() > max_len) {
+        Some((_, record)) => Err(Error::RecordTooLong(record.len())),
+        None => Ok(records),
+    }
+}
+
+/// Returns the directives matched by an include, which are the passing
+/// directives of the included record. Non-passing directives are only
+/// dropped when they do not overlap any passing directive after them.
+/// Records with mechanisms that depend on the domain being evaluated are
+/// not inlined, as they would be evaluated against the including domain.
+fn include_directives(
+    included: FlattenedRecord,
+    qualifier: &Qualifier,
+) -> Option<Vec<FlattenedDirective>> {
+    if included.redirect.is_some()
+        || included
+            .directives
+            .iter()
+            .any(|directive| depends_on_domain(&directive.directive.mechanism))
+    {
+        return None;
+    }
+
+    let mut directives = Vec::new();
+    for (pos, directive) in included.directives.iter().enumerate() {
+        if directive.directive.qualifier == Qualifier::Pass {
+            if directive.directive.mechanism == Mechanism::All {
+                return None;
+            }
+            directives.push(FlattenedDirective {
+                directive: Directive::new(qualifier.clone(), directive.directive.mechanism.clone()),
+                sources: directive.sources.clone(),
+            });
+        } else if included.directives[pos + 1..]
+            .iter()
+            .filter(|next| next.directive.qualifier == Qualifier::Pass)
+            .any(|next| {
+                match (
+                    Network::new(&directive.directive.mechanism),
+                    Network::new(&next.directive.mechanism),
+                ) {
+                    (Some(network), Some(next)) => network.overlaps(&next),
+                    _ => true,
+                }
+            })
+        {
+            return None;
+        }
+    }
+
+    Some(directives)
+}
+
+/// Removes networks already covered by a previous network and merges adjacent
+/// networks sharing the same qualifier.
+fn aggregate(directives: Vec<FlattenedDirective>) -> Vec<FlattenedDirective> {
+    let mut unique: Vec<FlattenedDirective> = Vec::with_capacity(directives.len());
+    'outer: for directive in directives {
+        if let Some(network) = Network::new(&directive.directive.mechanism) {
+            for prev in unique.iter_mut() {
+                if matches!(Network::new(&prev.directive.mechanism), Some(prev_network) if prev_network.contains(&network))
+                {
+                    if prev.directive.qualifier == directive.directive.qualifier {
+                        prev.sources.extend(directive.sources);
+                    }
+                    continue 'outer;
+                }
+            }
+        }
+        unique.push(directive);
+    }
+
+    let mut result = Vec::with_capacity(unique.len());
+    let mut run: Vec<(Network, FlattenedDirective)> = Vec::new();
+    for directive in unique {
+        match Network::new(&directive.directive.mechanism) {
+            Some(network)
+                if run.first().map_or(true, |(_, first)| {
+                    first.directive.qualifier == directive.directive.qualifier
+                }) =>
+            {
+                run.push((network, directive));
+            }
+            network => {
+                aggregate_run(std::mem::take(&mut run), &mut result);
+                if let Some(network) = network {
+                    run.push((network, directive));
+                } else {
+                    result.push(directive);
+                }
+            }
+        }
+    }
+    aggregate_run(run, &mut result);
+
+    result
+}
+
+fn aggregate_run(
+    mut run: Vec<(Network, FlattenedDirective)>,
+    result: &mut Vec<FlattenedDirective>,
+) {
+    run.sort_by_key(|(network, _)| (network.width, network.addr, network.len));
+
+    let mut merged: Vec<(Network, FlattenedDirective)> = Vec::with_capacity(run.len());
+    for (network, directive) in run {
+        if let Some((last, last_directive)) = merged.last_mut() {
+            if last.contains(&network) {
+                last_directive.sources.extend(directive.sources);
+                continue;
+            }
+        }
+        merged.push((network, directive));
+
+        // Merge sibling networks into their parent
+        while merged.len() >= 2 {
+            let (prev, last) = (&merged[merged.len() - 2].0, &merged[merged.len() - 1].0);
+            if prev.width == last.width
+                && prev.len == last.len
+                && prev.len > 0
+                && prev.addr & prev.bit() == 0
+                && prev.addr | prev.bit() == last.addr
+            {
+                let (_, last_directive) = merged.pop().unwrap();
+                let (prev, prev_directive) = merged.last_mut().unwrap();
+                prev.len -= 1;
+                prev_directive.sources.extend(last_directive.sources);
+            } else {
+                break;
+            }
+        }
+    }
+
+    for (network, mut directive) in merged {
+        directive.directive.mechanism = network.to_mechanism();
+        result.push(directive);
+    }
+}
+
+impl Network {
+    fn new(mechanism: &Mechanism) -> Option<Self> {
+        match mechanism {
+            Mechanism::Ip4 { addr, mask } => Network {
+                addr: (u32::from(*addr) & mask) as u128,
+                len: mask.leading_ones(),
+                width: 32,
+            }
+            .into(),
+            Mechanism::Ip6 { addr, mask } => Network {
+                addr: u128::from(*addr) & mask,
+                len: mask.leading_ones(),
+                width: 128,
+            }
+            .into(),
+            _ => None,
+        }
+    }
+
+    fn mask(&self, len: u32) -> u128 {
+        let mask = u128::MAX >> (128 - self.width);
+        mask.checked_shl(self.width - len).unwrap_or(0) & mask
+    }
+
+    fn bit(&self) -> u128 {
+        1u128 << (self.width - self.len)
+    }
+
+    fn contains(&self, other: &Network) -> bool {
+        self.width == other.width
+            && self.len <= other.len
+            && other.addr & self.mask(self.len) == self.addr
+    }
+
+    fn overlaps(&self, other: &Network) -> bool {
+        self.contains(other) || other.contains(self)
+    }
+
+    fn to_mechanism(self) -> Mechanism {
+        if self.width == 32 {
+            Mechanism::Ip4 {
+                addr: Ipv4Addr::from(self.addr as u32),
+                mask: self.mask(self.len) as u32,
+            }
+        } else {
+            Mechanism::Ip6 {
+                addr: Ipv6Addr::from(self.addr),
+                mask: self.mask(self.len),
+            }
+        }
+    }
+}
+
+/// Returns whether a mechanism left in a flattened record is a `ptr` or has a
+/// macro, both of which are evaluated against the domain of its record.
+fn depends_on_domain(mechanism: &Mechanism) -> bool {
+    match mechanism {
+        Mechanism::Ptr { .. } => true,
+        Mechanism::Include { macro_string }
+        | Mechanism::A { macro_string, .. }
+        | Mechanism::Mx { macro_string, .. }
+        | Mechanism::Exists { macro_string } => !matches!(macro_string, Macro::Literal(_)),
+        Mechanism::All | Mechanism::Ip4 { .. } | Mechanism::Ip6 { .. } => false,
+    }
+}
+
+pub(crate) fn literal_domain(macro_string: &Macro, domain: &str) -> Option<String> {
+    match macro_string {
+        Macro::Literal(literal) => std::str::from_utf8(literal)
+            .ok()
+            .map(|target| target.trim_end_matches('.').to_lowercase()),
+        Macro::None => domain.to_string().into(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        common::parse::TxtRecordParser,
+        spf::{Directive, Macro, Mechanism, Qualifier, Spf, SpfWarning},
+        Error, Resolver, MX,
+    };
+
+    #[tokio::test]
+    async fn spf_flatten() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (name, record) in [
+            (
+                "example.org",
+                concat!(
+                    "v=spf1 ip4:192.0.2.0/25 include:_spf.example.net a mx:mail.example.org ",
+                    "exists:%{i}._bl.example.org -all"
+                ),
+            ),
+            (
+                "_spf.example.net",
+                concat!(
+                    "v=spf1 ip4:192.0.2.128/25 ip6:2001:db8:8000::/33 -ip4:203.0.113.0/24 ",
+                    "include:_spf2.example.net ~all"
+                ),
+            ),
+            (
+                "_spf2.example.net",
+                "v=spf1 ip6:2001:db8::/33 redirect=_spf3.example.net",
+            ),
+            ("_spf3.example.net", "v=spf1 ip4:198.51.100.0/24 ?all"),
+            (
+                "overlap.example.org",
+                "v=spf1 include:_overlap.example.net -all",
+            ),
+            (
+                "_overlap.example.net",
+                "v=spf1 -ip4:198.51.100.64/26 ip4:198.51.100.0/24 -all",
+            ),
+            ("loop.example.org", "v=spf1 include:_loop.example.net -all"),
+            (
+                "_loop.example.net",
+                "v=spf1 ip4:192.0.2.1 redirect=%{d2}._spf.example.net",
+            ),
+            ("ptr.example.org", "v=spf1 include:_ptr.example.net -all"),
+            ("_ptr.example.net", "v=spf1 ip4:192.0.2.1 ptr -all"),
+            ("macro.example.org", "v=spf1 redirect=_macro.example.net"),
+            (
+                "_macro.example.net",
+                "v=spf1 ip4:192.0.2.1 exists:%{d}._bl.example.net -all",
+            ),
+            ("mx.example.org", "v=spf1 mx:many.example.org -all"),
+            ("loop1.example.org", "v=spf1 include:loop2.example.org -all"),
+            ("loop2.example.org", "v=spf1 redirect=loop1.example.org"),
+        ] {
+            resolver.txt_add(name, Spf::parse(record.as_bytes()), valid_until);
+        }
+        resolver.ipv4_add(
+            "example.org",
+            vec![Ipv4Addr::new(192, 0, 2, 10)],
+            valid_until,
+        );
+        resolver.mx_add(
+            "mail.example.org",
+            vec![MX {
+                exchanges: vec!["mx1.example.org".to_string()],
+                preference: 10,
+            }],
+            valid_until,
+        );
+        resolver.mx_add(
+            "many.example.org",
+            vec![MX {
+                exchanges: (0..11).map(|n| format!("mx{n}.example.org")).collect(),
+                preference: 10,
+            }],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "mx1.example.org",
+            vec![Ipv4Addr::new(198, 51, 100, 7)],
+            valid_until,
+        );
+        resolver.ipv6_add(
+            "mx1.example.org",
+            vec!["2001:db8:1::25".parse::<Ipv6Addr>().unwrap()],
+            valid_until,
+        );
+
+        // Networks are deduplicated and aggregated
+        let spf = resolver.txt_lookup::<Spf>("example.org").await.unwrap();
+        let flattened = spf.flatten(&resolver, "example.org").await.unwrap();
+        assert_eq!(
+            flattened.to_records("example.org").unwrap(),
+            vec![(
+                "example.org".to_string(),
+                concat!(
+                    "v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.0/24 ip6:2001:db8::/32 ",
+                    "exists:%{i}._bl.example.org -all"
+                )
+                .to_string()
+            )]
+        );
+        assert_eq!(
+            flattened.warnings(),
+            &[SpfWarning::NotFlattened(
+                "exists:%{i}._bl.example.org".to_string()
+            )]
+        );
+
+        // Each network lists the mechanisms it was obtained from
+        let sources = flattened.directives()[0]
+            .sources()
+            .iter()
+            .map(|source| format!("{} {}", source.domain(), source.directive()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                "example.org ip4:192.0.2.0/25",
+                "example.org a",
+                "_spf.example.net ip4:192.0.2.128/25"
+            ]
+        );
+        let sources = flattened.directives()[2]
+            .sources()
+            .iter()
+            .map(|source| format!("{} {}", source.domain(), source.directive()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                "_spf2.example.net ip6:2001:db8::/33",
+                "example.org mx:mail.example.org",
+                "_spf.example.net ip6:2001:db8:8000::/33"
+            ]
+        );
+
+        // Includes with non-passing networks overlapping passing ones are kept
+        let spf = resolver
+            .txt_lookup::<Spf>("overlap.example.org")
+            .await
+            .unwrap();
+        let flattened = spf.flatten(&resolver, "overlap.example.org").await.unwrap();
+        assert_eq!(
+            flattened.to_records("overlap.example.org").unwrap()[0].1,
+            "v=spf1 include:_overlap.example.net -all"
+        );
+        assert_eq!(
+            flattened.warnings(),
+            &[SpfWarning::NotFlattened(
+                "include:_overlap.example.net".to_string()
+            )]
+        );
+
+        // Includes of records redirecting to a macro are kept
+        let spf = resolver
+            .txt_lookup::<Spf>("loop.example.org")
+            .await
+            .unwrap();
+        let flattened = spf.flatten(&resolver, "loop.example.org").await.unwrap();
+        assert_eq!(
+            flattened.to_records("loop.example.org").unwrap()[0].1,
+            "v=spf1 include:_loop.example.net -all"
+        );
+
+        // Includes and redirects of records with ptr or macros are kept, as these
+        // would otherwise be evaluated against the including domain
+        for (domain, expected_record, expected_warnings) in [
+            (
+                "ptr.example.org",
+                "v=spf1 include:_ptr.example.net -all",
+                ["ptr", "include:_ptr.example.net"],
+            ),
+            (
+                "macro.example.org",
+                "v=spf1 redirect=_macro.example.net",
+                ["exists:%{d}._bl.example.net", "redirect=_macro.example.net"],
+            ),
+        ] {
+            let spf = resolver.txt_lookup::<Spf>(domain).await.unwrap();
+            let flattened = spf.flatten(&resolver, domain).await.unwrap();
+            assert_eq!(flattened.to_records(domain).unwrap()[0].1, expected_record);
+            assert_eq!(
+                flattened.warnings(),
+                expected_warnings
+                    .into_iter()
+                    .map(|warning| SpfWarning::NotFlattened(warning.to_string()))
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        // Mechanisms resolving to more than 10 MX names are kept
+        let spf = resolver.txt_lookup::<Spf>("mx.example.org").await.unwrap();
+        let flattened = spf.flatten(&resolver, "mx.example.org").await.unwrap();
+        assert_eq!(
+            flattened.to_records("mx.example.org").unwrap()[0].1,
+            "v=spf1 mx:many.example.org -all"
+        );
+        assert_eq!(
+            flattened.warnings(),
+            &[SpfWarning::NotFlattened("mx:many.example.org".to_string())]
+        );
+
+        // Loops are detected
+        let spf = resolver
+            .txt_lookup::<Spf>("loop1.example.org")
+            .await
+            .unwrap();
+        assert_eq!(
+            spf.flatten(&resolver, "loop1.example.org").await,
+            Err(Error::EvaluationLoop("loop1.example.org".to_string()))
+        );
+
+        // Deeply nested includes are rejected
+        for n in 0..12 {
+            resolver.txt_add(
+                format!("deep{n}.example.org"),
+                Spf::parse(format!("v=spf1 include:deep{}.example.org -all", n + 1).as_bytes()),
+                valid_until,
+            );
+        }
+        let spf = resolver
+            .txt_lookup::<Spf>("deep0.example.org")
+            .await
+            .unwrap();
+        assert_eq!(
+            spf.flatten(&resolver, "deep0.example.org").await,
+            Err(Error::LookupLimitExceeded)
+        );
+
+        // Long records are split into included records
+        let spf = Spf::parse(
+            format!(
+                "v=spf1 {} -ip4:10.0.100.1 {} ~all",
+                (0..30)
+                    .map(|i| format!("ip4:10.0.{}.1", i * 2))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                (0..30)
+                    .map(|i| format!("ip6:2001:db8:{:x}::/64", i * 2))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let flattened = spf.flatten(&resolver, "long.example.org").await.unwrap();
+        let records = flattened.to_records("long.example.org").unwrap();
+        assert_eq!(
+            records[0].1,
+            concat!(
+                "v=spf1 include:spf1.long.example.org -include:spf2.long.example.org ",
+                "include:spf3.long.example.org include:spf4.long.example.org ~all"
+            )
+        );
+        let mut num_networks = 0;
+        for (pos, (name, record)) in records.iter().enumerate() {
+            assert!(record.len() <= 512, "{name} {record}");
+            let spf = Spf::parse(record.as_bytes()).unwrap();
+            if pos > 0 {
+                assert_eq!(name, &format!("spf{pos}.long.example.org"));
+                for directive in spf.directives() {
+                    assert_eq!(directive.qualifier(), &Qualifier::Pass);
+                    assert!(matches!(
+                        directive.mechanism(),
+                        Mechanism::Ip4 { .. } | Mechanism::Ip6 { .. }
+                    ));
+                    num_networks += 1;
+                }
+            }
+        }
+        assert_eq!(num_networks, 61);
+        assert_eq!(
+            flattened.directives()[30].directive(),
+            &Directive::new(
+                Qualifier::Fail,
+                Mechanism::Ip4 {
+                    addr: Ipv4Addr::new(10, 0, 100, 1),
+                    mask: u32::MAX
+                }
+            )
+        );
+        assert_eq!(
+            flattened.directives()[0].sources()[0]
+                .directive()
+                .mechanism(),
+            &Mechanism::Ip4 {
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+                mask: u32::MAX
+            }
+        );
+        assert!(flattened.redirect().is_none());
+        assert_eq!(
+            Spf::parse(b"v=spf1 redirect=%{d}._spf.example.org")
+                .unwrap()
+                .flatten(&resolver, "example.org")
+                .await
+                .unwrap()
+                .redirect(),
+            Some(&Macro::List(vec![
+                Macro::Variable {
+                    letter: crate::spf::Variable::Domain,
+                    num_parts: 0,
+                    reverse: false,
+                    escape: false,
+                    delimiters: 1u64 << (b'.' - b'+'),
+                },
+                Macro::Literal(b"._spf.example.org".to_vec())
+            ]))
+        );
+
+        // Records that do not fit after moving the networks out are rejected
+        let spf = Spf::parse(
+            format!(
+                "v=spf1 ip4:10.0.0.1 {} -all",
+                (0..20)
+                    .map(|i| format!("exists:%{{i}}._bl{i}.example.org"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let flattened = spf.flatten(&resolver, "many.example.org").await.unwrap();
+        assert!(matches!(
+            flattened.to_records("many.example.org"),
+            Err(Error::RecordTooLong(len)) if len > 512
+        ));
+    }
+}
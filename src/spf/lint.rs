@@ -0,0 +1,154 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use super::{Mechanism, Qualifier, Spf, SpfWarning};
+
+impl Spf {
+    /// Returns the issues found in the record, without performing any DNS lookups.
+    pub fn lint(&self) -> Vec<SpfWarning> {
+        let mut warnings = Vec::new();
+
+        let num_lookups = self
+            .directives
+            .iter()
+            .filter(|directive| {
+                matches!(
+                    directive.mechanism,
+                    Mechanism::Include { .. }
+                        | Mechanism::A { .. }
+                        | Mechanism::Mx { .. }
+                        | Mechanism::Ptr { .. }
+                        | Mechanism::Exists { .. }
+                )
+            })
+            .count()
+            + usize::from(self.redirect.is_some());
+        if num_lookups > 10 {
+            warnings.push(SpfWarning::TooManyLookups(num_lookups));
+        }
+
+        if self
+            .directives
+            .iter()
+            .any(|directive| matches!(directive.mechanism, Mechanism::Ptr { .. }))
+        {
+            warnings.push(SpfWarning::PtrMechanism);
+        }
+
+        match self
+            .directives
+            .iter()
+            .find(|directive| directive.mechanism == Mechanism::All)
+        {
+            Some(directive) if directive.qualifier == Qualifier::Pass => {
+                warnings.push(SpfWarning::PassAll);
+            }
+            None if self.redirect.is_none() => {
+                warnings.push(SpfWarning::MissingAll);
+            }
+            _ => (),
+        }
+
+        // Issues found while parsing, such as duplicate or unknown modifiers
+        warnings.extend(self.warnings.iter().cloned());
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::parse::TxtRecordParser,
+        spf::{Spf, SpfParseError, SpfWarning},
+    };
+
+    #[test]
+    fn lint_spf() {
+        for (record, expected_warnings) in [
+            ("v=spf1 mx a:colo.example.com/28 -all", vec![]),
+            ("v=spf1 include:_spf.example.com ~all", vec![]),
+            ("v=spf1 redirect=_spf.example.com", vec![]),
+            ("v=spf1 ip4:192.0.2.0/24 +all", vec![SpfWarning::PassAll]),
+            ("v=spf1 ip4:192.0.2.0/24 all", vec![SpfWarning::PassAll]),
+            ("v=spf1 ip4:192.0.2.0/24", vec![SpfWarning::MissingAll]),
+            (
+                "v=spf1 ptr:example.com ?all",
+                vec![SpfWarning::PtrMechanism],
+            ),
+            (
+                concat!(
+                    "v=spf1 a:t1.org a:t2.org mx:t3.org include:t4.org include:t5.org ",
+                    "exists:t6.org a:t7.org a:t8.org a:t9.org a:t10.org redirect=t11.org"
+                ),
+                vec![SpfWarning::TooManyLookups(11)],
+            ),
+            (
+                concat!(
+                    "v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.0/24 ip4:203.0.113.0/24 ",
+                    "ip6:2001:db8:1::/48 ip6:2001:db8:2::/48 ip6:2001:db8:3::/48 ",
+                    "ip6:2001:db8:4::/48 ip6:2001:db8:5::/48 ip6:2001:db8:6::/48 ",
+                    "ip6:2001:db8:7::/48 ip6:2001:db8:8::/48 ip6:2001:db8:9::/48 ",
+                    "ip6:2001:db8:a::/48 -all"
+                ),
+                vec![SpfWarning::RecordTooLong(267)],
+            ),
+            (
+                "v=spf1 mx -all moo=cow ra=postmaster RA=abuse rp=10 rp=20 rp=30",
+                vec![
+                    SpfWarning::UnknownModifier("moo".to_string()),
+                    SpfWarning::DuplicateModifier("ra".to_string()),
+                    SpfWarning::DuplicateModifier("rp".to_string()),
+                ],
+            ),
+        ] {
+            assert_eq!(
+                record.parse::<Spf>().unwrap().lint(),
+                expected_warnings,
+                "{record}"
+            );
+        }
+
+        // Invalid records
+        for (record, expected_error) in [
+            (
+                "v=spf1 all/24",
+                SpfParseError::InvalidTerm("all/24".to_string()),
+            ),
+            (
+                "v=spf1 mx redirect=a.org REDIRECT=b.org",
+                SpfParseError::DuplicateModifier("redirect".to_string()),
+            ),
+            (
+                "v=spf1 exp=a.org exp=b.org -all",
+                SpfParseError::DuplicateModifier("exp".to_string()),
+            ),
+            ("v=spf2 -all", SpfParseError::InvalidVersion),
+        ] {
+            assert_eq!(record.parse::<Spf>(), Err(expected_error), "{record}");
+        }
+
+        // Case and whitespace are not significant
+        assert_eq!(
+            "V=SPF1\tMX  A:colo.example.com/28 \t -ALL"
+                .parse::<Spf>()
+                .unwrap(),
+            Spf::parse(b"v=spf1 mx a:colo.example.com/28 -all").unwrap()
+        );
+        assert_eq!(
+            "v=spf1 mx a:colo.example.com/28 -all"
+                .parse::<Spf>()
+                .unwrap()
+                .directives()
+                .len(),
+            3
+        );
+    }
+}
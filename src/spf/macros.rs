@@ -8,7 +8,7 @@
  * except according to those terms.
  */
 
-use std::{borrow::Cow, net::IpAddr, time::SystemTime};
+use std::{borrow::Cow, fmt::Display, net::IpAddr, time::SystemTime};
 
 use super::{Macro, Variable, Variables};
 
@@ -19,7 +19,7 @@ impl Macro {
         default: &'x str,
         fqdn: bool,
     ) -> Cow<'z, str> {
-        match self {
+        let result = match self {
             Macro::Literal(literal) => std::str::from_utf8(literal).unwrap_or_default().into(),
             Macro::Variable {
                 letter,
@@ -66,6 +66,12 @@ impl Macro {
                 String::from_utf8(result).unwrap_or_default().into()
             }
             Macro::None => default.into(),
+        };
+
+        if fqdn {
+            truncate_domain(result)
+        } else {
+            result
         }
     }
 
@@ -78,6 +84,78 @@ impl Macro {
     }
 }
 
+impl Display for Macro {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Macro::Literal(literal) => {
+                let mut start = 0;
+                for (pos, &ch) in literal.iter().enumerate() {
+                    let escaped = match ch {
+                        b'%' => "%%",
+                        b' ' => "%_",
+                        _ => continue,
+                    };
+                    f.write_str(&String::from_utf8_lossy(&literal[start..pos]))?;
+                    f.write_str(escaped)?;
+                    start = pos + 1;
+                }
+                f.write_str(&String::from_utf8_lossy(&literal[start..]))
+            }
+            Macro::Variable {
+                letter,
+                num_parts,
+                reverse,
+                escape,
+                delimiters,
+            } => {
+                let letter = match letter {
+                    Variable::Sender => 's',
+                    Variable::SenderLocalPart => 'l',
+                    Variable::SenderDomainPart => 'o',
+                    Variable::Domain => 'd',
+                    Variable::Ip => 'i',
+                    Variable::ValidatedDomain => 'p',
+                    Variable::IpVersion => 'v',
+                    Variable::HeloDomain => 'h',
+                    Variable::SmtpIp => 'c',
+                    Variable::HostDomain => 'r',
+                    Variable::CurrentTime => 't',
+                };
+                write!(
+                    f,
+                    "%{{{}",
+                    if *escape {
+                        letter.to_ascii_uppercase()
+                    } else {
+                        letter
+                    }
+                )?;
+                if *num_parts > 0 {
+                    write!(f, "{num_parts}")?;
+                }
+                if *reverse {
+                    f.write_str("r")?;
+                }
+                if *delimiters != 1u64 << (b'.' - b'+') {
+                    for ch in [b'.', b'-', b'+', b',', b'/', b'_', b'='] {
+                        if (delimiters & 1u64 << (ch - b'+')) != 0 {
+                            write!(f, "{}", char::from(ch))?;
+                        }
+                    }
+                }
+                f.write_str("}")
+            }
+            Macro::List(list) => {
+                for item in list {
+                    item.fmt(f)?;
+                }
+                Ok(())
+            }
+            Macro::None => Ok(()),
+        }
+    }
+}
+
 impl<'x> Variables<'x> {
     pub fn new() -> Self {
         let mut vars = Variables::default();
@@ -117,21 +195,26 @@ impl<'x> Variables<'x> {
     }
 
     pub fn set_sender(&mut self, value: impl Into<Cow<'x, [u8]>>) {
-        let value = value.into();
-        for (pos, ch) in value.as_ref().iter().enumerate() {
-            if ch == &b'@' {
-                if pos > 0 {
-                    self.vars[Variable::SenderLocalPart as usize] = match &value {
-                        Cow::Borrowed(value) => (&value[..pos]).into(),
-                        Cow::Owned(value) => value[..pos].to_vec().into(),
-                    };
-                }
-                self.vars[Variable::SenderDomainPart as usize] = match &value {
-                    Cow::Borrowed(value) => (value.get(pos + 1..).unwrap_or_default()).into(),
-                    Cow::Owned(value) => (value.get(pos + 1..).unwrap_or_default()).to_vec().into(),
-                };
-                break;
+        let mut value = value.into();
+
+        // Quoted local-parts may contain "@", so split at the last one
+        if let Some(mut pos) = value.iter().rposition(|&ch| ch == b'@') {
+            if pos == 0 {
+                // Senders without a local-part use "postmaster"
+                let mut sender = b"postmaster".to_vec();
+                sender.extend_from_slice(&value);
+                value = sender.into();
+                pos = b"postmaster".len();
             }
+            let (local_part, domain_part) = match &value {
+                Cow::Borrowed(value) => ((&value[..pos]).into(), (&value[pos + 1..]).into()),
+                Cow::Owned(value) => (
+                    value[..pos].to_vec().into(),
+                    value[pos + 1..].to_vec().into(),
+                ),
+            };
+            self.vars[Variable::SenderLocalPart as usize] = local_part;
+            self.vars[Variable::SenderDomainPart as usize] = domain_part;
         }
 
         self.vars[Variable::Sender as usize] = value;
@@ -204,6 +287,26 @@ impl<'x> Variables<'x> {
     }
 }
 
+/// Removes labels from the left side of a domain name until it
+/// does not exceed 253 characters (RFC 7208, Section 4.8).
+fn truncate_domain(domain: Cow<'_, str>) -> Cow<'_, str> {
+    let name = domain.strip_suffix('.').unwrap_or(&domain);
+    let mut pos = 0;
+    while name.len() - pos > 253 {
+        if let Some(dot) = name[pos..].find('.') {
+            pos += dot + 1;
+        } else {
+            break;
+        }
+    }
+
+    match domain {
+        Cow::Borrowed(domain) if pos > 0 => domain[pos..].into(),
+        Cow::Owned(domain) if pos > 0 => domain[pos..].to_string().into(),
+        domain => domain,
+    }
+}
+
 #[inline(always)]
 fn add_part(result: &mut Vec<u8>, part: &[u8], pos: usize, escape: bool) {
     if pos > 0 {
@@ -216,7 +319,8 @@ fn add_part(result: &mut Vec<u8>, part: &[u8], pos: usize, escape: bool) {
             if ch.is_ascii_alphanumeric() || [b'-', b'.', b'_', b'~'].contains(ch) {
                 result.push(*ch);
             } else {
-                result.extend_from_slice(format!("%{ch:02x}").as_bytes());
+                // Uppercase hex digits, as recommended by RFC 3986, Section 2.1
+                result.extend_from_slice(format!("%{ch:02X}").as_bytes());
             }
         }
     }
@@ -226,7 +330,7 @@ fn add_part(result: &mut Vec<u8>, part: &[u8], pos: usize, escape: bool) {
 mod test {
     use std::net::IpAddr;
 
-    use crate::spf::{parse::SPFParser, Variables};
+    use crate::spf::{parse::SPFParser, Macro, Variables};
 
     #[test]
     fn expand_macro() {
@@ -252,6 +356,12 @@ mod test {
             ("%{lr}", "strong-bad"),
             ("%{lr-}", "bad.strong"),
             ("%{l1r-}", "strong"),
+            ("%{l1r+-}", "strong"),
+            ("%{l2R+-}", "bad.strong"),
+            ("%{L}", "strong-bad"),
+            ("%{D2}", "example.com"),
+            ("%{d10r}", "com.example.email"),
+            ("%{p}", "mx.example.org"),
             ("%{p1r}", "mx"),
             ("%{h3r}", ".."),
             (
@@ -299,12 +409,84 @@ mod test {
                 "See http://%{d}/why.html?s=%{S}&i=%{C}",
                 concat!(
                     "See http://email.example.com/why.html?",
-                    "s=strong-bad%40email.example.com&i=2001%3adb8%3a%3acb01"
+                    "s=strong-bad%40email.example.com&i=2001%3Adb8%3A%3Acb01"
                 ),
             ),
         ] {
             let (m, _) = macro_string.as_bytes().iter().macro_string(true).unwrap();
             assert_eq!(m.eval(&vars, "", false), expansion, "{macro_string:?}");
         }
+
+        // Unusual local-parts
+        for (sender, macro_string, expansion) in [
+            ("user+tag@example.com", "%{l}", "user+tag"),
+            ("user+tag@example.com", "%{l+}", "user.tag"),
+            ("user+tag@example.com", "%{l1r+}", "user"),
+            ("user.name@example.com", "%{l}", "user.name"),
+            ("user.name@example.com", "%{lr}", "name.user"),
+            ("user.name@example.com", "%{l-}", "user.name"),
+            ("\"user name\"@example.com", "%{l}", "\"user name\""),
+            ("\"user name\"@example.com", "%{L}", "%22user%20name%22"),
+            ("\"user@home\"@example.com", "%{o}", "example.com"),
+            ("\"user@home\"@example.com", "%{L}", "%22user%40home%22"),
+            ("@example.com", "%{l}", "postmaster"),
+            ("@example.com", "%{s}", "postmaster@example.com"),
+            ("@example.com", "%{S}", "postmaster%40example.com"),
+        ] {
+            let mut vars = Variables::new();
+            vars.set_sender(sender.as_bytes());
+            let (m, _) = macro_string.as_bytes().iter().macro_string(true).unwrap();
+            assert_eq!(
+                m.eval(&vars, "", false),
+                expansion,
+                "{sender:?} {macro_string:?}"
+            );
+        }
+
+        // Invalid transformers
+        for macro_string in [
+            "%{d0}", "%{d00}", "%{dr2}", "%{d.r}", "%{d.2}", "%{drr}", "%{d2", "%{x}", "%{d@}",
+        ] {
+            assert!(
+                macro_string.as_bytes().iter().macro_string(false).is_err(),
+                "{macro_string:?}"
+            );
+        }
+
+        // Expanded domains are truncated to 253 characters
+        let mut vars = Variables::new();
+        let label = "a".repeat(63);
+        vars.set_domain(format!("{label}.{label}.{label}.{label}.example.com").into_bytes());
+        let (m, _) = "%{d}._spf.%{d2}"
+            .as_bytes()
+            .iter()
+            .macro_string(false)
+            .unwrap();
+        assert_eq!(
+            m.eval(&vars, "", true),
+            format!("{label}.{label}.{label}.example.com._spf.example.com.")
+        );
+        let (m, _) = "%{d}".as_bytes().iter().macro_string(false).unwrap();
+        assert_eq!(
+            m.eval(&vars, "", true),
+            format!("{label}.{label}.{label}.example.com")
+        );
+    }
+
+    #[test]
+    fn display_macro() {
+        for macro_string in [
+            "%{ir}.%{v}._spf.%{d2}",
+            "100%%%_blocked",
+            "Dirección%_%{i}%_no%_autorizada",
+            "送信元%_%{s}%_は許可されていません",
+        ] {
+            let (m, _) = macro_string.as_bytes().iter().macro_string(true).unwrap();
+            assert_eq!(m.to_string(), macro_string);
+        }
+        assert_eq!(
+            Macro::Literal("café".as_bytes().to_vec()).to_string(),
+            "café"
+        );
     }
 }
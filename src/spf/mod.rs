@@ -8,16 +8,24 @@
  * except according to those terms.
  */
 
+pub mod builder;
+pub mod disposition;
+pub mod flatten;
+pub mod lint;
 pub mod macros;
 pub mod parse;
 pub mod verify;
 
 use std::{
     borrow::Cow,
-    net::{Ipv4Addr, Ipv6Addr},
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
 };
 
-use crate::{is_within_pct, SpfOutput, SpfResult, Version};
+use trust_dns_resolver::proto::rr::RecordType;
+
+use crate::{is_within_pct, Error, SpfIdentity, SpfOutput, SpfResult, Version};
 
 /*
       "+" pass
@@ -27,7 +35,7 @@ use crate::{is_within_pct, SpfOutput, SpfResult, Version};
 */
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub(crate) enum Qualifier {
+pub enum Qualifier {
     Pass,
     Fail,
     SoftFail,
@@ -39,7 +47,7 @@ pub(crate) enum Qualifier {
                       / a / mx / ptr / ip4 / ip6 / exists )
 */
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub(crate) enum Mechanism {
+pub enum Mechanism {
     All,
     Include {
         macro_string: Macro,
@@ -74,7 +82,7 @@ pub(crate) enum Mechanism {
     directive        = [ qualifier ] mechanism
 */
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub(crate) struct Directive {
+pub struct Directive {
     pub(crate) qualifier: Qualifier,
     pub(crate) mechanism: Mechanism,
 }
@@ -139,6 +147,75 @@ pub struct Spf {
     ra: Option<Vec<u8>>,
     rp: u8,
     rr: u8,
+    warnings: Vec<SpfWarning>,
+}
+
+/// Issues found by `Spf::lint` in records that are otherwise valid.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SpfWarning {
+    /// The record contains more than 10 terms causing DNS lookups.
+    TooManyLookups(usize),
+    /// The record uses the discouraged ptr mechanism.
+    PtrMechanism,
+    /// The record authorizes any host with +all.
+    PassAll,
+    /// The record has no all mechanism and no redirect modifier.
+    MissingAll,
+    /// A modifier appears more than once.
+    DuplicateModifier(String),
+    /// A modifier is not known and will be ignored.
+    UnknownModifier(String),
+    /// The record is longer than 255 bytes and has to be published
+    /// as multiple strings.
+    RecordTooLong(usize),
+    /// A term could not be flattened and was kept as is.
+    NotFlattened(String),
+}
+
+/// Reason an SPF record passed to `str::parse` was rejected.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SpfParseError {
+    /// The record does not start with `v=spf1`.
+    InvalidVersion,
+    /// A term has invalid syntax.
+    InvalidTerm(String),
+    /// The redirect or exp modifier appears more than once.
+    DuplicateModifier(String),
+}
+
+/// Local policy for treating softfail and neutral SPF results as fail.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SpfPolicy {
+    pub softfail_as_fail: bool,
+    pub neutral_as_fail: bool,
+    /// Domains the policy applies to, including their subdomains. The policy
+    /// applies to all domains when empty.
+    pub domains: Vec<String>,
+}
+
+/// A term evaluated during an SPF check, recorded when tracing is enabled.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpfTraceEntry {
+    pub(crate) domain: String,
+    pub(crate) term: String,
+    pub(crate) outcome: SpfTraceOutcome,
+    pub(crate) queries: Vec<SpfTraceQuery>,
+}
+
+/// A DNS query issued while evaluating a term.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpfTraceQuery {
+    pub(crate) record_type: RecordType,
+    pub(crate) name: String,
+    pub(crate) answer: String,
+    pub(crate) duration: Duration,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SpfTraceOutcome {
+    Match,
+    NoMatch,
+    Error,
 }
 
 pub(crate) const RR_TEMP_PERM_ERROR: u8 = 0x01;
@@ -146,6 +223,42 @@ pub(crate) const RR_FAIL: u8 = 0x02;
 pub(crate) const RR_SOFTFAIL: u8 = 0x04;
 pub(crate) const RR_NEUTRAL_NONE: u8 = 0x08;
 
+impl Spf {
+    /// Returns the mechanisms of the record, in evaluation order.
+    pub fn directives(&self) -> &[Directive] {
+        &self.directives
+    }
+
+    /// Returns the domain-spec of the `redirect=` modifier.
+    pub fn redirect(&self) -> Option<&Macro> {
+        self.redirect.as_ref()
+    }
+
+    /// Returns the domain-spec of the `exp=` modifier.
+    pub fn exp(&self) -> Option<&Macro> {
+        self.exp.as_ref()
+    }
+}
+
+impl SpfPolicy {
+    pub(crate) fn escalates(&self, result: SpfResult, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        match result {
+            SpfResult::SoftFail => self.softfail_as_fail,
+            SpfResult::Neutral => self.neutral_as_fail,
+            _ => false,
+        }
+        &&(self.domains.is_empty()
+            || self.domains.iter().any(|name| {
+                let name = name.trim_end_matches('.').to_ascii_lowercase();
+                domain == name
+                    || domain
+                        .strip_suffix(&name)
+                        .map_or(false, |prefix| prefix.ends_with('.'))
+            }))
+    }
+}
+
 impl Directive {
     pub fn new(qualifier: Qualifier, mechanism: Mechanism) -> Self {
         Directive {
@@ -153,6 +266,66 @@ impl Directive {
             mechanism,
         }
     }
+
+    /// Returns the result produced when the mechanism matches.
+    pub fn qualifier(&self) -> &Qualifier {
+        &self.qualifier
+    }
+
+    /// Returns the mechanism to match the client against.
+    pub fn mechanism(&self) -> &Mechanism {
+        &self.mechanism
+    }
+}
+
+impl SpfTraceEntry {
+    pub(crate) fn new(domain: &str, term: String) -> Self {
+        SpfTraceEntry {
+            domain: domain.to_string(),
+            term,
+            outcome: SpfTraceOutcome::Error,
+            queries: Vec::new(),
+        }
+    }
+
+    /// Returns the domain of the record the term belongs to.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns the term as written in the record, such as `include:example.org`.
+    pub fn term(&self) -> &str {
+        &self.term
+    }
+
+    pub fn outcome(&self) -> SpfTraceOutcome {
+        self.outcome
+    }
+
+    /// Returns the DNS queries issued while evaluating the term, in order.
+    pub fn queries(&self) -> &[SpfTraceQuery] {
+        &self.queries
+    }
+}
+
+impl SpfTraceQuery {
+    pub fn record_type(&self) -> RecordType {
+        self.record_type
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a summary of the answer, such as the addresses or record
+    /// returned, or the error that occurred.
+    pub fn answer(&self) -> &str {
+        &self.answer
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
 }
 
 impl Mechanism {
@@ -170,6 +343,135 @@ impl Mechanism {
     }
 }
 
+impl Display for Qualifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Qualifier::Pass => "+",
+            Qualifier::Fail => "-",
+            Qualifier::SoftFail => "~",
+            Qualifier::Neutral => "?",
+        })
+    }
+}
+
+impl Display for Mechanism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mechanism::All => f.write_str("all"),
+            Mechanism::Include { macro_string } => write!(f, "include:{macro_string}"),
+            Mechanism::A {
+                macro_string,
+                ip4_mask,
+                ip6_mask,
+            }
+            | Mechanism::Mx {
+                macro_string,
+                ip4_mask,
+                ip6_mask,
+            } => {
+                f.write_str(if matches!(self, Mechanism::A { .. }) {
+                    "a"
+                } else {
+                    "mx"
+                })?;
+                if macro_string != &Macro::None {
+                    write!(f, ":{macro_string}")?;
+                }
+                if *ip4_mask != u32::MAX {
+                    write!(f, "/{}", ip4_mask.leading_ones())?;
+                }
+                if *ip6_mask != u128::MAX {
+                    write!(f, "//{}", ip6_mask.leading_ones())?;
+                }
+                Ok(())
+            }
+            Mechanism::Ptr { macro_string } => {
+                if macro_string != &Macro::None {
+                    write!(f, "ptr:{macro_string}")
+                } else {
+                    f.write_str("ptr")
+                }
+            }
+            Mechanism::Ip4 { addr, mask } => {
+                write!(f, "ip4:{addr}")?;
+                if *mask != u32::MAX {
+                    write!(f, "/{}", mask.leading_ones())?;
+                }
+                Ok(())
+            }
+            Mechanism::Ip6 { addr, mask } => {
+                write!(f, "ip6:{addr}")?;
+                if *mask != u128::MAX {
+                    write!(f, "/{}", mask.leading_ones())?;
+                }
+                Ok(())
+            }
+            Mechanism::Exists { macro_string } => write!(f, "exists:{macro_string}"),
+        }
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.qualifier != Qualifier::Pass {
+            self.qualifier.fmt(f)?;
+        }
+        self.mechanism.fmt(f)
+    }
+}
+
+impl Display for Spf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("v=spf1")?;
+        for directive in &self.directives {
+            write!(f, " {directive}")?;
+        }
+        if let Some(redirect) = &self.redirect {
+            write!(f, " redirect={redirect}")?;
+        }
+        if let Some(exp) = &self.exp {
+            write!(f, " exp={exp}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for SpfParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpfParseError::InvalidVersion => write!(f, "Not an SPF version 1 record"),
+            SpfParseError::InvalidTerm(term) => write!(f, "Invalid term {term:?}"),
+            SpfParseError::DuplicateModifier(name) => write!(f, "Duplicate {name} modifier"),
+        }
+    }
+}
+
+/// Renders the entry as a line with the term and its outcome, followed by
+/// an indented line for each DNS query.
+impl Display for SpfTraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} => {}",
+            self.domain,
+            self.term,
+            match self.outcome {
+                SpfTraceOutcome::Match => "match",
+                SpfTraceOutcome::NoMatch => "no match",
+                SpfTraceOutcome::Error => "error",
+            }
+        )?;
+        for query in &self.queries {
+            write!(
+                f,
+                "\n  {} {} -> {} ({:?})",
+                query.record_type, query.name, query.answer, query.duration
+            )?;
+        }
+        Ok(())
+    }
+}
+
 impl TryFrom<&str> for SpfResult {
     type Error = ();
 
@@ -206,8 +508,20 @@ impl SpfOutput {
     pub(crate) fn new(domain: String) -> Self {
         SpfOutput {
             result: SpfResult::None,
+            identity: SpfIdentity::MailFrom,
+            client_ip: None,
+            helo_domain: String::new(),
+            sender: String::new(),
+            matched: None,
+            trace: None,
+            ptr_names: None,
+            escalated: false,
+            best_guess: false,
             report: None,
             explanation: None,
+            error: None,
+            num_lookups: 0,
+            elapsed: Duration::ZERO,
             domain,
         }
     }
@@ -217,6 +531,47 @@ impl SpfOutput {
         self
     }
 
+    pub(crate) fn with_identity(mut self, identity: SpfIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub(crate) fn with_client_ip(mut self, ip: IpAddr) -> Self {
+        self.client_ip = ip.into();
+        self
+    }
+
+    pub(crate) fn with_envelope(mut self, helo_domain: &str, sender: &str) -> Self {
+        self.helo_domain = helo_domain.to_string();
+        self.sender = sender.to_string();
+        self
+    }
+
+    pub(crate) fn with_matched(mut self, matched: Option<(String, String)>) -> Self {
+        self.matched = matched;
+        self
+    }
+
+    pub(crate) fn with_trace(mut self, trace: Option<Vec<SpfTraceEntry>>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub(crate) fn with_ptr_names(mut self, ptr_names: Option<Vec<String>>) -> Self {
+        self.ptr_names = ptr_names;
+        self
+    }
+
+    pub(crate) fn with_policy(mut self, policy: &SpfPolicy) -> Self {
+        self.escalated = !self.best_guess && policy.escalates(self.result, &self.domain);
+        self
+    }
+
+    pub(crate) fn with_best_guess(mut self, best_guess: bool) -> Self {
+        self.best_guess = best_guess;
+        self
+    }
+
     pub(crate) fn with_report(mut self, spf: &Spf) -> Self {
         match &spf.ra {
             Some(ra) if is_within_pct(spf.rp) => {
@@ -242,14 +597,88 @@ impl SpfOutput {
         self
     }
 
+    pub(crate) fn with_error(mut self, error: Error) -> Self {
+        self.error = error.into();
+        self
+    }
+
+    pub(crate) fn with_num_lookups(mut self, num_lookups: u32) -> Self {
+        self.num_lookups = num_lookups;
+        self
+    }
+
+    pub(crate) fn with_elapsed(mut self, elapsed: Duration) -> Self {
+        self.elapsed = elapsed;
+        self
+    }
+
     pub fn result(&self) -> SpfResult {
         self.result
     }
 
+    /// Returns the result after applying the local policy set with
+    /// `Resolver::with_spf_policy`. DMARC uses the unmodified `result`.
+    pub fn effective_result(&self) -> SpfResult {
+        if self.escalated {
+            SpfResult::Fail
+        } else {
+            self.result
+        }
+    }
+
+    /// Returns whether the local policy turned a softfail or neutral result
+    /// into a fail.
+    pub fn is_escalated(&self) -> bool {
+        self.escalated
+    }
+
+    /// Returns whether the HELO or the MAIL FROM identity was checked.
+    pub fn identity(&self) -> SpfIdentity {
+        self.identity
+    }
+
+    /// Returns the client IP the record was evaluated against, with IPv4-mapped
+    /// IPv6 addresses converted to IPv4.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
     pub fn domain(&self) -> &str {
         &self.domain
     }
 
+    /// Returns the directive that determined the result, such as `-all` or
+    /// the `ip4` mechanism of an included record.
+    pub fn matched_mechanism(&self) -> Option<&str> {
+        self.matched
+            .as_ref()
+            .map(|(mechanism, _)| mechanism.as_str())
+    }
+
+    /// Returns the domain of the record the matched directive belongs to.
+    pub fn matched_domain(&self) -> Option<&str> {
+        self.matched.as_ref().map(|(_, domain)| domain.as_str())
+    }
+
+    /// Returns the terms evaluated in order, when tracing was enabled with
+    /// `Resolver::with_spf_trace`.
+    pub fn trace(&self) -> Option<&[SpfTraceEntry]> {
+        self.trace.as_deref()
+    }
+
+    /// Returns whether the domain published no SPF record and the result was
+    /// obtained by evaluating `v=spf1 a mx ptr ?all`, which is enabled with
+    /// `Resolver::with_spf_best_guess`. Best-guess results are not used by DMARC.
+    pub fn is_best_guess(&self) -> bool {
+        self.best_guess
+    }
+
+    /// Returns the PTR names of the client IP that were confirmed by a forward
+    /// lookup, when the record used the `ptr` mechanism or the `%{p}` macro.
+    pub fn ptr_names(&self) -> Option<&[String]> {
+        self.ptr_names.as_deref()
+    }
+
     pub fn explanation(&self) -> Option<&str> {
         self.explanation.as_deref()
     }
@@ -257,4 +686,20 @@ impl SpfOutput {
     pub fn report_address(&self) -> Option<&str> {
         self.report.as_deref()
     }
+
+    /// Returns the reason for a permerror caused by exceeding the lookup limits.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns the number of DNS-querying terms evaluated, which does not include
+    /// the query for the SPF record itself.
+    pub fn num_lookups(&self) -> u32 {
+        self.num_lookups
+    }
+
+    /// Returns the time taken by the evaluation.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
 }
@@ -11,6 +11,7 @@
 use std::{
     net::{Ipv4Addr, Ipv6Addr},
     slice::Iter,
+    str::FromStr,
 };
 
 use crate::{
@@ -19,8 +20,8 @@ use crate::{
 };
 
 use super::{
-    Directive, Macro, Mechanism, Qualifier, Spf, Variable, RR_FAIL, RR_NEUTRAL_NONE, RR_SOFTFAIL,
-    RR_TEMP_PERM_ERROR,
+    Directive, Macro, Mechanism, Qualifier, Spf, SpfParseError, SpfWarning, Variable, RR_FAIL,
+    RR_NEUTRAL_NONE, RR_SOFTFAIL, RR_TEMP_PERM_ERROR,
 };
 
 impl TxtRecordParser for Spf {
@@ -41,9 +42,19 @@ impl TxtRecordParser for Spf {
             ra: None,
             rp: 100,
             rr: u8::MAX,
+            warnings: Vec::new(),
         };
+        if bytes.len() > 255 {
+            spf.warnings.push(SpfWarning::RecordTooLong(bytes.len()));
+        }
 
-        while let Some((term, qualifier, mut stop_char)) = record.next_term() {
+        let mut modifiers = Vec::new();
+        loop {
+            let term_start = record.as_slice();
+            let (term, qualifier, mut stop_char) = match record.next_term() {
+                Some(term) => term,
+                None => break,
+            };
             match term {
                 A | MX => {
                     let mut ip4_cidr_length = 32;
@@ -192,24 +203,105 @@ impl TxtRecordParser for Spf {
                     if !ra.is_empty() {
                         spf.ra = ra.into();
                     }
+                    spf.add_modifier(&mut modifiers, term_start, true);
                 }
                 RP => {
                     spf.rp = std::cmp::min(record.cidr_length()?, 100);
+                    spf.add_modifier(&mut modifiers, term_start, true);
                 }
                 RR => {
                     spf.rr = record.rr()?;
+                    spf.add_modifier(&mut modifiers, term_start, true);
                 }
                 _ => {
+                    let is_modifier = stop_char == b'=';
                     let (_, stop_char) = record.macro_string(false)?;
                     if stop_char != b' ' {
                         return Err(Error::ParseError);
                     }
+                    if is_modifier {
+                        spf.add_modifier(&mut modifiers, term_start, false);
+                    }
                 }
             }
         }
 
         Ok(spf)
     }
+
+    /// Returns the only SPF record among the TXT records of a domain. Records
+    /// with invalid syntax count too, as publishing more than one is a
+    /// permanent error (RFC 7208, Section 4.5).
+    fn parse_records(records: impl IntoIterator<Item = impl AsRef<[u8]>>) -> crate::Result<Self> {
+        let mut result = Err(Error::InvalidRecordType);
+        for record in records {
+            match Spf::parse(record.as_ref()) {
+                Err(Error::InvalidRecordType) => (),
+                _ if !matches!(result, Err(Error::InvalidRecordType)) => {
+                    return Err(Error::MultipleRecords);
+                }
+                record => {
+                    result = record;
+                }
+            }
+        }
+        result
+    }
+}
+
+impl FromStr for Spf {
+    type Err = SpfParseError;
+
+    fn from_str(record: &str) -> Result<Self, Self::Err> {
+        match Spf::parse(record.as_bytes()) {
+            Ok(spf) => Ok(spf),
+            Err(Error::InvalidRecordType) => Err(SpfParseError::InvalidVersion),
+            Err(_) => {
+                // Parse each term on its own to find the offending one
+                let mut modifiers: Vec<String> = Vec::new();
+                for term in record.split_ascii_whitespace().skip(1) {
+                    match Spf::parse(format!("v=spf1 {term}").as_bytes()) {
+                        Ok(spf) if spf.redirect.is_some() || spf.exp.is_some() => {
+                            let name = term.split('=').next().unwrap_or_default();
+                            let name = name.to_ascii_lowercase();
+                            if modifiers.contains(&name) {
+                                return Err(SpfParseError::DuplicateModifier(name));
+                            }
+                            modifiers.push(name);
+                        }
+                        Ok(_) => (),
+                        Err(_) => return Err(SpfParseError::InvalidTerm(term.to_string())),
+                    }
+                }
+                Err(SpfParseError::InvalidTerm(record.to_string()))
+            }
+        }
+    }
+}
+
+impl Spf {
+    /// Records modifiers that appear more than once or are not known, which
+    /// are otherwise ignored.
+    fn add_modifier(&mut self, modifiers: &mut Vec<String>, term: &[u8], is_known: bool) {
+        let name = term
+            .iter()
+            .skip_while(|ch| ch.is_ascii_whitespace() || b"+-~?".contains(ch))
+            .take_while(|&&ch| ch != b'=')
+            .map(|ch| ch.to_ascii_lowercase() as char)
+            .collect::<String>();
+        if modifiers.contains(&name) {
+            let warning = SpfWarning::DuplicateModifier(name);
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+        } else {
+            if !is_known {
+                self.warnings
+                    .push(SpfWarning::UnknownModifier(name.clone()));
+            }
+            modifiers.push(name);
+        }
+    }
 }
 
 const A: u64 = b'a' as u64;
@@ -350,20 +442,25 @@ impl SPFParser for Iter<'_, u8> {
                         })
                         .ok_or(Error::ParseError)?;
                     let mut num_parts: u32 = 0;
+                    let mut has_digits = false;
                     let mut reverse = false;
                     let mut delimiters = 0;
+                    let mut is_closed = false;
 
+                    // transformers = *DIGIT [ "r" ], followed by *delimiter
                     while let Some(&ch) = self.next() {
                         match ch {
-                            b'0'..=b'9' => {
+                            b'0'..=b'9' if !reverse && delimiters == 0 => {
+                                has_digits = true;
                                 num_parts = num_parts
                                     .saturating_mul(10)
                                     .saturating_add((ch - b'0') as u32);
                             }
-                            b'r' | b'R' => {
+                            b'r' | b'R' if !reverse && delimiters == 0 => {
                                 reverse = true;
                             }
                             b'}' => {
+                                is_closed = true;
                                 break;
                             }
                             b'.' | b'-' | b'+' | b',' | b'/' | b'_' | b'=' => {
@@ -375,6 +472,10 @@ impl SPFParser for Iter<'_, u8> {
                         }
                     }
 
+                    if !is_closed || (has_digits && num_parts == 0) {
+                        return Err(Error::ParseError);
+                    }
+
                     if delimiters == 0 {
                         delimiters = 1u64 << (b'.' - b'+');
                     }
@@ -727,6 +828,10 @@ impl Variable {
 
 impl TxtRecordParser for Macro {
     fn parse(record: &[u8]) -> crate::Result<Self> {
+        // Explanation strings are restricted to US-ASCII
+        if !record.is_ascii() {
+            return Err(Error::ParseError);
+        }
         record.iter().macro_string(true).map(|(m, _)| m)
     }
 }
@@ -741,6 +846,7 @@ mod test {
             Directive, Macro, Mechanism, Qualifier, Spf, Variable, Version, RR_FAIL,
             RR_NEUTRAL_NONE, RR_SOFTFAIL, RR_TEMP_PERM_ERROR,
         },
+        Error,
     };
 
     use super::SPFParser;
@@ -755,6 +861,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -785,6 +892,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -807,6 +915,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -829,6 +938,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     redirect: Macro::Literal(b"_spf.example.com".to_vec()).into(),
                     exp: None,
                     directives: vec![Directive::new(
@@ -848,6 +958,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -878,6 +989,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -904,6 +1016,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -948,6 +1061,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: Macro::List(vec![
                         Macro::Literal(b"explain._spf.".to_vec()),
                         Macro::Variable {
@@ -980,6 +1094,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1008,6 +1123,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1037,6 +1153,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1067,6 +1184,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1087,6 +1205,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![Directive::new(
@@ -1120,6 +1239,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![Directive::new(
@@ -1161,6 +1281,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1222,6 +1343,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1259,6 +1381,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1303,6 +1426,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: Macro::List(vec![
                         Macro::Variable {
@@ -1350,6 +1474,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1382,6 +1507,7 @@ mod test {
                     ra: None,
                     rp: 100,
                     rr: u8::MAX,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1432,6 +1558,7 @@ mod test {
                     ra: b"postmaster".to_vec().into(),
                     rp: 15,
                     rr: RR_FAIL | RR_NEUTRAL_NONE | RR_SOFTFAIL | RR_TEMP_PERM_ERROR,
+                    warnings: Vec::new(),
                     exp: None,
                     redirect: None,
                     directives: vec![
@@ -1456,6 +1583,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn parse_spf_records() {
+        for (records, expected_result) in [
+            (vec!["v=spf1 -all"], Ok(())),
+            (
+                vec![
+                    "google-site-verification=abc",
+                    "v=spf1 -all",
+                    "v=spf10 +all",
+                ],
+                Ok(()),
+            ),
+            (
+                vec!["v=spf1 -all", "v=spf1 +all"],
+                Err(Error::MultipleRecords),
+            ),
+            (
+                vec!["v=spf1 -all", "V=SPF1 +all"],
+                Err(Error::MultipleRecords),
+            ),
+            (
+                vec!["v=spf1 -all", "v=spf1 all/24"],
+                Err(Error::MultipleRecords),
+            ),
+            (vec!["v=spf1 all/24"], Err(Error::ParseError)),
+            (vec!["v=spf10 -all"], Err(Error::InvalidRecordType)),
+            (vec![], Err(Error::InvalidRecordType)),
+        ] {
+            assert_eq!(
+                Spf::parse_records(&records).map(|_| ()),
+                expected_result,
+                "{records:?}"
+            );
+        }
+    }
+
     #[test]
     fn parse_ip6() {
         for test in [
@@ -9,13 +9,29 @@
  */
 
 use std::{
+    fmt::Display,
+    future::Future,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
     time::Instant,
 };
 
-use crate::{Error, Resolver, SpfOutput, SpfResult};
+use trust_dns_resolver::proto::rr::RecordType;
 
-use super::{Macro, Mechanism, Qualifier, Spf, Variables};
+use crate::{
+    common::resolver::domain_to_ascii, Error, Resolver, SpfIdentity, SpfOutput, SpfResult, Version,
+};
+
+use super::{
+    Directive, Macro, Mechanism, Qualifier, Spf, SpfTraceEntry, SpfTraceOutcome, SpfTraceQuery,
+    Variables,
+};
+
+/// Maximum length of an expanded explanation string, leaving room for the
+/// reply code and other text in a 512 octet SMTP reply line.
+const MAX_EXPLANATION_LEN: usize = 400;
+pub(crate) const MAX_MX_NAMES: usize = 10;
+const MAX_PTR_NAMES: usize = 10;
 
 impl Resolver {
     /// Verifies the SPF EHLO identity
@@ -25,21 +41,17 @@ impl Resolver {
         helo_domain: &str,
         host_domain: &str,
     ) -> SpfOutput {
-        if helo_domain.has_labels() {
-            self.check_host(
-                ip,
-                helo_domain,
-                helo_domain,
-                host_domain,
-                &format!("postmaster@{helo_domain}"),
-            )
-            .await
-        } else {
-            SpfOutput::new(helo_domain.to_string()).with_result(SpfResult::None)
-        }
+        self.check_helo(
+            ip,
+            helo_domain,
+            host_domain,
+            Instant::now() + self.spf_timeout,
+        )
+        .await
     }
 
-    /// Verifies the SPF MAIL FROM identity
+    /// Verifies the SPF MAIL FROM identity, using `postmaster@<helo_domain>`
+    /// as the sender when `sender` is empty.
     pub async fn verify_spf_sender(
         &self,
         ip: IpAddr,
@@ -47,17 +59,18 @@ impl Resolver {
         host_domain: &str,
         sender: &str,
     ) -> SpfOutput {
-        self.check_host(
+        self.check_sender(
             ip,
-            sender.rsplit_once('@').map_or(helo_domain, |(_, d)| d),
             helo_domain,
             host_domain,
             sender,
+            Instant::now() + self.spf_timeout,
         )
         .await
     }
 
-    /// Verifies both the SPF EHLO and MAIL FROM identities
+    /// Verifies both the SPF EHLO and MAIL FROM identities, sharing a
+    /// single time budget.
     pub async fn verify_spf(
         &self,
         ip: IpAddr,
@@ -65,71 +78,236 @@ impl Resolver {
         host_domain: &str,
         mail_from: &str,
     ) -> SpfOutput {
-        // Verify HELO identity
-        let output = self.verify_spf_helo(ip, helo_domain, host_domain).await;
-        if matches!(output.result(), SpfResult::Pass) {
-            // Verify MAIL FROM identity
-            self.verify_spf_sender(ip, helo_domain, host_domain, mail_from)
-                .await
+        let deadline = Instant::now() + self.spf_timeout;
+
+        // Verify HELO identity, address literals cannot be checked
+        if !is_address_literal(helo_domain) {
+            let output = self
+                .check_helo(ip, helo_domain, host_domain, deadline)
+                .await;
+            if !matches!(output.result(), SpfResult::Pass) {
+                return output;
+            }
+        }
+
+        // Verify MAIL FROM identity
+        self.check_sender(ip, helo_domain, host_domain, mail_from, deadline)
+            .await
+    }
+
+    pub async fn check_host(
+        &self,
+        ip: IpAddr,
+        domain: &str,
+        helo_domain: &str,
+        host_domain: &str,
+        sender: &str,
+    ) -> SpfOutput {
+        self.check_host_until(
+            ip,
+            domain,
+            helo_domain,
+            host_domain,
+            sender,
+            Instant::now() + self.spf_timeout,
+        )
+        .await
+    }
+
+    async fn check_helo(
+        &self,
+        ip: IpAddr,
+        helo_domain: &str,
+        host_domain: &str,
+        deadline: Instant,
+    ) -> SpfOutput {
+        let output = if helo_domain.has_labels() {
+            self.check_host_until(
+                ip,
+                helo_domain,
+                helo_domain,
+                host_domain,
+                &format!("postmaster@{helo_domain}"),
+                deadline,
+            )
+            .await
         } else {
-            output
+            SpfOutput::new(helo_domain.to_string())
+                .with_result(SpfResult::None)
+                .with_client_ip(normalize_ip(ip))
+                .with_envelope(helo_domain, &format!("postmaster@{helo_domain}"))
+        };
+        output.with_identity(SpfIdentity::Helo)
+    }
+
+    async fn check_sender(
+        &self,
+        ip: IpAddr,
+        helo_domain: &str,
+        host_domain: &str,
+        sender: &str,
+        deadline: Instant,
+    ) -> SpfOutput {
+        if !sender.is_empty() {
+            self.check_host_until(
+                ip,
+                sender.rsplit_once('@').map_or(helo_domain, |(_, d)| d),
+                helo_domain,
+                host_domain,
+                sender,
+                deadline,
+            )
+            .await
+        } else {
+            self.check_host_until(
+                ip,
+                helo_domain,
+                helo_domain,
+                host_domain,
+                &format!("postmaster@{helo_domain}"),
+                deadline,
+            )
+            .await
         }
     }
 
+    async fn check_host_until(
+        &self,
+        ip: IpAddr,
+        domain: &str,
+        helo_domain: &str,
+        host_domain: &str,
+        sender: &str,
+        deadline: Instant,
+    ) -> SpfOutput {
+        let started = Instant::now();
+        let ip = normalize_ip(ip);
+        let mut lookup_limit = LookupLimit::new(self);
+        let mut trace = self.spf_trace.then(Vec::new);
+        let mut ptr_names = None;
+        let output = match tokio::time::timeout_at(
+            deadline.into(),
+            self.evaluate_spf(
+                ip,
+                domain,
+                helo_domain,
+                host_domain,
+                sender,
+                &mut lookup_limit,
+                &mut trace,
+                &mut ptr_names,
+            ),
+        )
+        .await
+        {
+            Ok(output) => output,
+            Err(_) => SpfOutput::new(domain.to_string())
+                .with_result(SpfResult::TempError)
+                .with_error(Error::DeadlineExceeded),
+        };
+
+        output
+            .with_client_ip(ip)
+            .with_envelope(helo_domain, sender)
+            .with_num_lookups(lookup_limit.num_lookups)
+            .with_trace(trace)
+            .with_ptr_names(ptr_names.and_then(Result::ok))
+            .with_policy(&self.spf_policy)
+            .with_elapsed(started.elapsed())
+    }
+
     #[allow(clippy::while_let_on_iterator)]
-    pub async fn check_host(
+    async fn evaluate_spf(
         &self,
         ip: IpAddr,
         domain: &str,
         helo_domain: &str,
         host_domain: &str,
         sender: &str,
+        lookup_limit: &mut LookupLimit,
+        trace: &mut Option<Vec<SpfTraceEntry>>,
+        ptr_names: &mut Option<crate::Result<Vec<String>>>,
     ) -> SpfOutput {
-        let output = SpfOutput::new(domain.to_string());
-        if domain.is_empty() || domain.len() > 63 || !domain.has_labels() {
-            return output.with_result(SpfResult::None);
+        // Invalid U-labels cannot be converted to a name to query
+        if domain_to_ascii(domain).is_none() {
+            return SpfOutput::new(domain.to_string())
+                .with_result(SpfResult::PermError)
+                .with_error(Error::ParseError);
         }
+        let mut domain = match normalize_domain(domain) {
+            Some(domain)
+                if domain.len() <= 63
+                    && domain.as_str().has_labels()
+                    && !is_address_literal(&domain) =>
+            {
+                domain
+            }
+            _ => return SpfOutput::new(domain.to_string()).with_result(SpfResult::None),
+        };
+        let mut output = SpfOutput::new(domain.clone());
         let mut vars = Variables::new();
-        let mut has_p_var = false;
         vars.set_ip(&ip);
         if !sender.is_empty() {
-            vars.set_sender(sender.as_bytes());
+            vars.set_sender(sender_to_ascii(sender).into_bytes());
         } else {
             vars.set_sender(format!("postmaster@{domain}").into_bytes());
         }
-        vars.set_domain(domain.as_bytes());
+        vars.set_domain(domain.as_bytes().to_vec());
         vars.set_host_domain(host_domain.as_bytes());
-        vars.set_helo_domain(helo_domain.as_bytes());
+        vars.set_helo_domain(
+            normalize_domain(helo_domain)
+                .map_or_else(|| helo_domain.as_bytes().to_vec(), String::into_bytes),
+        );
 
-        let mut lookup_limit = LookupLimit::new();
-        let mut spf_record = match self.txt_lookup::<Spf>(domain).await {
+        // The query for the record itself does not count towards the limit
+        let trace_pos = trace_term(trace, &domain, || "v=spf1".to_string());
+        let spf_record = self
+            .traced_query(
+                trace,
+                RecordType::TXT,
+                &domain,
+                self.txt_lookup::<Spf>(domain.as_str()),
+                |spf| spf.to_string(),
+            )
+            .await;
+        trace_outcome(trace, trace_pos, spf_record.is_ok());
+        let mut spf_record = match spf_record {
             Ok(spf_record) => spf_record,
-            Err(err) => return output.with_result(err.into()),
+            Err(Error::MultipleRecords) => {
+                return output
+                    .with_result(SpfResult::PermError)
+                    .with_error(Error::MultipleRecords)
+            }
+            Err(err) => match SpfResult::from(err) {
+                SpfResult::None if self.spf_best_guess => {
+                    output = output.with_best_guess(true);
+                    Arc::new(best_guess_record())
+                }
+                result => return output.with_result(result),
+            },
         };
 
-        let mut domain = domain.to_string();
-        let mut include_stack = Vec::new();
+        let mut include_stack: Vec<IncludeFrame> = Vec::new();
+        let mut include_memo: Vec<IncludeMemo> = Vec::new();
+        let mut redirects: Vec<String> = Vec::new();
 
         let mut result = None;
+        let mut matched = None;
         let mut directives = spf_record.directives.iter().enumerate().skip(0);
 
         loop {
             while let Some((pos, directive)) = directives.next() {
-                if !has_p_var && directive.mechanism.needs_ptr() {
-                    if !lookup_limit.can_lookup() {
+                let trace_pos = trace_term(trace, &domain, || directive.to_string());
+                if directive.mechanism.needs_ptr() {
+                    if ptr_names.is_none() && !lookup_limit.can_lookup() {
                         return output
                             .with_result(SpfResult::PermError)
+                            .with_error(Error::LookupLimitExceeded)
                             .with_report(&spf_record);
                     }
-                    if let Some(ptr) = self
-                        .ptr_lookup(ip)
-                        .await
-                        .ok()
-                        .and_then(|ptrs| ptrs.first().map(|ptr| ptr.as_bytes().to_vec()))
-                    {
-                        vars.set_validated_domain(ptr);
-                    }
-                    has_p_var = true;
+                    vars.set_validated_domain(
+                        self.validated_domain(ip, &domain, ptr_names, trace).await,
+                    );
                 }
 
                 let matches = match &directive.mechanism {
@@ -144,19 +322,33 @@ impl Resolver {
                         if !lookup_limit.can_lookup() {
                             return output
                                 .with_result(SpfResult::PermError)
+                                .with_error(Error::LookupLimitExceeded)
                                 .with_report(&spf_record);
                         }
+                        let target_name = match expand_domain(macro_string, &vars, &domain) {
+                            Some(target_name) => target_name,
+                            None => {
+                                return output
+                                    .with_result(SpfResult::PermError)
+                                    .with_error(Error::ParseError)
+                                    .with_report(&spf_record);
+                            }
+                        };
                         match self
-                            .ip_matches(
-                                macro_string.eval(&vars, &domain, true).as_ref(),
-                                ip,
-                                *ip4_mask,
-                                *ip6_mask,
-                            )
+                            .ip_matches(&target_name, ip, *ip4_mask, *ip6_mask, trace)
                             .await
                         {
                             Ok(true) => true,
-                            Ok(false) | Err(Error::DnsRecordNotFound(_)) => false,
+                            Ok(false) => false,
+                            Err(Error::DnsRecordNotFound(_)) => {
+                                if !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(SpfResult::PermError)
+                                        .with_error(Error::VoidLookupLimitExceeded)
+                                        .with_report(&spf_record);
+                                }
+                                false
+                            }
                             Err(_) => {
                                 return output
                                     .with_result(SpfResult::TempError)
@@ -172,29 +364,68 @@ impl Resolver {
                         if !lookup_limit.can_lookup() {
                             return output
                                 .with_result(SpfResult::PermError)
+                                .with_error(Error::LookupLimitExceeded)
                                 .with_report(&spf_record);
                         }
 
+                        let target_name = match expand_domain(macro_string, &vars, &domain) {
+                            Some(target_name) => target_name,
+                            None => {
+                                return output
+                                    .with_result(SpfResult::PermError)
+                                    .with_error(Error::ParseError)
+                                    .with_report(&spf_record);
+                            }
+                        };
                         let mut matches = false;
                         match self
-                            .mx_lookup(macro_string.eval(&vars, &domain, true).as_ref())
+                            .traced_query(
+                                trace,
+                                RecordType::MX,
+                                &target_name,
+                                self.mx_lookup(&target_name),
+                                |records| {
+                                    join_answer(records.iter().flat_map(|mx| {
+                                        mx.exchanges.iter().map(move |exchange| {
+                                            format!("{} {exchange}", mx.preference)
+                                        })
+                                    }))
+                                },
+                            )
                             .await
                         {
                             Ok(records) => {
-                                for exchange in records.iter().flat_map(|mx| mx.exchanges.iter()) {
-                                    if !lookup_limit.can_lookup() {
+                                // Address lookups are limited per mechanism rather than
+                                // counted as terms (RFC 7208, Section 4.6.4)
+                                for (num_names, exchange) in records
+                                    .iter()
+                                    .flat_map(|mx| mx.exchanges.iter())
+                                    .enumerate()
+                                {
+                                    if num_names == MAX_MX_NAMES {
                                         return output
                                             .with_result(SpfResult::PermError)
+                                            .with_error(Error::MxLimitExceeded)
                                             .with_report(&spf_record);
                                     }
 
-                                    match self.ip_matches(exchange, ip, *ip4_mask, *ip6_mask).await
+                                    match self
+                                        .ip_matches(exchange, ip, *ip4_mask, *ip6_mask, trace)
+                                        .await
                                     {
                                         Ok(true) => {
                                             matches = true;
                                             break;
                                         }
-                                        Ok(false) | Err(Error::DnsRecordNotFound(_)) => (),
+                                        Ok(false) => (),
+                                        Err(Error::DnsRecordNotFound(_)) => {
+                                            if !lookup_limit.can_void_lookup() {
+                                                return output
+                                                    .with_result(SpfResult::PermError)
+                                                    .with_error(Error::VoidLookupLimitExceeded)
+                                                    .with_report(&spf_record);
+                                            }
+                                        }
                                         Err(_) => {
                                             return output
                                                 .with_result(SpfResult::TempError)
@@ -203,7 +434,14 @@ impl Resolver {
                                     }
                                 }
                             }
-                            Err(Error::DnsRecordNotFound(_)) => (),
+                            Err(Error::DnsRecordNotFound(_)) => {
+                                if !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(SpfResult::PermError)
+                                        .with_error(Error::VoidLookupLimitExceeded)
+                                        .with_report(&spf_record);
+                                }
+                            }
                             Err(_) => {
                                 return output
                                     .with_result(SpfResult::TempError)
@@ -216,36 +454,95 @@ impl Resolver {
                         if !lookup_limit.can_lookup() {
                             return output
                                 .with_result(SpfResult::PermError)
+                                .with_error(Error::LookupLimitExceeded)
                                 .with_report(&spf_record);
                         }
 
-                        let target_name = macro_string.eval(&vars, &domain, true);
-                        match self.txt_lookup::<Spf>(target_name.as_ref()).await {
-                            Ok(included_spf) => {
-                                let new_domain = target_name.to_string();
-                                include_stack.push((
-                                    std::mem::replace(&mut spf_record, included_spf),
-                                    pos,
-                                    domain,
-                                ));
-                                directives = spf_record.directives.iter().enumerate().skip(0);
-                                domain = new_domain;
-                                vars.set_domain(domain.as_bytes().to_vec());
-                                continue;
-                            }
-                            Err(
-                                Error::DnsRecordNotFound(_)
-                                | Error::InvalidRecordType
-                                | Error::ParseError,
-                            ) => {
+                        let target_name = match expand_domain(macro_string, &vars, &domain) {
+                            Some(target_name) => target_name,
+                            None => {
                                 return output
                                     .with_result(SpfResult::PermError)
-                                    .with_report(&spf_record)
+                                    .with_error(Error::ParseError)
+                                    .with_report(&spf_record);
                             }
-                            Err(_) => {
+                        };
+
+                        if is_evaluating(&target_name, &domain, &redirects, &include_stack) {
+                            return output
+                                .with_result(SpfResult::PermError)
+                                .with_error(Error::EvaluationLoop(target_name))
+                                .with_report(&spf_record);
+                        }
+
+                        // Reuse the result of a previous evaluation of the same target,
+                        // still counting the lookups it performed
+                        if let Some(memo) =
+                            include_memo.iter().find(|memo| memo.domain == target_name)
+                        {
+                            lookup_limit.num_lookups += memo.num_lookups;
+                            lookup_limit.num_void_lookups += memo.num_void_lookups;
+                            if lookup_limit.num_lookups > lookup_limit.max_lookups {
                                 return output
-                                    .with_result(SpfResult::TempError)
-                                    .with_report(&spf_record)
+                                    .with_result(SpfResult::PermError)
+                                    .with_error(Error::LookupLimitExceeded)
+                                    .with_report(&spf_record);
+                            } else if lookup_limit.num_void_lookups > lookup_limit.max_void_lookups
+                            {
+                                return output
+                                    .with_result(SpfResult::PermError)
+                                    .with_error(Error::VoidLookupLimitExceeded)
+                                    .with_report(&spf_record);
+                            }
+                            if memo.matched.is_some() {
+                                matched = memo.matched.clone();
+                            }
+                            memo.matched.is_some()
+                        } else {
+                            match self
+                                .traced_query(
+                                    trace,
+                                    RecordType::TXT,
+                                    &target_name,
+                                    self.txt_lookup::<Spf>(&target_name),
+                                    |spf| spf.to_string(),
+                                )
+                                .await
+                            {
+                                Ok(included_spf) => {
+                                    include_stack.push(IncludeFrame {
+                                        record: std::mem::replace(&mut spf_record, included_spf),
+                                        pos,
+                                        domain,
+                                        trace_pos,
+                                        num_lookups: lookup_limit.num_lookups,
+                                        num_void_lookups: lookup_limit.num_void_lookups,
+                                    });
+                                    directives = spf_record.directives.iter().enumerate().skip(0);
+                                    domain = target_name;
+                                    vars.set_domain(domain.as_bytes().to_vec());
+                                    continue;
+                                }
+                                Err(Error::MultipleRecords) => {
+                                    return output
+                                        .with_result(SpfResult::PermError)
+                                        .with_error(Error::MultipleRecords)
+                                        .with_report(&spf_record)
+                                }
+                                Err(
+                                    Error::DnsRecordNotFound(_)
+                                    | Error::InvalidRecordType
+                                    | Error::ParseError,
+                                ) => {
+                                    return output
+                                        .with_result(SpfResult::PermError)
+                                        .with_report(&spf_record)
+                                }
+                                Err(_) => {
+                                    return output
+                                        .with_result(SpfResult::TempError)
+                                        .with_report(&spf_record)
+                                }
                             }
                         }
                     }
@@ -253,88 +550,185 @@ impl Resolver {
                         if !lookup_limit.can_lookup() {
                             return output
                                 .with_result(SpfResult::PermError)
+                                .with_error(Error::LookupLimitExceeded)
                                 .with_report(&spf_record);
                         }
 
-                        let target_addr = macro_string.eval(&vars, &domain, true).to_lowercase();
-                        let target_sub_addr = format!(".{target_addr}");
-                        let mut matches = false;
-
-                        if let Ok(records) = self.ptr_lookup(ip).await {
-                            for record in records.iter() {
-                                if lookup_limit.can_lookup() {
-                                    if let Ok(true) =
-                                        self.ip_matches(record, ip, u32::MAX, u128::MAX).await
-                                    {
-                                        matches = record == &target_addr
-                                            || record.ends_with(&target_sub_addr);
-                                        if matches {
-                                            break;
-                                        }
-                                    }
+                        let target_name = match expand_domain(macro_string, &vars, &domain) {
+                            Some(target_name) => target_name,
+                            None => {
+                                return output
+                                    .with_result(SpfResult::PermError)
+                                    .with_error(Error::ParseError)
+                                    .with_report(&spf_record);
+                            }
+                        };
+                        // A missing PTR record is only counted as a void lookup once
+                        let is_cached = ptr_names.is_some();
+                        match self.validated_names(ip, ptr_names, trace).await {
+                            Ok(names) => names.iter().any(|name| {
+                                name.strip_suffix(&target_name).map_or(false, |prefix| {
+                                    prefix.is_empty() || prefix.ends_with('.')
+                                })
+                            }),
+                            Err(Error::DnsRecordNotFound(_)) => {
+                                if !is_cached && !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(SpfResult::PermError)
+                                        .with_error(Error::VoidLookupLimitExceeded)
+                                        .with_report(&spf_record);
                                 }
+                                false
                             }
+                            // PTR lookup errors do not match
+                            Err(_) => false,
                         }
-                        matches
                     }
                     Mechanism::Exists { macro_string } => {
                         if !lookup_limit.can_lookup() {
                             return output
                                 .with_result(SpfResult::PermError)
+                                .with_error(Error::LookupLimitExceeded)
                                 .with_report(&spf_record);
                         }
 
-                        if let Ok(result) = self
-                            .exists(macro_string.eval(&vars, &domain, true).as_ref())
+                        let target_name = match expand_domain(macro_string, &vars, &domain) {
+                            Some(target_name) => target_name,
+                            None => {
+                                return output
+                                    .with_result(SpfResult::PermError)
+                                    .with_error(Error::ParseError)
+                                    .with_report(&spf_record);
+                            }
+                        };
+                        match self
+                            .traced_query(
+                                trace,
+                                RecordType::A,
+                                &target_name,
+                                self.exists(&target_name),
+                                |exists| match exists {
+                                    true => "found".to_string(),
+                                    false => "not found".to_string(),
+                                },
+                            )
                             .await
                         {
-                            result
-                        } else {
-                            return output
-                                .with_result(SpfResult::TempError)
-                                .with_report(&spf_record);
+                            Ok(true) => true,
+                            Ok(false) => {
+                                if !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(SpfResult::PermError)
+                                        .with_error(Error::VoidLookupLimitExceeded)
+                                        .with_report(&spf_record);
+                                }
+                                false
+                            }
+                            Err(_) => {
+                                return output
+                                    .with_result(SpfResult::TempError)
+                                    .with_report(&spf_record);
+                            }
                         }
                     }
                 };
 
+                trace_outcome(trace, trace_pos, matches);
                 if matches {
                     result = Some((&directive.qualifier).into());
+                    if !matches!(directive.mechanism, Mechanism::Include { .. }) {
+                        matched = Some((directive.to_string(), domain.clone()));
+                    }
                     break;
                 }
             }
 
-            if let Some((prev_record, prev_pos, prev_domain)) = include_stack.pop() {
-                spf_record = prev_record;
-                directives = spf_record.directives.iter().enumerate().skip(prev_pos);
+            if let Some(frame) = include_stack.pop() {
+                spf_record = frame.record;
+                directives = spf_record.directives.iter().enumerate().skip(frame.pos);
                 let (_, directive) = directives.next().unwrap();
 
-                if matches!(result, Some(SpfResult::Pass)) {
+                // The include matches only if the included record passes
+                let matches = matches!(result, Some(SpfResult::Pass));
+                trace_outcome(trace, frame.trace_pos, matches);
+                if !matches {
+                    matched = None;
+                }
+                include_memo.push(IncludeMemo {
+                    domain: std::mem::replace(&mut domain, frame.domain),
+                    matched: matched.clone(),
+                    num_lookups: lookup_limit.num_lookups - frame.num_lookups,
+                    num_void_lookups: lookup_limit.num_void_lookups - frame.num_void_lookups,
+                });
+                if matches {
                     result = Some((&directive.qualifier).into());
                     break;
                 } else {
-                    vars.set_domain(prev_domain.as_bytes().to_vec());
-                    domain = prev_domain;
+                    vars.set_domain(domain.as_bytes().to_vec());
                     result = None;
                 }
             } else {
                 // Follow redirect
                 if let (Some(macro_string), None) = (&spf_record.redirect, &result) {
+                    let trace_pos =
+                        trace_term(trace, &domain, || format!("redirect={macro_string}"));
                     if !lookup_limit.can_lookup() {
                         return output
                             .with_result(SpfResult::PermError)
+                            .with_error(Error::LookupLimitExceeded)
                             .with_report(&spf_record);
                     }
+                    if macro_string.needs_ptr() {
+                        if ptr_names.is_none() && !lookup_limit.can_lookup() {
+                            return output
+                                .with_result(SpfResult::PermError)
+                                .with_error(Error::LookupLimitExceeded)
+                                .with_report(&spf_record);
+                        }
+                        vars.set_validated_domain(
+                            self.validated_domain(ip, &domain, ptr_names, trace).await,
+                        );
+                    }
 
-                    let target_name = macro_string.eval(&vars, &domain, true);
-                    match self.txt_lookup::<Spf>(target_name.as_ref()).await {
+                    let target_name = match expand_domain(macro_string, &vars, &domain) {
+                        Some(target_name) => target_name,
+                        None => {
+                            return output
+                                .with_result(SpfResult::PermError)
+                                .with_error(Error::ParseError)
+                                .with_report(&spf_record);
+                        }
+                    };
+                    if is_evaluating(&target_name, &domain, &redirects, &include_stack) {
+                        return output
+                            .with_result(SpfResult::PermError)
+                            .with_error(Error::EvaluationLoop(target_name))
+                            .with_report(&spf_record);
+                    }
+                    match self
+                        .traced_query(
+                            trace,
+                            RecordType::TXT,
+                            &target_name,
+                            self.txt_lookup::<Spf>(&target_name),
+                            |spf| spf.to_string(),
+                        )
+                        .await
+                    {
                         Ok(redirect_spf) => {
-                            let new_domain = target_name.to_string();
+                            trace_outcome(trace, trace_pos, true);
                             spf_record = redirect_spf;
                             directives = spf_record.directives.iter().enumerate().skip(0);
-                            domain = new_domain;
+                            redirects.push(std::mem::replace(&mut domain, target_name));
                             vars.set_domain(domain.as_bytes().to_vec());
                             continue;
                         }
+                        Err(Error::MultipleRecords) => {
+                            return output
+                                .with_result(SpfResult::PermError)
+                                .with_error(Error::MultipleRecords)
+                                .with_report(&spf_record)
+                        }
                         Err(
                             Error::DnsRecordNotFound(_)
                             | Error::InvalidRecordType
@@ -358,37 +752,188 @@ impl Resolver {
 
         // Evaluate explain
         if let (Some(macro_string), Some(SpfResult::Fail { .. })) = (&spf_record.exp, &result) {
-            if let Ok(macro_string) = self
-                .txt_lookup::<Macro>(macro_string.eval(&vars, &domain, true).to_string())
-                .await
-            {
-                return output
-                    .with_result(SpfResult::Fail)
-                    .with_explanation(macro_string.eval(&vars, &domain, false).to_string())
-                    .with_report(&spf_record);
+            if macro_string.needs_ptr() {
+                vars.set_validated_domain(
+                    self.validated_domain(ip, &domain, ptr_names, trace).await,
+                );
+            }
+            // Failures to fetch the explanation do not change the result
+            let exp_record = match expand_domain(macro_string, &vars, &domain) {
+                Some(target_name) => {
+                    self.traced_query(
+                        trace,
+                        RecordType::TXT,
+                        &target_name,
+                        self.txt_lookup::<Macro>(&target_name),
+                        |macro_string| macro_string.to_string(),
+                    )
+                    .await
+                }
+                None => Err(Error::ParseError),
+            };
+            match exp_record {
+                Ok(macro_string) => {
+                    if macro_string.needs_ptr() {
+                        vars.set_validated_domain(
+                            self.validated_domain(ip, &domain, ptr_names, trace).await,
+                        );
+                    }
+                    let explanation = macro_string.eval(&vars, &domain, false).to_string();
+                    if explanation.is_ascii() && explanation.len() <= MAX_EXPLANATION_LEN {
+                        return output
+                            .with_result(SpfResult::Fail)
+                            .with_matched(matched)
+                            .with_explanation(explanation)
+                            .with_report(&spf_record);
+                    }
+                }
+                Err(Error::DnsRecordNotFound(_)) => {
+                    lookup_limit.can_void_lookup();
+                }
+                Err(_) => (),
             }
         }
 
         output
             .with_result(result.unwrap_or(SpfResult::Neutral))
+            .with_matched(matched)
             .with_report(&spf_record)
     }
 
+    /// Returns the PTR names of `ip` whose forward lookup contains `ip`,
+    /// checking at most 10 names (RFC 7208, Section 5.5).
+    async fn validated_names<'y>(
+        &self,
+        ip: IpAddr,
+        ptr_names: &'y mut Option<crate::Result<Vec<String>>>,
+        trace: &mut Option<Vec<SpfTraceEntry>>,
+    ) -> &'y crate::Result<Vec<String>> {
+        if ptr_names.is_none() {
+            let result = match self
+                .traced_query(trace, RecordType::PTR, ip, self.ptr_lookup(ip), |names| {
+                    join_answer(names.iter())
+                })
+                .await
+            {
+                Ok(records) => {
+                    let mut names = Vec::new();
+                    for record in records.iter().take(MAX_PTR_NAMES) {
+                        // Names failing the forward lookup are skipped
+                        if let Ok(true) = self
+                            .ip_matches(record, ip, u32::MAX, u128::MAX, trace)
+                            .await
+                        {
+                            names.push(record.trim_end_matches('.').to_lowercase());
+                        }
+                    }
+                    Ok(names)
+                }
+                Err(err) => Err(err),
+            };
+            *ptr_names = Some(result);
+        }
+        ptr_names.as_ref().unwrap()
+    }
+
+    /// Returns the validated domain name used by the %{p} macro, preferring
+    /// `domain` or one of its subdomains (RFC 7208, Section 7.3).
+    async fn validated_domain(
+        &self,
+        ip: IpAddr,
+        domain: &str,
+        ptr_names: &mut Option<crate::Result<Vec<String>>>,
+        trace: &mut Option<Vec<SpfTraceEntry>>,
+    ) -> Vec<u8> {
+        let names = match self.validated_names(ip, ptr_names, trace).await {
+            Ok(names) => names.as_slice(),
+            Err(_) => &[],
+        };
+        let domain = domain.trim_end_matches('.').as_bytes();
+        names
+            .iter()
+            .find(|name| name.as_bytes().eq_ignore_ascii_case(domain))
+            .or_else(|| {
+                names.iter().find(|name| {
+                    let name = name.as_bytes();
+                    name.len() > domain.len()
+                        && name[name.len() - domain.len() - 1] == b'.'
+                        && name[name.len() - domain.len()..].eq_ignore_ascii_case(domain)
+                })
+            })
+            .or_else(|| names.first())
+            .map_or("unknown", |name| name.as_str())
+            .as_bytes()
+            .to_vec()
+    }
+
+    /// Applies the per-query timeout, if any, to a DNS lookup.
+    async fn query<T>(&self, lookup: impl Future<Output = crate::Result<T>>) -> crate::Result<T> {
+        if let Some(timeout) = self.spf_query_timeout {
+            tokio::time::timeout(timeout, lookup)
+                .await
+                .unwrap_or_else(|_| Err(Error::DnsError("query timed out".to_string())))
+        } else {
+            lookup.await
+        }
+    }
+
+    /// Applies the per-query timeout to a DNS lookup and, when tracing is
+    /// enabled, records it in the last trace entry along with a summary of
+    /// its answer.
+    async fn traced_query<T>(
+        &self,
+        trace: &mut Option<Vec<SpfTraceEntry>>,
+        record_type: RecordType,
+        name: impl Display,
+        lookup: impl Future<Output = crate::Result<T>>,
+        answer: impl FnOnce(&T) -> String,
+    ) -> crate::Result<T> {
+        if let Some(entry) = trace.as_mut().and_then(|trace| trace.last_mut()) {
+            let started = Instant::now();
+            let result = self.query(lookup).await;
+            entry.queries.push(SpfTraceQuery {
+                record_type,
+                name: name.to_string(),
+                answer: match &result {
+                    Ok(value) => answer(value),
+                    Err(err) => err.to_string(),
+                },
+                duration: started.elapsed(),
+            });
+            result
+        } else {
+            self.query(lookup).await
+        }
+    }
+
     async fn ip_matches(
         &self,
         target_name: &str,
         ip: IpAddr,
         ip4_mask: u32,
         ip6_mask: u128,
+        trace: &mut Option<Vec<SpfTraceEntry>>,
     ) -> crate::Result<bool> {
         Ok(match ip {
             IpAddr::V4(ip) => self
-                .ipv4_lookup(target_name)
+                .traced_query(
+                    trace,
+                    RecordType::A,
+                    target_name,
+                    self.ipv4_lookup(target_name),
+                    |addrs| join_answer(addrs.iter()),
+                )
                 .await?
                 .iter()
                 .any(|addr| ip.matches_ipv4_mask(addr, ip4_mask)),
             IpAddr::V6(ip) => self
-                .ipv6_lookup(target_name)
+                .traced_query(
+                    trace,
+                    RecordType::AAAA,
+                    target_name,
+                    self.ipv6_lookup(target_name),
+                    |addrs| join_answer(addrs.iter()),
+                )
                 .await?
                 .iter()
                 .any(|addr| ip.matches_ipv6_mask(addr, ip6_mask)),
@@ -460,34 +1005,202 @@ impl From<Error> for SpfResult {
     fn from(err: Error) -> Self {
         match err {
             Error::DnsRecordNotFound(_) | Error::InvalidRecordType => SpfResult::None,
-            Error::ParseError => SpfResult::PermError,
+            Error::ParseError | Error::MultipleRecords => SpfResult::PermError,
             _ => SpfResult::TempError,
         }
     }
 }
 
+struct IncludeFrame {
+    record: Arc<Spf>,
+    pos: usize,
+    domain: String,
+    trace_pos: Option<usize>,
+    num_lookups: u32,
+    num_void_lookups: u32,
+}
+
+/// Outcome of evaluating an included record, along with the lookups it
+/// performed. Includes match when `matched` is set.
+struct IncludeMemo {
+    domain: String,
+    matched: Option<(String, String)>,
+    num_lookups: u32,
+    num_void_lookups: u32,
+}
+
 struct LookupLimit {
     num_lookups: u32,
-    timer: Instant,
+    num_void_lookups: u32,
+    max_lookups: u32,
+    max_void_lookups: u32,
 }
 
 impl LookupLimit {
-    pub fn new() -> Self {
+    pub fn new(resolver: &Resolver) -> Self {
         LookupLimit {
-            num_lookups: 1,
-            timer: Instant::now(),
+            num_lookups: 0,
+            num_void_lookups: 0,
+            max_lookups: resolver.spf_lookup_limit,
+            max_void_lookups: resolver.spf_void_lookup_limit,
         }
     }
 
     #[inline(always)]
     fn can_lookup(&mut self) -> bool {
-        if self.num_lookups < 10 && self.timer.elapsed().as_secs() < 20 {
+        if self.num_lookups < self.max_lookups {
             self.num_lookups += 1;
             true
         } else {
             false
         }
     }
+
+    #[inline(always)]
+    fn can_void_lookup(&mut self) -> bool {
+        self.num_void_lookups += 1;
+        self.num_void_lookups <= self.max_void_lookups
+    }
+}
+
+/// Expands a domain-spec and normalizes the resulting name.
+fn expand_domain(macro_string: &Macro, vars: &Variables, domain: &str) -> Option<String> {
+    normalize_domain(&macro_string.eval(vars, domain, true))
+}
+
+/// Returns whether `target` is already being evaluated, either as the current
+/// domain, one of its including records or a record that redirected to it.
+fn is_evaluating(
+    target: &str,
+    domain: &str,
+    redirects: &[String],
+    include_stack: &[IncludeFrame],
+) -> bool {
+    target == domain
+        || redirects.iter().any(|redirect| redirect == target)
+        || include_stack.iter().any(|frame| frame.domain == target)
+}
+
+/// Converts a domain name to lowercase A-labels and removes its trailing dot,
+/// returning `None` when it has invalid U-labels, empty labels or labels
+/// longer than 63 octets.
+fn normalize_domain(name: &str) -> Option<String> {
+    let name = domain_to_ascii(name.strip_suffix('.').unwrap_or(name))?;
+    if !name.is_empty()
+        && name
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+    {
+        Some(name.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+/// The record evaluated for domains without an SPF record when best-guess
+/// evaluation is enabled, `v=spf1 a mx ptr ?all`.
+fn best_guess_record() -> Spf {
+    Spf {
+        version: Version::V1,
+        directives: vec![
+            Directive::new(
+                Qualifier::Pass,
+                Mechanism::A {
+                    macro_string: Macro::None,
+                    ip4_mask: u32::MAX,
+                    ip6_mask: u128::MAX,
+                },
+            ),
+            Directive::new(
+                Qualifier::Pass,
+                Mechanism::Mx {
+                    macro_string: Macro::None,
+                    ip4_mask: u32::MAX,
+                    ip6_mask: u128::MAX,
+                },
+            ),
+            Directive::new(
+                Qualifier::Pass,
+                Mechanism::Ptr {
+                    macro_string: Macro::None,
+                },
+            ),
+            Directive::new(Qualifier::Neutral, Mechanism::All),
+        ],
+        exp: None,
+        redirect: None,
+        ra: None,
+        rp: 100,
+        rr: u8::MAX,
+        warnings: Vec::new(),
+    }
+}
+
+/// Converts the domain of a sender to A-labels, so that `%{o}` expands to
+/// a name that can be queried.
+fn sender_to_ascii(sender: &str) -> String {
+    match sender.rsplit_once('@') {
+        Some((local_part, domain)) if !domain.is_ascii() => match domain_to_ascii(domain) {
+            Some(domain) => format!("{local_part}@{domain}"),
+            None => sender.to_string(),
+        },
+        _ => sender.to_string(),
+    }
+}
+
+fn join_answer(items: impl Iterator<Item = impl Display>) -> String {
+    let answer = items.map(|item| item.to_string()).collect::<Vec<_>>();
+    if !answer.is_empty() {
+        answer.join(", ")
+    } else {
+        "no records".to_string()
+    }
+}
+
+/// Adds a term to the trace, if enabled, returning its position.
+fn trace_term(
+    trace: &mut Option<Vec<SpfTraceEntry>>,
+    domain: &str,
+    term: impl FnOnce() -> String,
+) -> Option<usize> {
+    trace.as_mut().map(|trace| {
+        trace.push(SpfTraceEntry::new(domain, term()));
+        trace.len() - 1
+    })
+}
+
+fn trace_outcome(trace: &mut Option<Vec<SpfTraceEntry>>, pos: Option<usize>, matches: bool) {
+    if let (Some(trace), Some(pos)) = (trace.as_mut(), pos) {
+        trace[pos].outcome = if matches {
+            SpfTraceOutcome::Match
+        } else {
+            SpfTraceOutcome::NoMatch
+        };
+    }
+}
+
+/// Evaluates IPv4-mapped and IPv4-compatible IPv6 addresses as IPv4
+/// (RFC 7208, Section 5).
+fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip6) => match ip6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => {
+                IpAddr::V4(Ipv4Addr::from(((hi as u32) << 16) | lo as u32))
+            }
+            // Skip the unspecified and loopback addresses
+            [0, 0, 0, 0, 0, 0, hi, lo] if hi != 0 || lo > 1 => {
+                IpAddr::V4(Ipv4Addr::from(((hi as u32) << 16) | lo as u32))
+            }
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Returns `true` for address literals such as `[192.0.2.1]`, which are
+/// not domain names and have no SPF record.
+fn is_address_literal(domain: &str) -> bool {
+    domain.starts_with('[') || domain.parse::<IpAddr>().is_ok()
 }
 
 pub trait HasLabels {
@@ -517,18 +1230,1214 @@ impl HasLabels for &str {
 mod test {
 
     use std::{
+        collections::BTreeMap,
         fs,
         net::{IpAddr, Ipv4Addr, Ipv6Addr},
         path::PathBuf,
         time::{Duration, Instant},
     };
 
+    use trust_dns_resolver::proto::rr::RecordType;
+
     use crate::{
         common::parse::TxtRecordParser,
-        spf::{Macro, Spf},
-        Resolver, SpfResult, MX,
+        spf::{Macro, Spf, SpfPolicy, SpfTraceOutcome},
+        Error, Resolver, SpfIdentity, SpfOutput, SpfResult, MX,
     };
 
+    #[tokio::test]
+    async fn spf_lookup_limits() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip: IpAddr = "172.168.0.1".parse().unwrap();
+
+        for (resolver, expect_limit, expect_void) in [
+            (
+                Resolver::new_system_conf().unwrap(),
+                (SpfResult::SoftFail, None, 5),
+                (
+                    SpfResult::PermError,
+                    Some(Error::VoidLookupLimitExceeded),
+                    3,
+                ),
+            ),
+            (
+                Resolver::new_system_conf()
+                    .unwrap()
+                    .with_spf_lookup_limit(4)
+                    .with_spf_void_lookup_limit(3),
+                (SpfResult::PermError, Some(Error::LookupLimitExceeded), 4),
+                (SpfResult::SoftFail, None, 3),
+            ),
+        ] {
+            resolver.txt_add(
+                "test.org",
+                Spf::parse(b"v=spf1 a:t1.org a:t2.org a:t3.org a:t4.org a:t5.org ~all"),
+                valid_until,
+            );
+            resolver.txt_add(
+                "void.org",
+                Spf::parse(b"v=spf1 a:v1.org mx:v2.org exists:v3.org ~all"),
+                valid_until,
+            );
+            for name in ["t1.org", "t2.org", "t3.org", "t4.org", "t5.org"] {
+                resolver.ipv4_add(name, vec![Ipv4Addr::new(10, 0, 0, 1)], valid_until);
+            }
+
+            for (domain, (result, error, num_lookups)) in
+                [("test.org", expect_limit), ("void.org", expect_void)]
+            {
+                let output = resolver
+                    .check_host(ip, domain, domain, "localdomain.org", "")
+                    .await;
+                assert_eq!(output.result(), result, "{domain}");
+                assert_eq!(output.error(), error.as_ref(), "{domain}");
+                assert_eq!(output.num_lookups(), num_lookups, "{domain}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_explanation() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "test.org",
+            Spf::parse(b"v=spf1 -all exp=%{l}.explain.test.org"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "ok.explain.test.org",
+            Macro::parse(b"%{i} is not allowed to send mail as %{s}"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "utf8.explain.test.org",
+            Macro::parse("Dirección %{i} no autorizada".as_bytes()),
+            valid_until,
+        );
+        resolver.txt_add(
+            "long.explain.test.org",
+            Macro::parse(format!("{} %{{s}}", "x".repeat(390)).as_bytes()),
+            valid_until,
+        );
+
+        for (sender, expected_explanation) in [
+            (
+                "ok@test.org",
+                Some("192.0.2.1 is not allowed to send mail as ok@test.org"),
+            ),
+            ("utf8@test.org", None),
+            ("long@test.org", None),
+            ("missing@test.org", None),
+        ] {
+            let output = resolver
+                .check_host(ip, "test.org", "test.org", "localdomain.org", sender)
+                .await;
+            assert_eq!(output.result(), SpfResult::Fail, "{sender}");
+            assert_eq!(output.explanation(), expected_explanation, "{sender}");
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_identity() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "mail.example.org",
+            Spf::parse(b"v=spf1 exists:%{l}.%{o}.allow.example.org -all"),
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "postmaster.mail.example.org.allow.example.org",
+            vec![Ipv4Addr::new(127, 0, 0, 2)],
+            valid_until,
+        );
+
+        // HELO identity
+        let output = resolver
+            .verify_spf_helo(ip, "mail.example.org", "localdomain.org")
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        assert_eq!(output.identity(), SpfIdentity::Helo);
+
+        // Bounces and senders without a local-part use postmaster@<helo>
+        for sender in ["", "@mail.example.org", "postmaster@mail.example.org"] {
+            let output = resolver
+                .verify_spf_sender(ip, "mail.example.org", "localdomain.org", sender)
+                .await;
+            assert_eq!(output.result(), SpfResult::Pass, "{sender:?}");
+            assert_eq!(output.identity(), SpfIdentity::MailFrom, "{sender:?}");
+            assert_eq!(output.domain(), "mail.example.org", "{sender:?}");
+        }
+        let output = resolver
+            .verify_spf_sender(
+                ip,
+                "mail.example.org",
+                "localdomain.org",
+                "jdoe@mail.example.org",
+            )
+            .await;
+        assert_eq!(output.result(), SpfResult::Fail);
+
+        // Address literals are not checked
+        for helo in ["[192.0.2.1]", "[IPv6:2001:db8::1]", "192.0.2.1"] {
+            let output = resolver.verify_spf_helo(ip, helo, "localdomain.org").await;
+            assert_eq!(output.result(), SpfResult::None, "{helo}");
+            assert_eq!(output.identity(), SpfIdentity::Helo, "{helo}");
+            let output = resolver
+                .verify_spf_sender(ip, helo, "localdomain.org", "")
+                .await;
+            assert_eq!(output.result(), SpfResult::None, "{helo}");
+            assert_eq!(output.identity(), SpfIdentity::MailFrom, "{helo}");
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_ipv4_mapped() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "test.org",
+            Spf::parse(b"v=spf1 ip4:203.0.113.0/24 -all exp=explain.test.org"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "explain.test.org",
+            Macro::parse(b"%{i} is not allowed"),
+            valid_until,
+        );
+
+        for (ip, expected_ip, expected_result) in [
+            ("::ffff:203.0.113.5", "203.0.113.5", SpfResult::Pass),
+            ("::203.0.113.5", "203.0.113.5", SpfResult::Pass),
+            ("203.0.113.5", "203.0.113.5", SpfResult::Pass),
+            ("::ffff:198.51.100.7", "198.51.100.7", SpfResult::Fail),
+            (
+                "2001:db8::cb00:7105",
+                "2001:db8::cb00:7105",
+                SpfResult::Fail,
+            ),
+            ("::1", "::1", SpfResult::Fail),
+        ] {
+            let output = resolver
+                .check_host(
+                    ip.parse().unwrap(),
+                    "test.org",
+                    "test.org",
+                    "localdomain.org",
+                    "jdoe@test.org",
+                )
+                .await;
+            let expected_ip: IpAddr = expected_ip.parse().unwrap();
+            assert_eq!(output.result(), expected_result, "{ip}");
+            assert_eq!(output.client_ip(), Some(expected_ip), "{ip}");
+            if expected_result == SpfResult::Fail && expected_ip.is_ipv4() {
+                assert_eq!(
+                    output.explanation(),
+                    Some(format!("{expected_ip} is not allowed").as_str()),
+                    "{ip}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_trace() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        for resolver in [
+            Resolver::new_system_conf().unwrap(),
+            Resolver::new_system_conf().unwrap().with_spf_trace(true),
+        ] {
+            resolver.txt_add(
+                "test.org",
+                Spf::parse(b"v=spf1 include:_spf.test.org a:mail.test.org -all"),
+                valid_until,
+            );
+            resolver.txt_add(
+                "_spf.test.org",
+                Spf::parse(b"v=spf1 ip4:192.0.2.0/24 ~all"),
+                valid_until,
+            );
+            resolver.txt_add(
+                "redirect.test.org",
+                Spf::parse(b"v=spf1 redirect=test.org"),
+                valid_until,
+            );
+            resolver.ipv4_add(
+                "mail.test.org",
+                vec![Ipv4Addr::new(198, 51, 100, 1)],
+                valid_until,
+            );
+
+            for (ip, domain, result, matched, trace) in [
+                (
+                    "192.0.2.5",
+                    "test.org",
+                    SpfResult::Pass,
+                    Some(("ip4:192.0.2.0/24", "_spf.test.org")),
+                    vec![
+                        ("test.org", "v=spf1", SpfTraceOutcome::Match),
+                        ("test.org", "include:_spf.test.org", SpfTraceOutcome::Match),
+                        ("_spf.test.org", "ip4:192.0.2.0/24", SpfTraceOutcome::Match),
+                    ],
+                ),
+                (
+                    "198.51.100.1",
+                    "test.org",
+                    SpfResult::Pass,
+                    Some(("a:mail.test.org", "test.org")),
+                    vec![
+                        ("test.org", "v=spf1", SpfTraceOutcome::Match),
+                        (
+                            "test.org",
+                            "include:_spf.test.org",
+                            SpfTraceOutcome::NoMatch,
+                        ),
+                        (
+                            "_spf.test.org",
+                            "ip4:192.0.2.0/24",
+                            SpfTraceOutcome::NoMatch,
+                        ),
+                        ("_spf.test.org", "~all", SpfTraceOutcome::Match),
+                        ("test.org", "a:mail.test.org", SpfTraceOutcome::Match),
+                    ],
+                ),
+                (
+                    "203.0.113.1",
+                    "redirect.test.org",
+                    SpfResult::Fail,
+                    Some(("-all", "test.org")),
+                    vec![
+                        ("redirect.test.org", "v=spf1", SpfTraceOutcome::Match),
+                        (
+                            "redirect.test.org",
+                            "redirect=test.org",
+                            SpfTraceOutcome::Match,
+                        ),
+                        (
+                            "test.org",
+                            "include:_spf.test.org",
+                            SpfTraceOutcome::NoMatch,
+                        ),
+                        (
+                            "_spf.test.org",
+                            "ip4:192.0.2.0/24",
+                            SpfTraceOutcome::NoMatch,
+                        ),
+                        ("_spf.test.org", "~all", SpfTraceOutcome::Match),
+                        ("test.org", "a:mail.test.org", SpfTraceOutcome::NoMatch),
+                        ("test.org", "-all", SpfTraceOutcome::Match),
+                    ],
+                ),
+                (
+                    "203.0.113.1",
+                    "_spf.test.org",
+                    SpfResult::SoftFail,
+                    Some(("~all", "_spf.test.org")),
+                    vec![
+                        ("_spf.test.org", "v=spf1", SpfTraceOutcome::Match),
+                        (
+                            "_spf.test.org",
+                            "ip4:192.0.2.0/24",
+                            SpfTraceOutcome::NoMatch,
+                        ),
+                        ("_spf.test.org", "~all", SpfTraceOutcome::Match),
+                    ],
+                ),
+            ] {
+                let output = resolver
+                    .check_host(ip.parse().unwrap(), domain, domain, "localdomain.org", "")
+                    .await;
+                assert_eq!(output.result(), result, "{ip}");
+                assert_eq!(
+                    output.matched_mechanism().zip(output.matched_domain()),
+                    matched,
+                    "{ip}"
+                );
+                if resolver.spf_trace {
+                    assert_eq!(
+                        output
+                            .trace()
+                            .unwrap()
+                            .iter()
+                            .map(|entry| (entry.domain(), entry.term(), entry.outcome()))
+                            .collect::<Vec<_>>(),
+                        trace,
+                        "{ip}"
+                    );
+                } else {
+                    assert_eq!(output.trace(), None);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_trace_queries() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap().with_spf_trace(true);
+        resolver.txt_add(
+            "test.org",
+            Spf::parse(b"v=spf1 include:_spf.test.org a:mail.test.org ip4:203.0.113.0/24 -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_spf.test.org",
+            Spf::parse(b"v=spf1 ip4:192.0.2.0/24 ~all"),
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "mail.test.org",
+            vec![Ipv4Addr::new(198, 51, 100, 1)],
+            valid_until,
+        );
+
+        let output = resolver
+            .check_host(
+                "203.0.113.5".parse().unwrap(),
+                "test.org",
+                "test.org",
+                "localdomain.org",
+                "",
+            )
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        let trace = output.trace().unwrap();
+        assert_eq!(
+            trace
+                .iter()
+                .flat_map(|entry| entry.queries())
+                .map(|query| (query.record_type(), query.name(), query.answer()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    RecordType::TXT,
+                    "test.org",
+                    "v=spf1 include:_spf.test.org a:mail.test.org ip4:203.0.113.0/24 -all"
+                ),
+                (
+                    RecordType::TXT,
+                    "_spf.test.org",
+                    "v=spf1 ip4:192.0.2.0/24 ~all"
+                ),
+                (RecordType::A, "mail.test.org", "198.51.100.1"),
+            ]
+        );
+
+        // Render the trace without the query durations
+        let rendered = trace
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(
+            rendered
+                .lines()
+                .map(|line| line.rsplit_once(" (").map_or(line, |(line, _)| line))
+                .collect::<Vec<_>>(),
+            vec![
+                "test.org: v=spf1 => match",
+                "  TXT test.org -> v=spf1 include:_spf.test.org a:mail.test.org ip4:203.0.113.0/24 -all",
+                "test.org: include:_spf.test.org => no match",
+                "  TXT _spf.test.org -> v=spf1 ip4:192.0.2.0/24 ~all",
+                "_spf.test.org: ip4:192.0.2.0/24 => no match",
+                "_spf.test.org: ~all => match",
+                "test.org: a:mail.test.org => no match",
+                "  A mail.test.org -> 198.51.100.1",
+                "test.org: ip4:203.0.113.0/24 => match",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn spf_timeouts() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        for (resolver, expected_result, expected_error, expected_elapsed) in [
+            (
+                Resolver::new_system_conf().unwrap(),
+                SpfResult::Pass,
+                None,
+                400,
+            ),
+            (
+                Resolver::new_system_conf()
+                    .unwrap()
+                    .with_spf_timeout(Duration::from_millis(300)),
+                SpfResult::TempError,
+                Some(Error::DeadlineExceeded),
+                300,
+            ),
+            (
+                Resolver::new_system_conf()
+                    .unwrap()
+                    .with_spf_query_timeout(Duration::from_millis(50)),
+                SpfResult::TempError,
+                None,
+                50,
+            ),
+        ] {
+            resolver.txt_add(
+                "test.org",
+                Spf::parse(b"v=spf1 a:a._slow.test.org a:b._slow.test.org ip4:192.0.2.1 -all"),
+                valid_until,
+            );
+            let output = resolver
+                .check_host(ip, "test.org", "test.org", "localdomain.org", "")
+                .await;
+            assert_eq!(output.result(), expected_result);
+            assert_eq!(output.error(), expected_error.as_ref());
+            assert!(output.elapsed() >= Duration::from_millis(expected_elapsed));
+        }
+
+        // The time budget covers both identities
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_spf_timeout(Duration::from_millis(300));
+        resolver.txt_add(
+            "helo.org",
+            Spf::parse(b"v=spf1 a:c._slow.test.org +all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "test.org",
+            Spf::parse(b"v=spf1 a:d._slow.test.org ip4:192.0.2.1 -all"),
+            valid_until,
+        );
+        assert_eq!(
+            resolver
+                .verify_spf_sender(ip, "helo.org", "localdomain.org", "jdoe@test.org")
+                .await
+                .result(),
+            SpfResult::Pass
+        );
+        let output = resolver
+            .verify_spf(ip, "helo.org", "localdomain.org", "jdoe@test.org")
+            .await;
+        assert_eq!(output.result(), SpfResult::TempError);
+        assert_eq!(output.error(), Some(&Error::DeadlineExceeded));
+    }
+
+    #[tokio::test]
+    async fn spf_include_memo() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip: IpAddr = "198.51.100.1".parse().unwrap();
+
+        for (resolver, expected_result, expected_error) in [
+            (Resolver::new_system_conf().unwrap(), SpfResult::Pass, None),
+            (
+                Resolver::new_system_conf()
+                    .unwrap()
+                    .with_spf_lookup_limit(7),
+                SpfResult::PermError,
+                Some(Error::LookupLimitExceeded),
+            ),
+        ] {
+            // Diamond-shaped include graph
+            for (domain, record) in [
+                (
+                    "top.org",
+                    "v=spf1 include:a.org include:b.org ip4:198.51.100.0/24 -all",
+                ),
+                ("a.org", "v=spf1 a:mail.a.org include:shared.org"),
+                ("b.org", "v=spf1 a:mail.b.org include:shared.org"),
+                ("shared.org", "v=spf1 a:mail.shared.org"),
+            ] {
+                resolver.txt_add(domain, Spf::parse(record.as_bytes()), valid_until);
+            }
+            for (pos, domain) in ["mail.a.org", "mail.b.org", "mail.shared.org"]
+                .into_iter()
+                .enumerate()
+            {
+                resolver.ipv4_add(
+                    domain,
+                    vec![Ipv4Addr::new(10, 0, 0, pos as u8)],
+                    valid_until,
+                );
+            }
+
+            let output = resolver
+                .check_host(ip, "top.org", "top.org", "localdomain.org", "")
+                .await;
+            assert_eq!(output.result(), expected_result);
+            assert_eq!(output.error(), expected_error.as_ref());
+            if expected_result == SpfResult::Pass {
+                // shared.org is fetched and evaluated once, but counted twice
+                assert_eq!(output.num_lookups(), 8);
+                assert_eq!(
+                    resolver
+                        .mock_num_queries
+                        .load(std::sync::atomic::Ordering::Relaxed),
+                    7
+                );
+            }
+        }
+
+        // Targets expanded from macros are not shared
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (domain, record) in [
+            ("top.org", "v=spf1 include:a.org include:b.org -all"),
+            ("a.org", "v=spf1 include:_spf.%{d}"),
+            ("b.org", "v=spf1 include:_spf.%{d}"),
+            ("_spf.a.org", "v=spf1 -all"),
+            ("_spf.b.org", "v=spf1 +all"),
+        ] {
+            resolver.txt_add(domain, Spf::parse(record.as_bytes()), valid_until);
+        }
+        let output = resolver
+            .check_host(ip, "top.org", "top.org", "localdomain.org", "")
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        assert_eq!(output.num_lookups(), 4);
+    }
+
+    #[tokio::test]
+    async fn spf_ptr() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (domain, record) in [
+            ("test.org", "v=spf1 ptr:example.org -all"),
+            ("a.test.org", "v=spf1 ptr:A.Example.Org. -all"),
+            ("b.test.org", "v=spf1 ptr -all"),
+        ] {
+            resolver.txt_add(domain, Spf::parse(record.as_bytes()), valid_until);
+        }
+
+        // Only the first 10 names are checked
+        let many_names: IpAddr = "192.0.2.1".parse().unwrap();
+        resolver.ptr_add(
+            many_names,
+            (1..=12).map(|n| format!("h{n}.example.org.")).collect(),
+            valid_until,
+        );
+        for n in 11..=12 {
+            resolver.ipv4_add(
+                format!("h{n}.example.org."),
+                vec![Ipv4Addr::new(192, 0, 2, 1)],
+                valid_until,
+            );
+        }
+
+        // Names with mismatched forward lookups are not validated
+        let mismatched: IpAddr = "192.0.2.2".parse().unwrap();
+        resolver.ptr_add(
+            mismatched,
+            vec!["a.example.org.".to_string(), "b.example.org.".to_string()],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "a.example.org.",
+            vec![Ipv4Addr::new(192, 0, 2, 99)],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "b.example.org.",
+            vec![Ipv4Addr::new(192, 0, 2, 2)],
+            valid_until,
+        );
+
+        // No PTR records
+        let no_names: IpAddr = "192.0.2.3".parse().unwrap();
+
+        for (ip, domain, expected_result, expected_names) in [
+            (many_names, "test.org", SpfResult::Fail, Some(vec![])),
+            (
+                mismatched,
+                "test.org",
+                SpfResult::Pass,
+                Some(vec!["b.example.org".to_string()]),
+            ),
+            (
+                mismatched,
+                "a.test.org",
+                SpfResult::Fail,
+                Some(vec!["b.example.org".to_string()]),
+            ),
+            (
+                mismatched,
+                "b.test.org",
+                SpfResult::Fail,
+                Some(vec!["b.example.org".to_string()]),
+            ),
+            (no_names, "test.org", SpfResult::Fail, None),
+        ] {
+            let output = resolver
+                .check_host(ip, domain, "test.org", "localdomain.org", "")
+                .await;
+            assert_eq!(output.result(), expected_result, "{ip} {domain}");
+            assert_eq!(
+                output.ptr_names(),
+                expected_names.as_deref(),
+                "{ip} {domain}"
+            );
+        }
+
+        // A missing PTR record counts as a void lookup
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_spf_void_lookup_limit(0);
+        resolver.txt_add(
+            "test.org",
+            Spf::parse(b"v=spf1 ptr:example.org -all"),
+            valid_until,
+        );
+        let output = resolver
+            .check_host(no_names, "test.org", "test.org", "localdomain.org", "")
+            .await;
+        assert_eq!(output.result(), SpfResult::PermError);
+        assert_eq!(output.error(), Some(&Error::VoidLookupLimitExceeded));
+
+        // The PTR query is only made, and counted, by the first ptr term
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_spf_void_lookup_limit(1);
+        resolver.txt_add(
+            "test.org",
+            Spf::parse(b"v=spf1 ptr:example.org ptr:example.net ptr:example.com -all"),
+            valid_until,
+        );
+        let output = resolver
+            .check_host(no_names, "test.org", "test.org", "localdomain.org", "")
+            .await;
+        assert_eq!(output.result(), SpfResult::Fail);
+        assert_eq!(output.error(), None);
+    }
+
+    #[tokio::test]
+    async fn spf_multiple_records() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (domain, records) in [
+            ("test.org", vec!["v=spf1 +all", "v=spf1 -all"]),
+            ("invalid.org", vec!["v=spf1 +all", "v=spf1 ip4:/24 -all"]),
+            (
+                "valid.org",
+                vec!["v=spf1 +all", "v=spf10 -all", "spf2.0/pra -all"],
+            ),
+        ] {
+            resolver.txt_add(domain, Spf::parse_records(records), valid_until);
+        }
+        resolver.txt_add(
+            "include.org",
+            Spf::parse(b"v=spf1 include:test.org -all"),
+            valid_until,
+        );
+
+        for (domain, expected_result) in [
+            ("test.org", SpfResult::PermError),
+            ("invalid.org", SpfResult::PermError),
+            ("include.org", SpfResult::PermError),
+            ("valid.org", SpfResult::Pass),
+        ] {
+            let output = resolver
+                .check_host(
+                    "192.0.2.1".parse().unwrap(),
+                    domain,
+                    domain,
+                    "localdomain.org",
+                    "",
+                )
+                .await;
+            assert_eq!(output.result(), expected_result, "{domain}");
+            if expected_result == SpfResult::PermError {
+                assert_eq!(output.error(), Some(&Error::MultipleRecords), "{domain}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_normalize() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        let long_label = "a".repeat(64);
+        for (domain, record) in [
+            (
+                "test.org",
+                "v=spf1 include:_SPF.Example.COM. -all".to_string(),
+            ),
+            (
+                "_spf.example.com",
+                "v=spf1 ip4:192.0.2.0/24 -all".to_string(),
+            ),
+            (
+                "macro.org",
+                "v=spf1 a:%{d}.hosts.test.org exists:%{h}._h.test.org -all".to_string(),
+            ),
+            ("empty.org", "v=spf1 include:a..test.org -all".to_string()),
+            ("long.org", format!("v=spf1 a:{long_label}.test.org -all")),
+            (
+                "xn--mnchen-3ya.de",
+                "v=spf1 include:_spf.XN--BCHER-KVA.example -all".to_string(),
+            ),
+            (
+                "_spf.xn--bcher-kva.example",
+                "v=spf1 a:mail.xn--bcher-kva.example -all".to_string(),
+            ),
+        ] {
+            resolver.txt_add(domain, Spf::parse(record.as_bytes()), valid_until);
+        }
+        for domain in ["macro.org.hosts.test.org", "mail.xn--bcher-kva.example"] {
+            resolver.ipv4_add(domain, vec![Ipv4Addr::new(198, 51, 100, 1)], valid_until);
+        }
+
+        for (ip, domain, helo_domain, expected_domain, expected_result) in [
+            (
+                "192.0.2.1",
+                "Test.ORG.",
+                "test.org",
+                "test.org",
+                SpfResult::Pass,
+            ),
+            (
+                "198.51.100.1",
+                "MACRO.org.",
+                "",
+                "macro.org",
+                SpfResult::Pass,
+            ),
+            (
+                "198.51.100.2",
+                "macro.org",
+                "Mail.Example.ORG.",
+                "macro.org",
+                SpfResult::Fail,
+            ),
+            (
+                "198.51.100.1",
+                "empty.org",
+                "",
+                "empty.org",
+                SpfResult::PermError,
+            ),
+            (
+                "198.51.100.1",
+                "long.org",
+                "",
+                "long.org",
+                SpfResult::PermError,
+            ),
+            (
+                "198.51.100.1",
+                "XN--MNCHEN-3YA.de.",
+                "",
+                "xn--mnchen-3ya.de",
+                SpfResult::Pass,
+            ),
+        ] {
+            let output = resolver
+                .check_host(
+                    ip.parse().unwrap(),
+                    domain,
+                    helo_domain,
+                    "localdomain.org",
+                    "",
+                )
+                .await;
+            assert_eq!(output.result(), expected_result, "{domain}");
+            assert_eq!(output.domain(), expected_domain, "{domain}");
+        }
+
+        // %{h} expands to the normalized HELO domain
+        resolver.ipv4_add(
+            "mail.example.org._h.test.org",
+            vec![Ipv4Addr::new(198, 51, 100, 2)],
+            valid_until,
+        );
+        let output = resolver
+            .check_host(
+                "198.51.100.2".parse().unwrap(),
+                "macro.org",
+                "Mail.Example.ORG.",
+                "localdomain.org",
+                "",
+            )
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        assert_eq!(output.matched_mechanism(), Some("exists:%{h}._h.test.org"));
+
+        // Included domains are reported in normalized form
+        let output = resolver
+            .check_host(
+                "192.0.2.1".parse().unwrap(),
+                "test.org",
+                "test.org",
+                "localdomain.org",
+                "",
+            )
+            .await;
+        assert_eq!(output.matched_domain(), Some("_spf.example.com"));
+    }
+
+    #[tokio::test]
+    async fn spf_policy() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        for (policy, domain, expected_result, expected_effective_result) in [
+            (
+                SpfPolicy::default(),
+                "softfail.org",
+                SpfResult::SoftFail,
+                SpfResult::SoftFail,
+            ),
+            (
+                SpfPolicy {
+                    softfail_as_fail: true,
+                    ..Default::default()
+                },
+                "softfail.org",
+                SpfResult::SoftFail,
+                SpfResult::Fail,
+            ),
+            (
+                SpfPolicy {
+                    softfail_as_fail: true,
+                    ..Default::default()
+                },
+                "neutral.org",
+                SpfResult::Neutral,
+                SpfResult::Neutral,
+            ),
+            (
+                SpfPolicy {
+                    neutral_as_fail: true,
+                    ..Default::default()
+                },
+                "neutral.org",
+                SpfResult::Neutral,
+                SpfResult::Fail,
+            ),
+            (
+                SpfPolicy {
+                    softfail_as_fail: true,
+                    neutral_as_fail: true,
+                    domains: vec!["Neutral.ORG".to_string()],
+                },
+                "softfail.org",
+                SpfResult::SoftFail,
+                SpfResult::SoftFail,
+            ),
+            (
+                SpfPolicy {
+                    softfail_as_fail: true,
+                    neutral_as_fail: true,
+                    domains: vec!["softfail.org".to_string()],
+                },
+                "sub.softfail.org",
+                SpfResult::SoftFail,
+                SpfResult::Fail,
+            ),
+            (
+                SpfPolicy {
+                    softfail_as_fail: true,
+                    neutral_as_fail: true,
+                    ..Default::default()
+                },
+                "pass.org",
+                SpfResult::Pass,
+                SpfResult::Pass,
+            ),
+        ] {
+            let resolver = Resolver::new_system_conf().unwrap().with_spf_policy(policy);
+            for (name, record) in [
+                ("softfail.org", "v=spf1 ~all"),
+                ("sub.softfail.org", "v=spf1 ~all"),
+                ("neutral.org", "v=spf1 ?all"),
+                ("pass.org", "v=spf1 +all"),
+            ] {
+                resolver.txt_add(name, Spf::parse(record.as_bytes()), valid_until);
+            }
+
+            let output = resolver
+                .check_host(
+                    "192.0.2.1".parse().unwrap(),
+                    domain,
+                    domain,
+                    "localdomain.org",
+                    "",
+                )
+                .await;
+            assert_eq!(output.result(), expected_result, "{domain}");
+            assert_eq!(
+                output.effective_result(),
+                expected_effective_result,
+                "{domain}"
+            );
+            assert_eq!(
+                output.is_escalated(),
+                expected_result != expected_effective_result
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_loops() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (domain, record) in [
+            ("self.org", "v=spf1 ip4:192.0.2.1 redirect=SELF.org."),
+            ("a.org", "v=spf1 ip4:192.0.2.1 redirect=b.org"),
+            ("b.org", "v=spf1 ip4:192.0.2.2 redirect=a.org"),
+            ("parent.org", "v=spf1 include:child.org -all"),
+            ("child.org", "v=spf1 ip4:192.0.2.1 include:parent.org"),
+            (
+                "diamond.org",
+                "v=spf1 include:d1.org include:d2.org ip4:192.0.2.3 -all",
+            ),
+            ("d1.org", "v=spf1 include:shared.org"),
+            ("d2.org", "v=spf1 include:shared.org"),
+            ("shared.org", "v=spf1 ip4:192.0.2.4"),
+        ] {
+            resolver.txt_add(domain, Spf::parse(record.as_bytes()), valid_until);
+        }
+
+        for (domain, expected_result, expected_error) in [
+            (
+                "self.org",
+                SpfResult::PermError,
+                Some(Error::EvaluationLoop("self.org".to_string())),
+            ),
+            (
+                "a.org",
+                SpfResult::PermError,
+                Some(Error::EvaluationLoop("a.org".to_string())),
+            ),
+            (
+                "parent.org",
+                SpfResult::PermError,
+                Some(Error::EvaluationLoop("parent.org".to_string())),
+            ),
+            // Records reached more than once without a cycle are not loops
+            ("diamond.org", SpfResult::Pass, None),
+        ] {
+            let output = resolver
+                .check_host(
+                    "192.0.2.3".parse().unwrap(),
+                    domain,
+                    domain,
+                    "localdomain.org",
+                    "",
+                )
+                .await;
+            assert_eq!(output.result(), expected_result, "{domain}");
+            assert_eq!(output.error(), expected_error.as_ref(), "{domain}");
+        }
+    }
+
+    #[tokio::test]
+    async fn spf_mx_limit() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add("mx.org", Spf::parse(b"v=spf1 mx -all"), valid_until);
+        resolver.txt_add("void.org", Spf::parse(b"v=spf1 mx ~all"), valid_until);
+        resolver.mx_add(
+            "mx.org",
+            (1..=15)
+                .map(|n| MX {
+                    exchanges: vec![format!("mx{n}.mx.org")],
+                    preference: n,
+                })
+                .collect(),
+            valid_until,
+        );
+        for n in 1..=15 {
+            resolver.ipv4_add(
+                format!("mx{n}.mx.org"),
+                vec![Ipv4Addr::new(10, 0, 0, n)],
+                valid_until,
+            );
+        }
+        resolver.mx_add(
+            "void.org",
+            vec![MX {
+                exchanges: vec![
+                    "mx1.void.org".to_string(),
+                    "mx2.void.org".to_string(),
+                    "mx3.void.org".to_string(),
+                ],
+                preference: 10,
+            }],
+            valid_until,
+        );
+
+        for (domain, ip, expected_result, expected_error) in [
+            ("mx.org", "10.0.0.3", SpfResult::Pass, None),
+            (
+                "mx.org",
+                "10.0.0.12",
+                SpfResult::PermError,
+                Some(Error::MxLimitExceeded),
+            ),
+            (
+                "mx.org",
+                "192.0.2.1",
+                SpfResult::PermError,
+                Some(Error::MxLimitExceeded),
+            ),
+            (
+                "void.org",
+                "192.0.2.1",
+                SpfResult::PermError,
+                Some(Error::VoidLookupLimitExceeded),
+            ),
+        ] {
+            let output = resolver
+                .check_host(ip.parse().unwrap(), domain, domain, "localdomain.org", "")
+                .await;
+            assert_eq!(output.result(), expected_result, "{domain} {ip}");
+            assert_eq!(output.error(), expected_error.as_ref(), "{domain} {ip}");
+            // Address lookups of MX names are not counted as terms
+            assert_eq!(output.num_lookups(), 1, "{domain} {ip}");
+        }
+    }
+
+    #[cfg(feature = "idna")]
+    #[tokio::test]
+    async fn spf_idna() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (domain, record) in [
+            (
+                "xn--bcher-kva.example",
+                "v=spf1 a:mail.%{d} exists:%{l}.%{o}._spf.example.org -all",
+            ),
+            ("xn--0zwm56d.xn--55qx5d", "v=spf1 include:%{d1} -all"),
+            ("xn--55qx5d", "v=spf1 ip4:192.0.2.2 -all"),
+        ] {
+            resolver.txt_add(domain, Spf::parse(record.as_bytes()), valid_until);
+        }
+        resolver.ipv4_add(
+            "mail.xn--bcher-kva.example",
+            vec![Ipv4Addr::new(192, 0, 2, 1)],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "user.xn--bcher-kva.example._spf.example.org",
+            vec![Ipv4Addr::new(127, 0, 0, 2)],
+            valid_until,
+        );
+
+        for (ip, domain, sender, expected_domain, expected_result) in [
+            (
+                "192.0.2.1",
+                "bücher.example",
+                "other@bücher.example",
+                "xn--bcher-kva.example",
+                SpfResult::Pass,
+            ),
+            (
+                "192.0.2.9",
+                "BÜCHER.example.",
+                "user@bücher.example",
+                "xn--bcher-kva.example",
+                SpfResult::Pass,
+            ),
+            (
+                "192.0.2.9",
+                "bücher.example",
+                "other@bücher.example",
+                "xn--bcher-kva.example",
+                SpfResult::Fail,
+            ),
+            (
+                "192.0.2.2",
+                "测试.公司",
+                "user@测试.公司",
+                "xn--0zwm56d.xn--55qx5d",
+                SpfResult::Pass,
+            ),
+            (
+                "192.0.2.1",
+                "测试.公司",
+                "user@测试.公司",
+                "xn--0zwm56d.xn--55qx5d",
+                SpfResult::Fail,
+            ),
+        ] {
+            let output = resolver
+                .check_host(
+                    ip.parse().unwrap(),
+                    domain,
+                    domain,
+                    "localdomain.org",
+                    sender,
+                )
+                .await;
+            assert_eq!(output.result(), expected_result, "{domain} {ip}");
+            assert_eq!(output.domain(), expected_domain);
+        }
+
+        // U-labels that cannot be converted are not queried
+        let output = resolver
+            .check_host(
+                "192.0.2.1".parse().unwrap(),
+                "\u{300}bücher.example",
+                "\u{300}bücher.example",
+                "localdomain.org",
+                "",
+            )
+            .await;
+        assert_eq!(output.result(), SpfResult::PermError);
+        assert_eq!(output.error(), Some(&Error::ParseError));
+        assert_eq!(
+            resolver
+                .txt_lookup::<Spf>("\u{300}bücher.example")
+                .await
+                .unwrap_err(),
+            Error::ParseError
+        );
+    }
+
+    #[tokio::test]
+    async fn spf_best_guess() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        for resolver in [
+            Resolver::new_system_conf().unwrap(),
+            Resolver::new_system_conf()
+                .unwrap()
+                .with_spf_best_guess(true),
+        ] {
+            resolver.txt_add("published.org", Spf::parse(b"v=spf1 -all"), valid_until);
+            resolver.ipv4_add("nospf.org", vec![Ipv4Addr::new(192, 0, 2, 1)], valid_until);
+            resolver.mx_add(
+                "nospf.org",
+                vec![MX {
+                    exchanges: vec!["mail.nospf.org".to_string()],
+                    preference: 10,
+                }],
+                valid_until,
+            );
+            resolver.ipv4_add(
+                "mail.nospf.org",
+                vec![Ipv4Addr::new(192, 0, 2, 2)],
+                valid_until,
+            );
+
+            for (ip, domain, expected_result, expected_lookups) in [
+                ("192.0.2.1", "nospf.org", SpfResult::Pass, 1),
+                ("192.0.2.2", "nospf.org", SpfResult::Pass, 2),
+                ("192.0.2.9", "nospf.org", SpfResult::Neutral, 3),
+                ("192.0.2.1", "published.org", SpfResult::Fail, 0),
+            ] {
+                let output = resolver
+                    .check_host(ip.parse().unwrap(), domain, domain, "localdomain.org", "")
+                    .await;
+                if resolver.spf_best_guess || domain == "published.org" {
+                    assert_eq!(output.result(), expected_result, "{domain} {ip}");
+                    assert_eq!(output.num_lookups(), expected_lookups, "{domain} {ip}");
+                } else {
+                    assert_eq!(output.result(), SpfResult::None, "{domain} {ip}");
+                }
+                assert_eq!(
+                    output.is_best_guess(),
+                    resolver.spf_best_guess && domain == "nospf.org",
+                    "{domain} {ip}"
+                );
+            }
+        }
+    }
+
     #[tokio::test]
     async fn spf_verify() {
         let valid_until = Instant::now() + Duration::from_secs(30);
@@ -542,13 +2451,21 @@ mod test {
             let test_suite = String::from_utf8(fs::read(&file_name).unwrap()).unwrap();
 
             for test in test_suite.split("---\n") {
-                let resolver = Resolver::new_system_conf().unwrap();
+                // The examples in RFC 7208, Appendix D, do not account for void lookups
+                let resolver = if file_name.ends_with("examples.yml") {
+                    Resolver::new_system_conf()
+                        .unwrap()
+                        .with_spf_void_lookup_limit(u32::MAX)
+                } else {
+                    Resolver::new_system_conf().unwrap()
+                };
                 let mut test_name = "";
                 let mut last_test_name = "";
                 let mut helo = "";
                 let mut mail_from = "";
                 let mut client_ip = "127.0.0.1".parse::<IpAddr>().unwrap();
                 let mut test_num = 1;
+                let mut last_output = None;
 
                 for line in test.split('\n') {
                     let line = line.trim();
@@ -632,6 +2549,14 @@ mod test {
                             mail_from = value.trim();
                         } else if let Some(value) = line.strip_prefix("ip:") {
                             client_ip = value.trim().parse().unwrap();
+                        } else if let Some(value) = line.strip_prefix("received-spf:") {
+                            let output: &SpfOutput = last_output.as_ref().unwrap();
+                            assert_eq!(
+                                received_spf_fields(&output.to_received_spf("localdomain.org")),
+                                received_spf_fields(value.trim()),
+                                "Failed for {test_name:?}, test {}.",
+                                test_num - 1
+                            );
                         } else if let Some(value) = line.strip_prefix("expect:") {
                             let value = value.trim();
                             let (result, exp): (SpfResult, &str) =
@@ -652,6 +2577,7 @@ mod test {
                             if !exp.is_empty() {
                                 assert_eq!(Some(exp.to_string()).as_deref(), output.explanation());
                             }
+                            last_output = Some(output);
                             test_num += 1;
                             if test_name != last_test_name {
                                 println!("Passed test {test_name:?}");
@@ -663,4 +2589,77 @@ mod test {
             }
         }
     }
+
+    /// Splits a Received-SPF header into its result, comments and key-value
+    /// pairs, unquoting the values.
+    fn received_spf_fields(header: &str) -> (String, String, BTreeMap<String, String>) {
+        let mut chars = header.chars().peekable();
+        let mut result = String::new();
+        while let Some(ch) = chars.next_if(|ch| ch.is_ascii_alphabetic()) {
+            result.push(ch.to_ascii_lowercase());
+        }
+
+        let mut comments = String::new();
+        loop {
+            while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+            if chars.next_if_eq(&'(').is_none() {
+                break;
+            }
+            let mut depth = 1;
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '\\' => comments.extend(chars.next()),
+                    '(' => {
+                        depth += 1;
+                        comments.push(ch);
+                    }
+                    ')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                        comments.push(ch);
+                    }
+                    _ => comments.push(ch),
+                }
+            }
+            comments.push(' ');
+        }
+
+        let mut fields = BTreeMap::new();
+        loop {
+            while chars
+                .next_if(|ch| ch.is_whitespace() || *ch == ';')
+                .is_some()
+            {}
+            let mut key = String::new();
+            while let Some(ch) = chars.next_if(|ch| *ch != '=') {
+                key.push(ch);
+            }
+            if chars.next().is_none() {
+                break;
+            }
+            let mut value = String::new();
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(ch) = chars.next() {
+                    match ch {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        _ => value.push(ch),
+                    }
+                }
+            } else {
+                while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace() && *ch != ';') {
+                    value.push(ch);
+                }
+            }
+            fields.insert(key.trim().to_ascii_lowercase(), value);
+        }
+
+        (
+            result,
+            comments.split_whitespace().collect::<Vec<_>>().join(" "),
+            fields,
+        )
+    }
 }